@@ -0,0 +1,251 @@
+use std::{fmt, io};
+
+use crate::objects::object::ObjectKind;
+
+/// Every way a vcs operation can fail
+///
+/// The `Display` text of each variant is the message logged to the console for it.
+#[derive(Debug)]
+pub enum VcsError {
+    /// No `.vcs` directory could be found
+    NotARepository,
+    /// `init` was run somewhere that already has a `.vcs` directory
+    AlreadyARepository,
+    /// No object with this hash exists
+    ObjectNotFound(String),
+    /// The object with this hash could not be parsed
+    CorruptObject { hash: String, reason: String },
+    /// The object with this hash is not of the kind the operation needs
+    WrongObjectKind {
+        hash: String,
+        expected: ObjectKind,
+        found: ObjectKind,
+    },
+    /// The operation modifies the current branch, but HEAD is not on a branch
+    DetachedHead,
+    /// This name is neither a branch nor a commit
+    InvalidRef(String),
+    /// This line of `.vcs/index` could not be parsed
+    IndexParse(String),
+    /// `.vcs/index` is not a valid binary index, for this reason
+    CorruptIndex(String),
+    /// This abbreviated hash matches all of these objects
+    AmbiguousRevision {
+        prefix: String,
+        matches: Vec<String>,
+    },
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// This lock file exists, so another vcs process is changing the repository
+    RepositoryLocked(String),
+    /// The operation was called with the wrong operands
+    IncorrectOperands,
+    /// The operation was called with the wrong number of arguments; holds the expected number
+    IncorrectArgumentCount(String),
+    /// No operation with this name exists
+    NoSuchOperation(String),
+    /// This command-line option was given without the value it needs
+    MissingOptionValue(String),
+    /// This command-line option was given a value it can't use
+    InvalidOptionValue { option: String, value: String },
+    /// This line of this config file could not be parsed
+    ConfigParse { source: String, line: usize },
+    /// This name and email, from `--author`, the environment or the config, is not of the form
+    /// `Name <email>`
+    InvalidIdentity(String),
+    /// This line of the reflog of this ref could not be parsed
+    ReflogParse { reference: String, line: usize },
+    /// `fsck` found corrupt, missing or mistyped objects; holds every line of its report
+    IntegrityCheckFailed(Vec<String>),
+    /// A path given to an operation is not inside the worktree
+    OutsideRepository(String),
+    /// A path given to an operation does not exist
+    PathNotFound(String),
+    /// `add` was asked to stage a file that an ignore rule matches, without `--force`
+    IgnoredPath(String),
+    /// `add -p` couldn't run this editor to edit a hunk, or it exited with an error
+    EditorFailed(String),
+    /// `add -p` found that these files were staged again while it asked about their hunks
+    IndexChanged(Vec<String>),
+    /// `rm` was asked to remove a file that is neither staged nor tracked
+    NotStagedOrTracked(String),
+    /// `rm` was given a directory without `-r`
+    NotRemovingRecursively(String),
+    /// `rm` would lose changes to these files that aren't committed; holds a line describing each
+    WouldLoseChanges(Vec<String>),
+    /// `commit` was called without a message
+    EmptyCommitMessage,
+    /// `commit` was called with nothing staged
+    NothingToCommit,
+    /// A merge has stopped for conflicts to be resolved, so the operation can't run until it is
+    /// finished or aborted
+    MergeInProgress,
+    /// `merge --continue` or `merge --abort` was run without a merge in progress
+    NoMergeInProgress,
+    /// `merge` found conflicts; holds a line describing each
+    MergeConflicts(Vec<String>),
+    /// `merge` was given a commit that shares no history with the current branch
+    NoCommonAncestor(String),
+    /// The index still has conflicts in these files
+    UnresolvedConflicts(Vec<String>),
+    /// The operation needs the index to be empty, but changes are staged
+    StagedChanges,
+    /// The operation would overwrite uncommitted changes to these files in the worktree, or
+    /// untracked files in the way
+    WouldOverwrite(Vec<String>),
+    /// A branch with this name already exists
+    BranchExists(String),
+    /// This name can't be used for a branch
+    InvalidBranchName(String),
+    /// No branch with this name exists
+    BranchNotFound(String),
+    /// The branch with this name is checked out, so it can't be deleted
+    CannotDeleteCurrentBranch(String),
+}
+
+pub type Result<T> = std::result::Result<T, VcsError>;
+
+impl fmt::Display for VcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VcsError::NotARepository => write!(f, "Not in an initialized vcs directory."),
+            VcsError::AlreadyARepository => write!(f, "Already in a vcs directory."),
+            VcsError::ObjectNotFound(hash) => write!(f, "No object with hash of {} exists.", hash),
+            VcsError::CorruptObject { hash, reason } => {
+                write!(f, "Object {} is corrupt: {}", hash, reason)
+            }
+            VcsError::WrongObjectKind {
+                hash,
+                expected,
+                found,
+            } => write!(f, "Object {} is a {}, not a {}.", hash, found, expected),
+            VcsError::DetachedHead => write!(
+                f,
+                "Currently in a detached HEAD state. Check out a branch to modify the directory."
+            ),
+            VcsError::InvalidRef(name) => write!(f, "{} does not exist.", name),
+            VcsError::AmbiguousRevision { prefix, matches } => write!(
+                f,
+                "{} is ambiguous. It could be any of: {}.",
+                prefix,
+                matches.join(", ")
+            ),
+            VcsError::IndexParse(line) => write!(f, "Could not parse index line `{}`.", line),
+            VcsError::CorruptIndex(reason) => write!(f, "The index is corrupt: {}.", reason),
+            VcsError::Io(e) => write!(f, "{}", e),
+            VcsError::RepositoryLocked(lock_path) => write!(
+                f,
+                "Another vcs process is running in this repository: {} exists. If no other process is running, remove it and try again.",
+                lock_path
+            ),
+            VcsError::IncorrectOperands => write!(f, "Incorrect operands."),
+            VcsError::IncorrectArgumentCount(expected) => write!(
+                f,
+                "Incorrect number of arguments. Expected {} arguments.",
+                expected
+            ),
+            VcsError::NoSuchOperation(_) => {
+                write!(f, "No operation with that name exists (yet).")
+            }
+            VcsError::MissingOptionValue(option) => {
+                write!(f, "Option {} requires a value.", option)
+            }
+            VcsError::InvalidOptionValue { option, value } => {
+                write!(f, "Invalid value {} for option {}.", value, option)
+            }
+            VcsError::ConfigParse { source, line } => {
+                write!(f, "Could not parse line {} of {}.", line, source)
+            }
+            VcsError::ReflogParse { reference, line } => {
+                write!(f, "Could not parse line {} of the reflog of {}.", line, reference)
+            }
+            VcsError::InvalidIdentity(person) => {
+                write!(f, "Invalid identity `{}`. Expected `Name <email>`.", person)
+            }
+            VcsError::IntegrityCheckFailed(report) => {
+                write!(f, "The repository is damaged:\n{}", report.join("\n"))
+            }
+            VcsError::OutsideRepository(path) => {
+                write!(f, "{} is outside the repository.", path)
+            }
+            VcsError::PathNotFound(path) => write!(f, "File {} does not exist.", path),
+            VcsError::IgnoredPath(path) => write!(
+                f,
+                "{} is ignored by a .vcsignore rule. Use --force to add it anyway.",
+                path
+            ),
+            VcsError::EditorFailed(editor) => {
+                write!(f, "The editor {} failed, so nothing was edited.", editor)
+            }
+            VcsError::IndexChanged(names) => write!(
+                f,
+                "These files were staged while their hunks were chosen: {}. Nothing was staged.",
+                names.join(", ")
+            ),
+            VcsError::NotStagedOrTracked(_) => write!(f, "No reason to remove the file."),
+            VcsError::NotRemovingRecursively(path) => {
+                write!(f, "Not removing {} recursively without -r.", path)
+            }
+            VcsError::WouldLoseChanges(lines) => write!(
+                f,
+                "{}\nNothing was removed. Use --cached to keep the files on disk, or -f to remove them anyway.",
+                lines.join("\n")
+            ),
+            VcsError::EmptyCommitMessage => write!(f, "Please enter a commit message."),
+            VcsError::NothingToCommit => write!(f, "No changes added to the commit"),
+            VcsError::MergeInProgress => write!(
+                f,
+                "A merge is in progress. Finish it with `vcs merge --continue`, or undo it with `vcs merge --abort`."
+            ),
+            VcsError::NoMergeInProgress => write!(f, "There is no merge in progress."),
+            VcsError::MergeConflicts(conflicts) => write!(
+                f,
+                "{}\nAutomatic merge failed. Fix the conflicts, stage the files with `vcs add` or `vcs rm`, then run `vcs merge --continue`.",
+                conflicts.join("\n")
+            ),
+            VcsError::NoCommonAncestor(commit) => {
+                write!(f, "{} has no history in common with the current branch.", commit)
+            }
+            VcsError::UnresolvedConflicts(names) => write!(
+                f,
+                "These files still have conflicts: {}. Fix them and stage them with `vcs add` or `vcs rm` first.",
+                names.join(", ")
+            ),
+            VcsError::StagedChanges => {
+                write!(f, "There are staged changes. Commit them first.")
+            }
+            VcsError::WouldOverwrite(names) => write!(
+                f,
+                "These files have changes that would be overwritten: {}. Commit or remove them first.",
+                names.join(", ")
+            ),
+            VcsError::BranchExists(name) => {
+                write!(f, "A branch named {} already exists.", name)
+            }
+            VcsError::InvalidBranchName(name) => {
+                write!(f, "{} is not a valid branch name.", name)
+            }
+            VcsError::BranchNotFound(name) => write!(f, "Branch {} was not found.", name),
+            VcsError::CannotDeleteCurrentBranch(name) => write!(
+                f,
+                "Cannot delete branch {}. Switch to a different branch to delete.",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VcsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VcsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VcsError {
+    fn from(e: io::Error) -> VcsError {
+        VcsError::Io(e)
+    }
+}
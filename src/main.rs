@@ -1,7 +1,29 @@
+use std::{env, process::ExitCode};
+
+pub mod error;
 pub mod objects;
 pub mod operations;
 pub mod utils;
 
-fn main() {
-    println!("Hello, world!");
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let dir = match env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match operations::dispatch(&dir, &args) {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -1,24 +1,109 @@
-use std::io::Result;
+use std::{fs::read_link, path::Path};
 
-use crate::{
-    objects::write_object,
-    utils::{fs_utils::get_file_contents, hash::sha2},
+use crate::{error::Result, utils::fs_utils::get_file_bytes};
+
+use super::{
+    object::{hash_object, ObjectKind},
+    repo::Repository,
 };
 
-/// Creates a blob off of the given filename. Returns the hash of the blob.
+/// Creates a blob off of the file at `path`. Returns the hash of the blob.
 ///
-/// Throws an error if the filename doesn't exist
-pub fn create_blob(filename: &str) -> Result<String> {
-    let (hash, contents) = get_blob_hash(filename)?;
-    let _ = write_object(&hash, &contents);
-    Ok(hash)
+/// Throws an error if the file doesn't exist
+pub fn create_blob<P: AsRef<Path>>(repo: &Repository, path: P) -> Result<String> {
+    let contents = get_worktree_bytes(path)?;
+    repo.write_object(ObjectKind::Blob, contents)
 }
 
-/// Gets the hash of a blob given a filename and the new contents written in the object.
+/// Gets the hash of a blob given the path of a file and the contents of the file.
 ///
-/// Does not create the blob in the objects directory
-pub fn get_blob_hash(filename: &str) -> Result<(String, String)> {
-    let contents = get_file_contents(filename)?;
-    let contents = String::from("blob\n") + &contents;
-    Ok((sha2(&contents), contents))
+/// Does not create the blob in the objects directory. The file is read as raw bytes, so files
+/// that aren't valid UTF-8 are stored unchanged.
+pub fn get_blob_hash<P: AsRef<Path>>(path: P) -> Result<(String, Vec<u8>)> {
+    let contents = get_worktree_bytes(path)?;
+    Ok((hash_object(ObjectKind::Blob, &contents), contents))
+}
+
+/// Outputs the bytes a blob of the file at `path` holds: the contents of a file, or the target of
+/// a symlink, which is never followed
+fn get_worktree_bytes<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    if !path.is_symlink() {
+        return Ok(get_file_bytes(path)?);
+    }
+    let target = read_link(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(target.to_string_lossy().into_owned().into_bytes())
+    }
+}
+
+/// Reads the blob with hash `hash`, and outputs the bytes of the file it holds
+///
+/// Returns a `WrongObjectKind` error if the object is not a blob
+pub fn read_blob(repo: &Repository, hash: &str) -> Result<Vec<u8>> {
+    repo.read_object_of_kind(hash, ObjectKind::Blob)
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for get_blob_hash, create_blob and read_blob
+    //      file contents: empty, text, not valid UTF-8
+    //      object read: blob, not a blob
+
+    use std::fs::write;
+
+    use super::*;
+    use crate::{error::VcsError, utils::test_dir::make_test_dir};
+
+    #[test]
+    fn text_blob() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        write(test_dir.path().join("empty.txt"), "")?;
+        write(test_dir.path().join("test.txt"), "some text")?;
+        assert_eq!(
+            hash_object(ObjectKind::Blob, b""),
+            create_blob(&repo, test_dir.path().join("empty.txt"))?
+        );
+        let hash = create_blob(&repo, test_dir.path().join("test.txt"))?;
+        assert_eq!(
+            (hash.clone(), b"some text".to_vec()),
+            get_blob_hash(test_dir.path().join("test.txt"))?
+        );
+        assert_eq!(b"some text".to_vec(), read_blob(&repo, &hash)?);
+        Ok(())
+    }
+
+    #[test]
+    fn binary_blob() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let bytes: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe, b'\n', 0x00];
+        write(test_dir.path().join("image.png"), &bytes)?;
+        let hash = create_blob(&repo, test_dir.path().join("image.png"))?;
+        assert_eq!(bytes, read_blob(&repo, &hash)?);
+        assert!(matches!(
+            repo.read_object_text(&hash, ObjectKind::Blob),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn not_a_blob() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let hash = repo.write_object(ObjectKind::Tree, "Trees\nBlobs")?;
+        assert!(matches!(
+            read_blob(&repo, &hash),
+            Err(VcsError::WrongObjectKind { .. })
+        ));
+        Ok(())
+    }
 }
@@ -1,94 +1,226 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader, Error},
-};
+use crate::error::{Result, VcsError};
 
-use crate::{
-    objects::write_object,
-    utils::{
-        fs_utils::{directory_exists, file_exists, get_file_contents, get_line_in_object},
-        hash::sha2,
-    },
+use super::{
+    object::{hash_object, ObjectKind},
+    repo::Repository,
+    signature::Signature,
+    tree::{find_entry_in_tree, find_file_in_tree, TreeEntry},
 };
 
-use super::tree::find_file_in_tree;
-
 pub const INITIAL_COMMIT_HASH: &str =
-    "4dc93cdee44eeb4d71d3c1ff17bd16a715213cc4d8f27ac9d2ed77fadc3ffa63";
+    "7d39e9dc48d6efb2973dd9be7729807e81b05eba3d6c4c75b740053b9a553499";
 
+/// Outputs the text and hash of the commit with message `message`, parents `parents`, time `time`
+/// and tree `tree_hash`
+///
+/// Each parent gets a `Parent` line of its own, in order; a commit with no parents, like the root
+/// commit made by `init`, has the single line `No parent` instead. The author and committer, if
+/// given, are written after the tree hash. Commits made before they were recorded, and the root
+/// commit, have neither.
 pub fn commit_string_and_hash(
     message: &str,
-    parent: &str,
+    parents: &[&str],
     time: i64,
     tree_hash: &str,
+    author: Option<&Signature>,
+    committer: Option<&Signature>,
 ) -> (String, String) {
-    let commit_string = format!(
-        "Parent\n{}\nTime\n{}\nTree Hash\n{}\nMessage\n{}",
-        parent,
-        time.to_string(),
-        tree_hash,
-        message
-    );
-    (commit_string.clone(), sha2(&commit_string))
-}
-
-pub fn write_commit(message: &str, parent: &str, time: i64, tree_hash: &str) -> String {
-    let (commit_string, commit_hash) = commit_string_and_hash(message, parent, time, tree_hash);
-    let _ = write_object(&commit_hash, &commit_string);
-    commit_hash
-}
-
-/// Returns the hash of the current head commit. If unable to get a commit, panics.
-pub fn get_head_commit() -> Result<String, Error> {
-    assert!(directory_exists(".vcs"));
-    let head_branch_or_hash = get_file_contents(".vcs/HEAD")?;
-    let branch_name = format!(".vcs/branches/{}", head_branch_or_hash);
-    if file_exists(&branch_name) {
-        get_file_contents(&branch_name)
-    } else {
-        get_file_contents(".vcs/HEAD")
+    let mut commit_string = String::new();
+    if parents.is_empty() {
+        commit_string.push_str("Parent\nNo parent\n");
+    }
+    for parent in parents {
+        commit_string.push_str(&format!("Parent\n{}\n", parent));
+    }
+    commit_string.push_str(&format!("Time\n{}\nTree Hash\n{}\n", time, tree_hash));
+    if let Some(author) = author {
+        commit_string.push_str(&format!("Author\n{}\n", author));
     }
+    if let Some(committer) = committer {
+        commit_string.push_str(&format!("Committer\n{}\n", committer));
+    }
+    commit_string.push_str(&format!("Message\n{}", message));
+    let hash = hash_object(ObjectKind::Commit, commit_string.as_bytes());
+    (commit_string, hash)
 }
 
-/// Returns the hash of the given file, or `DNE` if the file didn't exist in the given commit.
+/// Writes the commit described by the arguments, as for `commit_string_and_hash`, to `repo`, and
+/// outputs its hash
+pub fn write_commit(
+    repo: &Repository,
+    message: &str,
+    parents: &[&str],
+    time: i64,
+    tree_hash: &str,
+    author: Option<&Signature>,
+    committer: Option<&Signature>,
+) -> Result<String> {
+    let (commit_string, _) =
+        commit_string_and_hash(message, parents, time, tree_hash, author, committer);
+    repo.write_object(ObjectKind::Commit, commit_string)
+}
+
+/// Returns the hash of the given file, or `None` if the file didn't exist in the given commit.
+///
+/// Returns an `ObjectNotFound` error if the commit doesn't exist
+pub fn get_hash_in_commit(
+    repo: &Repository,
+    commit: &str,
+    filename: &str,
+) -> Result<Option<String>> {
+    let tree_hash = get_commit_tree(repo, commit)?;
+    find_file_in_tree(repo, &tree_hash, filename)
+}
+
+/// Returns the tree entry of the given file, holding its mode and hash, or `None` if the file
+/// didn't exist in the given commit.
 ///
-/// Panics if the commit doesn't exist
-pub fn get_hash_in_commit(commit: &str, filename: &str) -> Result<String, Error> {
-    let tree_hash = get_commit_tree(&commit)?;
-    return find_file_in_tree(&tree_hash, filename);
+/// Returns an `ObjectNotFound` error if the commit doesn't exist
+pub fn get_entry_in_commit(
+    repo: &Repository,
+    commit: &str,
+    filename: &str,
+) -> Result<Option<TreeEntry>> {
+    let tree_hash = get_commit_tree(repo, commit)?;
+    find_entry_in_tree(repo, &tree_hash, filename)
 }
 
 /// Given a commit hash, returns the attached commit message
-pub fn get_commit_message(commit: &str) -> Result<String, Error> {
-    let filename = format!(".vcs/objects/{}/{}", &commit[0..2], &commit[2..]);
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    // Skip 7 because that's the number of lines before message starts
-    let lines: Vec<String> = reader.lines().skip(7).filter_map(Result::ok).collect();
-    Ok(lines.join("\n"))
+pub fn get_commit_message(repo: &Repository, commit: &str) -> Result<String> {
+    Ok(read_commit(repo, commit)?.1)
+}
+
+/// Given a commit hash, returns its author, or `None` if it was made before authors were recorded
+pub fn get_commit_author(repo: &Repository, commit: &str) -> Result<Option<Signature>> {
+    Ok(read_commit(repo, commit)?.0.author)
+}
+
+/// Given a commit hash, returns its committer, or `None` if it was made before committers were
+/// recorded
+pub fn get_commit_committer(repo: &Repository, commit: &str) -> Result<Option<Signature>> {
+    Ok(read_commit(repo, commit)?.0.committer)
 }
 
 /// Given a commit hash, returns the hash of the tree it points to
-pub fn get_commit_tree(commit: &str) -> Result<String, Error> {
-    get_line_in_object(commit, 5)
+pub fn get_commit_tree(repo: &Repository, commit: &str) -> Result<String> {
+    Ok(read_commit(repo, commit)?.0.tree)
 }
 
-/// Given a commit hash, returns the parent hash of the commit if it exists
-pub fn get_commit_parent(commit: &str) -> Result<Option<String>, Error> {
-    let line = get_line_in_object(commit, 1)?;
-    if line == "No parent" {
-        return Ok(None);
-    }
-    return Ok(Some(line));
+/// Given a commit hash, returns the first parent hash of the commit if it exists
+///
+/// For a merge commit, that is the commit that was checked out when the merge was made.
+pub fn get_commit_parent(repo: &Repository, commit: &str) -> Result<Option<String>> {
+    Ok(get_commit_parents(repo, commit)?.into_iter().next())
+}
+
+/// Given a commit hash, returns the hashes of all its parents, in order: none for the root
+/// commit, two for a merge commit, and one otherwise
+pub fn get_commit_parents(repo: &Repository, commit: &str) -> Result<Vec<String>> {
+    Ok(read_commit(repo, commit)?.0.parents)
 }
 
 /// Given a commit hash, returns the time of the commit if it exists
-pub fn get_commit_time(commit: &str) -> Result<i64, Error> {
-    let line = get_line_in_object(commit, 3)?;
-    match line.parse::<i64>() {
-        Ok(value) => Ok(value),
-        Err(e) => Err(Error::new(std::io::ErrorKind::InvalidData, e)),
+pub fn get_commit_time(repo: &Repository, commit: &str) -> Result<i64> {
+    Ok(read_commit(repo, commit)?.0.time)
+}
+
+/// Every part of a commit but its message
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommitHeader {
+    pub parents: Vec<String>,
+    pub time: i64,
+    pub tree: String,
+    pub author: Option<Signature>,
+    pub committer: Option<Signature>,
+}
+
+/// Given the contents of the commit with hash `commit`, checks that it has every field a commit
+/// needs and outputs its header
+///
+/// Returns a `CorruptObject` error if a field is missing or malformed
+pub fn parse_commit_header(commit: &str, contents: &str) -> Result<CommitHeader> {
+    Ok(parse_commit(commit, contents)?.0)
+}
+
+/// Reads the commit with hash `commit`, and outputs its header and its message
+///
+/// Returns a `WrongObjectKind` error if the object is not a commit, and a `CorruptObject` error if
+/// it can't be parsed
+fn read_commit(repo: &Repository, commit: &str) -> Result<(CommitHeader, String)> {
+    parse_commit(commit, &repo.read_object_text(commit, ObjectKind::Commit)?)
+}
+
+/// Given the contents of the commit with hash `commit`, outputs its header and its message
+///
+/// A commit is a list of fields, each a label line followed by a value line: one or more `Parent`
+/// fields, then `Time` and `Tree Hash`, then optionally `Author` and `Committer`, then `Message`,
+/// whose value is the rest of the commit.
+///
+/// Returns a `CorruptObject` error if a field is missing or malformed
+fn parse_commit(commit: &str, contents: &str) -> Result<(CommitHeader, String)> {
+    let corrupt = |reason: String| VcsError::CorruptObject {
+        hash: commit.to_string(),
+        reason,
+    };
+    let lines: Vec<&str> = contents.split('\n').collect();
+    let mut line_num = 0;
+    // Outputs the value of the field labelled `label` at `line_num`, and moves past it
+    let mut field = |label: &str, required: bool| -> Result<Option<&str>> {
+        match (lines.get(line_num), lines.get(line_num + 1)) {
+            (Some(line), Some(value)) if *line == label => {
+                line_num += 2;
+                Ok(Some(value))
+            }
+            _ if required => Err(corrupt(format!(
+                "expected `{}` on line {}",
+                label,
+                line_num + 1
+            ))),
+            _ => Ok(None),
+        }
+    };
+
+    let mut parents = vec![];
+    let first_parent = field("Parent", true)?.unwrap();
+    if first_parent != "No parent" {
+        parents.push(first_parent.to_string());
+        while let Some(parent) = field("Parent", false)? {
+            parents.push(parent.to_string());
+        }
+    }
+    let time = field("Time", true)?.unwrap();
+    let time = time
+        .parse::<i64>()
+        .map_err(|_| corrupt(format!("commit time `{}` is not a number", time)))?;
+    let tree = field("Tree Hash", true)?.unwrap().to_string();
+    let mut signatures = vec![];
+    for label in ["Author", "Committer"] {
+        signatures.push(match field(label, false)? {
+            Some(value) => Some(Signature::parse(value).ok_or_else(|| {
+                corrupt(format!("malformed {} `{}`", label.to_lowercase(), value))
+            })?),
+            None => None,
+        });
     }
+    if lines.get(line_num) != Some(&"Message") {
+        return Err(corrupt(format!(
+            "expected `Message` on line {}",
+            line_num + 1
+        )));
+    }
+    let message = lines[line_num + 1..].join("\n");
+    let committer = signatures.pop().unwrap();
+    let author = signatures.pop().unwrap();
+    Ok((
+        CommitHeader {
+            parents,
+            time,
+            tree,
+            author,
+            committer,
+        },
+        message,
+    ))
 }
 
 #[cfg(test)]
@@ -107,18 +239,35 @@ mod test {
 
     #[test]
     fn test_commit_text() {
-        let (commit_text, _) = commit_string_and_hash("message", "parent", 0, "tree_hash");
+        let (commit_text, _) =
+            commit_string_and_hash("message", &["parent"], 0, "tree_hash", None, None);
         assert_eq!(
             "Parent\nparent\nTime\n0\nTree Hash\ntree_hash\nMessage\nmessage",
             commit_text
         );
+        let (commit_text, _) = commit_string_and_hash(
+            "message",
+            &["parent"],
+            20,
+            "tree_hash",
+            Some(&signature("Ann <ann@example.com> 10 +0100")),
+            Some(&signature("Cid <cid@example.com> 20 -0500")),
+        );
+        assert_eq!(
+            "Parent\nparent\nTime\n20\nTree Hash\ntree_hash\nAuthor\nAnn <ann@example.com> 10 +0100\nCommitter\nCid <cid@example.com> 20 -0500\nMessage\nmessage",
+            commit_text
+        );
+    }
+
+    /// Parses `text` as a signature, which the test expects to be valid
+    fn signature(text: &str) -> Signature {
+        Signature::parse(text).unwrap()
     }
 
     #[test]
     fn test_initial_commit() {
-        let _ = make_test_dir();
         let (commit_text, commit_hash) =
-            commit_string_and_hash("Initial commit", "No parent", 0, EMPTY_TREE_HASH);
+            commit_string_and_hash("Initial commit", &[], 0, EMPTY_TREE_HASH, None, None);
         assert_eq!(
             format!(
                 "Parent\nNo parent\nTime\n0\nTree Hash\n{}\nMessage\nInitial commit",
@@ -130,76 +279,158 @@ mod test {
     }
 
     #[test]
-    fn test_get_head() -> Result<(), Error> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(INITIAL_COMMIT_HASH, get_head_commit()?);
+    fn test_parse_commit_header() -> Result<()> {
+        let (commit_text, _) =
+            commit_string_and_hash("message", &["parent"], 10, "tree_hash", None, None);
+        assert_eq!(
+            CommitHeader {
+                parents: vec![String::from("parent")],
+                time: 10,
+                tree: String::from("tree_hash"),
+                author: None,
+                committer: None,
+            },
+            parse_commit_header("hash", &commit_text)?
+        );
+        let (commit_text, _) =
+            commit_string_and_hash("Merge", &["ours", "theirs"], 10, "tree_hash", None, None);
+        assert!(commit_text.starts_with("Parent\nours\nParent\ntheirs\nTime\n"));
+        assert_eq!(
+            vec![String::from("ours"), String::from("theirs")],
+            parse_commit_header("hash", &commit_text)?.parents
+        );
+        let author = signature("Ann <ann@example.com> 10 +0100");
+        let (commit_text, _) = commit_string_and_hash(
+            "Author\nMessage\nis not a field",
+            &[],
+            10,
+            "tree_hash",
+            Some(&author),
+            None,
+        );
+        let (header, message) = parse_commit("hash", &commit_text)?;
+        assert!(header.parents.is_empty());
+        assert_eq!(Some(author), header.author);
+        assert_eq!(None, header.committer);
+        assert_eq!("Author\nMessage\nis not a field", message);
+        for bad in [
+            "Parent\nparent\nTime\n0\nTree Hash\ntree_hash",
+            "Parent\nparent\nTime\nnoon\nTree Hash\ntree_hash\nMessage\nm",
+            "Parent\nparent\nTime\n0\nTree Hash\ntree_hash\nAuthor\nAnn\nMessage\nm",
+            "Parent\nparent\nTime\n0\nTree Hash\ntree_hash\nAuthor",
+            "",
+        ] {
+            assert!(matches!(
+                parse_commit_header("hash", bad),
+                Err(VcsError::CorruptObject { .. })
+            ));
+        }
         Ok(())
     }
 
     #[test]
-    fn test_file_dne_in_prev_commit() -> Result<(), Error> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!("DNE", get_hash_in_commit(INITIAL_COMMIT_HASH, "file.py")?);
+    fn test_get_head() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(INITIAL_COMMIT_HASH, repo.get_head_commit()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_dne_in_prev_commit() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(
+            None,
+            get_hash_in_commit(&repo, INITIAL_COMMIT_HASH, "file.py")?
+        );
         Ok(())
     }
 
     #[test]
-    fn test_file_exists_in_prev_commit() -> Result<(), Error> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file = File::create("test.txt")?;
+    fn test_file_exists_in_prev_commit() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let mut file = File::create(dir.join("test.txt"))?;
         let _ = file.write("test prev commit hash thing".as_bytes());
-        let (_, file_hash) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let (_, commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("message heheheha"),
-        ])?;
-        assert_eq!(file_hash, get_hash_in_commit(&commit_hash, "test.txt")?);
+        let (_, file_hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("message heheheha"),
+            ],
+        )?;
+        assert_eq!(
+            Some(file_hash),
+            get_hash_in_commit(&repo, &commit_hash, "test.txt")?
+        );
         Ok(())
     }
 
     #[test]
-    fn test_getters_on_commit() -> Result<(), Error> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(None, get_commit_parent(INITIAL_COMMIT_HASH)?);
-        assert_eq!(0, get_commit_time(INITIAL_COMMIT_HASH)?);
-        assert_eq!("Initial commit", get_commit_message(INITIAL_COMMIT_HASH)?);
-        assert_eq!(EMPTY_TREE_HASH, get_commit_tree(INITIAL_COMMIT_HASH)?);
-        let mut file = File::create("test.txt")?;
+    fn test_getters_on_commit() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(None, get_commit_parent(&repo, INITIAL_COMMIT_HASH)?);
+        assert_eq!(0, get_commit_time(&repo, INITIAL_COMMIT_HASH)?);
+        assert_eq!(
+            "Initial commit",
+            get_commit_message(&repo, INITIAL_COMMIT_HASH)?
+        );
+        assert_eq!(
+            EMPTY_TREE_HASH,
+            get_commit_tree(&repo, INITIAL_COMMIT_HASH)?
+        );
+        let mut file = File::create(dir.join("test.txt"))?;
         let _ = file.write("test prev commit hash thing".as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let (_, commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("message heheheha"),
-        ])?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("message heheheha"),
+            ],
+        )?;
         assert_eq!(
             INITIAL_COMMIT_HASH,
-            get_commit_parent(&commit_hash)?.unwrap()
+            get_commit_parent(&repo, &commit_hash)?.unwrap()
         );
         Ok(())
     }
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    error::{Result, VcsError},
+    utils::fs_utils::{file_exists, get_file_contents},
+};
+
+use super::repo::Repository;
+
+/// Name of the config file in the user's home directory, read before the repository's own
+pub const GLOBAL_CONFIG_FILE: &str = ".vcsconfig";
+
+/// Settings read from config files, keyed by `<section>.<key>`, e.g. `user.name`
+#[derive(Debug, Default)]
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    /// Reads `.vcsconfig` in the user's home directory `home`, if there is one, and then
+    /// `.vcs/config` in `repo`, so settings in the repository override the user's own. Either
+    /// file may be missing.
+    ///
+    /// Returns a `ConfigParse` error if a file has a line that can't be parsed
+    pub fn load(repo: &Repository, home: Option<&Path>) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(home) = home {
+            config.read_file(&home.join(GLOBAL_CONFIG_FILE))?;
+        }
+        config.read_file(&repo.vcs_dir().join("config"))?;
+        Ok(config)
+    }
+
+    /// Outputs the value of `key`, or `None` if no file sets it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(&key.to_lowercase()).map(String::as_str)
+    }
+
+    /// Reads the settings in the file at `path`, if it exists, overriding any already read
+    fn read_file(&mut self, path: &Path) -> Result<()> {
+        if file_exists(path) {
+            self.parse(&path.display().to_string(), &get_file_contents(path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the settings in `text`, the contents of the file `source`, overriding any already
+    /// read
+    ///
+    /// A file is a list of sections, each a `[<section>]` line followed by `<key> = <value>`
+    /// lines. Blank lines and lines starting with `#` or `;` are skipped. Section and key names
+    /// are case-insensitive; a value may be wrapped in double quotes to keep surrounding spaces.
+    ///
+    /// Returns a `ConfigParse` error if a line is neither, or a key comes before any section
+    pub fn parse(&mut self, source: &str, text: &str) -> Result<()> {
+        let mut section: Option<String> = None;
+        for (line_num, line) in text.lines().enumerate() {
+            let error = || VcsError::ConfigParse {
+                source: source.to_string(),
+                line: line_num + 1,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                if name.trim().is_empty() {
+                    return Err(error());
+                }
+                section = Some(name.trim().to_lowercase());
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(error)?;
+            let section = section.as_ref().ok_or_else(error)?;
+            let key = key.trim();
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return Err(error());
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            self.values.insert(
+                format!("{}.{}", section, key.to_lowercase()),
+                value.to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for parse and get
+    //      lines: section, key, blank, comment, malformed, key before any section
+    //      values: plain, quoted, set twice
+    //      key case: same as the file, different
+
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_keys() -> Result<()> {
+        let mut config = Config::default();
+        config.parse(
+            "global",
+            "# who I am\n[user]\n\tname = Jane Doe\n\temail=jane@example.com\n\n; other\n[Core]\nPadded = \" spaces \"\n",
+        )?;
+        assert_eq!(Some("Jane Doe"), config.get("user.name"));
+        assert_eq!(Some("jane@example.com"), config.get("user.email"));
+        assert_eq!(Some(" spaces "), config.get("core.padded"));
+        assert_eq!(Some(" spaces "), config.get("CORE.PADDED"));
+        assert_eq!(None, config.get("user.signingkey"));
+
+        // Later files override earlier ones
+        config.parse("repository", "[user]\nname = J. Doe")?;
+        assert_eq!(Some("J. Doe"), config.get("user.name"));
+        assert_eq!(Some("jane@example.com"), config.get("user.email"));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for (text, bad_line) in [
+            ("name = Jane", 1),
+            ("[user]\nname Jane", 2),
+            ("[user]\n\n= Jane", 3),
+            ("[]\nname = Jane", 1),
+        ] {
+            assert!(matches!(
+                Config::default().parse("config", text),
+                Err(VcsError::ConfigParse { source, line }) if source == "config" && line == bad_line
+            ));
+        }
+    }
+}
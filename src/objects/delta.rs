@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+/// Length of the blocks of the base that `create_delta` looks for in the target. Matches shorter
+/// than this are stored as inserts.
+const BLOCK_SIZE: usize = 16;
+
+/// Most bytes a single insert instruction can hold
+const MAX_INSERT: usize = 0x7f;
+
+/// Opcode of a copy instruction; any smaller nonzero opcode is an insert of that many bytes
+const COPY: u8 = 0x80;
+
+/// Most bytes reserved up front for an output whose size is read from a delta or pack, so a
+/// corrupt size can't make the allocation itself fail. Larger outputs grow as they are read.
+pub const MAX_PREALLOCATION: usize = 1 << 20;
+
+/// Outputs a delta that turns `base` into `target`
+///
+/// A delta starts with the sizes of the base and of the target, followed by instructions, each
+/// either
+///     * a copy, `0x80 <offset> <length>`, appending `length` bytes of the base starting at
+///       `offset`, or
+///     * an insert, `<n> <n bytes>` with `1 <= n <= 127`, appending the `n` bytes that follow.
+/// All numbers are little-endian base-128 varints.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    write_varint(&mut delta, base.len() as u64);
+    write_varint(&mut delta, target.len() as u64);
+
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+        blocks.entry(block).or_insert(i * BLOCK_SIZE);
+    }
+
+    let mut pending: Vec<u8> = vec![];
+    let mut position = 0;
+    while position < target.len() {
+        let matched = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|window| blocks.get(window));
+        match matched {
+            Some(&offset) => {
+                let length = base[offset..]
+                    .iter()
+                    .zip(&target[position..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                flush_insert(&mut delta, &mut pending);
+                delta.push(COPY);
+                write_varint(&mut delta, offset as u64);
+                write_varint(&mut delta, length as u64);
+                position += length;
+            }
+            None => {
+                pending.push(target[position]);
+                position += 1;
+            }
+        }
+    }
+    flush_insert(&mut delta, &mut pending);
+    delta
+}
+
+/// Applies `delta` to `base`, outputting the target it describes, or `None` if the delta is
+/// malformed or was made against a different base
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    let base_size = read_varint(delta, &mut position)?;
+    let target_size = read_varint(delta, &mut position)?;
+    if base_size != base.len() as u64 {
+        return None;
+    }
+
+    let mut target = Vec::with_capacity((target_size as usize).min(MAX_PREALLOCATION));
+    while position < delta.len() {
+        let opcode = delta[position];
+        position += 1;
+        let bytes = if opcode == COPY {
+            let offset = read_varint(delta, &mut position)? as usize;
+            let length = read_varint(delta, &mut position)? as usize;
+            base.get(offset..offset.checked_add(length)?)?
+        } else if opcode != 0 && opcode as usize <= MAX_INSERT {
+            let end = position + opcode as usize;
+            let bytes = delta.get(position..end)?;
+            position = end;
+            bytes
+        } else {
+            return None;
+        };
+        // The target never grows past the size the delta declares
+        if (target.len() + bytes.len()) as u64 > target_size {
+            return None;
+        }
+        target.extend_from_slice(bytes);
+    }
+    if target.len() as u64 != target_size {
+        return None;
+    }
+    Some(target)
+}
+
+/// Writes the bytes in `pending` to `delta` as insert instructions, and empties `pending`
+fn flush_insert(delta: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    for chunk in pending.chunks(MAX_INSERT) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+    pending.clear();
+}
+
+/// Appends `value` to `bytes` as a little-endian base-128 varint
+pub fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads a little-endian base-128 varint from `bytes` starting at `position`, advancing
+/// `position` past it. Returns `None` if the varint is truncated or too long.
+pub fn read_varint(bytes: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*position)?;
+        *position += 1;
+        if shift >= 64 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for create_delta and apply_delta
+    //      base: empty, shorter than a block, long
+    //      target: empty, identical to base, small edit of base, unrelated to base
+    //      delta: valid, wrong base, truncated, target size wrong or huge
+
+    use super::*;
+
+    /// Some text long enough to have several blocks
+    fn long_text() -> Vec<u8> {
+        (0..200)
+            .map(|i| format!("line number {}\n", i))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn round_trips() {
+        let long = long_text();
+        let mut edited = long.clone();
+        edited.splice(100..110, b"an edit in the middle".iter().cloned());
+        edited.extend_from_slice(b"and a new last line\n");
+        let cases: Vec<(&[u8], &[u8])> = vec![
+            (b"", b""),
+            (b"", b"new"),
+            (b"short", b"shorter"),
+            (&long, b""),
+            (&long, &long),
+            (&long, &edited),
+            (b"unrelated", &long),
+        ];
+        for (base, target) in cases {
+            let delta = create_delta(base, target);
+            assert_eq!(Some(target.to_vec()), apply_delta(base, &delta));
+        }
+    }
+
+    #[test]
+    fn similar_files_make_small_deltas() {
+        let long = long_text();
+        let mut edited = long.clone();
+        edited.splice(1000..1004, b"EDIT".iter().cloned());
+        let delta = create_delta(&long, &edited);
+        assert!(delta.len() < 64, "delta was {} bytes", delta.len());
+    }
+
+    #[test]
+    fn rejects_bad_deltas() {
+        let long = long_text();
+        let delta = create_delta(&long, b"line number 1\nline number 2\n");
+        assert_eq!(None, apply_delta(b"another base", &delta));
+        assert_eq!(None, apply_delta(&long, &delta[..delta.len() - 1]));
+        assert_eq!(None, apply_delta(&long, &[0, 0, 0]));
+
+        // Declared target sizes are checked against what the instructions make, not trusted
+        let mut huge = vec![0];
+        write_varint(&mut huge, u64::MAX);
+        huge.extend_from_slice(&[1, b'a']);
+        assert_eq!(None, apply_delta(b"", &huge));
+        assert_eq!(None, apply_delta(b"", &[0, 1, 2, b'a', b'b']));
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, value);
+            let mut position = 0;
+            assert_eq!(Some(value), read_varint(&bytes, &mut position));
+            assert_eq!(bytes.len(), position);
+        }
+        assert_eq!(None, read_varint(&[0x80], &mut 0));
+    }
+}
@@ -0,0 +1,457 @@
+/// How a line fares going from the old version of a text to the new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Same,
+    Removed,
+    Added,
+}
+
+/// One line of an edit script. `old` and `new` are the indices of the line in the old and new
+/// versions; a removed line's `new` (or an added line's `old`) is the number of lines of that
+/// version that come before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    pub kind: EditKind,
+    pub old: usize,
+    pub new: usize,
+}
+
+/// A run of edits with the unchanged lines around them, as shown by a unified diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub edits: Vec<Edit>,
+}
+
+impl Hunk {
+    /// The header of the hunk, `@@ -<old start>,<old lines> +<new start>,<new lines> @@`, with
+    /// line numbers counted from 1. A count of 1 is left out, and a side with no lines starts at
+    /// the line before the hunk.
+    pub fn header(&self) -> String {
+        let count = |kind: EditKind| {
+            self.edits
+                .iter()
+                .filter(|edit| edit.kind == EditKind::Same || edit.kind == kind)
+                .count()
+        };
+        let range = |start: usize, len: usize| match len {
+            0 => format!("{},0", start),
+            1 => format!("{}", start + 1),
+            _ => format!("{},{}", start + 1, len),
+        };
+        let first = self.edits[0];
+        format!(
+            "@@ -{} +{} @@",
+            range(first.old, count(EditKind::Removed)),
+            range(first.new, count(EditKind::Added))
+        )
+    }
+
+    /// Outputs the hunk as a unified diff shows it: its header, then each line prefixed by ` `,
+    /// `-` or `+`, with a line without a line break followed by `\ No newline at end of file`.
+    /// `old_lines` and `new_lines` are the lines of the versions the hunk's edits index.
+    pub fn text(&self, old_lines: &[&[u8]], new_lines: &[&[u8]]) -> String {
+        let mut output = self.header();
+        output.push('\n');
+        for edit in &self.edits {
+            let (prefix, line) = match edit.kind {
+                EditKind::Same => (' ', new_lines[edit.new]),
+                EditKind::Removed => ('-', old_lines[edit.old]),
+                EditKind::Added => ('+', new_lines[edit.new]),
+            };
+            output.push(prefix);
+            output.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                output.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        output
+    }
+
+    /// Splits the hunk at each run of unchanged lines between its changes. Each smaller hunk
+    /// keeps the unchanged lines on either side of its changes, so neighbours share the lines
+    /// between them. A hunk with one run of changes is output as it is.
+    pub fn split(&self) -> Vec<Hunk> {
+        let mut hunks = vec![];
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.edits.len() {
+            // Skip the unchanged lines before the next run of changes, then the run itself
+            while i < self.edits.len() && self.edits[i].kind == EditKind::Same {
+                i += 1;
+            }
+            while i < self.edits.len() && self.edits[i].kind != EditKind::Same {
+                i += 1;
+            }
+            let mut end = i;
+            while end < self.edits.len() && self.edits[end].kind == EditKind::Same {
+                end += 1;
+            }
+            if self.edits[start..i]
+                .iter()
+                .any(|edit| edit.kind != EditKind::Same)
+            {
+                hunks.push(Hunk {
+                    edits: self.edits[start..end].to_vec(),
+                });
+            }
+            // The next hunk starts with the unchanged lines this one ends with
+            start = i;
+        }
+        hunks
+    }
+}
+
+/// Splits `text` into lines, each keeping its line break. A last line without one is a line too.
+pub fn split_lines(text: &[u8]) -> Vec<&[u8]> {
+    text.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+/// Outputs a shortest edit script that turns `a` into `b`, built from `matching_lines`: each line
+/// of `a` is kept or removed, and each line of `b` is kept or added. Removals come before the
+/// additions that replace them.
+pub fn edit_script<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let mut edits = vec![];
+    let (mut old, mut new) = (0, 0);
+    let matches = matching_lines(a, b);
+    for (next_old, next_new) in matches
+        .into_iter()
+        .chain(std::iter::once((a.len(), b.len())))
+    {
+        for line in old..next_old {
+            edits.push(Edit {
+                kind: EditKind::Removed,
+                old: line,
+                new,
+            });
+        }
+        for line in new..next_new {
+            edits.push(Edit {
+                kind: EditKind::Added,
+                old: next_old,
+                new: line,
+            });
+        }
+        if next_old < a.len() {
+            edits.push(Edit {
+                kind: EditKind::Same,
+                old: next_old,
+                new: next_new,
+            });
+        }
+        (old, new) = (next_old + 1, next_new + 1);
+    }
+    edits
+}
+
+/// Groups the changes in `edits` into hunks, each with up to `context` unchanged lines on either
+/// side. Changes with at most `2 * context` unchanged lines between them share a hunk.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|&i| edits[i].kind != EditKind::Same)
+        .collect();
+    let mut hunks = vec![];
+    let mut i = 0;
+    while i < changes.len() {
+        let start = changes[i].saturating_sub(context);
+        let mut last = changes[i];
+        while i + 1 < changes.len() && changes[i + 1] - last - 1 <= 2 * context {
+            i += 1;
+            last = changes[i];
+        }
+        let end = (last + context + 1).min(edits.len());
+        hunks.push(Hunk {
+            edits: edits[start..end].to_vec(),
+        });
+        i += 1;
+    }
+    hunks
+}
+
+/// Outputs the hunks of a unified diff from `old` to `new`, with `context` unchanged lines around
+/// each change, or an empty string if they are the same. A line without a line break is followed
+/// by `\ No newline at end of file`.
+pub fn unified_diff(old: &[u8], new: &[u8], context: usize) -> String {
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    hunks(&edit_script(&old_lines, &new_lines), context)
+        .iter()
+        .map(|hunk| hunk.text(&old_lines, &new_lines))
+        .collect()
+}
+
+/// Outputs a longest common subsequence of `a` and `b`, as the pairs of indices `(i, j)` with
+/// `a[i] == b[j]` that make it up, in increasing order
+///
+/// Uses Myers' O(ND) algorithm, in its linear-space form: each step finds the middle snake of the
+/// shortest edit script between the two ranges, then recurses on the ranges on either side of it.
+pub fn matching_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut matches = vec![];
+    match_ranges(a, b, 0, a.len(), 0, b.len(), &mut matches);
+    matches
+}
+
+/// Appends the matches between `a[a_start..a_end]` and `b[b_start..b_end]` to `matches`
+fn match_ranges<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    mut a_start: usize,
+    mut a_end: usize,
+    mut b_start: usize,
+    mut b_end: usize,
+    matches: &mut Vec<(usize, usize)>,
+) {
+    // Common prefixes and suffixes always match, and trimming them keeps the searches short
+    while a_start < a_end && b_start < b_end && a[a_start] == b[b_start] {
+        matches.push((a_start, b_start));
+        a_start += 1;
+        b_start += 1;
+    }
+    let mut suffix = 0;
+    while a_start < a_end && b_start < b_end && a[a_end - 1] == b[b_end - 1] {
+        a_end -= 1;
+        b_end -= 1;
+        suffix += 1;
+    }
+    if a_start < a_end && b_start < b_end {
+        let a_range = &a[a_start..a_end];
+        let b_range = &b[b_start..b_end];
+        let (x_start, y_start, x_end, y_end) = middle_snake(a_range, b_range);
+        match_ranges(
+            a,
+            b,
+            a_start,
+            a_start + x_start,
+            b_start,
+            b_start + y_start,
+            matches,
+        );
+        for offset in 0..x_end - x_start {
+            matches.push((a_start + x_start + offset, b_start + y_start + offset));
+        }
+        match_ranges(
+            a,
+            b,
+            a_start + x_end,
+            a_end,
+            b_start + y_end,
+            b_end,
+            matches,
+        );
+    }
+    for offset in 0..suffix {
+        matches.push((a_end + offset, b_end + offset));
+    }
+}
+
+/// Outputs the middle snake of a shortest edit script from `a` to `b`, both nonempty, as its
+/// start and end points `(x_start, y_start, x_end, y_end)`
+///
+/// The search runs forwards from the start of both sequences and backwards from their ends at
+/// once, and stops where the two meet. Diagonal `k` holds the points with `x - y == k`; a point
+/// `(x, y)` reached backwards is stored by its distance from the ends, on diagonal `delta - k`.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    let mut forward = vec![0; 2 * offset as usize + 1];
+    let mut backward = vec![0; 2 * offset as usize + 1];
+    let index = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let (x_start, y_start) = (x, x - k);
+            let mut y = y_start;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let reverse_k = delta - k;
+            if odd && -(d - 1) <= reverse_k && reverse_k < d && x + backward[index(reverse_k)] >= n
+            {
+                return (x_start as usize, y_start as usize, x as usize, y as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let (x_start, y_start) = (x, x - k);
+            let mut y = y_start;
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            let forward_k = delta - k;
+            if !odd && -d <= forward_k && forward_k <= d && forward[index(forward_k)] + x >= n {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - x_start) as usize,
+                    (m - y_start) as usize,
+                );
+            }
+        }
+    }
+    unreachable!("the forward and backward searches always meet by the middle")
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for matching_lines
+    //      a, b: empty, nonempty
+    //      relation: identical, disjoint, insertions, deletions, replacements, repeated lines
+    //      size: short, long enough to recurse several times
+    // Partitions for unified_diff
+    //      changes: none, one, several close together, several far apart
+    //      placement: start, middle, end; last line with and without a line break
+    //      context: 0, default
+    // Partitions for Hunk::split
+    //      runs of changes: one, several; unchanged lines at the edges: none, some
+
+    use super::*;
+
+    /// Outputs the length of a longest common subsequence of `a` and `b`, by dynamic programming
+    fn lcs_length(a: &[u8], b: &[u8]) -> usize {
+        let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                lengths[i + 1][j + 1] = if a[i] == b[j] {
+                    lengths[i][j] + 1
+                } else {
+                    lengths[i][j + 1].max(lengths[i + 1][j])
+                };
+            }
+        }
+        lengths[a.len()][b.len()]
+    }
+
+    /// Checks that `matches` is a longest common subsequence of `a` and `b`
+    fn check(a: &[u8], b: &[u8]) {
+        let matches = matching_lines(a, b);
+        for window in matches.windows(2) {
+            assert!(window[0].0 < window[1].0 && window[0].1 < window[1].1);
+        }
+        for &(i, j) in &matches {
+            assert_eq!(a[i], b[j]);
+        }
+        assert_eq!(lcs_length(a, b), matches.len(), "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn small_cases() {
+        let cases: [(&[u8], &[u8]); 9] = [
+            (b"", b""),
+            (b"", b"abc"),
+            (b"abc", b""),
+            (b"abc", b"abc"),
+            (b"abc", b"xyz"),
+            (b"abcabba", b"cbabac"),
+            (b"aaaa", b"aa"),
+            (b"abxcd", b"abcd"),
+            (b"abcd", b"axbycz"),
+        ];
+        for (a, b) in cases {
+            check(a, b);
+        }
+        assert_eq!(vec![(0, 0), (2, 1)], matching_lines(b"axb", b"ab"));
+    }
+
+    #[test]
+    fn generated_cases() {
+        // A fixed linear congruential generator, so failures can be reproduced
+        let mut state: u32 = 12345;
+        let mut next = |bound: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) % bound
+        };
+        for _ in 0..300 {
+            let mut a = vec![];
+            for _ in 0..next(40) {
+                a.push(b'a' + next(4) as u8);
+            }
+            let mut b = a.clone();
+            for _ in 0..next(8) {
+                let position = next(b.len() as u32 + 1) as usize;
+                match next(3) {
+                    0 if position < b.len() => {
+                        b.remove(position);
+                    }
+                    1 if position < b.len() => b[position] = b'a' + next(6) as u8,
+                    _ => b.insert(position, b'a' + next(6) as u8),
+                }
+            }
+            check(&a, &b);
+        }
+    }
+
+    #[test]
+    fn unified_diffs() {
+        assert_eq!("", unified_diff(b"a\nb\n", b"a\nb\n", 3));
+        assert_eq!("@@ -0,0 +1,2 @@\n+a\n+b\n", unified_diff(b"", b"a\nb\n", 3));
+        assert_eq!("@@ -1 +0,0 @@\n-a\n", unified_diff(b"a\n", b"", 3));
+        assert_eq!(
+            "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n",
+            unified_diff(b"a\nb\nc\n", b"a\nB\nc\n", 3)
+        );
+        assert_eq!(
+            "@@ -2 +2 @@\n-b\n+B\n",
+            unified_diff(b"a\nb\nc\n", b"a\nB\nc\n", 0)
+        );
+        assert_eq!(
+            "@@ -1 +1,2 @@\n-a\n\\ No newline at end of file\n+a\n+b\n",
+            unified_diff(b"a", b"a\nb\n", 3)
+        );
+    }
+
+    #[test]
+    fn hunks_split_on_distance() {
+        let old: String = (1..=20).map(|line| format!("{}\n", line)).collect();
+        let close = old
+            .replace("\n5\n", "\nfive\n")
+            .replace("\n11\n", "\neleven\n");
+        assert_eq!(
+            "@@ -2,13 +2,13 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n 9\n 10\n-11\n+eleven\n 12\n 13\n 14\n",
+            unified_diff(old.as_bytes(), close.as_bytes(), 3)
+        );
+        let far = old.replace("\n2\n", "\ntwo\n").replace("\n19\n", "\n");
+        assert_eq!(
+            "@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n@@ -16,5 +16,4 @@\n 16\n 17\n 18\n-19\n 20\n",
+            unified_diff(old.as_bytes(), far.as_bytes(), 3)
+        );
+    }
+
+    #[test]
+    fn split_hunks() {
+        let old: String = (1..=9).map(|line| format!("{}\n", line)).collect();
+        let new = old.replace("\n3\n", "\nthree\n").replace("\n6\n", "\n");
+        let (old_lines, new_lines) = (split_lines(old.as_bytes()), split_lines(new.as_bytes()));
+        let hunk = &hunks(&edit_script(&old_lines, &new_lines), 2)[0];
+        let split: Vec<String> = hunk
+            .split()
+            .iter()
+            .map(|hunk| hunk.text(&old_lines, &new_lines))
+            .collect();
+        assert_eq!(
+            vec![
+                "@@ -1,5 +1,5 @@\n 1\n 2\n-3\n+three\n 4\n 5\n",
+                "@@ -4,5 +4,4 @@\n 4\n 5\n-6\n 7\n 8\n",
+            ],
+            split
+        );
+
+        // A single run of changes, even one at the start of the file, can't be split further
+        let hunk = &hunks(&edit_script(&old_lines[..2], &new_lines[..1]), 3)[0];
+        assert_eq!(vec![hunk.clone()], hunk.split());
+        assert_eq!(vec![hunk.clone()], hunk.split()[0].split());
+    }
+}
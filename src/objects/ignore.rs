@@ -0,0 +1,329 @@
+use std::fmt;
+
+use crate::{error::Result, utils::fs_utils::get_file_contents};
+
+use super::{repo::Repository, worktree::worktree_files};
+
+/// Name of the files in the worktree that list patterns of files to ignore
+pub const IGNORE_FILE: &str = ".vcsignore";
+
+/// One line of an ignore file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreRule {
+    /// The file the rule came from, relative to the worktree root
+    pub source: String,
+    /// The line of `source` the rule is on, counting from 1
+    pub line: usize,
+    /// The line as written
+    pub pattern: String,
+    /// The directory of the ignore file, relative to the worktree root and ending in `/`, or
+    /// empty for the root; the rule only applies inside it
+    base: String,
+    /// The pattern, without `!`, a leading `/` or a trailing `/`
+    glob: String,
+    /// Whether the pattern started with `!`, so that it re-includes what it matches
+    pub negated: bool,
+    /// Whether the pattern ended with `/`, so that it only matches directories
+    dir_only: bool,
+    /// Whether the pattern is matched against the whole path below `base`, rather than against
+    /// just the last component
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    /// Parses line `line` of the ignore file `source`, which applies to paths under `base`.
+    /// Returns `None` for blank lines and comments.
+    fn parse(source: &str, line: usize, base: &str, text: &str) -> Option<IgnoreRule> {
+        let pattern = text.trim_end_matches(['\r', ' ']);
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return None;
+        }
+        let mut glob = pattern;
+        let negated = glob.starts_with('!');
+        // `\!` and `\#` start patterns that really begin with `!` or `#`
+        if negated || glob.starts_with("\\!") || glob.starts_with("\\#") {
+            glob = &glob[1..];
+        }
+        let dir_only = glob.ends_with('/');
+        glob = glob.trim_end_matches('/');
+        // A slash anywhere but the end ties the pattern to `base`
+        let anchored = glob.contains('/');
+        glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            return None;
+        }
+        Some(IgnoreRule {
+            source: source.to_string(),
+            line,
+            pattern: pattern.to_string(),
+            base: base.to_string(),
+            glob: glob.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Returns true iff the rule matches `path`, relative to the worktree root, which is a
+    /// directory iff `is_dir`
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Some(relative) => relative,
+            None => return false,
+        };
+        let subject = if self.anchored {
+            relative
+        } else {
+            relative.rsplit('/').next().unwrap()
+        };
+        glob_match(self.glob.as_bytes(), subject.as_bytes())
+    }
+}
+
+impl fmt::Display for IgnoreRule {
+    /// Formats the rule as `<source>:<line>:<pattern>`, as `check-ignore -v` shows it
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.pattern)
+    }
+}
+
+/// Every ignore rule of a repository, in increasing order of precedence
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    /// Reads the ignore rules of `repo`: those in `.vcs/info/exclude`, then those in each
+    /// `.vcsignore` in the worktree, shallowest first, so that deeper files take precedence
+    pub fn load(repo: &Repository) -> Result<IgnoreRules> {
+        let mut rules = IgnoreRules::default();
+        let exclude = repo.vcs_dir().join("info").join("exclude");
+        if exclude.is_file() {
+            rules.add_file(".vcs/info/exclude", "", &get_file_contents(exclude)?);
+        }
+        let mut ignore_files: Vec<String> = worktree_files(repo)?
+            .into_iter()
+            .filter(|name| name.rsplit('/').next() == Some(IGNORE_FILE))
+            .collect();
+        ignore_files.sort_by_key(|name| (name.matches('/').count(), name.clone()));
+        for name in ignore_files {
+            let base = &name[..name.len() - IGNORE_FILE.len()];
+            let contents = get_file_contents(repo.worktree_path(&name))?;
+            rules.add_file(&name, base, &contents);
+        }
+        Ok(rules)
+    }
+
+    /// Adds the rules in `contents`, the text of the ignore file `source` in the directory `base`
+    fn add_file(&mut self, source: &str, base: &str, contents: &str) {
+        for (i, line) in contents.lines().enumerate() {
+            self.rules
+                .extend(IgnoreRule::parse(source, i + 1, base, line));
+        }
+    }
+
+    /// Outputs the rule deciding whether `path`, relative to the worktree root, is ignored, or
+    /// `None` if no rule matches it. `path` is a directory iff `is_dir`.
+    ///
+    /// The last rule matching `path` decides, unless a directory above `path` is ignored, in
+    /// which case the rule ignoring that directory does: nothing inside an ignored directory can
+    /// be re-included.
+    pub fn matching_rule(&self, path: &str, is_dir: bool) -> Option<&IgnoreRule> {
+        for (i, _) in path.match_indices('/') {
+            if let Some(rule) = self.last_match(&path[..i], true) {
+                if !rule.negated {
+                    return Some(rule);
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// Returns true iff `path`, relative to the worktree root, is ignored. `path` is a directory
+    /// iff `is_dir`.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.matching_rule(path, is_dir)
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Outputs the last rule matching `path` itself
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<&IgnoreRule> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+    }
+}
+
+/// Returns true iff `text` matches the glob `pattern`
+///
+/// `*` matches anything but `/`, `?` matches one character other than `/`, `[...]` matches one
+/// character in the set (`[!...]` or `[^...]` one not in it), and `\` escapes the next character.
+/// `**/` matches any number of directories, and a trailing `/**` everything inside a directory.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            match rest.first() {
+                // `**` at the end matches everything left
+                None => true,
+                // `**/` matches zero or more whole directories
+                Some(b'/') => {
+                    let rest = &rest[1..];
+                    glob_match(rest, text)
+                        || text
+                            .iter()
+                            .enumerate()
+                            .any(|(i, byte)| *byte == b'/' && glob_match(rest, &text[i + 1..]))
+                }
+                // Otherwise it is just a `*`
+                Some(_) => glob_match(&pattern[1..], text),
+            }
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(byte) if *byte != b'/' => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => match text.first() {
+            Some(byte) if *byte != b'/' => match match_class(&pattern[1..], *byte) {
+                Some((matched, length)) => {
+                    matched && glob_match(&pattern[1 + length..], &text[1..])
+                }
+                // An unclosed `[` is just a `[`
+                None => *byte == b'[' && glob_match(&pattern[1..], &text[1..]),
+            },
+            _ => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(byte) => text.first() == Some(byte) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Given the part of a pattern after a `[`, returns whether `byte` is in the set it describes, and
+/// the length of the set including the closing `]`. Returns `None` if there is no closing `]`.
+fn match_class(pattern: &[u8], byte: u8) -> Option<(bool, usize)> {
+    let (negated, start) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    // A `]` straight after the `[` is part of the set
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|b| *b == b']')?;
+    let set = &pattern[start..end];
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            found |= set[i] <= byte && byte <= set[i + 2];
+            i += 3;
+        } else {
+            found |= set[i] == byte;
+            i += 1;
+        }
+    }
+    Some((found != negated, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for glob_match
+    //      pattern: literal, `*`, `?`, `[...]`, `[!...]`, `**/`, trailing `/**`, `\` escape
+    //      text: matching, not matching, crossing `/`
+    // Partitions for IgnoreRules
+    //      patterns: comments and blank lines, unanchored, anchored by a leading or middle `/`,
+    //          directory-only, negated
+    //      sources: info/exclude, root .vcsignore, nested .vcsignore
+    //      paths: top level, nested, inside an ignored directory
+
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    /// Returns true iff `text` matches the glob `pattern`
+    fn matches(pattern: &str, text: &str) -> bool {
+        glob_match(pattern.as_bytes(), text.as_bytes())
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("a.txt", "a.txt"));
+        assert!(!matches("a.txt", "b.txt"));
+        assert!(matches("*.log", "debug.log"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.log", "logs/debug.log"));
+        assert!(matches("file?.txt", "file1.txt"));
+        assert!(!matches("file?.txt", "file/.txt"));
+        assert!(matches("[abc].rs", "b.rs"));
+        assert!(matches("[a-c][!0-9]", "cx"));
+        assert!(!matches("[a-c][^0-9]", "c1"));
+        assert!(matches("[a", "[a"));
+        assert!(matches("**/build", "build"));
+        assert!(matches("**/build", "a/b/build"));
+        assert!(matches("src/**/test.rs", "src/test.rs"));
+        assert!(matches("src/**/test.rs", "src/a/b/test.rs"));
+        assert!(!matches("src/**/test.rs", "lib/src/test.rs"));
+        assert!(matches("target/**", "target/debug/vcs"));
+        assert!(matches("\\*literal", "*literal"));
+        assert!(!matches("\\*literal", "a literal"));
+    }
+
+    #[test]
+    fn rules() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = Repository::create(dir)?;
+        create_dir_all(dir.join(".vcs/info"))?;
+        write(dir.join(".vcs/info/exclude"), "secret.txt\n")?;
+        write(
+            dir.join(".vcsignore"),
+            "# Comment\n\n*.swp\n/root-only.txt\ndocs/*.html\nbuild/\n!important.swp\n\\#hash\n",
+        )?;
+        create_dir_all(dir.join("src/build"))?;
+        write(dir.join("src/.vcsignore"), "*.rs\n!keep.rs\nbuild/**\n")?;
+        let rules = IgnoreRules::load(&repo)?;
+
+        let ignored = |path: &str, is_dir: bool| rules.is_ignored(path, is_dir);
+        assert!(ignored("secret.txt", false));
+        assert!(ignored("a/b/.main.rs.swp", false));
+        assert!(!ignored("important.swp", false));
+        assert!(ignored("root-only.txt", false));
+        assert!(!ignored("sub/root-only.txt", false));
+        assert!(ignored("docs/index.html", false));
+        assert!(!ignored("other/docs/index.html", false));
+        assert!(ignored("build", true));
+        assert!(!ignored("build", false));
+        assert!(ignored("build/output.o", false));
+        assert!(ignored("#hash", false));
+        assert!(ignored("src/main.rs", false));
+        assert!(!ignored("src/keep.rs", false));
+        assert!(!ignored("main.rs", false));
+        assert!(ignored("src/build/out.o", false));
+
+        // Nothing in an ignored directory can be re-included
+        let rule = rules.matching_rule("build/important.swp", false).unwrap();
+        assert_eq!(".vcsignore:6:build/", rule.to_string());
+        let rule = rules.matching_rule("src/keep.rs", false).unwrap();
+        assert_eq!("src/.vcsignore:2:!keep.rs", rule.to_string());
+        assert!(rules.matching_rule("README.md", false).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    error::{Result, VcsError},
+    utils::{
+        hash::sha2_bytes,
+        quote::{quote_name, unquote_name},
+    },
+};
+
+use super::{
+    mode::FileMode,
+    stat_cache::{CachedFile, FileTime, StatCache, StatData},
+};
+
+/// Magic bytes at the start of a binary index
+const INDEX_MAGIC: &[u8; 4] = b"VDIR";
+/// Version of the index format written by `index_file_bytes`. Version 1 is the older text format
+/// read by `parse_index`.
+const INDEX_VERSION: u32 = 2;
+/// Number of bytes in the checksum at the end of a binary index
+const CHECKSUM_SIZE: usize = 32;
+
+/// Kind bytes of the staged entries of a binary index
+const BLOB_KIND: u8 = 0;
+const RM_KIND: u8 = 1;
+const CONFLICT_KIND: u8 = 2;
+
+/// Which version of a conflicted file a `Conflict` entry holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// The version in the merge base
+    Base,
+    /// The version in the commit that was checked out
+    Ours,
+    /// The version in the commit being merged in
+    Theirs,
+}
+
+impl Stage {
+    /// The number of the stage in the index: 1, 2 or 3
+    pub fn number(&self) -> u8 {
+        match self {
+            Stage::Base => 1,
+            Stage::Ours => 2,
+            Stage::Theirs => 3,
+        }
+    }
+
+    /// Returns the stage numbered `number` in the index, or `None` if there is no such stage
+    pub fn from_number(number: &str) -> Option<Stage> {
+        match number {
+            "1" => Some(Stage::Base),
+            "2" => Some(Stage::Ours),
+            "3" => Some(Stage::Theirs),
+            _ => None,
+        }
+    }
+}
+
+/// A single staged change in `.vcs/index`
+///
+/// The index only records how the next commit differs from the head commit: a `Blob` entry stages
+/// a new version of a file, along with its mode, and an `Rm` entry stages its removal. While a
+/// merge has unresolved conflicts, each conflicted file has a `Conflict` entry for every version
+/// of it that exists among the base, ours and theirs, until a `Blob` or `Rm` entry replaces them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexEntry {
+    Blob {
+        hash: String,
+        name: String,
+        mode: FileMode,
+    },
+    Rm {
+        name: String,
+    },
+    Conflict {
+        stage: Stage,
+        hash: String,
+        name: String,
+        mode: FileMode,
+    },
+}
+
+impl IndexEntry {
+    /// The name of the file this entry is about
+    pub fn name(&self) -> &str {
+        match self {
+            IndexEntry::Blob { name, .. } => name,
+            IndexEntry::Rm { name } => name,
+            IndexEntry::Conflict { name, .. } => name,
+        }
+    }
+}
+
+/// The contents of `.vcs/index`: the staged changes, and the stat cache of the tracked files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+    pub stat_cache: StatCache,
+}
+
+/// Given the bytes of `.vcs/index`, last modified at `written`, outputs the index they hold
+///
+/// An index in the binary format is laid out as
+///     * the magic bytes `VDIR` and the format version, as a big-endian u32
+///     * the number of staged entries, as a big-endian u32, then each entry: a kind byte (blob,
+///       rm or conflict), a stage byte (0 outside conflicts), the mode as a string, the hash (empty
+///       for removals) and the name
+///     * the number of cached files, then each file's mtime and ctime (seconds as an i64 and
+///       nanoseconds as a u32), size and inode as u64s, raw mode bits as a u32, then its mode,
+///       hash and name
+///     * the SHA-256 of everything before it
+/// Strings are written as their length, as a big-endian u32, followed by their UTF-8 bytes, so
+/// names need no quoting. Indexes in the older text format, which have no stat cache, are read
+/// with `parse_index`.
+///
+/// Returns `CorruptIndex` if the bytes are not a valid binary index, and the errors of
+/// `parse_index` for a text index
+pub fn parse_index_file(bytes: &[u8], written: Option<FileTime>) -> Result<Index> {
+    if !bytes.starts_with(INDEX_MAGIC) {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| VcsError::CorruptIndex(String::from("not UTF-8 text")))?;
+        return Ok(Index {
+            entries: parse_index(text)?,
+            stat_cache: StatCache::default(),
+        });
+    }
+    let corrupt = |reason: &str| VcsError::CorruptIndex(reason.to_string());
+    if bytes.len() < INDEX_MAGIC.len() + CHECKSUM_SIZE {
+        return Err(corrupt("truncated"));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    if sha2_bytes(body) != hex::encode(checksum) {
+        return Err(corrupt("checksum mismatch"));
+    }
+    let mut reader = IndexReader {
+        bytes: body,
+        offset: INDEX_MAGIC.len(),
+    };
+    let truncated = || corrupt("truncated");
+    if reader.u32().ok_or_else(truncated)? != INDEX_VERSION {
+        return Err(corrupt("unknown version"));
+    }
+
+    let mut entries = vec![];
+    for _ in 0..reader.u32().ok_or_else(truncated)? {
+        let kind = reader.u8().ok_or_else(truncated)?;
+        let stage = reader.u8().ok_or_else(truncated)?;
+        let mode = reader.string().ok_or_else(truncated)?;
+        let hash = reader.string().ok_or_else(truncated)?;
+        let name = reader.string().ok_or_else(truncated)?;
+        let mode = || FileMode::from_code(&mode).ok_or_else(|| corrupt("unknown mode"));
+        entries.push(match kind {
+            BLOB_KIND => IndexEntry::Blob {
+                hash,
+                name,
+                mode: mode()?,
+            },
+            RM_KIND => IndexEntry::Rm { name },
+            CONFLICT_KIND => IndexEntry::Conflict {
+                stage: Stage::from_number(&stage.to_string())
+                    .ok_or_else(|| corrupt("unknown stage"))?,
+                hash,
+                name,
+                mode: mode()?,
+            },
+            _ => return Err(corrupt("unknown entry kind")),
+        });
+    }
+
+    let mut files = BTreeMap::new();
+    for _ in 0..reader.u32().ok_or_else(truncated)? {
+        let stat = reader.stat_data().ok_or_else(truncated)?;
+        let mode = reader.string().ok_or_else(truncated)?;
+        let hash = reader.string().ok_or_else(truncated)?;
+        let name = reader.string().ok_or_else(truncated)?;
+        let mode = FileMode::from_code(&mode).ok_or_else(|| corrupt("unknown mode"))?;
+        files.insert(name, CachedFile { stat, mode, hash });
+    }
+    if reader.offset != body.len() {
+        return Err(corrupt("trailing bytes"));
+    }
+    Ok(Index {
+        entries,
+        stat_cache: StatCache::new(files, written),
+    })
+}
+
+/// Outputs the bytes `.vcs/index` should hold for `index`, in the binary format `parse_index_file`
+/// describes
+pub fn index_file_bytes(index: &Index) -> Vec<u8> {
+    let mut bytes = INDEX_MAGIC.to_vec();
+    bytes.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&(index.entries.len() as u32).to_be_bytes());
+    for entry in &index.entries {
+        let (kind, stage, mode, hash) = match entry {
+            IndexEntry::Blob { hash, mode, .. } => (BLOB_KIND, 0, mode.code(), hash.as_str()),
+            IndexEntry::Rm { .. } => (RM_KIND, 0, "", ""),
+            IndexEntry::Conflict {
+                stage, hash, mode, ..
+            } => (CONFLICT_KIND, stage.number(), mode.code(), hash.as_str()),
+        };
+        bytes.push(kind);
+        bytes.push(stage);
+        push_string(&mut bytes, mode);
+        push_string(&mut bytes, hash);
+        push_string(&mut bytes, entry.name());
+    }
+
+    let files = index.stat_cache.files();
+    bytes.extend_from_slice(&(files.len() as u32).to_be_bytes());
+    for (name, file) in files {
+        let stat = &file.stat;
+        for time in [stat.mtime, stat.ctime] {
+            bytes.extend_from_slice(&time.seconds.to_be_bytes());
+            bytes.extend_from_slice(&time.nanoseconds.to_be_bytes());
+        }
+        bytes.extend_from_slice(&stat.size.to_be_bytes());
+        bytes.extend_from_slice(&stat.inode.to_be_bytes());
+        bytes.extend_from_slice(&stat.mode.to_be_bytes());
+        push_string(&mut bytes, file.mode.code());
+        push_string(&mut bytes, &file.hash);
+        push_string(&mut bytes, name);
+    }
+    let checksum = sha2_bytes(&bytes);
+    bytes.extend_from_slice(&hex::decode(checksum).unwrap());
+    bytes
+}
+
+/// Appends `string` to `bytes`, prefixed by its length
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Reads the fields of a binary index in order. Each read outputs `None` if the index ends first.
+struct IndexReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl IndexReader<'_> {
+    /// Reads the next `N` bytes
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.offset..self.offset + N)?;
+        self.offset += N;
+        bytes.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take()?))
+    }
+
+    fn time(&mut self) -> Option<FileTime> {
+        Some(FileTime {
+            seconds: i64::from_be_bytes(self.take()?),
+            nanoseconds: self.u32()?,
+        })
+    }
+
+    fn stat_data(&mut self) -> Option<StatData> {
+        Some(StatData {
+            mtime: self.time()?,
+            ctime: self.time()?,
+            size: self.u64()?,
+            inode: self.u64()?,
+            mode: self.u32()?,
+        })
+    }
+
+    /// Reads a length-prefixed string. A string that isn't UTF-8 reads as `None` too.
+    fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        let bytes = self.bytes.get(self.offset..self.offset + length)?;
+        self.offset += length;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+/// Given the contents of a `.vcs/index` in the older text format, outputs the entries in it, in
+/// order
+///
+/// Blobs are staged as `blob <mode> <hash> <name>`; indexes written before modes existed leave out
+/// the mode, which is then a regular file. Conflicts are written
+/// `conflict <stage> <mode> <hash> <name>`. The name always comes last, as `quote_name` writes it,
+/// so it may hold spaces, and names with newlines are quoted.
+///
+/// Returns an `IndexParse` error if a line is not a `blob`, `rm` or `conflict` line
+pub fn parse_index(index_contents: &str) -> Result<Vec<IndexEntry>> {
+    let mut entries = vec![];
+    for line in index_contents.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let name = |quoted: &str| {
+            unquote_name(quoted).ok_or_else(|| VcsError::IndexParse(line.to_string()))
+        };
+        let split_line: Vec<&str> = line.splitn(3, ' ').collect();
+        match split_line[..] {
+            ["blob", code, rest] if FileMode::from_code(code).is_some() => {
+                let (hash, quoted) = rest
+                    .split_once(' ')
+                    .ok_or_else(|| VcsError::IndexParse(line.to_string()))?;
+                entries.push(IndexEntry::Blob {
+                    hash: hash.to_string(),
+                    name: name(quoted)?,
+                    mode: FileMode::from_code(code).unwrap(),
+                })
+            }
+            ["blob", hash, quoted] => entries.push(IndexEntry::Blob {
+                hash: hash.to_string(),
+                name: name(quoted)?,
+                mode: FileMode::Regular,
+            }),
+            ["rm", _, ..] => entries.push(IndexEntry::Rm {
+                name: name(&line[3..])?,
+            }),
+            ["conflict", stage, rest] => {
+                let fields: Vec<&str> = rest.splitn(3, ' ').collect();
+                match (Stage::from_number(stage), fields.as_slice()) {
+                    (Some(stage), &[code, hash, quoted]) if FileMode::from_code(code).is_some() => {
+                        entries.push(IndexEntry::Conflict {
+                            stage,
+                            hash: hash.to_string(),
+                            name: name(quoted)?,
+                            mode: FileMode::from_code(code).unwrap(),
+                        })
+                    }
+                    _ => return Err(VcsError::IndexParse(line.to_string())),
+                }
+            }
+            _ => return Err(VcsError::IndexParse(line.to_string())),
+        }
+    }
+    Ok(entries)
+}
+
+/// Outputs `entries` in the older text format of `.vcs/index`, one line per entry
+pub fn index_text(entries: &[IndexEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| match entry {
+            IndexEntry::Blob { hash, name, mode } => {
+                format!("blob {} {} {}", mode, hash, quote_name(name))
+            }
+            IndexEntry::Rm { name } => format!("rm {}", quote_name(name)),
+            IndexEntry::Conflict {
+                stage,
+                hash,
+                name,
+                mode,
+            } => format!(
+                "conflict {} {} {} {}",
+                stage.number(),
+                mode,
+                hash,
+                quote_name(name)
+            ),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for parse_index and index_text
+    //      index: empty, only blobs, only removes, mixed
+    //      blob lines: with a mode, without a mode
+    //      conflict lines: each stage, bad stage, missing field
+    //      names: plain, with spaces and colons, non-ASCII, quoted for newlines, badly quoted
+    // Partitions for parse_index_file and index_file_bytes
+    //      format: binary, text
+    //      stat cache: empty, with files
+    //      corruption: none, checksum mismatch, truncated, unknown version
+
+    use super::*;
+
+    #[test]
+    fn empty_index() -> Result<()> {
+        assert_eq!(Vec::<IndexEntry>::new(), parse_index("")?);
+        assert_eq!("", index_text(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_line() {
+        assert!(matches!(
+            parse_index("blob abc test.txt\nchmod test.txt"),
+            Err(VcsError::IndexParse(line)) if line == "chmod test.txt"
+        ));
+        assert!(matches!(
+            parse_index("blob abc"),
+            Err(VcsError::IndexParse(_))
+        ));
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let text = "blob 100644 abc test.txt\nrm dir/gone.txt\nblob 100755 def dir/test 2.sh";
+        let entries = parse_index(text)?;
+        assert_eq!(
+            vec![
+                IndexEntry::Blob {
+                    hash: String::from("abc"),
+                    name: String::from("test.txt"),
+                    mode: FileMode::Regular,
+                },
+                IndexEntry::Rm {
+                    name: String::from("dir/gone.txt")
+                },
+                IndexEntry::Blob {
+                    hash: String::from("def"),
+                    name: String::from("dir/test 2.sh"),
+                    mode: FileMode::Executable,
+                },
+            ],
+            entries
+        );
+        assert_eq!("dir/gone.txt", entries[1].name());
+        assert_eq!(text, index_text(&entries));
+        Ok(())
+    }
+
+    #[test]
+    fn conflicts() -> Result<()> {
+        let text = "conflict 1 100644 abc a b.txt\nconflict 3 100755 def a b.txt";
+        let entries = parse_index(text)?;
+        assert_eq!(
+            vec![
+                IndexEntry::Conflict {
+                    stage: Stage::Base,
+                    hash: String::from("abc"),
+                    name: String::from("a b.txt"),
+                    mode: FileMode::Regular,
+                },
+                IndexEntry::Conflict {
+                    stage: Stage::Theirs,
+                    hash: String::from("def"),
+                    name: String::from("a b.txt"),
+                    mode: FileMode::Executable,
+                },
+            ],
+            entries
+        );
+        assert_eq!(text, index_text(&entries));
+        for bad in ["conflict 4 100644 abc a.txt", "conflict 2 abc a.txt"] {
+            assert!(matches!(
+                parse_index(bad),
+                Err(VcsError::IndexParse(line)) if line == bad
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn special_names() -> Result<()> {
+        let names = [
+            "my notes.txt",
+            "a: b.md",
+            "dir/naïve 日本語.txt",
+            "line\nbreak.txt",
+            "\"quoted\"",
+        ];
+        let entries: Vec<IndexEntry> = names
+            .iter()
+            .flat_map(|name| {
+                [
+                    IndexEntry::Blob {
+                        hash: String::from("abc"),
+                        name: name.to_string(),
+                        mode: FileMode::Regular,
+                    },
+                    IndexEntry::Rm {
+                        name: name.to_string(),
+                    },
+                    IndexEntry::Conflict {
+                        stage: Stage::Ours,
+                        hash: String::from("def"),
+                        name: name.to_string(),
+                        mode: FileMode::Executable,
+                    },
+                ]
+            })
+            .collect();
+        let text = index_text(&entries);
+        // Every entry stays on a line of its own
+        assert_eq!(entries.len(), text.lines().count());
+        assert!(text.contains("rm \"line\\nbreak.txt\"\n"));
+        assert!(text.contains("blob 100644 abc a: b.md\n"));
+        assert_eq!(entries, parse_index(&text)?);
+        assert!(matches!(
+            parse_index("rm \"unclosed.txt"),
+            Err(VcsError::IndexParse(line)) if line == "rm \"unclosed.txt"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn without_modes() -> Result<()> {
+        assert_eq!(
+            vec![IndexEntry::Blob {
+                hash: String::from("abc"),
+                name: String::from("my file.txt"),
+                mode: FileMode::Regular,
+            }],
+            parse_index("blob abc my file.txt")?
+        );
+        Ok(())
+    }
+
+    /// An index holding every kind of entry, and a stat cache of two files
+    fn full_index() -> Index {
+        let stat = |seconds: i64| StatData {
+            mtime: FileTime {
+                seconds,
+                nanoseconds: 5,
+            },
+            ctime: FileTime {
+                seconds: seconds + 1,
+                nanoseconds: 0,
+            },
+            size: 3,
+            inode: 42,
+            mode: 0o100644,
+        };
+        let files = BTreeMap::from([
+            (
+                String::from("line\nbreak.txt"),
+                CachedFile {
+                    stat: stat(100),
+                    mode: FileMode::Regular,
+                    hash: String::from("abc"),
+                },
+            ),
+            (
+                String::from("run.sh"),
+                CachedFile {
+                    stat: stat(-7),
+                    mode: FileMode::Executable,
+                    hash: String::from("def"),
+                },
+            ),
+        ]);
+        Index {
+            entries: vec![
+                IndexEntry::Blob {
+                    hash: String::from("abc"),
+                    name: String::from("line\nbreak.txt"),
+                    mode: FileMode::Regular,
+                },
+                IndexEntry::Rm {
+                    name: String::from("naïve 日本語.txt"),
+                },
+                IndexEntry::Conflict {
+                    stage: Stage::Theirs,
+                    hash: String::from("def"),
+                    name: String::from("a: b.md"),
+                    mode: FileMode::Symlink,
+                },
+            ],
+            stat_cache: StatCache::new(files, None),
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() -> Result<()> {
+        let written = Some(FileTime {
+            seconds: 200,
+            nanoseconds: 0,
+        });
+        let empty = Index::default();
+        let bytes = index_file_bytes(&empty);
+        assert!(bytes.starts_with(INDEX_MAGIC));
+        assert_eq!(empty, parse_index_file(&bytes, None)?);
+
+        let index = full_index();
+        let parsed = parse_index_file(&index_file_bytes(&index), written)?;
+        assert_eq!(index.entries, parsed.entries);
+        assert_eq!(index.stat_cache.files(), parsed.stat_cache.files());
+        assert_eq!(
+            StatCache::new(index.stat_cache.files().clone(), written),
+            parsed.stat_cache
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn text_indexes() -> Result<()> {
+        let parsed = parse_index_file(b"blob 100644 abc a.txt\nrm b.txt", None)?;
+        assert_eq!(
+            parse_index("blob 100644 abc a.txt\nrm b.txt")?,
+            parsed.entries
+        );
+        assert!(parsed.stat_cache.files().is_empty());
+        assert_eq!(Index::default(), parse_index_file(b"", None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_binary_index() {
+        let bytes = index_file_bytes(&full_index());
+        let mut flipped = bytes.clone();
+        flipped[10] ^= 1;
+        let mut wrong_version = bytes[..bytes.len() - CHECKSUM_SIZE].to_vec();
+        wrong_version[7] = 9;
+        let checksum = hex::decode(sha2_bytes(&wrong_version)).unwrap();
+        wrong_version.extend_from_slice(&checksum);
+        let mut truncated = bytes[..bytes.len() - CHECKSUM_SIZE - 4].to_vec();
+        let checksum = hex::decode(sha2_bytes(&truncated)).unwrap();
+        truncated.extend_from_slice(&checksum);
+
+        for (bad, reason) in [
+            (flipped, "checksum mismatch"),
+            (wrong_version, "unknown version"),
+            (truncated, "truncated"),
+            (bytes[..6].to_vec(), "truncated"),
+        ] {
+            assert!(matches!(
+                parse_index_file(&bad, None),
+                Err(VcsError::CorruptIndex(found)) if found == reason
+            ));
+        }
+    }
+}
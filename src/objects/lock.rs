@@ -0,0 +1,173 @@
+use std::{
+    fs::{remove_file, rename, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Result, VcsError};
+
+/// The extension of the lock file that guards a file while it is rewritten
+pub const LOCK_EXTENSION: &str = "lock";
+
+/// A held lock on a file such as `HEAD`, a branch ref or the index
+///
+/// Taking the lock creates `<file>.lock`, which fails if another process holds it. The new
+/// contents are written into the lock file, and `commit` syncs it to disk and renames it over the
+/// file, so readers see either the old contents or the new ones and never a partial write. A lock
+/// dropped without being committed is removed and leaves the file untouched.
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    /// Takes the lock on `path`
+    ///
+    /// Returns `RepositoryLocked` if `<path>.lock` already exists
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<LockFile> {
+        let path = path.as_ref().to_path_buf();
+        let lock_path = lock_path(&path);
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(VcsError::RepositoryLocked(
+                    lock_path.to_string_lossy().to_string(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(LockFile {
+            path,
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Appends `contents` to the new contents of the file
+    pub fn write(&mut self, contents: &[u8]) -> Result<()> {
+        self.file.as_mut().unwrap().write_all(contents)?;
+        Ok(())
+    }
+
+    /// Syncs the new contents to disk and moves them into place, releasing the lock
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().unwrap();
+        let synced = file.sync_all();
+        drop(file);
+        if let Err(e) = synced.and_then(|_| rename(&self.lock_path, &self.path)) {
+            let _ = remove_file(&self.lock_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Deletes the file, then releases the lock
+    pub fn delete(mut self) -> Result<()> {
+        self.file = None;
+        let removed = remove_file(&self.path);
+        remove_file(&self.lock_path)?;
+        Ok(removed?)
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// The path of the lock file guarding `path`
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".");
+    name.push(LOCK_EXTENSION);
+    path.with_file_name(name)
+}
+
+/// Replaces the contents of `path` with `contents` while holding its lock
+///
+/// Returns `RepositoryLocked` if another process holds the lock
+pub fn write_locked<P: AsRef<Path>>(path: P, contents: &[u8]) -> Result<()> {
+    let mut lock = LockFile::acquire(path)?;
+    lock.write(contents)?;
+    lock.commit()
+}
+
+/// Returns true iff `path` is a lock file rather than a file it guards
+pub fn is_lock_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == LOCK_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for LockFile
+    //      Lock: free, held by another LockFile, left behind by a crashed process
+    //      Outcome: committed, dropped without committing, deleted
+
+    use std::fs::write;
+
+    use super::*;
+    use crate::utils::{
+        fs_utils::{file_exists, get_file_contents},
+        test_dir::make_test_dir,
+    };
+
+    #[test]
+    fn commit_replaces_contents() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let path = test_dir.path().join("HEAD");
+        write(&path, "main")?;
+
+        let mut lock = LockFile::acquire(&path)?;
+        lock.write(b"si")?;
+        lock.write(b"de")?;
+        // Readers still see the old contents until the lock is committed
+        assert_eq!("main", get_file_contents(&path)?);
+        assert!(file_exists(test_dir.path().join("HEAD.lock")));
+        lock.commit()?;
+        assert_eq!("side", get_file_contents(&path)?);
+        assert!(!file_exists(test_dir.path().join("HEAD.lock")));
+
+        write_locked(&path, b"main")?;
+        assert_eq!("main", get_file_contents(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn held_lock_is_refused() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let path = test_dir.path().join("index");
+        write(&path, "old")?;
+
+        let mut lock = LockFile::acquire(&path)?;
+        lock.write(b"partial")?;
+        assert!(matches!(
+            write_locked(&path, b"new"),
+            Err(VcsError::RepositoryLocked(lock_path)) if lock_path.ends_with("index.lock")
+        ));
+        // Dropping the lock without committing leaves the file as it was
+        drop(lock);
+        assert_eq!("old", get_file_contents(&path)?);
+        assert!(!file_exists(test_dir.path().join("index.lock")));
+
+        // A lock left behind by a process that died is still respected until removed
+        write(test_dir.path().join("index.lock"), "")?;
+        assert!(matches!(
+            LockFile::acquire(&path),
+            Err(VcsError::RepositoryLocked(_))
+        ));
+        remove_file(test_dir.path().join("index.lock"))?;
+        LockFile::acquire(&path)?.delete()?;
+        assert!(!file_exists(&path));
+        assert!(!file_exists(test_dir.path().join("index.lock")));
+        Ok(())
+    }
+}
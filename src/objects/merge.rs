@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+use crate::error::Result;
+
+use super::{
+    blob::read_blob,
+    commit::{get_commit_parents, get_commit_time},
+    diff::matching_lines,
+    index::{IndexEntry, Stage},
+    mode::FileMode,
+    object::ObjectKind,
+    repo::Repository,
+    tree::read_tree_files,
+};
+
+/// A version of a file in a tree: its mode and blob hash
+pub type FileVersion = (FileMode, String);
+
+/// Outputs the hashes of `commit` and of every commit it descends from
+pub fn ancestors(repo: &Repository, commit: &str) -> Result<HashSet<String>> {
+    let mut seen = HashSet::from([commit.to_string()]);
+    let mut pending = vec![commit.to_string()];
+    while let Some(current) = pending.pop() {
+        for parent in get_commit_parents(repo, &current)? {
+            if seen.insert(parent.clone()) {
+                pending.push(parent);
+            }
+        }
+    }
+    Ok(seen)
+}
+
+/// Outputs the best common ancestor of the commits `ours` and `theirs`, to merge them from, or
+/// `None` if they have no history in common
+///
+/// A common ancestor is best if no other common ancestor descends from it. When criss-cross
+/// merges leave several, the newest is chosen.
+pub fn merge_base(repo: &Repository, ours: &str, theirs: &str) -> Result<Option<String>> {
+    let our_ancestors = ancestors(repo, ours)?;
+    let mut candidates: BTreeSet<String> = BTreeSet::new();
+    let mut seen = HashSet::from([theirs.to_string()]);
+    let mut pending = VecDeque::from([theirs.to_string()]);
+    while let Some(current) = pending.pop_front() {
+        if our_ancestors.contains(&current) {
+            candidates.insert(current);
+            continue;
+        }
+        for parent in get_commit_parents(repo, &current)? {
+            if seen.insert(parent.clone()) {
+                pending.push_back(parent);
+            }
+        }
+    }
+
+    let mut best: Option<(i64, String)> = None;
+    for candidate in &candidates {
+        let mut is_best = true;
+        for other in &candidates {
+            if other != candidate && ancestors(repo, other)?.contains(candidate) {
+                is_best = false;
+                break;
+            }
+        }
+        if is_best {
+            let time = get_commit_time(repo, candidate)?;
+            if best.as_ref().is_none_or(|(best_time, _)| time > *best_time) {
+                best = Some((time, candidate.clone()));
+            }
+        }
+    }
+    Ok(best.map(|(_, hash)| hash))
+}
+
+/// Merges the changes made to the text `base` in `ours` and in `theirs`, line by line. Outputs
+/// the merged text, and whether it merged cleanly.
+///
+/// Each run of lines that only one side changed takes that side's version. Where both sides
+/// changed the same lines differently, both versions are kept between conflict markers:
+///
+/// ```text
+/// <<<<<<< <our_label>
+/// <our lines>
+/// =======
+/// <their lines>
+/// >>>>>>> <their_label>
+/// ```
+pub fn merge_lines(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    our_label: &str,
+    their_label: &str,
+) -> (Vec<u8>, bool) {
+    let base: Vec<&[u8]> = base.split_inclusive(|&byte| byte == b'\n').collect();
+    let ours: Vec<&[u8]> = ours.split_inclusive(|&byte| byte == b'\n').collect();
+    let theirs: Vec<&[u8]> = theirs.split_inclusive(|&byte| byte == b'\n').collect();
+    let matched_in = |side: &[&[u8]]| {
+        let mut matched = vec![None; base.len()];
+        for (base_line, side_line) in matching_lines(&base, side) {
+            matched[base_line] = Some(side_line);
+        }
+        matched
+    };
+    let (in_ours, in_theirs) = (matched_in(&ours), matched_in(&theirs));
+
+    let mut merged: Vec<u8> = vec![];
+    let mut clean = true;
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        // A stable run: lines of the base kept, in the same place, by both sides
+        let mut run = 0;
+        while o + run < base.len()
+            && in_ours[o + run] == Some(a + run)
+            && in_theirs[o + run] == Some(b + run)
+        {
+            run += 1;
+        }
+        if run > 0 {
+            merged.extend(base[o..o + run].concat());
+            (o, a, b) = (o + run, a + run, b + run);
+            continue;
+        }
+
+        // An unstable chunk, up to the next base line both sides kept
+        let next = (o..base.len()).find(|&i| in_ours[i].is_some() && in_theirs[i].is_some());
+        let (o_end, a_end, b_end) = match next {
+            Some(i) => (i, in_ours[i].unwrap(), in_theirs[i].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        if (o, a, b) == (o_end, a_end, b_end) {
+            break;
+        }
+        let (base_chunk, our_chunk, their_chunk) = (
+            base[o..o_end].concat(),
+            ours[a..a_end].concat(),
+            theirs[b..b_end].concat(),
+        );
+        if base_chunk == our_chunk {
+            merged.extend(their_chunk);
+        } else if base_chunk == their_chunk || our_chunk == their_chunk {
+            merged.extend(our_chunk);
+        } else {
+            clean = false;
+            merged.extend(format!("<<<<<<< {}\n", our_label).as_bytes());
+            merged.extend(&our_chunk);
+            if !our_chunk.is_empty() && !our_chunk.ends_with(b"\n") {
+                merged.push(b'\n');
+            }
+            merged.extend(b"=======\n");
+            merged.extend(&their_chunk);
+            if !their_chunk.is_empty() && !their_chunk.ends_with(b"\n") {
+                merged.push(b'\n');
+            }
+            merged.extend(format!(">>>>>>> {}\n", their_label).as_bytes());
+        }
+        (o, a, b) = (o_end, a_end, b_end);
+    }
+    (merged, clean)
+}
+
+/// A file that a merge couldn't merge on its own
+#[derive(Debug, PartialEq, Eq)]
+pub struct PathConflict {
+    pub path: String,
+    /// What went wrong, e.g. `CONFLICT (content): Merge conflict in <path>`
+    pub description: String,
+    pub base: Option<FileVersion>,
+    pub ours: Option<FileVersion>,
+    pub theirs: Option<FileVersion>,
+    /// What to leave in the worktree for the user to resolve, with conflict markers if the
+    /// versions could be merged line by line
+    pub contents: Vec<u8>,
+    pub mode: FileMode,
+}
+
+impl PathConflict {
+    /// The index entries recording the conflict: one per version that exists, by stage
+    pub fn index_entries(&self) -> Vec<IndexEntry> {
+        [
+            (Stage::Base, &self.base),
+            (Stage::Ours, &self.ours),
+            (Stage::Theirs, &self.theirs),
+        ]
+        .into_iter()
+        .filter_map(|(stage, version)| {
+            version.as_ref().map(|(mode, hash)| IndexEntry::Conflict {
+                stage,
+                hash: hash.clone(),
+                name: self.path.clone(),
+                mode: *mode,
+            })
+        })
+        .collect()
+    }
+}
+
+/// The result of merging two trees
+#[derive(Debug, Default)]
+pub struct TreeMerge {
+    /// The merged version of each file that differs from ours, or `None` if it is to be deleted
+    pub changes: BTreeMap<String, Option<FileVersion>>,
+    /// The files with conflicts, sorted by path
+    pub conflicts: Vec<PathConflict>,
+}
+
+/// Merges the changes made to the tree `base` in the trees `ours` and `theirs`, file by file
+///
+/// A file changed on one side only takes that side's version. A file changed on both sides is
+/// merged line by line with `merge_lines`, unless it is binary or a symlink, and its mode is
+/// merged the same way. Merged contents are written as blobs to `repo`. `our_label` and
+/// `their_label` label the sides in conflict markers.
+///
+/// Returns an error if one of the trees, or a blob in them, can't be read
+pub fn merge_trees(
+    repo: &Repository,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    our_label: &str,
+    their_label: &str,
+) -> Result<TreeMerge> {
+    let base_files = read_tree_files(repo, base)?;
+    let our_files = read_tree_files(repo, ours)?;
+    let their_files = read_tree_files(repo, theirs)?;
+    let paths: BTreeSet<&String> = base_files
+        .keys()
+        .chain(our_files.keys())
+        .chain(their_files.keys())
+        .collect();
+
+    let mut merge = TreeMerge::default();
+    for path in paths {
+        let base = base_files.get(path).cloned();
+        let ours = our_files.get(path).cloned();
+        let theirs = their_files.get(path).cloned();
+        if ours == theirs || base == theirs {
+            continue;
+        }
+        if base == ours {
+            merge.changes.insert(path.clone(), theirs);
+            continue;
+        }
+        let mut conflict = PathConflict {
+            path: path.clone(),
+            description: String::new(),
+            base: base.clone(),
+            ours: ours.clone(),
+            theirs: theirs.clone(),
+            contents: vec![],
+            mode: FileMode::Regular,
+        };
+        let ((our_mode, our_hash), (their_mode, their_hash)) = match (ours, theirs) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            (kept, _) => {
+                // One side deleted the file, and the other changed it
+                let (deleted_by, (mode, hash)) = match kept {
+                    Some(ours) => (their_label, ours),
+                    None => (our_label, conflict.theirs.clone().unwrap()),
+                };
+                conflict.description = format!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in the other.",
+                    path, deleted_by
+                );
+                conflict.contents = read_blob(repo, &hash)?;
+                conflict.mode = mode;
+                merge.conflicts.push(conflict);
+                continue;
+            }
+        };
+        let base_mode = base.as_ref().map(|(mode, _)| *mode);
+        let base_hash = base.as_ref().map(|(_, hash)| hash.as_str());
+        let mode = if our_mode == their_mode || base_mode == Some(their_mode) {
+            Some(our_mode)
+        } else if base_mode == Some(our_mode) {
+            Some(their_mode)
+        } else {
+            None
+        };
+
+        let merged_hash = if our_hash == their_hash || base_hash == Some(&their_hash) {
+            Some(our_hash.clone())
+        } else if base_hash == Some(&our_hash) {
+            Some(their_hash.clone())
+        } else {
+            None
+        };
+        let kind = if base.is_some() { "content" } else { "add/add" };
+        conflict.description = format!("CONFLICT ({}): Merge conflict in {}", kind, path);
+        conflict.mode = mode.unwrap_or(our_mode);
+        match (merged_hash, mode) {
+            (Some(hash), Some(mode)) => {
+                merge.changes.insert(path.clone(), Some((mode, hash)));
+                continue;
+            }
+            (Some(hash), None) => {
+                conflict.description =
+                    format!("CONFLICT (mode): {} has different modes on each side", path);
+                conflict.contents = read_blob(repo, &hash)?;
+            }
+            (None, _) => {
+                let base_contents = match base_hash {
+                    Some(hash) => read_blob(repo, hash)?,
+                    None => vec![],
+                };
+                let our_contents = read_blob(repo, &our_hash)?;
+                let their_contents = read_blob(repo, &their_hash)?;
+                let mergeable = mode.is_some_and(|mode| mode != FileMode::Symlink)
+                    && ![&base_contents, &our_contents, &their_contents]
+                        .iter()
+                        .any(|contents| contents.contains(&0));
+                if !mergeable {
+                    // Binary files and symlinks can't be merged line by line, so ours is kept
+                    conflict.contents = our_contents;
+                    conflict.mode = our_mode;
+                } else {
+                    let (merged, clean) = merge_lines(
+                        &base_contents,
+                        &our_contents,
+                        &their_contents,
+                        our_label,
+                        their_label,
+                    );
+                    if clean {
+                        let hash = repo.write_object(ObjectKind::Blob, &merged)?;
+                        merge
+                            .changes
+                            .insert(path.clone(), Some((conflict.mode, hash)));
+                        continue;
+                    }
+                    conflict.contents = merged;
+                }
+            }
+        }
+        merge.conflicts.push(conflict);
+    }
+    Ok(merge)
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for merge_lines
+    //      changes: none, one side only, both sides in different places, both sides the same,
+    //          both sides in the same place
+    //      placement: start, middle, end of the text; last line with and without a newline
+    // Partitions for merge_base
+    //      history: one descends from the other, diverged, criss-cross merges
+    // Partitions for merge_trees
+    //      each file: unchanged, changed on one side, added, deleted, changed on both sides
+    //          cleanly or with conflicts, deleted on one side and modified on the other,
+    //          mode changed, binary
+
+    use super::*;
+    use crate::{
+        objects::{commit::write_commit, object::hash_object, tree::write_tree},
+        utils::test_dir::make_test_dir,
+    };
+
+    /// Merges with the labels `ours` and `theirs`, and outputs the result as text
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, bool) {
+        let (merged, clean) = merge_lines(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            "ours",
+            "theirs",
+        );
+        (String::from_utf8(merged).unwrap(), clean)
+    }
+
+    #[test]
+    fn clean_line_merges() {
+        let base = "a\nb\nc\nd\ne\n";
+        assert_eq!((base.to_string(), true), merge(base, base, base));
+        assert_eq!(
+            (String::from("a\nB\nc\nd\ne\n"), true),
+            merge(base, base, "a\nB\nc\nd\ne\n")
+        );
+        assert_eq!(
+            (String::from("A\nb\nc\nd\nE\nf"), true),
+            merge(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\nf")
+        );
+        assert_eq!(
+            (String::from("a\nc\nd\ne\nnew\n"), true),
+            merge(base, "a\nc\nd\ne\n", "a\nb\nc\nd\ne\nnew\n")
+        );
+        assert_eq!(
+            (String::from("a\nX\nc\nd\ne\n"), true),
+            merge(base, "a\nX\nc\nd\ne\n", "a\nX\nc\nd\ne\n")
+        );
+        assert_eq!((String::from("x\n"), true), merge("", "", "x\n"));
+    }
+
+    #[test]
+    fn conflicting_line_merges() {
+        let base = "a\nb\nc\n";
+        assert_eq!(
+            (
+                String::from("a\n<<<<<<< ours\nB\n=======\nb2\n>>>>>>> theirs\nc\n"),
+                false
+            ),
+            merge(base, "a\nB\nc\n", "a\nb2\nc\n")
+        );
+        // Markers always start on a line of their own
+        assert_eq!(
+            (
+                String::from("a\n<<<<<<< ours\nend\n=======\nEND\n>>>>>>> theirs\n"),
+                false
+            ),
+            merge("a\nb", "a\nend", "a\nEND")
+        );
+        // Deleting lines the other side changed conflicts too
+        assert_eq!(
+            (
+                String::from("a\n<<<<<<< ours\n=======\nB\n>>>>>>> theirs\nc\n"),
+                false
+            ),
+            merge(base, "a\nc\n", "a\nB\nc\n")
+        );
+        assert_eq!(
+            (
+                String::from("<<<<<<< ours\none\n=======\ntwo\n>>>>>>> theirs\n"),
+                false
+            ),
+            merge("", "one\n", "two\n")
+        );
+    }
+
+    /// Writes a commit of a tree with the files in `files`, with `parents`, at `time`
+    fn make_commit(
+        repo: &Repository,
+        parents: &[&str],
+        time: i64,
+        files: &[(&str, FileMode, &[u8])],
+    ) -> Result<String> {
+        let mut blobs = vec![];
+        for (name, mode, contents) in files {
+            let hash = repo.write_object(ObjectKind::Blob, contents)?;
+            blobs.push(format!("{} {}: {}", mode.code(), name, hash));
+        }
+        let tree = write_tree(repo, &vec![], &blobs)?;
+        write_commit(repo, "commit", parents, time, &tree, None, None)
+    }
+
+    #[test]
+    fn merge_bases() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let root = make_commit(&repo, &[], 0, &[])?;
+        let main = make_commit(&repo, &[&root], 1, &[])?;
+        let side = make_commit(&repo, &[&root], 2, &[])?;
+        let later = make_commit(&repo, &[&main], 3, &[])?;
+        assert_eq!(Some(main.clone()), merge_base(&repo, &main, &later)?);
+        assert_eq!(Some(main.clone()), merge_base(&repo, &later, &main)?);
+        assert_eq!(Some(root.clone()), merge_base(&repo, &later, &side)?);
+        assert_eq!(Some(later.clone()), merge_base(&repo, &later, &later)?);
+
+        // Criss-cross: both merges have main and side as best common ancestors
+        let merge_one = make_commit(&repo, &[&main, &side], 4, &[])?;
+        let merge_two = make_commit(&repo, &[&side, &main], 5, &[])?;
+        assert_eq!(
+            Some(side.clone()),
+            merge_base(&repo, &merge_one, &merge_two)?
+        );
+
+        let unrelated = make_commit(&repo, &[], 6, &[("a", FileMode::Regular, b"a")])?;
+        assert_eq!(None, merge_base(&repo, &main, &unrelated)?);
+        Ok(())
+    }
+
+    #[test]
+    fn tree_merges() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let tree = |files: &[(&str, FileMode, &[u8])]| -> Result<String> {
+            let commit = make_commit(&repo, &[], 0, files)?;
+            crate::objects::commit::get_commit_tree(&repo, &commit)
+        };
+        let regular = FileMode::Regular;
+        let base = tree(&[
+            ("same", regular, b"same\n"),
+            ("ours", regular, b"ours\n"),
+            ("theirs", regular, b"theirs\n"),
+            ("both", regular, b"1\n2\n3\n"),
+            ("clash", regular, b"x\n"),
+            ("gone", regular, b"gone\n"),
+            ("run", regular, b"run\n"),
+            ("data", regular, b"\0"),
+        ])?;
+        let ours = tree(&[
+            ("same", regular, b"same\n"),
+            ("ours", regular, b"OURS\n"),
+            ("theirs", regular, b"theirs\n"),
+            ("both", regular, b"one\n2\n3\n"),
+            ("clash", regular, b"ours\n"),
+            ("gone", regular, b"changed\n"),
+            ("run", FileMode::Executable, b"run\n"),
+            ("data", regular, b"\0ours"),
+            ("new", regular, b"ours\n"),
+        ])?;
+        let theirs = tree(&[
+            ("same", regular, b"same\n"),
+            ("ours", regular, b"ours\n"),
+            ("theirs", regular, b"THEIRS\n"),
+            ("both", regular, b"1\n2\nthree\n"),
+            ("clash", regular, b"theirs\n"),
+            ("run", regular, b"RUN\n"),
+            ("data", regular, b"\0theirs"),
+            ("new", regular, b"theirs\n"),
+        ])?;
+        let merge = merge_trees(&repo, &base, &ours, &theirs, "HEAD", "side")?;
+
+        let blob = |contents: &[u8]| hash_object(ObjectKind::Blob, contents);
+        let changes: Vec<(&String, &Option<FileVersion>)> = merge.changes.iter().collect();
+        assert_eq!(
+            vec![
+                (
+                    &String::from("both"),
+                    &Some((regular, blob(b"one\n2\nthree\n")))
+                ),
+                (
+                    &String::from("run"),
+                    &Some((FileMode::Executable, blob(b"RUN\n")))
+                ),
+                (&String::from("theirs"), &Some((regular, blob(b"THEIRS\n")))),
+            ],
+            changes
+        );
+
+        let descriptions: Vec<&str> = merge
+            .conflicts
+            .iter()
+            .map(|conflict| conflict.description.as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "CONFLICT (content): Merge conflict in clash",
+                "CONFLICT (content): Merge conflict in data",
+                "CONFLICT (modify/delete): gone deleted in side and modified in the other.",
+                "CONFLICT (add/add): Merge conflict in new",
+            ],
+            descriptions
+        );
+        let clash = &merge.conflicts[0];
+        assert_eq!(
+            b"<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> side\n".to_vec(),
+            clash.contents
+        );
+        assert_eq!(Some((regular, blob(b"x\n"))), clash.base);
+        assert_eq!(b"\0ours".to_vec(), merge.conflicts[1].contents);
+        assert_eq!(b"changed\n".to_vec(), merge.conflicts[2].contents);
+        assert_eq!(None, merge.conflicts[2].theirs);
+        assert_eq!(None, merge.conflicts[3].base);
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, fs::symlink_metadata, io, path::Path};
+
+/// The kind of a file in a tree, along with whether it is executable
+///
+/// Modes are written in trees and the index as the octal codes git uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileMode {
+    Regular,
+    Executable,
+    Symlink,
+    Directory,
+}
+
+impl FileMode {
+    /// The code of the mode, as written in trees and the index
+    pub fn code(&self) -> &'static str {
+        match self {
+            FileMode::Regular => "100644",
+            FileMode::Executable => "100755",
+            FileMode::Symlink => "120000",
+            FileMode::Directory => "040000",
+        }
+    }
+
+    /// Returns the mode with code `code`, or `None` if there is no such mode
+    pub fn from_code(code: &str) -> Option<FileMode> {
+        match code {
+            "100644" => Some(FileMode::Regular),
+            "100755" => Some(FileMode::Executable),
+            "120000" => Some(FileMode::Symlink),
+            "040000" => Some(FileMode::Directory),
+            _ => None,
+        }
+    }
+
+    /// Outputs the mode of the file at `path`, without following it if it is a symlink
+    ///
+    /// A file is executable if anyone may execute it. On platforms without an executable bit,
+    /// every file that isn't a symlink is regular.
+    pub fn of_path<P: AsRef<Path>>(path: P) -> io::Result<FileMode> {
+        let metadata = symlink_metadata(path)?;
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            Ok(FileMode::Symlink)
+        } else if file_type.is_dir() {
+            Ok(FileMode::Directory)
+        } else if is_executable(&metadata) {
+            Ok(FileMode::Executable)
+        } else {
+            Ok(FileMode::Regular)
+        }
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for FileMode
+    //      mode: regular, executable, symlink, directory
+    //      code: known, unknown
+
+    use std::fs::write;
+
+    use super::*;
+    use crate::{error::Result, utils::test_dir::make_test_dir};
+
+    #[test]
+    fn codes() {
+        for mode in [
+            FileMode::Regular,
+            FileMode::Executable,
+            FileMode::Symlink,
+            FileMode::Directory,
+        ] {
+            assert_eq!(Some(mode), FileMode::from_code(mode.code()));
+        }
+        assert_eq!(None, FileMode::from_code("100664"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modes_of_paths() -> Result<()> {
+        use std::{
+            fs::{set_permissions, Permissions},
+            os::unix::fs::{symlink, PermissionsExt},
+        };
+
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        write(dir.join("file.txt"), "text")?;
+        write(dir.join("script.sh"), "#!/bin/sh")?;
+        set_permissions(dir.join("script.sh"), Permissions::from_mode(0o755))?;
+        symlink("file.txt", dir.join("link"))?;
+        symlink("missing.txt", dir.join("dangling"))?;
+        assert_eq!(FileMode::Regular, FileMode::of_path(dir.join("file.txt"))?);
+        assert_eq!(
+            FileMode::Executable,
+            FileMode::of_path(dir.join("script.sh"))?
+        );
+        assert_eq!(FileMode::Symlink, FileMode::of_path(dir.join("link"))?);
+        assert_eq!(FileMode::Symlink, FileMode::of_path(dir.join("dangling"))?);
+        assert_eq!(FileMode::Directory, FileMode::of_path(dir)?);
+        Ok(())
+    }
+}
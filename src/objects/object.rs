@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    error::{Result, VcsError},
+    utils::hash::sha2_bytes,
+};
+
+/// The kind of a vcs object, written at the start of its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+}
+
+impl ObjectKind {
+    /// The name of the kind, as written in object headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+        }
+    }
+
+    /// Returns the kind named `name` in an object header, or `None` if there is no such kind
+    pub fn from_name(name: &str) -> Option<ObjectKind> {
+        match name {
+            "blob" => Some(ObjectKind::Blob),
+            "tree" => Some(ObjectKind::Tree),
+            "commit" => Some(ObjectKind::Commit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Outputs the uncompressed bytes of an object of kind `kind` holding `body`: the header
+/// `<kind> <size>\0` followed by the body
+pub fn object_bytes(kind: ObjectKind, body: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("{} {}\0", kind, body.len()).into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Outputs the hash of an object of kind `kind` holding `body`
+///
+/// The hash covers the header as well as the body, so objects of different kinds with the same
+/// body never share a hash.
+pub fn hash_object(kind: ObjectKind, body: &[u8]) -> String {
+    sha2_bytes(&object_bytes(kind, body))
+}
+
+/// Outputs the bytes stored on disk for an object of kind `kind` holding `body`
+pub fn encode_object(kind: ObjectKind, body: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(&object_bytes(kind, body))?;
+    Ok(encoder.finish()?)
+}
+
+/// Given the bytes stored on disk for the object with hash `hash`, outputs its kind and body
+///
+/// Objects written before headers existed are stored uncompressed, with the kind implied by their
+/// first line (`blob`, `Trees` or `Parent`); those are still read so that older repositories keep
+/// working. Returns a `CorruptObject` error if the bytes are neither format.
+pub fn decode_object(hash: &str, stored: &[u8]) -> Result<(ObjectKind, Vec<u8>)> {
+    let corrupt = |reason: &str| VcsError::CorruptObject {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    };
+    if is_legacy_object(stored) {
+        return decode_legacy_object(stored).ok_or_else(|| corrupt("unknown object format"));
+    }
+
+    let mut bytes = vec![];
+    ZlibDecoder::new(stored)
+        .read_to_end(&mut bytes)
+        .map_err(|_| corrupt("could not decompress object"))?;
+    let header_end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| corrupt("missing object header"))?;
+    let header =
+        std::str::from_utf8(&bytes[..header_end]).map_err(|_| corrupt("malformed header"))?;
+    let (kind, size) = header
+        .split_once(' ')
+        .ok_or_else(|| corrupt("malformed header"))?;
+    let kind = ObjectKind::from_name(kind).ok_or_else(|| corrupt("unknown object type"))?;
+    if size.parse::<usize>() != Ok(bytes.len() - header_end - 1) {
+        return Err(corrupt("object size does not match header"));
+    }
+    Ok((kind, bytes.split_off(header_end + 1)))
+}
+
+/// Returns true iff `stored` is an object written before objects were compressed
+///
+/// A zlib stream always starts with `0x78`, which no legacy object starts with.
+pub fn is_legacy_object(stored: &[u8]) -> bool {
+    stored.first() != Some(&0x78)
+}
+
+/// Outputs the kind and body of an object written before headers existed, or `None` if its first
+/// line doesn't identify a kind
+fn decode_legacy_object(stored: &[u8]) -> Option<(ObjectKind, Vec<u8>)> {
+    if let Some(body) = stored.strip_prefix(b"blob\n") {
+        Some((ObjectKind::Blob, body.to_vec()))
+    } else if stored.starts_with(b"Trees\n") {
+        Some((ObjectKind::Tree, stored.to_vec()))
+    } else if stored.starts_with(b"Parent\n") {
+        Some((ObjectKind::Commit, stored.to_vec()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for encode_object and decode_object
+    //      kind: blob, tree, commit
+    //      body: empty, nonempty
+    //      stored format: compressed, legacy, neither, bad header, wrong size
+
+    use super::*;
+    use crate::utils::hash::sha2;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for kind in [ObjectKind::Blob, ObjectKind::Tree, ObjectKind::Commit] {
+            for body in [&b""[..], &b"some\0body\xff"[..]] {
+                let stored = encode_object(kind, body)?;
+                assert!(!is_legacy_object(&stored));
+                assert_eq!((kind, body.to_vec()), decode_object("hash", &stored)?);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn header_format() {
+        assert_eq!(
+            b"blob 3\0abc".to_vec(),
+            object_bytes(ObjectKind::Blob, b"abc")
+        );
+        assert_eq!(sha2("tree 0\0"), hash_object(ObjectKind::Tree, b""));
+        assert_ne!(
+            hash_object(ObjectKind::Blob, b"x"),
+            hash_object(ObjectKind::Tree, b"x")
+        );
+    }
+
+    #[test]
+    fn legacy_objects() -> Result<()> {
+        assert_eq!(
+            (ObjectKind::Blob, b"text".to_vec()),
+            decode_object("hash", b"blob\ntext")?
+        );
+        assert_eq!(
+            (ObjectKind::Tree, b"Trees\nBlobs".to_vec()),
+            decode_object("hash", b"Trees\nBlobs")?
+        );
+        let commit = b"Parent\nNo parent\nTime\n0\nTree Hash\nabc\nMessage\nm";
+        assert_eq!(
+            (ObjectKind::Commit, commit.to_vec()),
+            decode_object("hash", commit)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_objects() -> Result<()> {
+        assert!(matches!(
+            decode_object("hash", b"not an object"),
+            Err(VcsError::CorruptObject { hash, .. }) if hash == "hash"
+        ));
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"blob 5\0abc")?;
+        assert!(matches!(
+            decode_object("hash", &encoder.finish()?),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"sock 3\0abc")?;
+        assert!(matches!(
+            decode_object("hash", &encoder.finish()?),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        assert!(matches!(
+            decode_object("hash", &[0x78, 0x00, 0x01]),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+use std::{
+    cell::OnceCell,
+    fs::read_dir,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    error::{Result, VcsError},
+    utils::{
+        fs_utils::{get_file_bytes, write_atomically},
+        hash::sha2_bytes,
+    },
+};
+
+use super::{
+    delta::{apply_delta, create_delta, MAX_PREALLOCATION},
+    object::ObjectKind,
+};
+
+/// Magic bytes at the start of every `.pack` file
+const PACK_MAGIC: &[u8; 4] = b"VPAK";
+/// Magic bytes at the start of every `.idx` file
+const INDEX_MAGIC: &[u8; 4] = b"VIDX";
+/// Version of the pack and index formats written by `write_pack`
+const PACK_VERSION: u32 = 1;
+/// Number of bytes in a raw object hash
+const HASH_SIZE: usize = 32;
+
+/// Type byte of a pack entry holding a delta against an earlier entry
+const DELTA_TYPE: u8 = 4;
+
+/// How many of the previous objects of the same kind `write_pack` tries as delta bases
+const DELTA_WINDOW: usize = 10;
+/// Longest chain of deltas `write_pack` makes, so reading an object stays cheap
+const MAX_DELTA_DEPTH: usize = 50;
+
+/// An object to be written to a pack
+pub struct PackObject {
+    pub hash: String,
+    pub kind: ObjectKind,
+    pub body: Vec<u8>,
+}
+
+/// The `.idx` file of a pack: the hashes of the objects in the pack, and where each one starts
+///
+/// An index is laid out as
+///     * the magic bytes `VIDX` and the format version, as a big-endian u32
+///     * a fanout table of 256 big-endian u32s, where entry `i` is the number of objects whose
+///       hash starts with a byte at most `i`
+///     * the raw hashes of the objects, sorted
+///     * the offset of each object in the `.pack` file, as a big-endian u64, in the same order
+///     * the hash of the `.pack` file
+///
+/// The `.pack` file itself is read the first time an object is read from it, and kept.
+#[derive(Debug, Clone)]
+pub struct PackIndex {
+    pack_path: PathBuf,
+    fanout: Vec<u32>,
+    hashes: Vec<[u8; HASH_SIZE]>,
+    offsets: Vec<u64>,
+    pack: OnceCell<Vec<u8>>,
+}
+
+impl PackIndex {
+    /// Reads the index at `idx_path`, which describes the `.pack` file next to it
+    ///
+    /// Returns a `CorruptObject` error if the index is malformed
+    pub fn read<P: AsRef<Path>>(idx_path: P) -> Result<PackIndex> {
+        let idx_path = idx_path.as_ref();
+        let corrupt = |reason: &str| VcsError::CorruptObject {
+            hash: idx_path.display().to_string(),
+            reason: reason.to_string(),
+        };
+        let bytes = get_file_bytes(idx_path)?;
+        if bytes.len() < 8 + 256 * 4 || &bytes[0..4] != INDEX_MAGIC {
+            return Err(corrupt("not a pack index"));
+        }
+        if read_u32(&bytes, 4) != PACK_VERSION {
+            return Err(corrupt("unsupported pack index version"));
+        }
+        let fanout: Vec<u32> = (0..256).map(|i| read_u32(&bytes, 8 + i * 4)).collect();
+        let count = fanout[255] as usize;
+        let hashes_start = 8 + 256 * 4;
+        let offsets_start = hashes_start + count * HASH_SIZE;
+        if bytes.len() != offsets_start + count * 8 + HASH_SIZE {
+            return Err(corrupt("pack index has the wrong size"));
+        }
+        let hashes = (0..count)
+            .map(|i| {
+                let start = hashes_start + i * HASH_SIZE;
+                bytes[start..start + HASH_SIZE].try_into().unwrap()
+            })
+            .collect();
+        let offsets = (0..count)
+            .map(|i| read_u64(&bytes, offsets_start + i * 8))
+            .collect();
+        Ok(PackIndex {
+            pack_path: idx_path.with_extension("pack"),
+            fanout,
+            hashes,
+            offsets,
+            pack: OnceCell::new(),
+        })
+    }
+
+    /// The path of the `.pack` file this index describes
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    /// Outputs the bytes of the `.pack` file, reading it if it hasn't been read yet
+    fn pack_bytes(&self) -> Result<&[u8]> {
+        if let Some(pack) = self.pack.get() {
+            return Ok(pack);
+        }
+        let pack = get_file_bytes(&self.pack_path)?;
+        Ok(self.pack.get_or_init(|| pack))
+    }
+
+    /// Outputs the hashes of every object in the pack, in hex
+    pub fn hashes(&self) -> Vec<String> {
+        self.hashes.iter().map(hex::encode).collect()
+    }
+
+    /// Returns the offset of the object with hash `hash` in the pack, or `None` if it isn't in the
+    /// pack
+    pub fn find(&self, hash: &str) -> Option<u64> {
+        let raw: [u8; HASH_SIZE] = hex::decode(hash).ok()?.try_into().ok()?;
+        let first = raw[0] as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let end = self.fanout[first] as usize;
+        let position = self.hashes[start..end].binary_search(&raw).ok()?;
+        Some(self.offsets[start + position])
+    }
+
+    /// Returns true iff the object with hash `hash` is in the pack
+    pub fn contains(&self, hash: &str) -> bool {
+        self.find(hash).is_some()
+    }
+
+    /// Reads every object in the pack, outputting them in hash order
+    pub fn read_objects(&self) -> Result<Vec<PackObject>> {
+        let pack = self.pack_bytes()?;
+        let mut objects = vec![];
+        for (raw, offset) in self.hashes.iter().zip(&self.offsets) {
+            let hash = hex::encode(raw);
+            let (kind, body) = read_entry(pack, *offset, &hash)?;
+            objects.push(PackObject { hash, kind, body });
+        }
+        Ok(objects)
+    }
+
+    /// Reads the object with hash `hash` from the pack, outputting its kind and body, or `None`
+    /// if it isn't in the pack
+    pub fn read_object(&self, hash: &str) -> Result<Option<(ObjectKind, Vec<u8>)>> {
+        match self.find(hash) {
+            Some(offset) => Ok(Some(read_entry(self.pack_bytes()?, offset, hash)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Outputs the indexes of every pack in the pack directory `pack_dir`
+///
+/// A pack only counts once its `.idx` file exists, since the index is written last.
+pub fn read_pack_indexes<P: AsRef<Path>>(pack_dir: P) -> Result<Vec<PackIndex>> {
+    let pack_dir = pack_dir.as_ref();
+    if !pack_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut idx_paths: Vec<PathBuf> = vec![];
+    for entry in read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "idx") {
+            idx_paths.push(path);
+        }
+    }
+    idx_paths.sort();
+    idx_paths.iter().map(PackIndex::read).collect()
+}
+
+/// Writes `objects` into a new pack in `pack_dir`, storing objects as deltas against similar
+/// earlier objects where that is smaller. Returns the path of the new `.pack` file and the number
+/// of objects stored as deltas.
+///
+/// A pack is laid out as
+///     * the magic bytes `VPAK`, the format version and the number of objects, as big-endian u32s
+///     * one entry per object: a type byte (1 blob, 2 tree, 3 commit, 4 delta), the size of the
+///       uncompressed data and the size of the compressed data as big-endian u64s, for deltas the
+///       offset of the base entry as a big-endian u64, and the zlib-compressed data
+///     * the hash of everything before it
+/// The `.idx` file is written after the `.pack` file, so readers never see half a pack.
+pub fn write_pack<P: AsRef<Path>>(pack_dir: P, objects: &[PackObject]) -> Result<(PathBuf, usize)> {
+    let pack_dir = pack_dir.as_ref();
+    std::fs::create_dir_all(pack_dir)?;
+
+    // Similar objects tend to be of the same kind and of similar sizes, so look for delta bases
+    // among the objects just before each one in this order
+    let mut order: Vec<usize> = (0..objects.len()).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (&objects[*a], &objects[*b]);
+        (type_byte(a.kind), b.body.len(), &a.hash).cmp(&(type_byte(b.kind), a.body.len(), &b.hash))
+    });
+
+    let mut pack = PACK_MAGIC.to_vec();
+    pack.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+    let mut offsets: Vec<u64> = vec![0; objects.len()];
+    let mut depths: Vec<usize> = vec![0; objects.len()];
+    let mut delta_count = 0;
+    for (position, &i) in order.iter().enumerate() {
+        let object = &objects[i];
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for &candidate in order[position.saturating_sub(DELTA_WINDOW)..position]
+            .iter()
+            .rev()
+        {
+            if objects[candidate].kind != object.kind || depths[candidate] >= MAX_DELTA_DEPTH {
+                continue;
+            }
+            let delta = create_delta(&objects[candidate].body, &object.body);
+            let best_len = best
+                .as_ref()
+                .map_or(object.body.len() / 2, |(_, d)| d.len());
+            if delta.len() < best_len {
+                best = Some((candidate, delta));
+            }
+        }
+
+        offsets[i] = pack.len() as u64;
+        let (type_byte, data) = match &best {
+            Some((base, delta)) => {
+                depths[i] = depths[*base] + 1;
+                delta_count += 1;
+                (DELTA_TYPE, delta.as_slice())
+            }
+            None => (type_byte(object.kind), object.body.as_slice()),
+        };
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+        pack.push(type_byte);
+        pack.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        pack.extend_from_slice(&(compressed.len() as u64).to_be_bytes());
+        if let Some((base, _)) = &best {
+            pack.extend_from_slice(&offsets[*base].to_be_bytes());
+        }
+        pack.extend_from_slice(&compressed);
+    }
+    let checksum = sha2_bytes(&pack);
+    pack.extend_from_slice(&hex::decode(&checksum).unwrap());
+
+    let mut sorted: Vec<([u8; HASH_SIZE], u64)> = vec![];
+    for (object, offset) in objects.iter().zip(&offsets) {
+        let raw: [u8; HASH_SIZE] = hex::decode(&object.hash)
+            .ok()
+            .and_then(|raw| raw.try_into().ok())
+            .ok_or_else(|| VcsError::CorruptObject {
+                hash: object.hash.clone(),
+                reason: String::from("not a valid object hash"),
+            })?;
+        sorted.push((raw, *offset));
+    }
+    sorted.sort();
+    sorted.dedup_by(|a, b| a.0 == b.0);
+    let mut index = INDEX_MAGIC.to_vec();
+    index.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    for byte in 0..=255u8 {
+        let count = sorted.iter().filter(|(raw, _)| raw[0] <= byte).count() as u32;
+        index.extend_from_slice(&count.to_be_bytes());
+    }
+    for (raw, _) in &sorted {
+        index.extend_from_slice(raw);
+    }
+    for (_, offset) in &sorted {
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    index.extend_from_slice(&pack[pack.len() - HASH_SIZE..]);
+
+    let pack_path = pack_dir.join(format!("pack-{}.pack", checksum));
+    write_atomically(pack_dir, &pack_path, &pack)?;
+    write_atomically(pack_dir, pack_path.with_extension("idx"), &index)?;
+    Ok((pack_path, delta_count))
+}
+
+/// Reads the entry at `offset` in the pack with bytes `pack`, resolving deltas. `hash` is the
+/// hash of the object, used in errors.
+fn read_entry(pack: &[u8], offset: u64, hash: &str) -> Result<(ObjectKind, Vec<u8>)> {
+    let corrupt = |reason: &str| VcsError::CorruptObject {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    };
+
+    // Follow the chain of delta bases back to a whole object
+    let mut deltas: Vec<Vec<u8>> = vec![];
+    let mut offset = offset as usize;
+    loop {
+        if offset + 17 > pack.len().saturating_sub(HASH_SIZE) {
+            return Err(corrupt("pack entry is out of bounds"));
+        }
+        let type_byte = pack[offset];
+        let size = read_u64(pack, offset + 1) as usize;
+        let compressed_size = read_u64(pack, offset + 9) as usize;
+        let mut data_start = offset + 17;
+        let base_offset = if type_byte == DELTA_TYPE {
+            data_start += 8;
+            Some(read_u64(pack, offset + 17) as usize)
+        } else {
+            None
+        };
+        let compressed = data_start
+            .checked_add(compressed_size)
+            .and_then(|data_end| pack.get(data_start..data_end))
+            .ok_or_else(|| corrupt("pack entry is out of bounds"))?;
+        // Sizes are only trusted once the data is read: no more than one byte past the declared
+        // size is decompressed, and the size is checked after
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+        ZlibDecoder::new(compressed)
+            .take((size as u64).saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(|_| corrupt("could not decompress pack entry"))?;
+        if data.len() != size {
+            return Err(corrupt("pack entry has the wrong size"));
+        }
+
+        match base_offset {
+            Some(base_offset) => {
+                if base_offset >= offset {
+                    return Err(corrupt("delta base does not come before the delta"));
+                }
+                deltas.push(data);
+                offset = base_offset;
+            }
+            None => {
+                let kind = kind_from_type_byte(type_byte)
+                    .ok_or_else(|| corrupt("unknown pack entry type"))?;
+                let mut body = data;
+                for delta in deltas.iter().rev() {
+                    body = apply_delta(&body, delta).ok_or_else(|| corrupt("malformed delta"))?;
+                }
+                return Ok((kind, body));
+            }
+        }
+    }
+}
+
+/// The type byte of a whole object of kind `kind` in a pack
+fn type_byte(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Blob => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Commit => 3,
+    }
+}
+
+/// The kind of a whole object with type byte `byte` in a pack, or `None` if there is no such kind
+fn kind_from_type_byte(byte: u8) -> Option<ObjectKind> {
+    match byte {
+        1 => Some(ObjectKind::Blob),
+        2 => Some(ObjectKind::Tree),
+        3 => Some(ObjectKind::Commit),
+        _ => None,
+    }
+}
+
+/// Reads the big-endian u32 at `offset` in `bytes`
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads the big-endian u64 at `offset` in `bytes`
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for write_pack and PackIndex
+    //      objects: none, one, many of mixed kinds
+    //      similar objects: none, several versions of one file
+    //      lookups: present, absent, malformed hash
+    //      .pack file: read, already read, entry declaring a huge size
+
+    use super::*;
+    use crate::{objects::object::hash_object, utils::test_dir::make_test_dir};
+
+    /// Outputs a pack object of kind `kind` holding `body`
+    fn pack_object(kind: ObjectKind, body: &[u8]) -> PackObject {
+        PackObject {
+            hash: hash_object(kind, body),
+            kind,
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn empty_pack() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let (pack_path, deltas) = write_pack(test_dir.path(), &[])?;
+        assert_eq!(0, deltas);
+        let index = PackIndex::read(pack_path.with_extension("idx"))?;
+        assert!(index.hashes().is_empty());
+        assert!(!index.contains(&hash_object(ObjectKind::Blob, b"")));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_with_deltas() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let base_text: String = (0..500).map(|i| format!("line {}\n", i)).collect();
+        let mut objects = vec![
+            pack_object(ObjectKind::Tree, b"Trees\nBlobs"),
+            pack_object(ObjectKind::Commit, b"Parent\nNo parent"),
+            pack_object(ObjectKind::Blob, b"\x00\xffbinary"),
+        ];
+        for version in 0..5 {
+            let text = base_text.replace("line 250\n", &format!("edited {}\n", version));
+            objects.push(pack_object(ObjectKind::Blob, text.as_bytes()));
+        }
+        let (pack_path, deltas) = write_pack(test_dir.path(), &objects)?;
+        assert!(deltas >= 4);
+        let total_size: usize = objects.iter().map(|object| object.body.len()).sum();
+        assert!((std::fs::metadata(&pack_path)?.len() as usize) < total_size / 4);
+
+        let indexes = read_pack_indexes(test_dir.path())?;
+        assert_eq!(1, indexes.len());
+        assert_eq!(pack_path, indexes[0].pack_path());
+        for object in &objects {
+            assert_eq!(
+                Some((object.kind, object.body.clone())),
+                indexes[0].read_object(&object.hash)?
+            );
+        }
+        assert_eq!(
+            None,
+            indexes[0].read_object(&hash_object(ObjectKind::Blob, b"x"))?
+        );
+        assert!(!indexes[0].contains("not a hash"));
+
+        // The pack was read once and kept, so its objects can still be read without it
+        std::fs::remove_file(&pack_path)?;
+        assert_eq!(
+            Some((objects[0].kind, objects[0].body.clone())),
+            indexes[0].read_object(&objects[0].hash)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn huge_declared_size() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let object = pack_object(ObjectKind::Blob, b"small");
+        let (pack_path, _) = write_pack(test_dir.path(), std::slice::from_ref(&object))?;
+        // The size of the only entry follows the 12-byte header and its type byte
+        let mut pack = std::fs::read(&pack_path)?;
+        pack[13..21].copy_from_slice(&u64::MAX.to_be_bytes());
+        std::fs::write(&pack_path, pack)?;
+        let index = PackIndex::read(pack_path.with_extension("idx"))?;
+        assert!(matches!(
+            index.read_object(&object.hash),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_index() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let idx_path = test_dir.path().join("pack-bad.idx");
+        std::fs::write(&idx_path, b"VIDX")?;
+        assert!(matches!(
+            PackIndex::read(&idx_path),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use super::diff::{edit_script, split_lines, Edit, EditKind, Hunk};
+
+/// One change a patch makes to a text: the lines `start..end` of the old version are replaced by
+/// `lines`. An insertion has `start == end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub start: usize,
+    pub end: usize,
+    pub lines: Vec<Vec<u8>>,
+}
+
+/// Outputs the changes each run of removed and added lines in `edits` makes, with the line
+/// numbers of the old version moved on by `offset`. `new_lines` are the lines of the new
+/// version the edits index.
+fn runs_of_changes(edits: &[Edit], new_lines: &[&[u8]], offset: usize) -> Vec<Change> {
+    let mut changes: Vec<Change> = vec![];
+    let mut in_run = false;
+    for edit in edits {
+        if edit.kind == EditKind::Same {
+            in_run = false;
+            continue;
+        }
+        if !in_run {
+            changes.push(Change {
+                start: edit.old + offset,
+                end: edit.old + offset,
+                lines: vec![],
+            });
+            in_run = true;
+        }
+        let change = changes.last_mut().unwrap();
+        match edit.kind {
+            EditKind::Removed => change.end += 1,
+            _ => change.lines.push(new_lines[edit.new].to_vec()),
+        }
+    }
+    changes
+}
+
+/// Outputs the changes `hunk` makes, where `new_lines` are the lines of the new version its edits
+/// index
+pub fn hunk_changes(hunk: &Hunk, new_lines: &[&[u8]]) -> Vec<Change> {
+    runs_of_changes(&hunk.edits, new_lines, 0)
+}
+
+/// Reads `text`, `hunk` as the user edited it, and outputs the changes it makes
+///
+/// `text` is in the form `Hunk::text` outputs, with lines starting with `#` and `@@` left out. A
+/// line that lost its prefix, as an empty unchanged line can in some editors, counts as unchanged.
+/// Returns `None` if the unchanged and removed lines are no longer the old lines of `hunk`, since
+/// the edited hunk then doesn't apply to the old version. `old_lines` are the lines of the old
+/// version the hunk's edits index.
+pub fn parse_edited_hunk(hunk: &Hunk, old_lines: &[&[u8]], text: &str) -> Option<Vec<Change>> {
+    let mut old: Vec<Vec<u8>> = vec![];
+    let mut new: Vec<Vec<u8>> = vec![];
+    // Which sides the last line read went to, for a `\ No newline at end of file` after it
+    let mut last = (false, false);
+    for line in split_lines(text.as_bytes()) {
+        let (to_old, to_new) = match line.first() {
+            Some(b'#') => continue,
+            Some(b'@') if line.starts_with(b"@@") => continue,
+            Some(b'\\') => {
+                for (went, side) in [(last.0, &mut old), (last.1, &mut new)] {
+                    if went {
+                        let line = side.last_mut()?;
+                        line.pop_if(|byte| *byte == b'\n');
+                    }
+                }
+                continue;
+            }
+            Some(b'-') => (true, false),
+            Some(b'+') => (false, true),
+            _ => (true, true),
+        };
+        let contents = match line.first() {
+            Some(b' ' | b'-' | b'+') => &line[1..],
+            _ => line,
+        };
+        if to_old {
+            old.push(contents.to_vec());
+        }
+        if to_new {
+            new.push(contents.to_vec());
+        }
+        last = (to_old, to_new);
+    }
+
+    let expected: Vec<&[u8]> = hunk
+        .edits
+        .iter()
+        .filter(|edit| edit.kind != EditKind::Added)
+        .map(|edit| old_lines[edit.old])
+        .collect();
+    if old != expected {
+        return None;
+    }
+    let new: Vec<&[u8]> = new.iter().map(|line| line.as_slice()).collect();
+    Some(runs_of_changes(
+        &edit_script(&expected, &new),
+        &new,
+        hunk.edits[0].old,
+    ))
+}
+
+/// Outputs `old_lines` with `changes` made to them, joined back into a text. The changes must not
+/// overlap.
+pub fn apply_changes(old_lines: &[&[u8]], changes: &[Change]) -> Vec<u8> {
+    let mut changes: Vec<&Change> = changes.iter().collect();
+    changes.sort_by_key(|change| (change.start, change.end));
+    let mut output = vec![];
+    let mut line = 0;
+    for change in changes {
+        for kept in &old_lines[line..change.start] {
+            output.extend_from_slice(kept);
+        }
+        for added in &change.lines {
+            output.extend_from_slice(added);
+        }
+        line = change.end;
+    }
+    for kept in &old_lines[line..] {
+        output.extend_from_slice(kept);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for hunk_changes and apply_changes
+    //      changes applied: none, some, all; insertions, removals, replacements
+    // Partitions for parse_edited_hunk
+    //      edit: unchanged, added line dropped, removed line kept, line added, old lines changed
+    //      last line: with a line break, without one
+
+    use super::*;
+    use crate::objects::diff::hunks;
+
+    /// Outputs the lines of `old` and `new`, and the hunks between them with a line of context
+    fn diff<'a>(old: &'a [u8], new: &'a [u8]) -> (Vec<&'a [u8]>, Vec<&'a [u8]>, Vec<Hunk>) {
+        let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+        let hunks = hunks(&edit_script(&old_lines, &new_lines), 1);
+        (old_lines, new_lines, hunks)
+    }
+
+    #[test]
+    fn applies_selected_hunks() {
+        let old = b"a\nb\nc\nd\ne\nf\ng\n";
+        let new = b"a\nB\nc\nd\ne\ng\nh\n";
+        let (old_lines, new_lines, hunks) = diff(old, new);
+        assert_eq!(2, hunks.len());
+        assert_eq!(old.to_vec(), apply_changes(&old_lines, &[]));
+        assert_eq!(
+            b"a\nB\nc\nd\ne\nf\ng\n".to_vec(),
+            apply_changes(&old_lines, &hunk_changes(&hunks[0], &new_lines))
+        );
+        assert_eq!(
+            b"a\nb\nc\nd\ne\ng\nh\n".to_vec(),
+            apply_changes(&old_lines, &hunk_changes(&hunks[1], &new_lines))
+        );
+        let all: Vec<Change> = hunks
+            .iter()
+            .flat_map(|hunk| hunk_changes(hunk, &new_lines))
+            .collect();
+        assert_eq!(new.to_vec(), apply_changes(&old_lines, &all));
+    }
+
+    #[test]
+    fn edited_hunks() {
+        let (old_lines, new_lines, hunks) = diff(b"a\nb\nc\n", b"a\nB\nBB\nc\n");
+        let hunk = &hunks[0];
+        let text = hunk.text(&old_lines, &new_lines);
+        assert_eq!("@@ -1,3 +1,4 @@\n a\n-b\n+B\n+BB\n c\n", text);
+        let apply = |text: &str| {
+            parse_edited_hunk(hunk, &old_lines, text)
+                .map(|changes| apply_changes(&old_lines, &changes))
+        };
+
+        assert_eq!(Some(b"a\nB\nBB\nc\n".to_vec()), apply(&text));
+        // Dropping an added line, keeping a removed one, and adding a line of one's own
+        assert_eq!(
+            Some(b"a\nb\nB\nnew\nc\n".to_vec()),
+            apply("# comment\n a\n b\n+B\n+new\n c\n")
+        );
+        // An empty line is an unchanged empty line, so it no longer matches
+        assert_eq!(None, apply(" a\n-b\n\n c\n"));
+        assert_eq!(None, apply(" a\n-x\n+B\n c\n"));
+
+        let (old_lines, new_lines, hunks) = diff(b"a\nb", b"a\nc");
+        let text = hunks[0].text(&old_lines, &new_lines);
+        assert_eq!(
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n",
+            text
+        );
+        let changes = parse_edited_hunk(&hunks[0], &old_lines, &text).unwrap();
+        assert_eq!(b"a\nc".to_vec(), apply_changes(&old_lines, &changes));
+        let changes =
+            parse_edited_hunk(&hunks[0], &old_lines, " a\n-b\n\\ No newline\n+c\n").unwrap();
+        assert_eq!(b"a\nc\n".to_vec(), apply_changes(&old_lines, &changes));
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashSet,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Result, VcsError},
+    utils::{fs_utils::get_file_contents, hash::is_hash},
+};
+
+use super::{
+    commit::{get_commit_parents, get_commit_tree},
+    index::IndexEntry,
+    object::ObjectKind,
+    repo::Repository,
+    tree::read_tree_entries,
+};
+
+/// Something outside the object store that refers to an object
+pub struct Root {
+    /// What refers to the object, such as `branch main` or `index entry a.txt`
+    pub name: String,
+    /// The hash of the object
+    pub hash: String,
+    /// The kind of object it should be, if it refers to a particular kind
+    pub kind: Option<ObjectKind>,
+}
+
+/// Outputs everything reachable objects are reached from: the commit of each branch and tag,
+/// HEAD, each blob staged in the index, the commit being merged by a merge in progress, and each
+/// object named in a reflog
+///
+/// Tags live in `.vcs/tags` and reflogs in `.vcs/logs`; either may be missing.
+pub fn roots(repo: &Repository) -> Result<Vec<Root>> {
+    let mut roots = vec![];
+    for branch in repo.list_branches()? {
+        roots.push(Root {
+            name: format!("branch {}", branch),
+            hash: repo.get_branch_commit(&branch)?,
+            kind: Some(ObjectKind::Commit),
+        });
+    }
+    roots.push(Root {
+        name: String::from("HEAD"),
+        hash: repo.get_head_commit()?,
+        kind: Some(ObjectKind::Commit),
+    });
+    for entry in repo.read_index()? {
+        if let IndexEntry::Blob { hash, name, .. } | IndexEntry::Conflict { hash, name, .. } = entry
+        {
+            roots.push(Root {
+                name: format!("index entry {}", name),
+                hash,
+                kind: Some(ObjectKind::Blob),
+            });
+        }
+    }
+    if let Some(hash) = repo.merge_head()? {
+        roots.push(Root {
+            name: String::from("MERGE_HEAD"),
+            hash,
+            kind: Some(ObjectKind::Commit),
+        });
+    }
+    let tags_dir = repo.vcs_dir().join("tags");
+    for path in files_under(&tags_dir)? {
+        roots.push(Root {
+            name: format!("tag {}", path.strip_prefix(&tags_dir).unwrap().display()),
+            hash: get_file_contents(&path)?.trim().to_string(),
+            kind: None,
+        });
+    }
+    let logs_dir = repo.vcs_dir().join("logs");
+    for path in files_under(&logs_dir)? {
+        for word in get_file_contents(&path)?.split_whitespace() {
+            if is_hash(word) && repo.object_exists(word) {
+                roots.push(Root {
+                    name: format!("reflog {}", path.strip_prefix(&logs_dir).unwrap().display()),
+                    hash: word.to_string(),
+                    kind: None,
+                });
+            }
+        }
+    }
+    Ok(roots)
+}
+
+/// Outputs the hash of every object reachable from `roots`: those roots, the parents and
+/// trees of reachable commits, and the entries of reachable trees
+///
+/// Returns an `ObjectNotFound` error if a reachable object is missing, so nothing is ever treated
+/// as unreachable because of a broken history.
+pub fn reachable_objects(repo: &Repository) -> Result<HashSet<String>> {
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<String> = roots(repo)?.into_iter().map(|root| root.hash).collect();
+    while let Some(hash) = pending.pop() {
+        if reachable.contains(&hash) {
+            continue;
+        }
+        let (kind, _) = repo.read_object(&hash)?;
+        match kind {
+            ObjectKind::Commit => {
+                pending.extend(get_commit_parents(repo, &hash)?);
+                pending.push(get_commit_tree(repo, &hash)?);
+            }
+            ObjectKind::Tree => {
+                for entry in read_tree_entries(repo, &hash)? {
+                    if entry.kind() == ObjectKind::Tree {
+                        pending.push(entry.hash);
+                    } else if !reachable.contains(&entry.hash) {
+                        // Blobs refer to nothing, so there's no need to read them
+                        if !repo.object_exists(&entry.hash) {
+                            return Err(VcsError::ObjectNotFound(entry.hash));
+                        }
+                        reachable.insert(entry.hash);
+                    }
+                }
+            }
+            ObjectKind::Blob => {}
+        }
+        reachable.insert(hash);
+    }
+    Ok(reachable)
+}
+
+/// Outputs every file in `dir` and its subdirectories, or nothing if `dir` doesn't exist
+fn files_under(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,452 @@
+use std::{
+    env,
+    fs::{create_dir_all, read_dir, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use crate::{
+    error::{Result, VcsError},
+    utils::fs_utils::{file_exists, get_file_contents},
+};
+
+use super::{
+    lock::{is_lock_file, LockFile},
+    repo::Repository,
+    signature::{new_signatures, Signature},
+};
+
+/// The hash a reflog records for a ref that doesn't exist, before it is created or after it is
+/// deleted
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The start of the message of every reflog entry made by switching branches or commits
+const CHECKOUT_PREFIX: &str = "checkout: moving from ";
+
+/// One update of a ref, as recorded in its reflog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// The hash the ref held before, or `NULL_HASH` if it was created
+    pub old: String,
+    /// The hash the ref held after, or `NULL_HASH` if it was deleted
+    pub new: String,
+    /// Who made the update, and when
+    pub committer: Signature,
+    /// Why the ref moved, e.g. `commit: <message>` or `checkout: moving from <a> to <b>`
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Parses a reflog line, `<old> <new> <signature>\t<message>`, or outputs `None` if `line`
+    /// isn't one
+    fn parse(line: &str) -> Option<ReflogEntry> {
+        let (header, message) = line.split_once('\t')?;
+        let (old, rest) = header.split_once(' ')?;
+        let (new, committer) = rest.split_once(' ')?;
+        Some(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            committer: Signature::parse(committer)?,
+            message: message.to_string(),
+        })
+    }
+
+    /// The line of the reflog holding the entry
+    fn line(&self) -> String {
+        format!(
+            "{} {} {}\t{}\n",
+            self.old, self.new, self.committer, self.message
+        )
+    }
+}
+
+/// The ref whose reflog records the moves of the branch named `name`
+pub fn branch_ref(name: &str) -> String {
+    format!("branches/{}", name)
+}
+
+/// The path of the reflog of `reference`, which is `HEAD` or a ref made by `branch_ref`
+fn reflog_path(repo: &Repository, reference: &str) -> PathBuf {
+    repo.vcs_dir().join("logs").join(reference)
+}
+
+/// Returns true iff `reference` has a reflog
+pub fn reflog_exists(repo: &Repository, reference: &str) -> bool {
+    file_exists(reflog_path(repo, reference))
+}
+
+/// Outputs the entries of the reflog of `reference`, oldest first. A ref without a reflog has
+/// none.
+///
+/// Returns `ReflogParse` if a line of the reflog can't be parsed
+pub fn read_reflog(repo: &Repository, reference: &str) -> Result<Vec<ReflogEntry>> {
+    if !reflog_exists(repo, reference) {
+        return Ok(vec![]);
+    }
+    get_file_contents(reflog_path(repo, reference))?
+        .lines()
+        .enumerate()
+        .map(|(number, line)| {
+            ReflogEntry::parse(line).ok_or_else(|| VcsError::ReflogParse {
+                reference: reference.to_string(),
+                line: number + 1,
+            })
+        })
+        .collect()
+}
+
+/// Takes the lock on the reflog of `reference`
+///
+/// Returns `RepositoryLocked` if another process is writing the reflog
+fn lock_reflog(repo: &Repository, reference: &str) -> Result<LockFile> {
+    LockFile::acquire(reflog_path(repo, reference))
+}
+
+/// Replaces the reflog whose lock is `lock` with `entries`
+fn write_reflog(mut lock: LockFile, entries: &[ReflogEntry]) -> Result<()> {
+    let text: String = entries.iter().map(ReflogEntry::line).collect();
+    lock.write(text.as_bytes())?;
+    lock.commit()
+}
+
+/// Outputs the entry recording that a ref moved from `old` to `new` because of `message`, signed
+/// by the current committer, as `new_signatures` works them out
+///
+/// Returns the errors of `new_signatures`
+fn new_entry(repo: &Repository, old: &str, new: &str, message: &str) -> Result<ReflogEntry> {
+    let (_, committer) = new_signatures(repo, None, |name| env::var(name).ok())?;
+    Ok(ReflogEntry {
+        old: old.to_string(),
+        new: new.to_string(),
+        committer,
+        // A message is kept to one line, so the reflog stays one entry per line
+        message: message.lines().next().unwrap_or("").to_string(),
+    })
+}
+
+/// Appends `entry` to the reflog of `reference`
+fn append_entry(repo: &Repository, reference: &str, entry: &ReflogEntry) -> Result<()> {
+    let path = reflog_path(repo, reference);
+    create_dir_all(path.parent().unwrap())?;
+    // Appending under the reflog's lock keeps `expire_reflogs` from rewriting it at the same time.
+    // The lock is dropped without being committed, so the file is appended to in place.
+    let _lock = lock_reflog(repo, reference)?;
+    let mut log_file = OpenOptions::new().create(true).append(true).open(path)?;
+    log_file.write_all(entry.line().as_bytes())?;
+    Ok(())
+}
+
+/// Records in the reflog of `reference` that it moved from `old` to `new` because of `message`,
+/// signed by the current committer, as `new_signatures` works them out
+///
+/// Returns the errors of `new_signatures`
+pub fn append_reflog(
+    repo: &Repository,
+    reference: &str,
+    old: &str,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    append_entry(repo, reference, &new_entry(repo, old, new, message)?)
+}
+
+/// Points the branch named `name` at `commit_hash`, creating it if needed, and records the move
+/// with `message` in the branch's reflog, and in HEAD's if the branch is checked out
+///
+/// The commit the branch pointed at before is read under the branch's lock, so the reflog records
+/// the move that was made. The entry is signed before the branch moves, so a bad identity leaves
+/// the branch where it was. Returns `RepositoryLocked` if another process is writing the branch,
+/// and the errors of `new_signatures`.
+pub fn move_branch(repo: &Repository, name: &str, commit_hash: &str, message: &str) -> Result<()> {
+    let mut entry = new_entry(repo, NULL_HASH, commit_hash, message)?;
+    repo.update_branch(name, |current| {
+        if let Some(current) = current {
+            entry.old = current;
+        }
+        Ok(commit_hash.to_string())
+    })?;
+    append_entry(repo, &branch_ref(name), &entry)?;
+    if repo.get_branch_name()? == name {
+        append_entry(repo, "HEAD", &entry)?;
+    }
+    Ok(())
+}
+
+/// Points the current branch at `commit_hash`, as `move_branch` does. HEAD is locked while the
+/// branch moves, so another process can't switch to a different branch in between.
+///
+/// Returns a `DetachedHead` error if the current checked out commit is not on a branch, and
+/// `RepositoryLocked` if another process is writing HEAD or the branch
+pub fn move_head(repo: &Repository, commit_hash: &str, message: &str) -> Result<()> {
+    // Dropped without being committed, which leaves HEAD as it is
+    let _head_lock = LockFile::acquire(repo.vcs_dir().join("HEAD"))?;
+    let head = repo.get_branch_name()?;
+    if !repo.branch_exists(&head) {
+        return Err(VcsError::DetachedHead);
+    }
+    move_branch(repo, &head, commit_hash, message)
+}
+
+/// Points HEAD at `branch_or_hash`, recording in HEAD's reflog that it moved from the branch or
+/// commit checked out before as `checkout: moving from <old> to <branch_or_hash>`
+pub fn switch_head(repo: &Repository, branch_or_hash: &str) -> Result<()> {
+    let old_name = repo.get_branch_name()?;
+    let message = format!("{}{} to {}", CHECKOUT_PREFIX, old_name, branch_or_hash);
+    // Signed before HEAD moves, like the entries of `move_branch`
+    let mut entry = new_entry(repo, &repo.get_head_commit()?, NULL_HASH, &message)?;
+    repo.set_head(branch_or_hash)?;
+    entry.new = repo.get_head_commit()?;
+    append_entry(repo, "HEAD", &entry)
+}
+
+/// Deletes the branch named `name`, recording the deletion in its reflog. The reflog is kept, so
+/// the branch's commits can still be found, and aren't deleted by `gc` until it expires.
+///
+/// Returns a `BranchNotFound` error if the branch doesn't exist
+pub fn delete_branch(repo: &Repository, name: &str) -> Result<()> {
+    let old = repo.get_branch_commit(name)?;
+    repo.delete_branch(name)?;
+    append_reflog(
+        repo,
+        &branch_ref(name),
+        &old,
+        NULL_HASH,
+        &format!("branch: Deleted {}", name),
+    )
+}
+
+/// Outputs the branch or commit that was checked out before the `n`th most recent checkout, as
+/// `@{-n}` names it, or `None` if there weren't that many checkouts
+pub fn previous_checkout(repo: &Repository, n: usize) -> Result<Option<String>> {
+    if n == 0 {
+        return Ok(None);
+    }
+    Ok(read_reflog(repo, "HEAD")?
+        .into_iter()
+        .rev()
+        .filter_map(|entry| {
+            let moved = entry.message.strip_prefix(CHECKOUT_PREFIX)?;
+            Some(moved.split_once(" to ")?.0.to_string())
+        })
+        .nth(n - 1))
+}
+
+/// Outputs every ref with a reflog, sorted
+pub fn reflog_refs(repo: &Repository) -> Result<Vec<String>> {
+    let mut refs = vec![];
+    if reflog_exists(repo, "HEAD") {
+        refs.push(String::from("HEAD"));
+    }
+    let branch_logs = reflog_path(repo, "branches");
+    if branch_logs.is_dir() {
+        let mut branches = vec![];
+        for entry in read_dir(branch_logs)? {
+            let path = entry?.path();
+            if !is_lock_file(&path) {
+                branches.push(branch_ref(&path.file_name().unwrap().to_string_lossy()));
+            }
+        }
+        branches.sort();
+        refs.extend(branches);
+    }
+    Ok(refs)
+}
+
+/// Deletes every reflog entry made at or before `cutoff`, in seconds since the Unix epoch, and outputs
+/// how many were deleted. With `dry_run`, only counts them.
+///
+/// Returns `ReflogParse` if a reflog can't be parsed
+pub fn expire_reflogs(repo: &Repository, cutoff: i64, dry_run: bool) -> Result<usize> {
+    let mut expired = 0;
+    for reference in reflog_refs(repo)? {
+        // The reflog is read under the lock it is rewritten with, so no entry appended in between
+        // is lost
+        let lock = match dry_run {
+            true => None,
+            false => Some(lock_reflog(repo, &reference)?),
+        };
+        let entries = read_reflog(repo, &reference)?;
+        let kept: Vec<ReflogEntry> = entries
+            .iter()
+            .filter(|entry| entry.committer.time > cutoff)
+            .cloned()
+            .collect();
+        if kept.len() < entries.len() {
+            expired += entries.len() - kept.len();
+            if let Some(lock) = lock {
+                write_reflog(lock, &kept)?;
+            }
+        }
+    }
+    Ok(expired)
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for the reflog
+    //      refs: HEAD, checked out branch, other branch, deleted branch
+    //      updates: create, move, switch, delete
+    //      expiry: nothing old, some entries old, dry run, reflog locked
+    //      previous checkouts: none, one, several
+    //      locks: branch held by another process, HEAD held by another process
+    //      identity: valid, malformed
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    #[test]
+    fn records_ref_updates() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        repo.set_head("main")?;
+        move_branch(&repo, "main", "aaa", "commit (initial): first\nmore")?;
+        move_branch(&repo, "side", "aaa", "branch: Created from HEAD")?;
+        move_head(&repo, "bbb", "commit: second")?;
+        switch_head(&repo, "side")?;
+        switch_head(&repo, "ccc")?;
+        delete_branch(&repo, "side")?;
+
+        let summary = |reference: &str| -> Result<Vec<(String, String, String)>> {
+            Ok(read_reflog(&repo, reference)?
+                .into_iter()
+                .map(|entry| (entry.old, entry.new, entry.message))
+                .collect())
+        };
+        let entry = |old: &str, new: &str, message: &str| {
+            (old.to_string(), new.to_string(), message.to_string())
+        };
+        assert_eq!(
+            vec![
+                entry(NULL_HASH, "aaa", "commit (initial): first"),
+                entry("aaa", "bbb", "commit: second"),
+                entry("bbb", "aaa", "checkout: moving from main to side"),
+                entry("aaa", "ccc", "checkout: moving from side to ccc"),
+            ],
+            summary("HEAD")?
+        );
+        assert_eq!(
+            vec![
+                entry(NULL_HASH, "aaa", "commit (initial): first"),
+                entry("aaa", "bbb", "commit: second"),
+            ],
+            summary(&branch_ref("main"))?
+        );
+        assert_eq!(
+            vec![
+                entry(NULL_HASH, "aaa", "branch: Created from HEAD"),
+                entry("aaa", NULL_HASH, "branch: Deleted side"),
+            ],
+            summary(&branch_ref("side"))?
+        );
+        assert_eq!(Some(String::from("side")), previous_checkout(&repo, 1)?);
+        assert_eq!(Some(String::from("main")), previous_checkout(&repo, 2)?);
+        assert_eq!(None, previous_checkout(&repo, 3)?);
+        assert!(matches!(
+            move_head(&repo, "ddd", "commit: detached"),
+            Err(VcsError::DetachedHead)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn locked_refs() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        repo.set_head("main")?;
+        move_branch(&repo, "main", "aaa", "commit (initial): first")?;
+
+        // Neither the branch nor its reflog moves while another process holds a lock
+        let branch_lock = LockFile::acquire(repo.vcs_dir().join("branches").join("main"))?;
+        assert!(matches!(
+            move_head(&repo, "bbb", "commit: second"),
+            Err(VcsError::RepositoryLocked(_))
+        ));
+        drop(branch_lock);
+        let head_lock = LockFile::acquire(repo.vcs_dir().join("HEAD"))?;
+        assert!(matches!(
+            move_head(&repo, "bbb", "commit: second"),
+            Err(VcsError::RepositoryLocked(_))
+        ));
+        drop(head_lock);
+        assert_eq!("aaa", repo.get_branch_commit("main")?);
+        assert_eq!(1, read_reflog(&repo, "HEAD")?.len());
+
+        move_head(&repo, "bbb", "commit: second")?;
+        assert_eq!("bbb", repo.get_branch_commit("main")?);
+        assert_eq!("main", repo.get_branch_name()?);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_identity() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        repo.set_head("main")?;
+        move_branch(&repo, "main", "aaa", "commit (initial): first")?;
+
+        // Neither ref moves if its reflog entry can't be signed
+        std::fs::write(repo.vcs_dir().join("config"), "[user]\n\tname = <root>\n")?;
+        assert!(matches!(
+            move_branch(&repo, "main", "bbb", "commit: second"),
+            Err(VcsError::InvalidIdentity(..))
+        ));
+        assert!(matches!(
+            switch_head(&repo, "bbb"),
+            Err(VcsError::InvalidIdentity(..))
+        ));
+        assert_eq!("aaa", repo.get_branch_commit("main")?);
+        assert_eq!("main", repo.get_branch_name()?);
+        assert_eq!(1, read_reflog(&repo, "HEAD")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn expiry() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        repo.set_head("main")?;
+        let mut entries = vec![];
+        for (time, new) in [(100, "aaa"), (200, "bbb"), (300, "ccc")] {
+            entries.push(ReflogEntry {
+                old: NULL_HASH.to_string(),
+                new: new.to_string(),
+                committer: Signature::parse(&format!("Jane <jane@example.com> {} +0000", time))
+                    .unwrap(),
+                message: String::from("commit: test"),
+            });
+        }
+        create_dir_all(reflog_path(&repo, "branches"))?;
+        write_reflog(lock_reflog(&repo, "HEAD")?, &entries)?;
+        write_reflog(lock_reflog(&repo, &branch_ref("main"))?, &entries[2..])?;
+        assert_eq!(
+            vec![String::from("HEAD"), branch_ref("main")],
+            reflog_refs(&repo)?
+        );
+
+        // Neither gc nor a new entry can touch a reflog another process holds the lock on
+        let lock = lock_reflog(&repo, "HEAD")?;
+        assert!(matches!(
+            expire_reflogs(&repo, 250, false),
+            Err(VcsError::RepositoryLocked(_))
+        ));
+        assert!(matches!(
+            append_reflog(&repo, "HEAD", "aaa", "bbb", "commit: test"),
+            Err(VcsError::RepositoryLocked(_))
+        ));
+        drop(lock);
+        assert_eq!(3, read_reflog(&repo, "HEAD")?.len());
+
+        assert_eq!(2, expire_reflogs(&repo, 250, true)?);
+        assert_eq!(3, read_reflog(&repo, "HEAD")?.len());
+        assert_eq!(2, expire_reflogs(&repo, 250, false)?);
+        assert_eq!(entries[2..], read_reflog(&repo, "HEAD")?);
+        assert_eq!(0, expire_reflogs(&repo, 250, false)?);
+
+        std::fs::write(reflog_path(&repo, "HEAD"), "not a reflog line\n")?;
+        assert!(matches!(
+            read_reflog(&repo, "HEAD"),
+            Err(VcsError::ReflogParse { reference, line: 1 }) if reference == "HEAD"
+        ));
+        Ok(())
+    }
+}
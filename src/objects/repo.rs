@@ -0,0 +1,677 @@
+use std::{
+    cell::OnceCell,
+    env,
+    fs::{create_dir, create_dir_all, metadata, read_dir, remove_file, File},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    error::{Result, VcsError},
+    utils::fs_utils::{
+        directory_exists, file_exists, get_file_bytes, get_file_contents, no_dir_string,
+        normalize_path, write_atomically,
+    },
+};
+
+/// Environment variable naming the `.vcs` directory to use instead of searching for one
+pub const VCS_DIR_ENV: &str = "VCS_DIR";
+/// Environment variable naming the worktree root to use instead of searching for one
+pub const VCS_WORK_TREE_ENV: &str = "VCS_WORK_TREE";
+
+use super::{
+    index::{index_file_bytes, parse_index_file, Index, IndexEntry},
+    lock::{is_lock_file, write_locked, LockFile},
+    object::{decode_object, encode_object, hash_object, ObjectKind},
+    pack::{read_pack_indexes, PackIndex},
+    stat_cache::{FileTime, StatCache},
+};
+
+/// Returns true iff `name` can name a branch: it stays a single file in `.vcs/branches` that
+/// isn't hidden or taken for a lock file, so it is not empty, contains no `/`, `..` or NUL, doesn't
+/// start with `.` and doesn't end in `.lock`
+pub fn is_valid_branch_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(['/', '\0'])
+        && !name.contains("..")
+        && !name.starts_with('.')
+        && !name.ends_with(".lock")
+}
+
+/// A handle on a vcs repository: a worktree root and the `.vcs` directory inside it
+///
+/// All object, ref and index access goes through a `Repository`, so nothing depends on the
+/// working directory of the process and several repositories can be open at once.
+///
+/// The pack indexes are read the first time they are needed and kept for the life of the
+/// `Repository`, so packs written or removed after that are only seen by a new `Repository`.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    root: PathBuf,
+    vcs_dir: PathBuf,
+    packs: OnceCell<Vec<PackIndex>>,
+}
+
+impl Repository {
+    /// Opens the repository whose worktree root is `root`. Returns `None` if `root` does not
+    /// contain a `.vcs` directory.
+    pub fn open<P: AsRef<Path>>(root: P) -> Option<Repository> {
+        let root = root.as_ref().to_path_buf();
+        let vcs_dir = root.join(".vcs");
+        if !directory_exists(&vcs_dir) {
+            return None;
+        }
+        Some(Repository::new(root, vcs_dir))
+    }
+
+    /// Makes a handle on the repository with worktree root `root` and `.vcs` directory `vcs_dir`
+    fn new(root: PathBuf, vcs_dir: PathBuf) -> Repository {
+        Repository {
+            root,
+            vcs_dir,
+            packs: OnceCell::new(),
+        }
+    }
+
+    /// Finds the repository that `dir` is in, honouring the `VCS_DIR` and `VCS_WORK_TREE`
+    /// environment variables. Returns `None` if there is no such repository.
+    pub fn find<P: AsRef<Path>>(dir: P) -> Option<Repository> {
+        Repository::locate(
+            dir,
+            env::var_os(VCS_DIR_ENV).map(PathBuf::from),
+            env::var_os(VCS_WORK_TREE_ENV).map(PathBuf::from),
+        )
+    }
+
+    /// Finds the repository that `dir` is in, given the values of the `VCS_DIR` and
+    /// `VCS_WORK_TREE` overrides. Relative overrides are taken relative to `dir`.
+    ///
+    /// If `vcs_dir` is given it is used as the `.vcs` directory, with `work_tree` (or else the
+    /// parent of `vcs_dir`) as the worktree root. If only `work_tree` is given, its `.vcs` directory
+    /// is used. Otherwise, `dir` and each of its parents are searched for a `.vcs` directory, like
+    /// `Repository::discover`.
+    pub fn locate<P: AsRef<Path>>(
+        dir: P,
+        vcs_dir: Option<PathBuf>,
+        work_tree: Option<PathBuf>,
+    ) -> Option<Repository> {
+        let dir = normalize_path(dir.as_ref());
+        match (vcs_dir, work_tree) {
+            (Some(vcs_dir), work_tree) => {
+                let vcs_dir = normalize_path(&dir.join(vcs_dir));
+                let root = match work_tree {
+                    Some(work_tree) => normalize_path(&dir.join(work_tree)),
+                    None => vcs_dir.parent()?.to_path_buf(),
+                };
+                if !directory_exists(&vcs_dir) {
+                    return None;
+                }
+                Some(Repository::new(root, vcs_dir))
+            }
+            (None, Some(work_tree)) => Repository::open(normalize_path(&dir.join(work_tree))),
+            (None, None) => Repository::discover(dir),
+        }
+    }
+
+    /// Finds the repository that `dir` is in by searching `dir` and each of its parents for a
+    /// `.vcs` directory. Returns `None` if none of them contain one.
+    pub fn discover<P: AsRef<Path>>(dir: P) -> Option<Repository> {
+        let dir = normalize_path(dir.as_ref());
+        dir.ancestors().find_map(Repository::open)
+    }
+
+    /// Creates the skeleton of an empty `.vcs` directory (`objects`, `branches` and `index`) in
+    /// `root`, and returns a handle to it. Does not create any commits or refs.
+    pub fn create<P: AsRef<Path>>(root: P) -> Result<Repository> {
+        let root = root.as_ref().to_path_buf();
+        let vcs_dir = root.join(".vcs");
+        create_dir(&vcs_dir)?;
+        create_dir(vcs_dir.join("objects"))?;
+        create_dir(vcs_dir.join("branches"))?;
+        File::create(vcs_dir.join("index"))?;
+        Ok(Repository::new(root, vcs_dir))
+    }
+
+    /// The root of the worktree
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The `.vcs` directory
+    pub fn vcs_dir(&self) -> &Path {
+        &self.vcs_dir
+    }
+
+    /// The path of `name` in the worktree, where `name` is relative to the worktree root
+    pub fn worktree_path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Converts `pathspec`, a path given relative to `cwd`, into the name of the file relative to
+    /// the worktree root, with `/` separators. Returns `None` if the path is outside the worktree.
+    pub fn relative_name(&self, cwd: &Path, pathspec: &str) -> Option<String> {
+        let path = normalize_path(&cwd.join(pathspec));
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let mut parts: Vec<String> = vec![];
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_str()?.to_string()),
+                _ => return None,
+            }
+        }
+        Some(parts.join("/"))
+    }
+
+    /// The path of the object file with hash `hash`
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        self.vcs_dir
+            .join("objects")
+            .join(&hash[0..2])
+            .join(&hash[2..])
+    }
+
+    /// Writes an object of kind `kind` holding `body` into the objects directory, compressed and
+    /// with a `<kind> <size>` header. Returns the hash of the object.
+    ///
+    /// The object is written to a temporary file in the objects directory and renamed into place,
+    /// so an interrupted write never leaves a partial object behind. An object that already
+    /// exists is not rewritten, but its modification time is brought up to now, so `gc` treats it
+    /// as just written and doesn't delete it before whatever is about to use it points at it.
+    pub fn write_object<B: AsRef<[u8]>>(&self, kind: ObjectKind, body: B) -> Result<String> {
+        let hash = hash_object(kind, body.as_ref());
+        let path = self.object_path(&hash);
+        if self.freshen_object(&hash)? {
+            return Ok(hash);
+        }
+
+        // Create parent directories if they do not exist
+        let parent_dir = path.parent().unwrap();
+        if !parent_dir.exists() {
+            create_dir_all(parent_dir)?;
+        }
+
+        write_atomically(
+            &self.vcs_dir.join("objects"),
+            path,
+            &encode_object(kind, body.as_ref())?,
+        )?;
+        Ok(hash)
+    }
+
+    /// Returns true iff a loose object with hash `hash` exists, bringing its modification time up
+    /// to now, so `gc` treats it as just written
+    pub fn freshen_object(&self, hash: &str) -> Result<bool> {
+        let path = self.object_path(hash);
+        if !file_exists(&path) {
+            return Ok(false);
+        }
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now())?;
+        Ok(true)
+    }
+
+    /// The directory holding packs of objects
+    pub fn pack_dir(&self) -> PathBuf {
+        self.vcs_dir.join("objects").join("pack")
+    }
+
+    /// Outputs the indexes of every pack in the repository, reading them the first time
+    pub fn packs(&self) -> Result<&[PackIndex]> {
+        if let Some(packs) = self.packs.get() {
+            return Ok(packs);
+        }
+        let packs = read_pack_indexes(self.pack_dir())?;
+        Ok(self.packs.get_or_init(|| packs))
+    }
+
+    /// Outputs the hashes of every loose object, that is every object not in a pack, sorted
+    pub fn loose_object_hashes(&self) -> Result<Vec<String>> {
+        let mut hashes = vec![];
+        for entry in read_dir(self.vcs_dir.join("objects"))? {
+            let path = entry?.path();
+            let prefix = no_dir_string(path.clone());
+            if !path.is_dir() || prefix.len() != 2 {
+                continue;
+            }
+            for object in read_dir(&path)? {
+                let object = object?.path();
+                if object.is_file() {
+                    hashes.push(prefix.clone() + &no_dir_string(object));
+                }
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Given a hash of the object, returns its kind and body, looking in loose objects first and
+    /// then in packs
+    ///
+    /// Returns an `ObjectNotFound` error if the hash does not exist in the objects dir, and a
+    /// `CorruptObject` error if the object can't be decoded
+    pub fn read_object(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>)> {
+        if hash.len() > 2 && file_exists(self.object_path(hash)) {
+            return decode_object(hash, &get_file_bytes(self.object_path(hash))?);
+        }
+        for pack in self.packs()? {
+            if let Some(object) = pack.read_object(hash)? {
+                return Ok(object);
+            }
+        }
+        Err(VcsError::ObjectNotFound(hash.to_string()))
+    }
+
+    /// Given a hash of the object, returns its body, checking that it is of kind `kind`
+    ///
+    /// Returns a `WrongObjectKind` error if the object is of another kind
+    pub fn read_object_of_kind(&self, hash: &str, kind: ObjectKind) -> Result<Vec<u8>> {
+        let (found, body) = self.read_object(hash)?;
+        if found != kind {
+            return Err(VcsError::WrongObjectKind {
+                hash: hash.to_string(),
+                expected: kind,
+                found,
+            });
+        }
+        Ok(body)
+    }
+
+    /// Given a hash of the object, returns its body as text, checking that it is of kind `kind`
+    ///
+    /// Returns a `CorruptObject` error if the body is not valid UTF-8
+    pub fn read_object_text(&self, hash: &str, kind: ObjectKind) -> Result<String> {
+        String::from_utf8(self.read_object_of_kind(hash, kind)?).map_err(|_| {
+            VcsError::CorruptObject {
+                hash: hash.to_string(),
+                reason: String::from("object is not valid UTF-8"),
+            }
+        })
+    }
+
+    /// Returns true iff a vcs object with the given hash exists, either loose or in a pack
+    pub fn object_exists(&self, hash: &str) -> bool {
+        if hash.len() > 2 && file_exists(self.object_path(hash)) {
+            return true;
+        }
+        self.packs()
+            .map(|packs| packs.iter().any(|pack| pack.contains(hash)))
+            .unwrap_or(false)
+    }
+
+    /// Outputs the contents of HEAD: the name of the current branch, or a commit hash if HEAD is
+    /// detached
+    pub fn get_branch_name(&self) -> Result<String> {
+        Ok(get_file_contents(self.vcs_dir.join("HEAD"))?)
+    }
+
+    /// Points HEAD at `branch_or_hash`
+    ///
+    /// Returns `RepositoryLocked` if another process is writing HEAD
+    pub fn set_head(&self, branch_or_hash: &str) -> Result<()> {
+        write_locked(self.vcs_dir.join("HEAD"), branch_or_hash.as_bytes())
+    }
+
+    /// The path of the ref file of the branch named `name`
+    fn branch_path(&self, name: &str) -> PathBuf {
+        self.vcs_dir.join("branches").join(name)
+    }
+
+    /// Returns true iff a branch named `name` exists
+    pub fn branch_exists(&self, name: &str) -> bool {
+        is_valid_branch_name(name) && file_exists(self.branch_path(name))
+    }
+
+    /// Returns the names of every branch, sorted alphabetically
+    pub fn list_branches(&self) -> Result<Vec<String>> {
+        let mut branches = vec![];
+        for entry in read_dir(self.vcs_dir.join("branches"))? {
+            let path = entry?.path();
+            if path.is_file() && !is_lock_file(&path) {
+                branches.push(no_dir_string(path));
+            }
+        }
+        branches.sort();
+        Ok(branches)
+    }
+
+    /// Returns the hash of the commit the branch named `name` points at
+    pub fn get_branch_commit(&self, name: &str) -> Result<String> {
+        if !self.branch_exists(name) {
+            return Err(VcsError::BranchNotFound(name.to_string()));
+        }
+        Ok(get_file_contents(self.branch_path(name))?)
+    }
+
+    /// Points the branch named `name` at `commit_hash`, creating the branch if needed
+    ///
+    /// Returns `InvalidBranchName` if `name` can't name a branch, and `RepositoryLocked` if
+    /// another process is writing the branch
+    pub fn set_branch_commit(&self, name: &str, commit_hash: &str) -> Result<()> {
+        self.update_branch(name, |_| Ok(commit_hash.to_string()))
+    }
+
+    /// Takes the lock on the branch named `name`, then points it at the commit `update` outputs
+    /// given the commit it points at now, or `None` if it doesn't exist yet. The branch is read
+    /// and written under the same lock, so no other process can move it in between. Nothing is
+    /// written if `update` fails.
+    ///
+    /// Returns `InvalidBranchName` if `name` can't name a branch, `RepositoryLocked` if another
+    /// process is writing the branch, and the errors of `update`
+    pub fn update_branch(
+        &self,
+        name: &str,
+        update: impl FnOnce(Option<String>) -> Result<String>,
+    ) -> Result<()> {
+        if !is_valid_branch_name(name) {
+            return Err(VcsError::InvalidBranchName(name.to_string()));
+        }
+        let mut lock = LockFile::acquire(self.branch_path(name))?;
+        let current = match self.branch_exists(name) {
+            true => Some(get_file_contents(self.branch_path(name))?),
+            false => None,
+        };
+        lock.write(update(current)?.as_bytes())?;
+        lock.commit()
+    }
+
+    /// Deletes the branch named `name`
+    ///
+    /// Returns `RepositoryLocked` if another process is writing the branch
+    pub fn delete_branch(&self, name: &str) -> Result<()> {
+        if !self.branch_exists(name) {
+            return Err(VcsError::BranchNotFound(name.to_string()));
+        }
+        LockFile::acquire(self.branch_path(name))?.delete()
+    }
+
+    /// Returns true iff HEAD does not point at a branch
+    pub fn is_detached(&self) -> Result<bool> {
+        Ok(!self.branch_exists(&self.get_branch_name()?))
+    }
+
+    /// Returns the hash of the current head commit
+    pub fn get_head_commit(&self) -> Result<String> {
+        let head_branch_or_hash = self.get_branch_name()?;
+        if self.branch_exists(&head_branch_or_hash) {
+            self.get_branch_commit(&head_branch_or_hash)
+        } else {
+            Ok(head_branch_or_hash)
+        }
+    }
+
+    /// Returns the commit being merged in by a merge that stopped for conflicts, or `None` if no
+    /// merge is in progress
+    pub fn merge_head(&self) -> Result<Option<String>> {
+        let path = self.vcs_dir.join("MERGE_HEAD");
+        if !file_exists(&path) {
+            return Ok(None);
+        }
+        Ok(Some(get_file_contents(path)?))
+    }
+
+    /// Returns the message for the commit that finishes the merge in progress
+    pub fn merge_message(&self) -> Result<String> {
+        Ok(get_file_contents(self.vcs_dir.join("MERGE_MSG"))?)
+    }
+
+    /// Records that a merge of `commit_hash` has stopped for conflicts, and will be committed with
+    /// `message`
+    pub fn start_merge(&self, commit_hash: &str, message: &str) -> Result<()> {
+        write_locked(self.vcs_dir.join("MERGE_MSG"), message.as_bytes())?;
+        write_locked(self.vcs_dir.join("MERGE_HEAD"), commit_hash.as_bytes())
+    }
+
+    /// Forgets the merge in progress, if there is one
+    pub fn end_merge(&self) -> Result<()> {
+        for name in ["MERGE_HEAD", "MERGE_MSG"] {
+            let path = self.vcs_dir.join(name);
+            if file_exists(&path) {
+                remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the whole index: the staged changes and the stat cache
+    ///
+    /// Returns `CorruptIndex` or `IndexParse` if the index can't be parsed
+    pub fn load_index(&self) -> Result<Index> {
+        let path = self.vcs_dir.join("index");
+        let written = metadata(&path)?.modified().ok();
+        parse_index_file(
+            &get_file_bytes(&path)?,
+            written.map(FileTime::from_system_time),
+        )
+    }
+
+    /// Returns the entries currently staged in the index
+    pub fn read_index(&self) -> Result<Vec<IndexEntry>> {
+        Ok(self.load_index()?.entries)
+    }
+
+    /// Takes the lock on the index, applies `update` to it, and writes it back. Nothing is written
+    /// if `update` fails.
+    ///
+    /// Returns `RepositoryLocked` if another process is writing the index, and the errors of
+    /// `update`
+    pub fn update_index(&self, update: impl FnOnce(&mut Index) -> Result<()>) -> Result<()> {
+        let mut lock = LockFile::acquire(self.vcs_dir.join("index"))?;
+        let mut index = self.load_index()?;
+        update(&mut index)?;
+        lock.write(&index_file_bytes(&index))?;
+        lock.commit()
+    }
+
+    /// Replaces the staged changes in the index with `entries`, keeping its stat cache
+    ///
+    /// Returns `RepositoryLocked` if another process is writing the index
+    pub fn write_index(&self, entries: &[IndexEntry]) -> Result<()> {
+        self.update_index(|index| {
+            index.entries = entries.to_vec();
+            Ok(())
+        })
+    }
+
+    /// Replaces the stat cache in the index with `stat_cache`, keeping its staged changes. Does
+    /// nothing if the cache hasn't changed since it was read.
+    ///
+    /// Returns `RepositoryLocked` if another process is writing the index
+    pub fn write_stat_cache(&self, stat_cache: &StatCache) -> Result<()> {
+        if !stat_cache.is_changed() {
+            return Ok(());
+        }
+        self.update_index(|index| {
+            index.stat_cache = stat_cache.clone();
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /*
+     * tests that an object is created at the correct place, and made new again if it is
+     * written again
+     *
+     * tests that read_object and read_object_text return the correct contents if the object
+     * exists, and that they error when it doesn't
+     *
+     * tests that two repositories can be open at once without interfering
+     */
+
+    use std::time::Duration;
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    #[test]
+    fn open_requires_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(Repository::open(test_dir.path()).is_none());
+        Repository::create(test_dir.path())?;
+        let repo = Repository::open(test_dir.path()).unwrap();
+        assert_eq!(test_dir.path(), repo.root());
+        assert_eq!(test_dir.path().join(".vcs"), repo.vcs_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn discover_from_subdirectory() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        Repository::create(test_dir.path())?;
+        let nested = test_dir.path().join("a/b");
+        create_dir_all(&nested)?;
+        let repo = Repository::discover(&nested).unwrap();
+        assert_eq!(test_dir.path(), repo.root());
+        assert_eq!(
+            Some(String::from("a/b/c.txt")),
+            repo.relative_name(&nested, "c.txt")
+        );
+        assert_eq!(
+            Some(String::from("a/d.txt")),
+            repo.relative_name(&nested, "../d.txt")
+        );
+        assert_eq!(
+            Some(String::from("e.txt")),
+            repo.relative_name(&nested, "./../../e.txt")
+        );
+        assert_eq!(None, repo.relative_name(&nested, "../../../f.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn discover_outside_repository() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(Repository::discover(test_dir.path()).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn locate_with_overrides() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let work_tree = test_dir.path().join("work");
+        let elsewhere = test_dir.path().join("elsewhere");
+        create_dir_all(&work_tree)?;
+        create_dir_all(&elsewhere)?;
+        Repository::create(&work_tree)?;
+
+        // VCS_WORK_TREE only
+        let repo = Repository::locate(&elsewhere, None, Some(PathBuf::from("../work"))).unwrap();
+        assert_eq!(work_tree, repo.root());
+
+        // VCS_DIR only: the worktree is its parent
+        let repo = Repository::locate(&elsewhere, Some(work_tree.join(".vcs")), None).unwrap();
+        assert_eq!(work_tree, repo.root());
+        assert_eq!(work_tree.join(".vcs"), repo.vcs_dir());
+
+        // Both: the worktree can be anywhere
+        let repo = Repository::locate(
+            test_dir.path(),
+            Some(PathBuf::from("work/.vcs")),
+            Some(PathBuf::from("elsewhere")),
+        )
+        .unwrap();
+        assert_eq!(elsewhere, repo.root());
+        assert_eq!(work_tree.join(".vcs"), repo.vcs_dir());
+
+        // Overrides that point nowhere
+        assert!(Repository::locate(&work_tree, Some(PathBuf::from("nope")), None).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_object() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let text = "test text";
+        let hash = repo.write_object(ObjectKind::Blob, text)?;
+        assert_eq!(hash_object(ObjectKind::Blob, text.as_bytes()), hash);
+
+        // tests that write_object has the correct side effects
+        let filename = test_dir
+            .path()
+            .join(".vcs/objects")
+            .join(&hash[0..2])
+            .join(&hash[2..]);
+        assert!(file_exists(&filename));
+        let contents = get_file_bytes(&filename)?;
+        assert_eq!(encode_object(ObjectKind::Blob, text.as_bytes())?, contents);
+
+        // test that read_object gets the right contents
+        assert!(repo.object_exists(&hash));
+        assert_eq!(
+            (ObjectKind::Blob, text.as_bytes().to_vec()),
+            repo.read_object(&hash)?
+        );
+        assert_eq!(text, repo.read_object_text(&hash, ObjectKind::Blob)?);
+
+        // tests that writing it again makes it new, so gc keeps it
+        let long_ago = SystemTime::now() - Duration::from_secs(365 * 24 * 60 * 60);
+        File::options()
+            .write(true)
+            .open(&filename)?
+            .set_modified(long_ago)?;
+        assert_eq!(hash, repo.write_object(ObjectKind::Blob, text)?);
+        assert!(metadata(&filename)?.modified()? > long_ago);
+        assert_eq!(
+            encode_object(ObjectKind::Blob, text.as_bytes())?,
+            get_file_bytes(&filename)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_legacy_objects() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let path = repo.object_path("1234567890");
+        create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, "blob\nold text")?;
+        assert_eq!(
+            "old text",
+            repo.read_object_text("1234567890", ObjectKind::Blob)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors_correctly() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let hash = "1234567890";
+        assert!(matches!(
+            repo.read_object(hash),
+            Err(VcsError::ObjectNotFound(missing)) if missing == hash
+        ));
+        let hash = repo.write_object(ObjectKind::Blob, "one line")?;
+        assert!(matches!(
+            repo.read_object_of_kind(&hash, ObjectKind::Commit),
+            Err(VcsError::WrongObjectKind {
+                expected: ObjectKind::Commit,
+                found: ObjectKind::Blob,
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn independent_repositories() -> Result<()> {
+        let first_dir = make_test_dir()?;
+        let second_dir = make_test_dir()?;
+        let first = Repository::create(first_dir.path())?;
+        let second = Repository::create(second_dir.path())?;
+        first.set_branch_commit("main", "abc")?;
+        first.set_head("main")?;
+        second.set_branch_commit("main", "def")?;
+        second.set_head("main")?;
+        assert_eq!("abc", first.get_head_commit()?);
+        assert_eq!("def", second.get_head_commit()?);
+        let hash = first.write_object(ObjectKind::Blob, "only in first")?;
+        assert!(!second.object_exists(&hash));
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::HashSet;
+
+use crate::error::{Result, VcsError};
+
+use super::{
+    commit::{get_commit_parents, get_commit_tree},
+    merge::{ancestors, merge_base},
+    mode::FileMode,
+    object::ObjectKind,
+    reflog::{branch_ref, previous_checkout, read_reflog, NULL_HASH},
+    repo::Repository,
+    tree::{find_entry_in_tree, read_tree_entries},
+};
+
+/// The fewest hex digits an abbreviated hash can have
+pub const MIN_ABBREVIATION: usize = 4;
+
+/// A set of commits named on the command line, as `log` and `diff` take them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevisionRange {
+    /// A single commit, and for `log` everything it descends from
+    Commit(String),
+    /// `A..B`: the commits `B` descends from that `A` doesn't
+    Between(String, String),
+    /// `A...B`: the commits either descends from, but not both
+    Symmetric(String, String),
+}
+
+impl RevisionRange {
+    /// The commits the range starts from
+    pub fn tips(&self) -> Vec<String> {
+        match self {
+            RevisionRange::Commit(commit) | RevisionRange::Between(_, commit) => {
+                vec![commit.clone()]
+            }
+            RevisionRange::Symmetric(a, b) => vec![a.clone(), b.clone()],
+        }
+    }
+
+    /// The commits the tips descend from that are left out of the range
+    pub fn excluded(&self, repo: &Repository) -> Result<HashSet<String>> {
+        match self {
+            RevisionRange::Commit(_) => Ok(HashSet::new()),
+            RevisionRange::Between(a, _) => ancestors(repo, a),
+            RevisionRange::Symmetric(a, b) => {
+                let b_ancestors = ancestors(repo, b)?;
+                Ok(ancestors(repo, a)?
+                    .into_iter()
+                    .filter(|commit| b_ancestors.contains(commit))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Parses `spec` as a single revision, `A..B` or `A...B`, and resolves each side to a commit
+/// hash with `resolve_commit`. A side left empty is `HEAD`.
+///
+/// Returns the errors of `resolve_commit` for either side
+pub fn resolve_range(repo: &Repository, spec: &str) -> Result<RevisionRange> {
+    let side = |rev: &str| match rev {
+        "" => resolve_commit(repo, "HEAD"),
+        rev => resolve_commit(repo, rev),
+    };
+    if let Some((a, b)) = spec.split_once("...") {
+        Ok(RevisionRange::Symmetric(side(a)?, side(b)?))
+    } else if let Some((a, b)) = spec.split_once("..") {
+        Ok(RevisionRange::Between(side(a)?, side(b)?))
+    } else {
+        Ok(RevisionRange::Commit(resolve_commit(repo, spec)?))
+    }
+}
+
+/// Resolves the range `spec` to the two commits to compare, as `diff` does: `A..B` compares `A`
+/// with `B`, and `A...B` compares the best common ancestor of `A` and `B` with `B`. Outputs
+/// `None` if `spec` is a single revision.
+///
+/// Returns the errors of `resolve_range`, and `NoCommonAncestor` if `A...B` has no common
+/// ancestor
+pub fn resolve_diff_range(repo: &Repository, spec: &str) -> Result<Option<(String, String)>> {
+    match resolve_range(repo, spec)? {
+        RevisionRange::Commit(_) => Ok(None),
+        RevisionRange::Between(a, b) => Ok(Some((a, b))),
+        RevisionRange::Symmetric(a, b) => {
+            let base = merge_base(repo, &a, &b)?.ok_or(VcsError::NoCommonAncestor(a))?;
+            Ok(Some((base, b)))
+        }
+    }
+}
+
+/// Outputs the name of the branch `rev` names, or `None` if it names a commit another way.
+/// `rev` names a branch if it is the branch's name, or `@{-<n>}` while the branch checked out
+/// before the `n`th most recent checkout still exists.
+pub fn branch_name(repo: &Repository, rev: &str) -> Result<Option<String>> {
+    let name = match reflog_selector(rev) {
+        Some(("", n)) if n < 0 => match previous_checkout(repo, n.unsigned_abs() as usize)? {
+            Some(previous) => previous,
+            None => return Ok(None),
+        },
+        _ => rev.to_string(),
+    };
+    Ok(repo.branch_exists(&name).then_some(name))
+}
+
+/// Resolves `rev` to the hash of a commit, as `resolve_revision` does
+///
+/// Returns a `WrongObjectKind` error if `rev` names another kind of object, and the errors of
+/// `resolve_revision`
+pub fn resolve_commit(repo: &Repository, rev: &str) -> Result<String> {
+    let hash = resolve_revision(repo, rev)?;
+    let (found, _) = repo.read_object(&hash)?;
+    if found != ObjectKind::Commit {
+        return Err(VcsError::WrongObjectKind {
+            hash,
+            expected: ObjectKind::Commit,
+            found,
+        });
+    }
+    Ok(hash)
+}
+
+/// Resolves the revision `rev` to the hash of the object it names
+///
+/// A revision starts with one of:
+///     - `HEAD` or `@`, for the head commit
+///     - `@{-<n>}`, for the branch or commit checked out before the `n`th most recent checkout,
+///       so `@{-1}` is the one checked out before the current one
+///     - `<ref>@{<n>}`, for the commit `ref` pointed at `n` moves ago, as its reflog records,
+///       where `ref` is `HEAD` or a branch, and defaults to the current branch
+///     - a branch name, for the commit at its head
+///     - a hash, or an abbreviation of one at least `MIN_ABBREVIATION` digits long
+/// followed by any number of `~<n>` (the `n`th first-parent ancestor) and `^<n>` (the `n`th
+/// parent, where `^0` is the commit itself) suffixes, with `n` defaulting to 1. `<rev>:<path>`
+/// names the file or directory at `path` in the tree of the commit `rev`.
+///
+/// Returns `InvalidRef` if `rev` names nothing, including a parent or path that doesn't exist,
+/// and `AmbiguousRevision` if an abbreviated hash matches several objects
+pub fn resolve_revision(repo: &Repository, rev: &str) -> Result<String> {
+    let invalid = || VcsError::InvalidRef(rev.to_string());
+    if let Some((commit_rev, path)) = rev.split_once(':') {
+        let commit = resolve_commit(repo, commit_rev)?;
+        return find_path(repo, &get_commit_tree(repo, &commit)?, path)?.ok_or_else(invalid);
+    }
+
+    let suffix_start = rev.find(['~', '^']).unwrap_or(rev.len());
+    let (base, mut suffixes) = rev.split_at(suffix_start);
+    let mut hash = resolve_base(repo, base)?.ok_or_else(invalid)?;
+    while let Some(operator) = suffixes.chars().next() {
+        // The operator is only checked below, so it may be a character of several bytes
+        let digits_start = operator.len_utf8();
+        let digits_end = suffixes[digits_start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(suffixes.len(), |end| end + digits_start);
+        let count: usize = match &suffixes[digits_start..digits_end] {
+            "" => 1,
+            digits => digits.parse().map_err(|_| invalid())?,
+        };
+        suffixes = &suffixes[digits_end..];
+        match operator {
+            '~' => {
+                for _ in 0..count {
+                    hash = get_commit_parents(repo, &hash)?
+                        .into_iter()
+                        .next()
+                        .ok_or_else(invalid)?;
+                }
+            }
+            '^' if count > 0 => {
+                hash = get_commit_parents(repo, &hash)?
+                    .into_iter()
+                    .nth(count - 1)
+                    .ok_or_else(invalid)?;
+            }
+            '^' => {
+                get_commit_parents(repo, &hash)?;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(hash)
+}
+
+/// Resolves a revision without suffixes, or outputs `None` if it names nothing
+fn resolve_base(repo: &Repository, base: &str) -> Result<Option<String>> {
+    if base == "HEAD" || base == "@" {
+        return Ok(Some(repo.get_head_commit()?));
+    }
+    match reflog_selector(base) {
+        Some(("", n)) if n < 0 => {
+            return match previous_checkout(repo, n.unsigned_abs() as usize)? {
+                Some(previous) => resolve_base(repo, &previous),
+                None => Ok(None),
+            }
+        }
+        Some((_, n)) if n < 0 => return Ok(None),
+        Some((name, n)) => {
+            let reference = match name {
+                "" if !repo.is_detached()? => branch_ref(&repo.get_branch_name()?),
+                "" | "HEAD" => String::from("HEAD"),
+                name => branch_ref(name),
+            };
+            // Going back one past the oldest entry reaches where the ref started
+            let entries = read_reflog(repo, &reference)?;
+            let n = n as usize;
+            let hash = match entries.len() {
+                len if n < len => Some(entries[len - 1 - n].new.clone()),
+                len if n == len && len > 0 => Some(entries[0].old.clone()),
+                _ => None,
+            };
+            return Ok(hash.filter(|hash| hash != NULL_HASH));
+        }
+        None => {}
+    }
+    if repo.branch_exists(base) {
+        return Ok(Some(repo.get_branch_commit(base)?));
+    }
+    if base.len() < MIN_ABBREVIATION || !base.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let prefix = base.to_lowercase();
+    if repo.object_exists(&prefix) {
+        return Ok(Some(prefix));
+    }
+    let mut matches: Vec<String> = repo
+        .loose_object_hashes()?
+        .into_iter()
+        .filter(|hash| hash.starts_with(&prefix))
+        .collect();
+    for pack in repo.packs()? {
+        matches.extend(
+            pack.hashes()
+                .into_iter()
+                .filter(|hash| hash.starts_with(&prefix)),
+        );
+    }
+    matches.sort();
+    matches.dedup();
+    match matches.len() {
+        0 => Ok(None),
+        1 => Ok(matches.pop()),
+        _ => Err(VcsError::AmbiguousRevision {
+            prefix: base.to_string(),
+            matches,
+        }),
+    }
+}
+
+/// Splits a revision of the form `<ref>@{<n>}` into `ref` and `n`, or outputs `None` if `base`
+/// isn't one
+fn reflog_selector(base: &str) -> Option<(&str, i64)> {
+    let (name, selector) = base.strip_suffix('}')?.rsplit_once("@{")?;
+    Some((name, selector.parse().ok()?))
+}
+
+/// Outputs the hash of the file or directory at `path` in the tree `tree_hash`, or `None` if
+/// there is nothing there. An empty path is the tree itself.
+fn find_path(repo: &Repository, tree_hash: &str, path: &str) -> Result<Option<String>> {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return Ok(Some(tree_hash.to_string()));
+    }
+    if let Some(entry) = find_entry_in_tree(repo, tree_hash, path)? {
+        return Ok(Some(entry.hash));
+    }
+    let mut current = tree_hash.to_string();
+    for name in path.split('/') {
+        match read_tree_entries(repo, &current)?
+            .into_iter()
+            .find(|entry| entry.mode == FileMode::Directory && entry.name == name)
+        {
+            Some(entry) => current = entry.hash,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(current))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for resolve_revision
+    //      base: HEAD, @, @{-n}, <ref>@{n}, branch, full hash, unique abbreviation, ambiguous abbreviation,
+    //          too short, unknown
+    //      suffixes: none, ~, ~n, ^, ^n on a merge, ^0, chained, past the root commit, not ~ or ^
+    //      path: file, nested file, directory, missing
+    // Partitions for resolve_range
+    //      form: single, A..B, A...B, empty side
+
+    use super::*;
+    use crate::{
+        objects::{
+            blob::read_blob,
+            commit::write_commit,
+            reflog::{move_branch, switch_head},
+            tree::write_tree,
+        },
+        utils::test_dir::make_test_dir,
+    };
+
+    /// Sets up a repository in `dir` with a merge on `main`, and outputs it along with the root
+    /// commit, the commits on either side of the merge, and the merge
+    fn history(dir: &std::path::Path) -> Result<(Repository, [String; 4])> {
+        let repo = Repository::create(dir)?;
+        let blob = repo.write_object(ObjectKind::Blob, "lib")?;
+        let subtree = write_tree(&repo, &vec![], &vec![format!("100644 lib.rs: {}", blob)])?;
+        let tree = write_tree(&repo, &vec![format!("040000 src: {}", subtree)], &vec![])?;
+        let root = write_commit(&repo, "root", &[], 0, &tree, None, None)?;
+        let ours = write_commit(&repo, "ours", &[&root], 1, &tree, None, None)?;
+        let theirs = write_commit(&repo, "theirs", &[&root], 2, &tree, None, None)?;
+        let merge = write_commit(&repo, "merge", &[&ours, &theirs], 3, &tree, None, None)?;
+        repo.set_branch_commit("main", &merge)?;
+        repo.set_branch_commit("side", &theirs)?;
+        repo.set_head("main")?;
+        Ok((repo, [root, ours, theirs, merge]))
+    }
+
+    #[test]
+    fn names_and_suffixes() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let (repo, [root, ours, theirs, merge]) = history(test_dir.path())?;
+        for (rev, expected) in [
+            ("HEAD", &merge),
+            ("@", &merge),
+            ("main", &merge),
+            ("side", &theirs),
+            (merge.as_str(), &merge),
+            (&merge[..10], &merge),
+            ("HEAD~", &ours),
+            ("HEAD~2", &root),
+            ("HEAD^", &ours),
+            ("HEAD^2", &theirs),
+            ("main^2~1", &root),
+            ("HEAD^0", &merge),
+            ("side~0", &theirs),
+        ] {
+            assert_eq!(*expected, resolve_revision(&repo, rev)?, "{}", rev);
+        }
+        for rev in [
+            "HEAD~3", "HEAD^3", "nope", "abc", "HEAD~x", "HEAD@", "HEAD~é", "HEAD^é", "HEAD~1é",
+        ] {
+            assert!(matches!(
+                resolve_revision(&repo, rev),
+                Err(VcsError::InvalidRef(name)) if name == rev
+            ));
+        }
+
+        // @{-1} follows the branch checked out before, to wherever it is now
+        assert!(matches!(
+            resolve_revision(&repo, "@{-1}"),
+            Err(VcsError::InvalidRef(..))
+        ));
+        switch_head(&repo, "side")?;
+        switch_head(&repo, "main")?;
+        assert_eq!(root, resolve_revision(&repo, "@{-1}~")?);
+        assert_eq!(merge, resolve_revision(&repo, "@{-2}")?);
+        assert_eq!(Some(String::from("side")), branch_name(&repo, "@{-1}")?);
+        assert_eq!(None, branch_name(&repo, &theirs)?);
+
+        // @{n} goes back through the reflog of HEAD or a branch, the current one by default
+        assert_eq!(theirs, resolve_revision(&repo, "HEAD@{1}")?);
+        move_branch(&repo, "main", &ours, "reset: moving to HEAD~")?;
+        for (rev, expected) in [
+            ("@{0}", &ours),
+            ("main@{0}", &ours),
+            ("HEAD@{0}", &ours),
+            ("HEAD@{1}", &merge),
+            ("HEAD@{1}^2", &theirs),
+        ] {
+            assert_eq!(*expected, resolve_revision(&repo, rev)?, "{}", rev);
+        }
+        assert_eq!(merge, resolve_revision(&repo, "main@{1}")?);
+        for rev in ["main@{2}", "side@{0}", "@{-3}", "main@{-1}", "@{x}"] {
+            assert!(matches!(
+                resolve_revision(&repo, rev),
+                Err(VcsError::InvalidRef(name)) if name == rev
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn abbreviations() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let (repo, [root, ..]) = history(test_dir.path())?;
+        // Write objects until two share a prefix
+        let mut seen: std::collections::HashMap<String, String> = Default::default();
+        let mut counter = 0;
+        let (prefix, first, second) = loop {
+            let hash = repo.write_object(ObjectKind::Blob, format!("{}", counter))?;
+            counter += 1;
+            if let Some(other) = seen.insert(hash[..MIN_ABBREVIATION].to_string(), hash.clone()) {
+                break (hash[..MIN_ABBREVIATION].to_string(), other, hash);
+            }
+        };
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert!(matches!(
+            resolve_revision(&repo, &prefix),
+            Err(VcsError::AmbiguousRevision { matches, .. }) if matches == expected
+        ));
+        assert_eq!(root, resolve_revision(&repo, &root[..12].to_uppercase())?);
+        assert!(matches!(
+            resolve_commit(&repo, &expected[0]),
+            Err(VcsError::WrongObjectKind { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn paths_and_ranges() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let (repo, [root, ours, theirs, merge]) = history(test_dir.path())?;
+        assert_eq!(
+            b"lib".to_vec(),
+            read_blob(&repo, &resolve_revision(&repo, "HEAD:src/lib.rs")?)?
+        );
+        let subtree = resolve_revision(&repo, "HEAD~1:src")?;
+        assert_eq!(1, read_tree_entries(&repo, &subtree)?.len());
+        assert_eq!(
+            get_commit_tree(&repo, &root)?,
+            resolve_revision(&repo, "HEAD:")?
+        );
+        assert!(matches!(
+            resolve_revision(&repo, "HEAD:missing.rs"),
+            Err(VcsError::InvalidRef(..))
+        ));
+
+        assert_eq!(
+            RevisionRange::Between(ours.clone(), merge.clone()),
+            resolve_range(&repo, "HEAD~..")?
+        );
+        assert_eq!(
+            RevisionRange::Symmetric(ours.clone(), theirs.clone()),
+            resolve_range(&repo, "main^1...side")?
+        );
+        assert_eq!(
+            RevisionRange::Commit(root.clone()),
+            resolve_range(&repo, "HEAD~2")?
+        );
+        assert_eq!(
+            HashSet::from([root.clone()]),
+            resolve_range(&repo, "main^1...side")?.excluded(&repo)?
+        );
+        assert_eq!(
+            Some((root, theirs)),
+            resolve_diff_range(&repo, "main^1...side")?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use std::{fmt, path::Path};
+
+use chrono::{DateTime, FixedOffset, Local};
+
+use crate::error::{Result, VcsError};
+
+use super::{config::Config, repo::Repository};
+
+/// Who authored or committed a change, and when, in their own timezone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the Unix epoch
+    pub time: i64,
+    /// Minutes east of UTC
+    pub offset: i32,
+}
+
+impl Signature {
+    /// Parses a signature written as `<name> <<email>> <time> <+hhmm>`, the way it is stored in
+    /// commits, or outputs `None` if `text` isn't one
+    pub fn parse(text: &str) -> Option<Signature> {
+        let (rest, offset) = text.rsplit_once(' ')?;
+        let (person, time) = rest.rsplit_once(' ')?;
+        let (name, email) = parse_person(person)?;
+        Some(Signature {
+            name,
+            email,
+            time: time.parse().ok()?,
+            offset: parse_offset(offset)?,
+        })
+    }
+
+    /// The name and email of the signer, as `<name> <<email>>`
+    pub fn person(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+
+    /// The time of the signature in the signer's timezone, e.g. `Tue Mar 05 14:03:09 2024 +0100`,
+    /// or `None` if it is out of range
+    pub fn date(&self) -> Option<String> {
+        let offset = FixedOffset::east_opt(self.offset * 60)?;
+        let date = DateTime::from_timestamp(self.time, 0)?.with_timezone(&offset);
+        Some(date.format("%a %b %d %H:%M:%S %Y %z").to_string())
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.person(),
+            self.time,
+            format_offset(self.offset)
+        )
+    }
+}
+
+/// Parses `<name> <<email>>` into the name and the email, or outputs `None` if `text` isn't of
+/// that form. The name can't be empty, and neither can contain `<`, `>` or a line break.
+pub fn parse_person(text: &str) -> Option<(String, String)> {
+    let (name, email) = text.strip_suffix('>')?.rsplit_once(" <")?;
+    let name = name.trim();
+    if name.is_empty() || !is_valid_part(name) || !is_valid_part(email) {
+        return None;
+    }
+    Some((name.to_string(), email.to_string()))
+}
+
+/// Returns true iff `part` can be a name or email in a signature
+fn is_valid_part(part: &str) -> bool {
+    !part.contains(['<', '>', '\n', '\r'])
+}
+
+/// Writes `offset`, in minutes east of UTC, as `+hhmm` or `-hhmm`
+fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs();
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Parses a `+hhmm` or `-hhmm` timezone offset into minutes east of UTC
+fn parse_offset(text: &str) -> Option<i32> {
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = &text[1..];
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    if minutes >= 60 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Parses a date given in a `VCS_*_DATE` variable: either `<seconds since the epoch> <+hhmm>`,
+/// as in commits, or RFC 3339, e.g. `2024-03-05T14:03:09+01:00`. Outputs the time and offset.
+fn parse_date(text: &str) -> Option<(i64, i32)> {
+    if let Some((time, offset)) = text.trim().split_once(' ') {
+        return Some((time.parse().ok()?, parse_offset(offset)?));
+    }
+    let date = DateTime::parse_from_rfc3339(text.trim()).ok()?;
+    Some((date.timestamp(), date.offset().local_minus_utc() / 60))
+}
+
+/// Works out the author and committer of a new commit in `repo`, made now
+///
+/// The author's name and email come from `author`, given as `<name> <<email>>` by `--author`, if
+/// it is `Some`; otherwise, like the committer's, from the `VCS_AUTHOR_NAME`/`VCS_AUTHOR_EMAIL`
+/// (or `VCS_COMMITTER_NAME`/`VCS_COMMITTER_EMAIL`) variables, then from `user.name` and
+/// `user.email` in the config, and failing all of those from the login name, with the email
+/// `<login>@localhost`. `VCS_AUTHOR_DATE` and `VCS_COMMITTER_DATE` override the current time.
+/// `env` looks up an environment variable; `HOME` in it locates the user's own config file.
+///
+/// Returns `InvalidIdentity` if `author` or a configured name or email is malformed,
+/// `InvalidOptionValue` if a date variable can't be parsed, and `ConfigParse` if a config file
+/// can't be parsed
+pub fn new_signatures(
+    repo: &Repository,
+    author: Option<&str>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<(Signature, Signature)> {
+    let home = env("HOME").or_else(|| env("USERPROFILE"));
+    let config = Config::load(repo, home.as_deref().map(Path::new))?;
+    let now = Local::now();
+    let now = (now.timestamp(), now.offset().local_minus_utc() / 60);
+    let login = env("USER")
+        .or_else(|| env("USERNAME"))
+        .unwrap_or_else(|| String::from("unknown"));
+
+    let sign = |role: &str, person: Option<(String, String)>| -> Result<Signature> {
+        let (name, email) = match person {
+            Some(person) => person,
+            None => {
+                let name = env(&format!("VCS_{}_NAME", role))
+                    .or_else(|| config.get("user.name").map(str::to_string))
+                    .unwrap_or_else(|| login.clone());
+                let email = env(&format!("VCS_{}_EMAIL", role))
+                    .or_else(|| config.get("user.email").map(str::to_string))
+                    .unwrap_or_else(|| format!("{}@localhost", login));
+                let person = format!("{} <{}>", name, email);
+                parse_person(&person).ok_or(VcsError::InvalidIdentity(person))?
+            }
+        };
+        let date_variable = format!("VCS_{}_DATE", role);
+        let (time, offset) = match env(&date_variable) {
+            Some(date) => parse_date(&date).ok_or(VcsError::InvalidOptionValue {
+                option: date_variable,
+                value: date,
+            })?,
+            None => now,
+        };
+        Ok(Signature {
+            name,
+            email,
+            time,
+            offset,
+        })
+    };
+
+    let author_person = match author {
+        Some(author) => Some(
+            parse_person(author).ok_or_else(|| VcsError::InvalidIdentity(author.to_string()))?,
+        ),
+        None => None,
+    };
+    Ok((sign("AUTHOR", author_person)?, sign("COMMITTER", None)?))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for Signature::parse and Display
+    //      offset: zero, positive, negative, with minutes; malformed parts
+    // Partitions for new_signatures
+    //      source: --author, environment, config, login name
+    //      date: now, seconds and offset, RFC 3339, malformed
+    //      identity: valid, malformed
+
+    use std::{collections::HashMap, fs::write};
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    #[test]
+    fn round_trips() {
+        for (text, offset) in [
+            ("Jane Doe <jane@example.com> 0 +0000", 0),
+            ("Jane Doe <jane@example.com> 1709643789 +0100", 60),
+            ("J <> 1709643789 -0930", -570),
+        ] {
+            let signature = Signature::parse(text).unwrap();
+            assert_eq!(offset, signature.offset);
+            assert_eq!(text, signature.to_string());
+        }
+        let signature = Signature::parse("Jane Doe <jane@example.com> 1709643789 +0100").unwrap();
+        assert_eq!("Jane Doe <jane@example.com>", signature.person());
+        assert_eq!(
+            Some(String::from("Tue Mar 05 14:03:09 2024 +0100")),
+            signature.date()
+        );
+        for bad in [
+            "Jane Doe 0 +0000",
+            "<jane@example.com> 0 +0000",
+            "Jane Doe <jane@example.com> noon +0000",
+            "Jane Doe <jane@example.com> 0 0100",
+            "Jane Doe <jane@example.com> 0 +0160",
+            "Jane Doe <jane@example.com>",
+        ] {
+            assert_eq!(None, Signature::parse(bad));
+        }
+    }
+
+    /// Outputs a lookup function for the environment variables in `variables`
+    fn env<'a>(variables: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        let variables: HashMap<&str, &str> = variables.iter().cloned().collect();
+        move |name| variables.get(name).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn sources_of_identity() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+
+        // Nothing configured falls back to the login name
+        let (author, committer) = new_signatures(&repo, None, env(&[("USER", "jane")]))?;
+        assert_eq!("jane <jane@localhost>", author.person());
+        assert_eq!(author, committer);
+
+        // Config, then environment, then --author for the author only
+        write(
+            repo.vcs_dir().join("config"),
+            "[user]\n\tname = Jane Doe\n\temail = jane@example.com\n",
+        )?;
+        let (author, committer) = new_signatures(&repo, None, env(&[]))?;
+        assert_eq!("Jane Doe <jane@example.com>", author.person());
+        assert_eq!("Jane Doe <jane@example.com>", committer.person());
+        let variables = [
+            ("VCS_AUTHOR_NAME", "Ann Author"),
+            ("VCS_AUTHOR_DATE", "1709643789 +0100"),
+            ("VCS_COMMITTER_EMAIL", "ci@example.com"),
+            ("VCS_COMMITTER_DATE", "2024-03-05T14:03:09-05:00"),
+        ];
+        let (author, committer) = new_signatures(&repo, None, env(&variables))?;
+        assert_eq!(
+            "Ann Author <jane@example.com> 1709643789 +0100",
+            author.to_string()
+        );
+        assert_eq!(
+            "Jane Doe <ci@example.com> 1709665389 -0500",
+            committer.to_string()
+        );
+        let (author, committer) =
+            new_signatures(&repo, Some("Pat Patch <pat@example.com>"), env(&variables))?;
+        assert_eq!("Pat Patch <pat@example.com>", author.person());
+        assert_eq!(1709643789, author.time);
+        assert_eq!("Jane Doe <ci@example.com>", committer.person());
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_identities() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        assert!(matches!(
+            new_signatures(&repo, Some("Pat Patch"), env(&[])),
+            Err(VcsError::InvalidIdentity(author)) if author == "Pat Patch"
+        ));
+        assert!(matches!(
+            new_signatures(&repo, None, env(&[("VCS_AUTHOR_NAME", "<root>")])),
+            Err(VcsError::InvalidIdentity(..))
+        ));
+        assert!(matches!(
+            new_signatures(&repo, None, env(&[("VCS_COMMITTER_DATE", "yesterday")])),
+            Err(VcsError::InvalidOptionValue { option, .. }) if option == "VCS_COMMITTER_DATE"
+        ));
+        Ok(())
+    }
+}
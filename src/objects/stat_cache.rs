@@ -0,0 +1,289 @@
+use std::{collections::BTreeMap, fs::symlink_metadata, io, path::Path};
+
+use crate::error::Result;
+
+use super::{
+    blob::{create_blob, get_blob_hash},
+    mode::FileMode,
+    repo::Repository,
+};
+
+/// A time as a file's metadata records it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileTime {
+    /// Seconds since the Unix epoch
+    pub seconds: i64,
+    pub nanoseconds: u32,
+}
+
+impl FileTime {
+    /// Outputs `time` as a `FileTime`, or the Unix epoch if it is before it
+    pub fn from_system_time(time: std::time::SystemTime) -> FileTime {
+        let since_epoch = time
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        FileTime {
+            seconds: since_epoch.as_secs() as i64,
+            nanoseconds: since_epoch.subsec_nanos(),
+        }
+    }
+}
+
+/// What the filesystem says about a file, enough to tell whether it has changed since it was last
+/// hashed without reading it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatData {
+    /// When the contents last changed
+    pub mtime: FileTime,
+    /// When the contents or the metadata last changed
+    pub ctime: FileTime,
+    pub size: u64,
+    pub inode: u64,
+    /// The raw mode bits, type and permissions
+    pub mode: u32,
+}
+
+impl StatData {
+    /// Outputs the stat data of the file at `path`, without following it if it is a symlink
+    #[cfg(unix)]
+    pub fn of_path<P: AsRef<Path>>(path: P) -> io::Result<StatData> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = symlink_metadata(path)?;
+        Ok(StatData {
+            mtime: FileTime {
+                seconds: metadata.mtime(),
+                nanoseconds: metadata.mtime_nsec() as u32,
+            },
+            ctime: FileTime {
+                seconds: metadata.ctime(),
+                nanoseconds: metadata.ctime_nsec() as u32,
+            },
+            size: metadata.size(),
+            inode: metadata.ino(),
+            mode: metadata.mode(),
+        })
+    }
+
+    /// Outputs the stat data of the file at `path`, without following it if it is a symlink
+    ///
+    /// Platforms without inodes or a change time record them as 0, and the creation time stands
+    /// in for the change time where there is one.
+    #[cfg(not(unix))]
+    pub fn of_path<P: AsRef<Path>>(path: P) -> io::Result<StatData> {
+        let metadata = symlink_metadata(path)?;
+        let mtime = FileTime::from_system_time(metadata.modified()?);
+        Ok(StatData {
+            mtime,
+            ctime: metadata
+                .created()
+                .map(FileTime::from_system_time)
+                .unwrap_or(mtime),
+            size: metadata.len(),
+            inode: 0,
+            mode: 0,
+        })
+    }
+}
+
+/// A file of the worktree as it was when it was last hashed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedFile {
+    pub stat: StatData,
+    pub mode: FileMode,
+    pub hash: String,
+}
+
+/// The stat cache kept in the index: for each tracked file, its stat data and blob hash when it
+/// was last hashed
+///
+/// A file whose stat data hasn't changed since is taken to still have that hash, so it doesn't
+/// need to be read again. The exception is a racily clean file, one modified no earlier than the
+/// index was written: it could have changed again within the same tick of the clock after it was
+/// hashed, leaving its stat data the same, so it is always hashed again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatCache {
+    files: BTreeMap<String, CachedFile>,
+    /// When the index holding the cache was last written, or `None` if it never was
+    written: Option<FileTime>,
+    changed: bool,
+}
+
+impl StatCache {
+    /// Makes a cache of `files`, read from an index last modified at `written`
+    pub fn new(files: BTreeMap<String, CachedFile>, written: Option<FileTime>) -> StatCache {
+        StatCache {
+            files,
+            written,
+            changed: false,
+        }
+    }
+
+    /// The cached files, by name
+    pub fn files(&self) -> &BTreeMap<String, CachedFile> {
+        &self.files
+    }
+
+    /// Returns true iff files were recorded or forgotten since the cache was read
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Outputs the cached version of the file `name` if it can be trusted: its stat data is still
+    /// `stat`, and it isn't racily clean
+    fn lookup(&self, name: &str, stat: &StatData) -> Option<&CachedFile> {
+        let cached = self.files.get(name)?;
+        let written = self.written?;
+        (cached.stat == *stat && cached.stat.mtime < written).then_some(cached)
+    }
+
+    /// Outputs the cached version of the file `name` in the worktree, or `None` if it changed
+    /// since it was cached or can't be trusted
+    ///
+    /// Returns an error if the file doesn't exist
+    pub fn cached(&self, repo: &Repository, name: &str) -> Result<Option<&CachedFile>> {
+        let stat = StatData::of_path(repo.worktree_path(name))?;
+        Ok(self.lookup(name, &stat))
+    }
+
+    /// Outputs the mode and blob hash of the file `name` in the worktree, taken from the cache if
+    /// the file hasn't changed since it was cached, and otherwise by hashing it and recording the
+    /// result
+    ///
+    /// Returns an error if the file can't be read
+    pub fn hash_file(&mut self, repo: &Repository, name: &str) -> Result<(FileMode, String)> {
+        self.refresh(repo, name, false)
+    }
+
+    /// Like `hash_file`, but also writes the blob of the file into the objects directory, unless
+    /// the file is unchanged and its blob is already there
+    pub fn store_file(&mut self, repo: &Repository, name: &str) -> Result<(FileMode, String)> {
+        self.refresh(repo, name, true)
+    }
+
+    /// Does the work of `hash_file`, and of `store_file` if `store` is true
+    fn refresh(
+        &mut self,
+        repo: &Repository,
+        name: &str,
+        store: bool,
+    ) -> Result<(FileMode, String)> {
+        if let Some(cached) = self.cached(repo, name)? {
+            // A loose blob that is already there is freshened, as `write_object` would
+            if !store || repo.freshen_object(&cached.hash)? || repo.object_exists(&cached.hash) {
+                return Ok((cached.mode, cached.hash.clone()));
+            }
+        }
+        let path = repo.worktree_path(name);
+        // The stat data is taken before the file is read, so a change made while it is being
+        // read shows up as a different stat the next time
+        let stat = StatData::of_path(&path)?;
+        let mode = FileMode::of_path(&path)?;
+        let hash = if store {
+            create_blob(repo, &path)?
+        } else {
+            get_blob_hash(&path)?.0
+        };
+        self.record(
+            name,
+            CachedFile {
+                stat,
+                mode,
+                hash: hash.clone(),
+            },
+        );
+        Ok((mode, hash))
+    }
+
+    /// Records that the file `name` was `file` when it was last hashed
+    pub fn record(&mut self, name: &str, file: CachedFile) {
+        if self.files.get(name) != Some(&file) {
+            self.files.insert(name.to_string(), file);
+            self.changed = true;
+        }
+    }
+
+    /// Forgets every file whose name `keep` returns false for
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        let before = self.files.len();
+        self.files.retain(|name, _| keep(name));
+        self.changed |= self.files.len() != before;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for StatCache::hash_file
+    //      file: not cached, cached and unchanged, cached and changed, racily clean
+    //      cache: never written, written
+
+    use std::{fs::write, time::Duration};
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    /// A time well after any file in the tests was modified
+    fn later() -> FileTime {
+        FileTime::from_system_time(std::time::SystemTime::now() + Duration::from_secs(60))
+    }
+
+    #[test]
+    fn uses_unchanged_entries() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        write(test_dir.path().join("a.txt"), "a")?;
+
+        // Nothing is trusted in a cache that was never written
+        let mut cache = StatCache::default();
+        let (mode, hash) = cache.hash_file(&repo, "a.txt")?;
+        assert_eq!(FileMode::Regular, mode);
+        assert_eq!(get_blob_hash(test_dir.path().join("a.txt"))?.0, hash);
+        assert!(cache.is_changed());
+
+        // A cached hash is used as long as the stat data matches, even if it is wrong
+        let mut files = cache.files().clone();
+        files.get_mut("a.txt").unwrap().hash = String::from("cached");
+        let mut cache = StatCache::new(files.clone(), Some(later()));
+        assert_eq!(
+            (FileMode::Regular, String::from("cached")),
+            cache.hash_file(&repo, "a.txt")?
+        );
+        assert!(!cache.is_changed());
+
+        // A file whose stat data changed is hashed again
+        write(test_dir.path().join("a.txt"), "longer")?;
+        assert_eq!(
+            get_blob_hash(test_dir.path().join("a.txt"))?.0,
+            cache.hash_file(&repo, "a.txt")?.1
+        );
+        assert!(cache.is_changed());
+
+        cache.retain(|name| name != "a.txt");
+        assert!(cache.files().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn racily_clean_entries() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        write(test_dir.path().join("a.txt"), "a")?;
+        let stat = StatData::of_path(test_dir.path().join("a.txt"))?;
+        let files = BTreeMap::from([(
+            String::from("a.txt"),
+            CachedFile {
+                stat,
+                mode: FileMode::Regular,
+                hash: String::from("cached"),
+            },
+        )]);
+
+        // The index was written in the same tick the file was modified in, so the file could
+        // have changed after it was hashed without its stat data changing
+        let mut cache = StatCache::new(files, Some(stat.mtime));
+        assert_eq!(
+            get_blob_hash(test_dir.path().join("a.txt"))?.0,
+            cache.hash_file(&repo, "a.txt")?.1
+        );
+        Ok(())
+    }
+}
@@ -1,76 +1,296 @@
-use std::{collections::HashMap, io::Result};
+use std::collections::{BTreeMap, HashMap};
 
-use crate::{objects::write_object, utils::hash::sha2};
+use crate::{
+    error::{Result, VcsError},
+    utils::quote::{quote_name, unquote_name},
+};
 
-use super::get_object_contents;
+use super::{
+    mode::FileMode,
+    object::{hash_object, ObjectKind},
+    repo::Repository,
+};
 
 pub const EMPTY_TREE_HASH: &str =
-    "c26c7c45d0bbe8f237fa087485e47bffd26e0a93e1cb14caf8711169014262fe";
+    "a2f38f83b7e28fbf0e8b37bdda493b0be73611f7f08f039ff2ad3046fc352ada";
+
+/// One entry of a tree: a file or a subdirectory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: FileMode,
+    pub name: String,
+    pub hash: String,
+}
+
+impl TreeEntry {
+    /// The kind of object the entry points at: a tree for directories, and a blob otherwise
+    pub fn kind(&self) -> ObjectKind {
+        match self.mode {
+            FileMode::Directory => ObjectKind::Tree,
+            _ => ObjectKind::Blob,
+        }
+    }
+
+    /// The line of a tree object holding the entry, `<mode> <name>: <hash>`, with the name as
+    /// `quote_name` writes it
+    pub fn line(&self) -> String {
+        format!("{} {}: {}", self.mode, quote_name(&self.name), self.hash)
+    }
+}
 
 /// Given the subtrees and subblobs, outputs the text and hash of the tree object, respectively
 fn get_tree_text_and_hash(subtrees: &Vec<String>, subblobs: &Vec<String>) -> (String, String) {
     let mut output = String::from("Trees\n");
     for line in subtrees {
         output.push_str(line);
-        output.push_str("\n");
+        output.push('\n');
     }
 
     output.push_str("Blobs");
     for line in subblobs {
-        output.push_str("\n");
+        output.push('\n');
         output.push_str(line);
     }
-    (output.clone(), sha2(&output))
+    let hash = hash_object(ObjectKind::Tree, output.as_bytes());
+    (output, hash)
+}
+
+/// Reads the tree with hash `tree_hash` and every tree nested in it, and outputs a map from the
+/// path of each file, relative to the tree, to its blob hash
+///
+/// Trees written before subdirectories had trees of their own list `dir/file` as a blob of the
+/// top-level tree; those paths come out the same either way.
+///
+/// Returns a `CorruptObject` error if the object or a nested tree is not a valid tree
+pub fn read_tree(repo: &Repository, tree_hash: &str) -> Result<HashMap<String, String>> {
+    Ok(read_tree_files(repo, tree_hash)?
+        .into_iter()
+        .map(|(path, (_, hash))| (path, hash))
+        .collect())
+}
+
+/// Like `read_tree`, but outputs the mode of each file along with its blob hash
+pub fn read_tree_files(
+    repo: &Repository,
+    tree_hash: &str,
+) -> Result<HashMap<String, (FileMode, String)>> {
+    let mut files = HashMap::new();
+    let mut pending = vec![(String::new(), tree_hash.to_string())];
+    while let Some((prefix, hash)) = pending.pop() {
+        for entry in read_tree_entries(repo, &hash)? {
+            let path = format!("{}{}", prefix, entry.name);
+            match entry.mode {
+                FileMode::Directory => pending.push((path + "/", entry.hash)),
+                mode => {
+                    files.insert(path, (mode, entry.hash));
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reads the tree with hash `tree_hash`, and outputs its entries
+///
+/// Returns a `CorruptObject` error if the object is not a valid tree
+pub fn read_tree_entries(repo: &Repository, tree_hash: &str) -> Result<Vec<TreeEntry>> {
+    let tree_contents = repo.read_object_text(tree_hash, ObjectKind::Tree)?;
+    parse_tree_entries(tree_hash, &tree_contents)
 }
 
-/// Given the contents of a vcs tree, outputs a tree mapping filename to blob hashes
-pub fn serialize_tree(tree_contents: &str) -> HashMap<String, String> {
-    let mut tree = HashMap::new();
-    for line in tree_contents.split('\n') {
-        if line == "Trees" || line == "Blobs" {
+/// Given the contents of the tree with hash `tree_hash`, outputs its entries
+///
+/// Entries under `Trees` are directories and entries under `Blobs` are files. Each is written as
+/// `<mode> <name>: <hash>`; trees written before modes existed leave out the mode, which is then
+/// directory or regular file depending on the section. The hash follows the last `: `, so names
+/// may hold colons, and names quoted by `quote_name` are read back.
+///
+/// Returns a `CorruptObject` error if the contents are not a valid tree
+pub fn parse_tree_entries(tree_hash: &str, tree_contents: &str) -> Result<Vec<TreeEntry>> {
+    let corrupt = |reason: &str| VcsError::CorruptObject {
+        hash: tree_hash.to_string(),
+        reason: reason.to_string(),
+    };
+    let mut lines = tree_contents.split('\n');
+    if lines.next() != Some("Trees") {
+        return Err(corrupt("missing Trees line"));
+    }
+    let mut in_blobs = false;
+    let mut entries = vec![];
+    for line in lines {
+        if line == "Blobs" && !in_blobs {
+            in_blobs = true;
             continue;
         }
-        let split_line: Vec<&str> = line.split(": ").collect();
-        let (object_name, object_hash) = (split_line[0].to_string(), split_line[1].to_string());
-        tree.insert(object_name, object_hash);
+        let (name, hash) = line
+            .rsplit_once(": ")
+            .ok_or_else(|| corrupt("malformed tree entry"))?;
+        let (mode, name) = match name
+            .split_once(' ')
+            .and_then(|(code, rest)| Some((FileMode::from_code(code)?, rest)))
+        {
+            Some((mode, rest)) => (mode, rest),
+            None if in_blobs => (FileMode::Regular, name),
+            None => (FileMode::Directory, name),
+        };
+        if in_blobs == (mode == FileMode::Directory) {
+            return Err(corrupt("entry mode does not match its section"));
+        }
+        entries.push(TreeEntry {
+            mode,
+            name: unquote_name(name).ok_or_else(|| corrupt("malformed quoted name"))?,
+            hash: hash.to_string(),
+        });
+    }
+    if !in_blobs {
+        return Err(corrupt("missing Blobs line"));
     }
-    tree
+    Ok(entries)
 }
 
 /// Returns the hash of a tree with subtrees and subblobs. Also creates the tree object
-pub fn write_tree(subtrees: &Vec<String>, subblobs: &Vec<String>) -> String {
-    let (tree_text, tree_hash) = get_tree_text_and_hash(subtrees, subblobs);
-    let _ = write_object(&tree_hash, &tree_text);
-    tree_hash
+pub fn write_tree(
+    repo: &Repository,
+    subtrees: &Vec<String>,
+    subblobs: &Vec<String>,
+) -> Result<String> {
+    let (tree_text, _) = get_tree_text_and_hash(subtrees, subblobs);
+    repo.write_object(ObjectKind::Tree, tree_text)
 }
 
-/// Returns the hash of `filename` in the tree given by `tree_hash`, or `DNE` if hash doesn't exist
+/// Writes the tree that results from applying `changes` to the tree `tree_hash`, and outputs its
+/// hash. `changes` maps file paths to their new mode and blob hash, or to `None` for files to
+/// remove.
+///
+/// Every directory gets a tree of its own. Subtrees that no change touches are reused as they are,
+/// without being read, and directories left empty are dropped.
 ///
-/// Throws an error if `tree_hash` is not a valid tree
-pub fn find_file_in_tree(tree_hash: &str, filename: &str) -> Result<String> {
-    let tree_contents = get_object_contents(tree_hash)?;
-    let serialized_tree = serialize_tree(&tree_contents);
-    if filename.contains('/') {
-        let mut parts = filename.splitn(2, '/');
-        let first_part = parts.next();
-        let second_part = parts.next();
-        match (first_part, second_part) {
-            (Some(parent), Some(subpath)) => {
-                if let Some(hash) = serialized_tree.get(parent) {
-                    find_file_in_tree(hash, subpath)
-                } else {
-                    Ok(String::from("DNE"))
+/// Returns an error if `tree_hash` or one of the subtrees that changes is not a valid tree
+pub fn update_tree(
+    repo: &Repository,
+    tree_hash: &str,
+    changes: &BTreeMap<String, Option<(FileMode, String)>>,
+) -> Result<String> {
+    match update_subtree(repo, Some(tree_hash), changes)? {
+        Some(hash) => Ok(hash),
+        None => write_tree(repo, &vec![], &vec![]),
+    }
+}
+
+/// Like `update_tree`, but for the tree of a directory that might not exist yet (`tree_hash` is
+/// `None`) and that might be left empty, in which case no tree is written and `None` is output
+fn update_subtree(
+    repo: &Repository,
+    tree_hash: Option<&str>,
+    changes: &BTreeMap<String, Option<(FileMode, String)>>,
+) -> Result<Option<String>> {
+    let mut entries: BTreeMap<String, (FileMode, String)> = BTreeMap::new();
+    let mut changes = changes.clone();
+    if let Some(tree_hash) = tree_hash {
+        for entry in read_tree_entries(repo, tree_hash)? {
+            if entry.name.contains('/') {
+                // A file of an older flat tree, which is moved into a subtree of its own
+                changes
+                    .entry(entry.name)
+                    .or_insert(Some((entry.mode, entry.hash)));
+            } else {
+                entries.insert(entry.name, (entry.mode, entry.hash));
+            }
+        }
+    }
+
+    let mut subtree_changes: BTreeMap<String, BTreeMap<String, Option<(FileMode, String)>>> =
+        BTreeMap::new();
+    for (path, change) in changes {
+        match path.split_once('/') {
+            Some((dir, rest)) => {
+                subtree_changes
+                    .entry(dir.to_string())
+                    .or_default()
+                    .insert(rest.to_string(), change);
+            }
+            None => match change {
+                Some(file) => {
+                    entries.insert(path, file);
+                }
+                None => {
+                    entries.remove(&path);
                 }
+            },
+        }
+    }
+    for (dir, dir_changes) in subtree_changes {
+        let old_subtree = match entries.get(&dir) {
+            Some((FileMode::Directory, hash)) => Some(hash.clone()),
+            _ => None,
+        };
+        match update_subtree(repo, old_subtree.as_deref(), &dir_changes)? {
+            Some(hash) => {
+                entries.insert(dir, (FileMode::Directory, hash));
             }
-            _ => panic!("Expected strings for both parts!"),
+            // Don't remove a file that replaced the directory
+            None if old_subtree.is_some() => {
+                entries.remove(&dir);
+            }
+            None => {}
         }
-    } else {
-        if let Some(hash) = serialized_tree.get(filename) {
-            Ok((*hash).to_string())
-        } else {
-            Ok(String::from("DNE"))
+    }
+
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let mut subtrees = vec![];
+    let mut subblobs = vec![];
+    for (name, (mode, hash)) in entries {
+        let line = TreeEntry { mode, name, hash }.line();
+        match mode {
+            FileMode::Directory => subtrees.push(line),
+            _ => subblobs.push(line),
         }
     }
+    write_tree(repo, &subtrees, &subblobs).map(Some)
+}
+
+/// Returns the hash of `filename` in the tree given by `tree_hash`, or `None` if the file doesn't
+/// exist in the tree
+///
+/// Returns an error if `tree_hash` is not a valid tree
+pub fn find_file_in_tree(
+    repo: &Repository,
+    tree_hash: &str,
+    filename: &str,
+) -> Result<Option<String>> {
+    Ok(find_entry_in_tree(repo, tree_hash, filename)?.map(|entry| entry.hash))
+}
+
+/// Returns the entry of the file `filename` in the tree given by `tree_hash`, or `None` if the
+/// file doesn't exist in the tree. The name of the entry is the last component of `filename`.
+///
+/// Returns an error if `tree_hash` is not a valid tree
+pub fn find_entry_in_tree(
+    repo: &Repository,
+    tree_hash: &str,
+    filename: &str,
+) -> Result<Option<TreeEntry>> {
+    let entries = read_tree_entries(repo, tree_hash)?;
+    let is_dir = |entry: &TreeEntry| entry.mode == FileMode::Directory;
+    // Older flat trees list `dir/file` as a blob of the top-level tree
+    if let Some(entry) = entries
+        .iter()
+        .find(|entry| !is_dir(entry) && entry.name == filename)
+    {
+        return Ok(Some(entry.clone()));
+    }
+    match filename.split_once('/') {
+        Some((parent, subpath)) => match entries
+            .iter()
+            .find(|entry| is_dir(entry) && entry.name == parent)
+        {
+            Some(entry) => find_entry_in_tree(repo, &entry.hash, subpath),
+            None => Ok(None),
+        },
+        None => Ok(None),
+    }
 }
 
 #[cfg(test)]
@@ -79,9 +299,16 @@ mod tests {
      * Testing partition for get_tree_text
      *      subtrees: empty, nonempty
      *      subblobs: empty, nonempty
+     * Testing partition for update_tree, read_tree and find_file_in_tree
+     *      changes: add, remove, in the top-level tree, in nested directories
+     *      directories: new, emptied, untouched, replaced by a file
+     *      tree format: nested, flat
+     *      modes: regular, executable, symlink, directory, missing (legacy), in the wrong section
+     *      names: with spaces, colons, newlines, non-ASCII; of files and directories
      */
 
     use super::*;
+    use crate::utils::test_dir::make_test_dir;
 
     #[test]
     fn all_empty() {
@@ -94,15 +321,232 @@ mod tests {
 
     #[test]
     fn both_populated() {
-        let subtrees: Vec<String> = [(String::from("hello: world"))].iter().cloned().collect();
-        let subblobs: Vec<String> = [(String::from("I: love")), (String::from("rust: "))]
-            .iter()
-            .cloned()
-            .collect();
+        let subtrees: Vec<String> = [(String::from("hello: world"))].to_vec();
+        let subblobs: Vec<String> = [(String::from("I: love")), (String::from("rust: "))].to_vec();
         let (tree_text, _) = get_tree_text_and_hash(&subtrees, &subblobs);
         assert!(
-            String::from("Trees\nhello: world\nBlobs\nI: love\nrust: ") == tree_text
-                || String::from("Trees\nhello: world\nBlobs\nrust: \nI: love") == tree_text
+            "Trees\nhello: world\nBlobs\nI: love\nrust: " == tree_text
+                || "Trees\nhello: world\nBlobs\nrust: \nI: love" == tree_text
         ); // order isn't fixed by HashMaps, so we check if either is correct
     }
+
+    #[test]
+    fn entries_by_kind() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let tree_hash = write_tree(
+            &repo,
+            &vec![String::from("dir: abc")],
+            &vec![
+                String::from("a: b: def"),
+                String::from("100755 run.sh: 123"),
+                String::from("120000 link: 456"),
+            ],
+        )?;
+        let entry = |mode, name: &str, hash: &str| TreeEntry {
+            mode,
+            name: name.to_string(),
+            hash: hash.to_string(),
+        };
+        assert_eq!(
+            vec![
+                entry(FileMode::Directory, "dir", "abc"),
+                entry(FileMode::Regular, "a: b", "def"),
+                entry(FileMode::Executable, "run.sh", "123"),
+                entry(FileMode::Symlink, "link", "456"),
+            ],
+            read_tree_entries(&repo, &tree_hash)?
+        );
+        assert_eq!(
+            ObjectKind::Tree,
+            entry(FileMode::Directory, "d", "1").kind()
+        );
+        assert_eq!(ObjectKind::Blob, entry(FileMode::Symlink, "l", "1").kind());
+        assert_eq!(
+            "100755 run.sh: 123",
+            entry(FileMode::Executable, "run.sh", "123").line()
+        );
+        let bad_tree = repo.write_object(ObjectKind::Tree, "Trees\nnot an entry\nBlobs")?;
+        assert!(matches!(
+            read_tree_entries(&repo, &bad_tree),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        let misplaced = repo.write_object(ObjectKind::Tree, "Trees\nBlobs\n040000 dir: abc")?;
+        assert!(matches!(
+            read_tree_entries(&repo, &misplaced),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        Ok(())
+    }
+
+    /// Outputs `changes` as the map `update_tree` takes, with every file a regular file
+    fn changes(changes: &[(&str, Option<&str>)]) -> BTreeMap<String, Option<(FileMode, String)>> {
+        changes
+            .iter()
+            .map(|(path, hash)| {
+                let file = hash.map(|hash| (FileMode::Regular, hash.to_string()));
+                (path.to_string(), file)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_trees() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let empty = write_tree(&repo, &vec![], &vec![])?;
+        let tree = update_tree(
+            &repo,
+            &empty,
+            &changes(&[
+                ("top.txt", Some("1")),
+                ("src/main.rs", Some("2")),
+                ("src/deep/lib.rs", Some("3")),
+                ("docs/guide.md", Some("4")),
+            ]),
+        )?;
+        let entries = read_tree_entries(&repo, &tree)?;
+        assert_eq!(3, entries.len());
+        assert!(entries.contains(&TreeEntry {
+            mode: FileMode::Regular,
+            name: String::from("top.txt"),
+            hash: String::from("1"),
+        }));
+        assert_eq!(
+            Some(String::from("3")),
+            find_file_in_tree(&repo, &tree, "src/deep/lib.rs")?
+        );
+        assert_eq!(None, find_file_in_tree(&repo, &tree, "src/deep")?);
+        assert_eq!(None, find_file_in_tree(&repo, &tree, "missing/lib.rs")?);
+        let files = read_tree(&repo, &tree)?;
+        assert_eq!(4, files.len());
+        assert_eq!("2", files["src/main.rs"]);
+
+        // Only the changed directories get new trees
+        let docs = find_subtree(&repo, &tree, "docs");
+        let updated = update_tree(
+            &repo,
+            &tree,
+            &changes(&[("src/deep/lib.rs", None), ("src/main.rs", Some("5"))]),
+        )?;
+        assert_eq!(docs, find_subtree(&repo, &updated, "docs"));
+        assert_ne!(
+            find_subtree(&repo, &tree, "src"),
+            find_subtree(&repo, &updated, "src")
+        );
+        let files = read_tree(&repo, &updated)?;
+        assert_eq!(3, files.len());
+        assert!(!files.contains_key("src/deep/lib.rs"));
+
+        // A file can replace a directory, and emptying every directory leaves the empty tree
+        let replaced = update_tree(
+            &repo,
+            &updated,
+            &changes(&[("docs/guide.md", None), ("docs", Some("6"))]),
+        )?;
+        assert_eq!("6", read_tree(&repo, &replaced)?["docs"]);
+        let emptied = update_tree(
+            &repo,
+            &updated,
+            &changes(&[
+                ("top.txt", None),
+                ("src/main.rs", None),
+                ("docs/guide.md", None),
+            ]),
+        )?;
+        assert_eq!(EMPTY_TREE_HASH, emptied);
+
+        // Modes are kept, and changing only the mode makes a new tree
+        let mut executable = changes(&[]);
+        executable.insert(
+            String::from("src/main.rs"),
+            Some((FileMode::Executable, String::from("5"))),
+        );
+        let chmodded = update_tree(&repo, &updated, &executable)?;
+        assert_ne!(updated, chmodded);
+        assert_eq!(
+            (FileMode::Executable, String::from("5")),
+            read_tree_files(&repo, &chmodded)?["src/main.rs"]
+        );
+        Ok(())
+    }
+
+    /// Outputs the hash of the subtree `name` of the tree `tree_hash`
+    fn find_subtree(repo: &Repository, tree_hash: &str, name: &str) -> Option<String> {
+        read_tree_entries(repo, tree_hash)
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.mode == FileMode::Directory && entry.name == name)
+            .map(|entry| entry.hash)
+    }
+
+    #[test]
+    fn flat_trees() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let flat = write_tree(
+            &repo,
+            &vec![],
+            &vec![String::from("a.txt: 1"), String::from("dir/b.txt: 2")],
+        )?;
+        assert_eq!(
+            Some(String::from("2")),
+            find_file_in_tree(&repo, &flat, "dir/b.txt")?
+        );
+        assert_eq!("2", read_tree(&repo, &flat)?["dir/b.txt"]);
+
+        // Updating a flat tree nests it
+        let nested = update_tree(&repo, &flat, &changes(&[("dir/c.txt", Some("3"))]))?;
+        let files = read_tree(&repo, &nested)?;
+        assert_eq!(3, files.len());
+        assert!(find_subtree(&repo, &nested, "dir").is_some());
+        assert_eq!(
+            Some(String::from("2")),
+            find_file_in_tree(&repo, &nested, "dir/b.txt")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn special_names() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        let empty = write_tree(&repo, &vec![], &vec![])?;
+        let paths = [
+            "my notes.txt",
+            "a: b.md",
+            "line\nbreak.txt",
+            "naïve 日本語.txt",
+            "dir: with\nnewline/Blobs",
+            "Trees/inner.txt",
+        ];
+        let tree = update_tree(
+            &repo,
+            &empty,
+            &changes(&paths.map(|path| (path, Some("1")))),
+        )?;
+        // Each entry is one line between the Trees and Blobs lines
+        let text = repo.read_object_text(&tree, ObjectKind::Tree)?;
+        assert_eq!(paths.len() + 2, text.lines().count());
+        assert!(text.contains("\n100644 \"line\\nbreak.txt\": 1"));
+
+        let files = read_tree(&repo, &tree)?;
+        assert_eq!(paths.len(), files.len());
+        for path in paths {
+            assert_eq!("1", files[path]);
+            assert_eq!(
+                Some(String::from("1")),
+                find_file_in_tree(&repo, &tree, path)?
+            );
+        }
+        assert!(find_subtree(&repo, &tree, "dir: with\nnewline").is_some());
+
+        let bad_tree =
+            repo.write_object(ObjectKind::Tree, "Trees\nBlobs\n100644 \"unclosed: abc")?;
+        assert!(matches!(
+            read_tree_entries(&repo, &bad_tree),
+            Err(VcsError::CorruptObject { .. })
+        ));
+        Ok(())
+    }
 }
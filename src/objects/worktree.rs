@@ -0,0 +1,259 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{create_dir_all, read_dir, remove_dir, remove_file, symlink_metadata, File},
+    io::Write,
+    path::Path,
+};
+
+use crate::error::Result;
+
+use super::{ignore::glob_match, mode::FileMode, repo::Repository, stat_cache::StatCache};
+
+/// Outputs the name of every file in the worktree of `repo`, relative to its root and separated
+/// by `/`, sorted
+///
+/// Walks every directory except `.vcs` (and the vcs dir, wherever `VCS_DIR` puts it). Symlinks are
+/// listed as files, and never followed. A file or directory whose name isn't valid UTF-8 can't be
+/// tracked, so it is skipped with a warning.
+pub fn worktree_files(repo: &Repository) -> Result<Vec<String>> {
+    let mut files = vec![];
+    let mut pending = vec![String::new()];
+    while let Some(prefix) = pending.pop() {
+        for entry in read_dir(repo.root().join(&prefix))? {
+            let entry = entry?;
+            let path = entry.path();
+            let Some(file_name) = entry.file_name().to_str().map(String::from) else {
+                eprintln!(
+                    "warning: skipping {}: its name isn't valid UTF-8",
+                    path.display()
+                );
+                continue;
+            };
+            let name = format!("{}{}", prefix, file_name);
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() != ".vcs" && path != repo.vcs_dir() {
+                    pending.push(name + "/");
+                }
+            } else {
+                files.push(name);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Outputs the names in `candidates` that the pathspec `name`, relative to the worktree root,
+/// matches: `name` itself, every name inside the directory `name`, or, if `name` is a glob, every
+/// name it matches along with every name inside a directory it matches. The empty name is the
+/// root, and matches everything.
+pub fn match_pathspec(name: &str, candidates: &BTreeSet<String>) -> Vec<String> {
+    if candidates.contains(name) {
+        return vec![name.to_string()];
+    }
+    let prefix = if name.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", name)
+    };
+    let inside: Vec<String> = candidates
+        .iter()
+        .filter(|candidate| candidate.starts_with(&prefix))
+        .cloned()
+        .collect();
+    if !inside.is_empty() || !name.contains(['*', '?', '[']) {
+        return inside;
+    }
+    candidates
+        .iter()
+        .filter(|candidate| {
+            glob_match(name.as_bytes(), candidate.as_bytes())
+                || candidate
+                    .match_indices('/')
+                    .any(|(i, _)| glob_match(name.as_bytes(), &candidate.as_bytes()[..i]))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Writes the file `name` in the worktree with mode `mode`, creating the directories above it if
+/// they don't exist. For a symlink, `contents` is the target of the link; otherwise it is the
+/// contents of the file, and the file is made executable or not according to `mode`.
+///
+/// An existing symlink at `name` is replaced rather than written through.
+pub fn write_worktree_file(
+    repo: &Repository,
+    name: &str,
+    contents: &[u8],
+    mode: FileMode,
+) -> Result<()> {
+    let path = repo.worktree_path(name);
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    if path.is_symlink() || (mode == FileMode::Symlink && path.exists()) {
+        remove_file(&path)?;
+    }
+    if mode == FileMode::Symlink {
+        return make_symlink(contents, &path);
+    }
+    let mut file = File::create(&path)?;
+    file.write_all(contents)?;
+    set_executable(&path, mode == FileMode::Executable)
+}
+
+/// Creates a symlink at `path` pointing at `target`
+///
+/// Platforms without symlinks get a regular file holding the target instead.
+#[cfg(unix)]
+fn make_symlink(target: &[u8], path: &Path) -> Result<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    std::os::unix::fs::symlink(OsStr::from_bytes(target), path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_symlink(target: &[u8], path: &Path) -> Result<()> {
+    std::fs::write(path, target)?;
+    Ok(())
+}
+
+/// Makes the file at `path` executable by everyone who can read it, or by no one
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> Result<()> {
+    use std::{fs::set_permissions, os::unix::fs::PermissionsExt};
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    let new_mode = if executable {
+        mode | ((mode & 0o444) >> 2)
+    } else {
+        mode & !0o111
+    };
+    if new_mode != mode {
+        permissions.set_mode(new_mode);
+        set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> Result<()> {
+    Ok(())
+}
+
+/// Removes the file `name` from the worktree, then every directory above it that is left empty,
+/// up to the root of the worktree
+///
+/// Does nothing if the file doesn't exist.
+pub fn remove_worktree_file(repo: &Repository, name: &str) -> Result<()> {
+    let path = repo.worktree_path(name);
+    if symlink_metadata(&path).is_err() {
+        return Ok(());
+    }
+    remove_file(&path)?;
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == repo.root() || !is_empty_dir(current)? {
+            break;
+        }
+        remove_dir(current)?;
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+/// Outputs the names in `names` whose file in the worktree would lose something if it was
+/// overwritten or removed: files whose contents or mode differ from their version in `tracked`,
+/// which maps names to the mode and blob hash they were committed with, and files or directories
+/// in the way of a name `tracked` doesn't have. Missing files are not listed. Files are hashed
+/// through `stat_cache`, so only those changed since it last saw them are read.
+pub fn local_changes<'a>(
+    repo: &Repository,
+    stat_cache: &mut StatCache,
+    tracked: &HashMap<String, (FileMode, String)>,
+    names: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<String>> {
+    let mut changed = vec![];
+    for name in names {
+        let path = repo.worktree_path(name);
+        if symlink_metadata(&path).is_err() {
+            continue;
+        }
+        let unchanged = match (tracked.get(name), FileMode::of_path(&path)?) {
+            (Some((mode, hash)), current_mode) if *mode == current_mode => {
+                stat_cache.hash_file(repo, name)?.1 == *hash
+            }
+            _ => false,
+        };
+        if !unchanged {
+            changed.push(name.clone());
+        }
+    }
+    changed.sort();
+    Ok(changed)
+}
+
+/// Returns true iff `dir` is a directory with nothing in it
+fn is_empty_dir(dir: &Path) -> Result<bool> {
+    Ok(dir.is_dir() && read_dir(dir)?.next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for worktree_files
+    //      files: at the root, nested, in .vcs, named with invalid UTF-8
+    // Partitions for write_worktree_file and remove_worktree_file
+    //      parent directories: exist, missing, left empty, still holding other files
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    #[test]
+    fn walks_nested_directories() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        write_worktree_file(&repo, "top.txt", b"top", FileMode::Regular)?;
+        write_worktree_file(&repo, "src/deep/lib.rs", b"lib", FileMode::Regular)?;
+        write_worktree_file(&repo, "src/main.rs", b"main", FileMode::Regular)?;
+        create_dir_all(repo.root().join("empty"))?;
+        assert_eq!(
+            vec!["src/deep/lib.rs", "src/main.rs", "top.txt"],
+            worktree_files(&repo)?
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_names_that_are_not_utf8() -> Result<()> {
+        use std::{ffi::OsStr, fs::write, os::unix::ffi::OsStrExt};
+
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        write_worktree_file(&repo, "top.txt", b"top", FileMode::Regular)?;
+        write(repo.root().join(OsStr::from_bytes(b"bad\xff.txt")), "bad")?;
+        create_dir_all(repo.root().join(OsStr::from_bytes(b"dir\xfe")))?;
+        write(
+            repo.root().join(OsStr::from_bytes(b"dir\xfe/inside.txt")),
+            "inside",
+        )?;
+        assert_eq!(vec!["top.txt"], worktree_files(&repo)?);
+        Ok(())
+    }
+
+    #[test]
+    fn prunes_empty_directories() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let repo = Repository::create(test_dir.path())?;
+        write_worktree_file(&repo, "src/deep/lib.rs", b"lib", FileMode::Regular)?;
+        write_worktree_file(&repo, "src/main.rs", b"main", FileMode::Regular)?;
+        remove_worktree_file(&repo, "src/deep/lib.rs")?;
+        assert!(!repo.root().join("src/deep").exists());
+        assert!(repo.root().join("src").exists());
+        remove_worktree_file(&repo, "src/main.rs")?;
+        assert!(!repo.root().join("src").exists());
+        assert!(repo.root().exists());
+        remove_worktree_file(&repo, "missing.txt")?;
+        Ok(())
+    }
+}
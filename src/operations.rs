@@ -1,8 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, VcsError};
+
 pub mod add;
 pub mod branch;
+pub mod check_ignore;
 pub mod checkout;
 pub mod commit;
+pub mod diff;
+pub mod fsck;
+pub mod gc;
 pub mod init;
 pub mod log;
+pub mod merge;
+pub mod migrate;
+pub mod reflog;
+pub mod repack;
+pub mod rev_parse;
 pub mod rm;
 pub mod status;
+
+/// Names of every operation `dispatch` knows how to run, in the order `vcs help` lists them
+pub const OPERATIONS: [&str; 17] = [
+    "init",
+    "add",
+    "commit",
+    "rm",
+    "status",
+    "diff",
+    "log",
+    "reflog",
+    "branch",
+    "checkout",
+    "merge",
+    "migrate",
+    "repack",
+    "gc",
+    "fsck",
+    "check-ignore",
+    "rev-parse",
+];
+
+/// Returns the usage text of the operation named `name`, or `None` if no such operation exists
+pub fn usage(name: &str) -> Option<&'static str> {
+    match name {
+        "init" => Some(init::USAGE),
+        "add" => Some(add::USAGE),
+        "commit" => Some(commit::USAGE),
+        "rm" => Some(rm::USAGE),
+        "status" => Some(status::USAGE),
+        "diff" => Some(diff::USAGE),
+        "log" => Some(log::USAGE),
+        "reflog" => Some(reflog::USAGE),
+        "branch" => Some(branch::USAGE),
+        "checkout" => Some(checkout::USAGE),
+        "merge" => Some(merge::USAGE),
+        "migrate" => Some(migrate::USAGE),
+        "repack" => Some(repack::USAGE),
+        "gc" => Some(gc::USAGE),
+        "fsck" => Some(fsck::USAGE),
+        "check-ignore" => Some(check_ignore::USAGE),
+        "rev-parse" => Some(rev_parse::USAGE),
+        _ => None,
+    }
+}
+
+/// Routes `args` (as given by `std::env::args()`) to the operation named by `args[1]`, running it
+/// from the directory `dir`. Returns the string that should be logged to the console.
+///
+/// Any number of leading `-C <path>` options are accepted before the operation name; each one
+/// makes the operation run as if it was started in `<path>` (relative to the previous directory).
+/// Unknown operations return `NoSuchOperation`, and a missing operation lists every operation.
+pub fn dispatch(dir: &Path, args: &[String]) -> Result<String> {
+    let mut dir: PathBuf = dir.to_path_buf();
+    let mut args: Vec<String> = args.to_vec();
+    while args.len() > 1 && args[1] == "-C" {
+        if args.len() < 3 {
+            return Err(VcsError::MissingOptionValue(String::from("-C")));
+        }
+        dir = dir.join(&args[2]);
+        args.drain(1..3);
+    }
+    let (dir, args) = (dir.as_path(), args.as_slice());
+    if args.len() < 2 {
+        return Ok(general_help());
+    }
+    match args[1].as_str() {
+        "init" => init::init(dir, args),
+        "add" => Ok(add::add(dir, args)?.0),
+        "commit" => Ok(commit::commit(dir, args)?.0),
+        "rm" => rm::rm(dir, args),
+        "status" => status::status(dir, args),
+        "diff" => diff::diff(dir, args),
+        "log" => log::log(dir, args),
+        "reflog" => reflog::reflog(dir, args),
+        "branch" => branch::branch(dir, args),
+        "checkout" => checkout::checkout(dir, args),
+        "merge" => merge::merge(dir, args),
+        "migrate" => migrate::migrate(dir, args),
+        "repack" => repack::repack(dir, args),
+        "gc" => gc::gc(dir, args),
+        "fsck" => fsck::fsck(dir, args),
+        "check-ignore" => check_ignore::check_ignore(dir, args),
+        "rev-parse" => rev_parse::rev_parse(dir, args),
+        "help" => help(args),
+        name => Err(VcsError::NoSuchOperation(name.to_string())),
+    }
+}
+
+/// Executes `vcs help [<operation>]`
+///
+/// With no arguments, lists every operation. With one argument, logs the usage text of that
+/// operation, or returns `NoSuchOperation` if there is no such operation.
+fn help(args: &[String]) -> Result<String> {
+    assert!(args[1] == "help");
+    match args.len() {
+        2 => Ok(general_help()),
+        3 => usage(&args[2])
+            .map(String::from)
+            .ok_or_else(|| VcsError::NoSuchOperation(args[2].clone())),
+        _ => Err(VcsError::IncorrectArgumentCount(String::from("0 or 1"))),
+    }
+}
+
+/// The text listing every operation, shown by `vcs help` and when no operation is given
+fn general_help() -> String {
+    let mut output = vec![String::from(
+        "usage: vcs [-C <path>] <operation> [<args>]\n",
+    )];
+    for name in OPERATIONS {
+        let summary = usage(name).unwrap().split("\n\n").nth(1).unwrap_or("");
+        output.push(format!("   {:<10}{}", name, summary));
+    }
+    output.push(String::from(
+        "\nSee `vcs help <operation>` for more information on an operation.",
+    ));
+    output.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for dispatch
+    //      No operation given, unknown operation, help with and without an operation
+
+    use super::*;
+    use crate::utils::test_dir::make_test_dir;
+
+    #[test]
+    fn unknown_operation() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            dispatch(
+                test_dir.path(),
+                &[String::from("target/debug/vcs"), String::from("frobnicate")],
+            ),
+            Err(VcsError::NoSuchOperation(name)) if name == "frobnicate"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn no_operation() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let output = dispatch(test_dir.path(), &[String::from("target/debug/vcs")])?;
+        assert!(output.starts_with("usage: vcs [-C <path>] <operation>"));
+        Ok(())
+    }
+
+    #[test]
+    fn change_directory_option() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        std::fs::create_dir_all(test_dir.path().join("repo/sub"))?;
+        dispatch(
+            test_dir.path(),
+            &[
+                String::from("target/debug/vcs"),
+                String::from("-C"),
+                String::from("repo"),
+                String::from("init"),
+            ],
+        )?;
+        assert!(test_dir.path().join("repo/.vcs").is_dir());
+        let output = dispatch(
+            test_dir.path(),
+            &[
+                String::from("target/debug/vcs"),
+                String::from("-C"),
+                String::from("repo"),
+                String::from("-C"),
+                String::from("sub"),
+                String::from("status"),
+            ],
+        )?;
+        assert_eq!("On branch main\nnothing to commit\n", output);
+        assert!(matches!(
+            dispatch(
+                test_dir.path(),
+                &[String::from("target/debug/vcs"), String::from("-C")],
+            ),
+            Err(VcsError::MissingOptionValue(option)) if option == "-C"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn help_for_every_operation() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        for name in OPERATIONS {
+            let output = dispatch(
+                test_dir.path(),
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("help"),
+                    String::from(name),
+                ],
+            )?;
+            assert!(output.starts_with(&format!("usage: vcs {}", name)));
+        }
+        assert!(matches!(
+            dispatch(
+                test_dir.path(),
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("help"),
+                    String::from("frobnicate"),
+                ],
+            ),
+            Err(VcsError::NoSuchOperation(_))
+        ));
+        Ok(())
+    }
+}
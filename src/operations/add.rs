@@ -1,103 +1,463 @@
 use std::{
-    fs::File,
-    io::{Result, Write},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs::{remove_file, symlink_metadata},
+    io::{self, BufRead, Write},
+    path::Path,
+    process::Command,
 };
 
 use crate::{
+    error::{Result, VcsError},
     objects::{
-        blob::create_blob,
-        commit::{get_hash_in_commit, get_head_commit},
-        get_branch_name,
+        blob::{get_blob_hash, read_blob},
+        commit::get_commit_tree,
+        diff::{edit_script, hunks, split_lines, Hunk},
+        ignore::IgnoreRules,
+        index::IndexEntry,
+        mode::FileMode,
+        object::ObjectKind,
+        patch::{apply_changes, hunk_changes, parse_edited_hunk, Change},
+        repo::Repository,
+        tree::read_tree_files,
+        worktree::{match_pathspec, worktree_files},
     },
-    utils::fs_utils::{clear_file_contents, directory_exists, file_exists, get_file_contents},
+    operations::diff::DEFAULT_CONTEXT,
+    utils::fs_utils::get_file_contents,
 };
+
+/// Usage text shown by `vcs help add`
+pub const USAGE: &str = "usage: vcs add [-f | --force] [-A | --all | -u | --update] [--] [<pathspec>...]\n   or: vcs add -p [--] [<pathspec>...]\n\nStage the current contents of the files each <pathspec> matches for the next commit.\n\nA pathspec is a file, a directory, meaning everything in it (so . is the current\ndirectory), or a glob, where * and ? match within one directory and **/ matches any\nnumber of directories. Tracked files that were deleted are staged for removal.\n\n    -f, --force     add files even if a .vcsignore rule ignores them\n    -A, --all       stage new, modified and deleted files; everywhere without a <pathspec>\n    -u, --update    stage only modified and deleted tracked files; everywhere without a\n                    <pathspec>\n    -p, --patch     choose which hunks of the changes to tracked files to stage, and edit\n                    them; everywhere without a <pathspec>";
+
+/// Help shown by `add -p` for an answer it doesn't know
+const PATCH_HELP: &str = "y - stage this hunk\nn - do not stage this hunk\nq - quit; do not stage this hunk or any of the remaining ones\ns - split the current hunk into smaller hunks\ne - manually edit the current hunk\n? - print help\n";
+
+/// The guide under a hunk opened in the editor by `add -p`
+const EDIT_GUIDE: &str = "# ---\n# To remove '-' lines, make them ' ' lines (context).\n# To remove '+' lines, delete them.\n# Lines starting with # will be removed.\n# If the edited hunk doesn't apply, you will be asked about it again.\n";
+
+/// Where `add -p` shows hunks and reads the answers to its questions
+pub struct Interaction<'a> {
+    /// The answers, one per line
+    pub input: &'a mut dyn BufRead,
+    /// Where hunks and questions are shown
+    pub output: &'a mut dyn Write,
+    /// Lets the user edit the text of a hunk, and outputs the edited text
+    pub edit: &'a mut dyn FnMut(&str) -> Result<String>,
+}
+
+/// A file whose hunks `add -p` chose, to be staged once every question is answered
+struct ChosenFile {
+    name: String,
+    /// The staged mode and hash the hunks were chosen from
+    base: (FileMode, String),
+    mode: FileMode,
+    hash: String,
+}
+
 /// Executes `vcs add` with `args` as arguments. Returns the string that should be logged to the
-/// console and the hash of the added object if operation was successful.
+/// console and the hash of the last file staged, or an empty hash if no file was.
+///
+/// Stages every file a pathspec matches: the file it names, every file inside the directory it
+/// names, or every file its glob matches. Files tracked by the head commit, or staged, that no
+/// longer exist in the worktree are staged for removal. With `-A` (or `--all`) or `-u` (or
+/// `--update`), the pathspecs can be left out to stage every change in the worktree, and `-u`
+/// leaves untracked files alone.
 ///
-/// If there is one argument, adds the file in the argument to the .vcs index
-/// If not in a vcs directory, log `Not in an initialized vcs directory.`
-/// If incorrect number of commands, log `Incorrect operands.`
-/// If file doesn't exist, log `File does not exist.`
-/// Explicitly, if the file exists, this function updates the index file with a file's new hash,
-/// and adds the text of the file to the objects directory. It also updates the parent trees'
-/// hashes.
+/// With `-p` (or `--patch`), asks on stdin which hunks of the changes to each tracked file to
+/// stage instead, as described by `add_patch`. Hunks opened in the editor go to `VCS_EDITOR`, or
+/// else `EDITOR`, or else `vi`.
 ///
+/// If not in a vcs directory, returns `NotARepository`
+/// If no pathspecs are given without `-A`, `-u` or `-p`, or more than one of them is given, or
+/// `-p` is given with `--force`, returns `IncorrectOperands`
+/// If a pathspec is outside the worktree, returns `OutsideRepository`
+/// If a pathspec matches no file, returns `PathNotFound`
+/// If a pathspec names an untracked file that a `.vcsignore` rule ignores, returns
+/// `IgnoredPath`, unless `--force` (or `-f`) was given. Ignored files inside a directory or
+/// matching a glob are skipped instead.
+/// Explicitly, this function adds each file's blob to the objects directory, and updates the
+/// index with its hash and mode, or removes it from the index if it is unchanged from the head
+/// commit. A file whose stat data is unchanged since the index's stat cache last saw it is not
+/// read again.
+///
+/// * `dir` - directory `add` was called from
+/// * `args` - arguments `add` was called with
+pub fn add(dir: &Path, args: &[String]) -> Result<(String, String)> {
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout();
+    let mut edit = |text: &str| edit_in_editor(&repo, text);
+    add_with(
+        &repo,
+        dir,
+        args,
+        Interaction {
+            input: &mut input,
+            output: &mut output,
+            edit: &mut edit,
+        },
+    )
+}
+
+/// Executes `vcs add` like `add` does, with `interaction` standing in for stdin, stdout and the
+/// editor when `-p` is given
+///
+/// * `repo` - the repository `dir` is in
+/// * `dir` - directory `add` was called from
 /// * `args` - arguments `add` was called with
-pub fn add(args: &Vec<String>) -> Result<(String, String)> {
+/// * `interaction` - where `add -p` shows hunks and reads answers
+pub fn add_with(
+    repo: &Repository,
+    dir: &Path,
+    args: &[String],
+    interaction: Interaction,
+) -> Result<(String, String)> {
     assert!(args[1] == "add");
-    if !directory_exists(".vcs") {
-        return Ok((
-            String::from("Not in an initialized vcs directory."),
-            String::from(""),
-        ));
-    } else if !file_exists(&format!(".vcs/branches/{}", get_branch_name()?)) {
-        return Ok((
-            String::from(
-                "Currently in a detached HEAD state. Check out a branch to modify the directory.",
-            ),
-            String::from(""),
-        ));
+    if repo.is_detached()? {
+        return Err(VcsError::DetachedHead);
+    }
+
+    let mut force = false;
+    let mut all = false;
+    let mut update = false;
+    let mut patch = false;
+    let mut pathspecs: Vec<&String> = vec![];
+    let mut options = args[2..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "-f" | "--force" => force = true,
+            "-A" | "--all" => all = true,
+            "-u" | "--update" => update = true,
+            "-p" | "--patch" => patch = true,
+            "--" => pathspecs.extend(options.by_ref()),
+            _ => pathspecs.push(arg),
+        }
+    }
+    let modes = [all, update, patch].iter().filter(|&&mode| mode).count();
+    if modes > 1 || (pathspecs.is_empty() && modes == 0) || (patch && force) {
+        return Err(VcsError::IncorrectOperands);
     }
 
-    match args.len() {
-        3 => {
-            let filename = &args[2];
-            if !file_exists(filename) {
-                return Ok((String::from("File does not exist."), String::from("")));
+    let head_files = read_tree_files(repo, &get_commit_tree(repo, &repo.get_head_commit()?)?)?;
+    if patch {
+        return add_patch(repo, dir, &pathspecs, &head_files, interaction);
+    }
+    let ignore_rules = IgnoreRules::load(repo)?;
+    let mut staged_hash = String::new();
+    repo.update_index(|index| {
+        // Every file that could be staged: those in the worktree, and those that were deleted
+        // from it while tracked or staged
+        let mut candidates: BTreeSet<String> = worktree_files(repo)?.into_iter().collect();
+        candidates.extend(head_files.keys().cloned());
+        candidates.extend(index.entries.iter().map(|entry| entry.name().to_string()));
+
+        for (name, pathspec) in resolve_pathspecs(repo, dir, &pathspecs, &candidates)? {
+            let committed = head_files.get(&name);
+            let staged = index.entries.iter().any(|entry| entry.name() == name);
+            let exists = symlink_metadata(repo.worktree_path(&name)).is_ok();
+            if exists && committed.is_none() && !staged {
+                if update {
+                    continue;
+                }
+                // Files that are already tracked stay tracked, whatever the ignore rules say
+                if !force && ignore_rules.is_ignored(&name, false) {
+                    match pathspec {
+                        Some(pathspec) => return Err(VcsError::IgnoredPath(pathspec.to_string())),
+                        None => continue,
+                    }
+                }
             }
-            let hash = create_blob(filename)?;
-            let prev_hash = get_hash_in_commit(&get_head_commit()?, filename)?;
-            let same_as_commit_version = prev_hash == hash;
-            let index_contents = get_file_contents(".vcs/index")?;
-            let mut output: Vec<String> = vec![];
-            let mut seen_file = false;
-            for line in index_contents.split('\n') {
-                if line == "" {
-                    break;
+            // Staging a file replaces whatever was staged for it, conflicts from a merge included
+            index.entries.retain(|entry| entry.name() != name);
+            if exists {
+                let (mode, hash) = index.stat_cache.store_file(repo, &name)?;
+                if committed != Some(&(mode, hash.clone())) {
+                    index.entries.push(IndexEntry::Blob {
+                        hash: hash.clone(),
+                        name,
+                        mode,
+                    });
                 }
-                let split_line: Vec<&str> = line.split(" ").collect();
-                match split_line[0] {
-                    "blob" => {
-                        let line_filename = split_line[2];
-                        if line_filename != filename {
-                            output.push(line.to_string());
-                            continue;
-                        }
-                        seen_file = true;
-                        if !same_as_commit_version {
-                            output.push(format!("blob {} {}", hash, filename))
-                        }
+                staged_hash = hash;
+            } else if committed.is_some() {
+                index.entries.push(IndexEntry::Rm { name });
+            }
+        }
+        Ok(())
+    })?;
+    Ok((String::from(""), staged_hash))
+}
+
+/// Outputs the names in `candidates` that `pathspecs`, relative to `dir`, match, or every
+/// candidate if there are no pathspecs. Each name is paired with the pathspec that names it
+/// exactly, if one does.
+///
+/// Returns `OutsideRepository` for a pathspec outside the worktree, and `PathNotFound` for one
+/// that matches no candidate
+fn resolve_pathspecs<'a>(
+    repo: &Repository,
+    dir: &Path,
+    pathspecs: &[&'a String],
+    candidates: &BTreeSet<String>,
+) -> Result<BTreeMap<String, Option<&'a String>>> {
+    let mut matched: BTreeMap<String, Option<&String>> = BTreeMap::new();
+    if pathspecs.is_empty() {
+        matched.extend(candidates.iter().map(|name| (name.clone(), None)));
+    }
+    for pathspec in pathspecs {
+        let name = repo
+            .relative_name(dir, pathspec)
+            .ok_or_else(|| VcsError::OutsideRepository(pathspec.to_string()))?;
+        let matches = match_pathspec(&name, candidates);
+        if matches.is_empty() {
+            return Err(VcsError::PathNotFound(pathspec.to_string()));
+        }
+        for name in matches {
+            matched.entry(name).or_insert(None);
+        }
+        if candidates.contains(&name) {
+            matched.insert(name, Some(*pathspec));
+        }
+    }
+    Ok(matched)
+}
+
+/// Executes `vcs add -p`: for each file `pathspecs` match that has unstaged changes, shows the
+/// diff from its staged version (or its version in the head commit, `head_files`) to the worktree
+/// one hunk at a time, and asks whether to stage it. The answers are
+///     y: stage the hunk
+///     n: leave it out
+///     q: leave it and the hunks after it out
+///     s: split the hunk at each run of unchanged lines in it, and ask about each part
+///     e: open the hunk in the editor, and stage it as edited
+/// and anything else shows a help text. The end of the input stands for q. A change of mode is
+/// asked about before the hunks of its file.
+///
+/// The hunks chosen for a file are applied to its staged version, and the result is written as a
+/// blob and staged. New, deleted, conflicted and binary files, and symlinks, are left out.
+///
+/// The questions are asked without holding the lock on the index. It is only taken once they are
+/// all answered, to stage the chosen hunks.
+///
+/// Returns the errors of `resolve_pathspecs`, `EditorFailed` if the editor can't be run, and
+/// `IndexChanged` if a file was staged by something else while its hunks were asked about
+fn add_patch(
+    repo: &Repository,
+    dir: &Path,
+    pathspecs: &[&String],
+    head_files: &HashMap<String, (FileMode, String)>,
+    interaction: Interaction,
+) -> Result<(String, String)> {
+    let Interaction {
+        input,
+        output,
+        edit,
+    } = interaction;
+    let mut index = repo.load_index()?;
+    let staged = staged_versions(head_files, &index.entries);
+    let candidates: BTreeSet<String> = staged.keys().cloned().collect();
+
+    let mut chosen: Vec<ChosenFile> = vec![];
+    let mut quit = false;
+    for name in resolve_pathspecs(repo, dir, pathspecs, &candidates)?.into_keys() {
+        if quit {
+            break;
+        }
+        let path = repo.worktree_path(&name);
+        let Ok(metadata) = symlink_metadata(&path) else {
+            continue;
+        };
+        let (old_mode, old_hash) = &staged[&name];
+        if !metadata.is_file() || *old_mode == FileMode::Symlink {
+            continue;
+        }
+        let (mode, hash) = index.stat_cache.hash_file(repo, &name)?;
+        if (mode, &hash) == (*old_mode, old_hash) {
+            continue;
+        }
+        let old = read_blob(repo, old_hash)?;
+        let new = get_blob_hash(&path)?.1;
+        if old.contains(&0) || new.contains(&0) {
+            continue;
+        }
+
+        writeln!(output, "diff --vcs a/{} b/{}", name, name)?;
+        let mut new_mode = *old_mode;
+        if mode != *old_mode {
+            writeln!(output, "old mode {}\nnew mode {}", old_mode, mode)?;
+            match ask(input, output, "Stage mode change [y,n,q,?]? ")? {
+                'y' => new_mode = mode,
+                'n' => {}
+                _ => quit = true,
+            }
+        }
+        let (old_lines, new_lines) = (split_lines(&old), split_lines(&new));
+        let mut pending: VecDeque<Hunk> =
+            hunks(&edit_script(&old_lines, &new_lines), DEFAULT_CONTEXT).into();
+        if !pending.is_empty() {
+            writeln!(output, "--- a/{}\n+++ b/{}", name, name)?;
+        }
+        let mut changes: Vec<Change> = vec![];
+        let mut asked = 0;
+        while !quit {
+            let Some(hunk) = pending.pop_front() else {
+                break;
+            };
+            let text = hunk.text(&old_lines, &new_lines);
+            write!(output, "{}", text)?;
+            let can_split = hunk.split().len() > 1;
+            let question = format!(
+                "({}/{}) Stage this hunk [y,n,q,{}e,?]? ",
+                asked + 1,
+                asked + 1 + pending.len(),
+                if can_split { "s," } else { "" }
+            );
+            match ask(input, output, &question)? {
+                'y' => changes.extend(hunk_changes(&hunk, &new_lines)),
+                'n' => {}
+                'q' => quit = true,
+                's' if can_split => {
+                    let parts = hunk.split();
+                    writeln!(output, "Split into {} hunks.", parts.len())?;
+                    for part in parts.into_iter().rev() {
+                        pending.push_front(part);
                     }
-                    "rm" => {
-                        let line_filename = split_line[1];
-                        if line_filename != filename {
-                            output.push(line.to_string());
+                    continue;
+                }
+                'e' => {
+                    let edited = edit(&format!(
+                        "# Manual hunk edit mode -- see bottom for a quick guide.\n{}{}",
+                        text, EDIT_GUIDE
+                    ))?;
+                    match parse_edited_hunk(&hunk, &old_lines, &edited) {
+                        Some(edited_changes) => changes.extend(edited_changes),
+                        None => {
+                            writeln!(output, "Your edited hunk does not apply.")?;
+                            pending.push_front(hunk);
                             continue;
                         }
-                        seen_file = true;
-                        if !same_as_commit_version {
-                            output.push(format!("blob {} {}", hash, filename))
-                        }
-                    }
-                    _ => {
-                        panic!(
-                            "Expected either `blob` or `rm` as the first part of the index file line, but got {}",
-                            split_line[0]
-                        );
                     }
                 }
+                _ => {
+                    write!(output, "{}", PATCH_HELP)?;
+                    pending.push_front(hunk);
+                    continue;
+                }
+            }
+            asked += 1;
+        }
+
+        if changes.is_empty() && new_mode == *old_mode {
+            continue;
+        }
+        let hash = repo.write_object(ObjectKind::Blob, apply_changes(&old_lines, &changes))?;
+        chosen.push(ChosenFile {
+            base: (*old_mode, old_hash.clone()),
+            name,
+            mode: new_mode,
+            hash,
+        });
+    }
+
+    let mut staged_hash = String::new();
+    repo.update_index(|current| {
+        let now_staged = staged_versions(head_files, &current.entries);
+        let changed: Vec<String> = chosen
+            .iter()
+            .filter(|file| now_staged.get(&file.name) != Some(&file.base))
+            .map(|file| file.name.clone())
+            .collect();
+        if !changed.is_empty() {
+            return Err(VcsError::IndexChanged(changed));
+        }
+        for ChosenFile {
+            name, mode, hash, ..
+        } in chosen
+        {
+            current.entries.retain(|entry| entry.name() != name);
+            if head_files.get(&name) != Some(&(mode, hash.clone())) {
+                current.entries.push(IndexEntry::Blob {
+                    hash: hash.clone(),
+                    name,
+                    mode,
+                });
+            }
+            staged_hash = hash;
+        }
+        if index.stat_cache.is_changed() {
+            current.stat_cache = index.stat_cache;
+        }
+        Ok(())
+    })?;
+    Ok((String::from(""), staged_hash))
+}
+
+/// Outputs the staged version of each tracked file: its version in the head commit, `head_files`,
+/// unless `entries` stage another one or remove it
+fn staged_versions(
+    head_files: &HashMap<String, (FileMode, String)>,
+    entries: &[IndexEntry],
+) -> BTreeMap<String, (FileMode, String)> {
+    let mut staged: BTreeMap<String, (FileMode, String)> = head_files
+        .iter()
+        .map(|(name, version)| (name.clone(), version.clone()))
+        .collect();
+    for entry in entries {
+        match entry {
+            IndexEntry::Blob { hash, name, mode } => {
+                staged.insert(name.clone(), (*mode, hash.clone()));
             }
-            if !seen_file && !same_as_commit_version {
-                output.push(format!("blob {} {}", hash, filename));
+            IndexEntry::Rm { name } | IndexEntry::Conflict { name, .. } => {
+                staged.remove(name);
             }
-            clear_file_contents(".vcs/index")?;
-            let new_index = output.join("\n");
-            let mut file = File::create(".vcs/index")?;
-            file.write_all(new_index.as_bytes())?;
-            Ok((String::from(""), hash))
         }
-        _ => Ok((String::from("Incorrect operands."), String::from(""))),
     }
+    staged
+}
+
+/// Shows `question` on `output`, and outputs the first character of the answer read from
+/// `input`, or `q` at the end of the input
+fn ask(input: &mut dyn BufRead, output: &mut dyn Write, question: &str) -> Result<char> {
+    write!(output, "{}", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        return Ok('q');
+    }
+    Ok(answer.trim().chars().next().unwrap_or('?'))
+}
+
+/// Opens `text` in the user's editor, and outputs the text once the editor exits
+///
+/// The editor is `VCS_EDITOR`, or else `EDITOR`, or else `vi`, and is run through the shell so it
+/// can be given arguments. The text is kept in `.vcs/ADD_EDIT.hunk` while it is edited.
+///
+/// Returns `EditorFailed` if the editor can't be run or exits with an error
+fn edit_in_editor(repo: &Repository, text: &str) -> Result<String> {
+    let editor = std::env::var("VCS_EDITOR")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let path = repo.vcs_dir().join("ADD_EDIT.hunk");
+    std::fs::write(&path, text)?;
+    let status = if cfg!(windows) {
+        Command::new("cmd")
+            .arg("/C")
+            .arg(format!("{} \"{}\"", editor, path.display()))
+            .status()
+    } else {
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg(&editor)
+            .arg(&path)
+            .status()
+    };
+    if !status.is_ok_and(|status| status.success()) {
+        let _ = remove_file(&path);
+        return Err(VcsError::EditorFailed(editor));
+    }
+    let edited = get_file_contents(&path)?;
+    remove_file(&path)?;
+    Ok(edited)
 }
 
 #[cfg(test)]
@@ -109,75 +469,102 @@ pub mod tests {
     // Further partition on no error,
     //      no error no subdirectories, there are subdirectories, same version as commit version,
     //      file was removed
+    // Further partition on ignored files: without --force, with --force, already tracked
+    // Further partition on file mode: regular, executable, symlink, only the mode changed
+    // Further partition on pathspecs: several, directory, glob, ., none with -A or -u
+    // Further partition on files matched: new, modified, deleted while tracked, ignored, deleted
+    //      after being staged
+    // Partitions for add -p
+    //      answers: y, n, q, s, e with a hunk that applies and one that doesn't, unknown, none left
+    //      files: unchanged, modified, untracked; options it can't be combined with
+    //      index: unchanged while asking, another file staged, the same file staged
 
     use super::*;
     use crate::{
-        objects::get_object_contents,
+        objects::{index::index_text, object::hash_object},
         operations::{commit::commit, init::init, rm::rm},
         utils::{
-            fs_utils::{clear_file_contents, get_file_contents},
-            hash::sha2,
-            test_dir::make_test_dir,
+            fs_utils::clear_file_contents,
+            test_dir::{make_test_dir, run},
         },
     };
-    use std::fs::{create_dir_all, File};
+    use std::{
+        fs::{create_dir_all, remove_file, write, File},
+        io::Write,
+    };
 
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = File::create("test.txt");
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = File::create(dir.join("test.txt"));
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("add"),
             String::from("test.txt"),
         ];
-        assert_eq!("Not in an initialized vcs directory.", add(&test_args)?.0);
+        assert!(matches!(
+            add(dir, &test_args),
+            Err(VcsError::NotARepository)
+        ));
         Ok(())
     }
 
     #[test]
     fn incorrect_arg_number() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = File::create("test1.txt");
-        let test_args: Vec<String> = vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-            String::from("test1.txt"),
-        ];
-        assert_eq!("Incorrect operands.", add(&test_args)?.0);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        assert!(matches!(
+            run(dir, "add", &[]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "add", &["-f"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "add", &["-A", "-u", "test.txt"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(repo_index_is_empty(dir)?);
         Ok(())
     }
 
     #[test]
     fn file_does_not_exist() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("add"),
             String::from("test.txt"),
         ];
-        assert_eq!("File does not exist.", add(&test_args)?.0);
+        assert!(matches!(
+            add(dir, &test_args),
+            Err(VcsError::PathNotFound(path)) if path == "test.txt"
+        ));
         Ok(())
     }
 
     #[test]
     fn correct_add_operation() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let _ = File::create(dir.join("test.txt"));
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("add"),
@@ -185,39 +572,44 @@ pub mod tests {
         ];
 
         // Console output check
-        let (output_string, output_hash) = add(&test_args)?;
+        let (output_string, output_hash) = add(dir, &test_args)?;
         assert_eq!("", output_string);
 
         // Mutation of vcs dir check
-        let empty_string_hash = sha2("blob\n");
-        assert_eq!("blob\n", get_object_contents(&empty_string_hash)?);
+        let empty_string_hash = hash_object(ObjectKind::Blob, b"");
+        assert_eq!(
+            "",
+            repo.read_object_text(&empty_string_hash, ObjectKind::Blob)?
+        );
         assert_eq!(output_hash, empty_string_hash);
-        let index_contents = get_file_contents(".vcs/index")?;
+        let index_contents = index_text(&Repository::open(dir).unwrap().read_index()?);
         assert_eq!(
-            format!("blob {} test.txt", empty_string_hash),
+            format!("blob 100644 {} test.txt", empty_string_hash),
             index_contents
         );
 
         // Subdirectory
-        let _ = create_dir_all("test_dir1/test_dir2/");
-        let mut file = File::create("test_dir1/test_dir2/test.txt")?;
+        let _ = create_dir_all(dir.join("test_dir1/test_dir2/"));
+        let mut file = File::create(dir.join("test_dir1/test_dir2/test.txt"))?;
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("add"),
             String::from("test_dir1/test_dir2/test.txt"),
         ];
         let file_text = "Test subdirectories!";
-        let blob_text = String::from("blob\n") + file_text;
-        let blob_hash = sha2(&blob_text);
+        let blob_hash = hash_object(ObjectKind::Blob, file_text.as_bytes());
         let _ = file.write(file_text.as_bytes());
-        let (output_text, output_hash) = add(&test_args)?;
+        let (output_text, output_hash) = add(dir, &test_args)?;
         assert_eq!("", output_text);
-        assert_eq!(output_hash, sha2(&blob_text));
-        assert_eq!(blob_text, get_object_contents(&blob_hash)?);
-        let index_contents = get_file_contents(".vcs/index")?;
+        assert_eq!(output_hash, blob_hash);
+        assert_eq!(
+            file_text,
+            repo.read_object_text(&blob_hash, ObjectKind::Blob)?
+        );
+        let index_contents = index_text(&Repository::open(dir).unwrap().read_index()?);
         assert_eq!(
             format!(
-                "blob {} test.txt\nblob {} test_dir1/test_dir2/test.txt",
+                "blob 100644 {} test.txt\nblob 100644 {} test_dir1/test_dir2/test.txt",
                 empty_string_hash, blob_hash
             ),
             index_contents
@@ -227,137 +619,735 @@ pub mod tests {
 
     #[test]
     fn same_as_commit_version() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add empty test.txt"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add empty test.txt"),
+            ],
+        );
 
         // Test emptiness of index after adding file of same state
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        assert_eq!("", get_file_contents(".vcs/index")?);
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        assert_eq!(
+            "",
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
 
         // Change file and change back
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         file.write_all(b"test text")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        clear_file_contents("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        assert_eq!("", get_file_contents(".vcs/index")?);
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        clear_file_contents(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        assert_eq!(
+            "",
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
 
         // just change file
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         file.write_all(b"test text")?;
-        let (_, hash) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let (_, hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!(
-            format!("blob {} test.txt", hash),
-            get_file_contents(".vcs/index")?
+            format!("blob 100644 {} test.txt", hash),
+            index_text(&Repository::open(dir).unwrap().read_index()?)
         );
-        clear_file_contents("test.txt")?;
+        clear_file_contents(dir.join("test.txt"))?;
         file.write_all(b"different")?;
-        let (_, hash) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let (_, hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!(
-            format!("blob {} test.txt", hash),
-            get_file_contents(".vcs/index")?
+            format!("blob 100644 {} test.txt", hash),
+            index_text(&Repository::open(dir).unwrap().read_index()?)
         );
         Ok(())
     }
 
     #[test]
     fn undoes_remove() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ]);
-        let (_, hash) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        );
+        let (_, hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!(
-            format!("blob {} test.txt", hash),
-            get_file_contents(".vcs/index")?
+            format!("blob 100644 {} test.txt", hash),
+            index_text(&Repository::open(dir).unwrap().read_index()?)
         );
         Ok(())
     }
 
     #[test]
     fn coverage_for_rm_seen_file_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _file = File::create("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ]);
-        let mut file = File::create("test.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _file = File::create(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        );
+        let mut file = File::create(dir.join("test.txt"))?;
         file.write_all(b"coverage!")?;
-        let (_, hash) = add(&vec![
+        let (_, hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        assert_eq!(
+            format!("blob 100644 {} test.txt", hash),
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_subdirectory() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let sub_dir = dir.join("sub");
+        create_dir_all(&sub_dir)?;
+        File::create(sub_dir.join("test.txt"))?;
+        File::create(dir.join("top.txt"))?;
+        let (_, hash) = add(
+            &sub_dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = add(
+            &sub_dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("../top.txt"),
+            ],
+        )?;
+        assert_eq!(
+            format!(
+                "blob 100644 {} sub/test.txt\nblob 100644 {} top.txt",
+                hash, hash
+            ),
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
+        assert!(matches!(
+            add(
+                &sub_dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from("../../outside.txt"),
+                ],
+            ),
+            Err(VcsError::OutsideRepository(path)) if path == "../../outside.txt"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ignored_files() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut ignore = File::create(dir.join(".vcsignore"))?;
+        ignore.write_all(b"*.log\n")?;
+        let _ = File::create(dir.join("debug.log"))?;
+        assert!(matches!(
+            add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from("debug.log"),
+                ]
+            ),
+            Err(VcsError::IgnoredPath(path)) if path == "debug.log"
+        ));
+        assert!(repo_index_is_empty(dir)?);
+        let (_, hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("--force"),
+                String::from("debug.log"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add debug.log"),
+            ],
+        )?;
+
+        // Once tracked, changes can be added without --force
+        let mut log = File::create(dir.join("debug.log"))?;
+        log.write_all(b"more")?;
+        let (_, new_hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("debug.log"),
+            ],
+        )?;
+        assert_ne!(hash, new_hash);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executables_and_symlinks() -> Result<()> {
+        use std::{
+            fs::{set_permissions, Permissions},
+            os::unix::fs::{symlink, PermissionsExt},
+        };
+
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        std::fs::write(dir.join("run.sh"), "echo hi")?;
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o755))?;
+        symlink("missing.txt", dir.join("link"))?;
+        for name in ["run.sh", "link"] {
+            let _ = add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(name),
+                ],
+            )?;
+        }
+        // A symlink is stored as its target, even if the target doesn't exist
+        assert_eq!(
+            vec![
+                IndexEntry::Blob {
+                    hash: hash_object(ObjectKind::Blob, b"echo hi"),
+                    name: String::from("run.sh"),
+                    mode: FileMode::Executable,
+                },
+                IndexEntry::Blob {
+                    hash: hash_object(ObjectKind::Blob, b"missing.txt"),
+                    name: String::from("link"),
+                    mode: FileMode::Symlink,
+                },
+            ],
+            repo.read_index()?
+        );
+
+        // Changing only the mode of a committed file still stages it
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add run.sh and link"),
+            ],
+        )?;
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o644))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("run.sh"),
+            ],
+        )?;
+        assert_eq!(
+            vec![IndexEntry::Blob {
+                hash: hash_object(ObjectKind::Blob, b"echo hi"),
+                name: String::from("run.sh"),
+                mode: FileMode::Regular,
+            }],
+            repo.read_index()?
+        );
+        Ok(())
+    }
+
+    /// Outputs the index of the repository in `dir` as text
+    fn staged(dir: &Path) -> Result<String> {
+        Ok(index_text(&Repository::open(dir).unwrap().read_index()?))
+    }
+
+    #[test]
+    fn pathspecs() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        create_dir_all(dir.join("src/nested"))?;
+        create_dir_all(dir.join("docs"))?;
+        write(dir.join(".vcsignore"), "*.log\n")?;
+        for name in [
+            "a.txt",
+            "b.txt",
+            "c.md",
+            "src/main.rs",
+            "src/nested/lib.rs",
+            "src/nested/notes.txt",
+            "src/debug.log",
+            "docs/guide.md",
+        ] {
+            write(dir.join(name), name)?;
+        }
+        let hash = |name: &str| hash_object(ObjectKind::Blob, name.as_bytes());
+        let line = |name: &str| format!("blob 100644 {} {}", hash(name), name);
+
+        // Several files at once, and a directory, whose ignored files are skipped
+        let args = ["target/debug/vcs", "add", "a.txt", "c.md", "src"].map(String::from);
+        let (_, last_hash) = add(dir, &args)?;
+        assert_eq!(hash("src/nested/notes.txt"), last_hash);
+        assert_eq!(
+            [
+                "a.txt",
+                "c.md",
+                "src/main.rs",
+                "src/nested/lib.rs",
+                "src/nested/notes.txt"
+            ]
+            .map(line)
+            .join("\n"),
+            staged(dir)?
+        );
+
+        // Globs match within one directory, unless they use **/
+        let repo = Repository::open(dir).unwrap();
+        repo.write_index(&[])?;
+        run(dir, "add", &["*.txt"])?;
+        assert_eq!(["a.txt", "b.txt"].map(line).join("\n"), staged(dir)?);
+        run(dir, "add", &["**/*.rs", "doc?"])?;
+        assert_eq!(
+            [
+                "a.txt",
+                "b.txt",
+                "docs/guide.md",
+                "src/main.rs",
+                "src/nested/lib.rs"
+            ]
+            .map(line)
+            .join("\n"),
+            staged(dir)?
+        );
+
+        // . is the directory add is run from
+        repo.write_index(&[])?;
+        run(&dir.join("src"), "add", &["."])?;
+        assert_eq!(
+            ["src/main.rs", "src/nested/lib.rs", "src/nested/notes.txt"]
+                .map(line)
+                .join("\n"),
+            staged(dir)?
+        );
+
+        // Nothing is staged if any pathspec matches nothing, or names an ignored file
+        assert!(matches!(
+            run(dir, "add", &["b.txt", "*.py"]),
+            Err(VcsError::PathNotFound(path)) if path == "*.py"
+        ));
+        assert!(matches!(
+            run(dir, "add", &["b.txt", "src/debug.log"]),
+            Err(VcsError::IgnoredPath(path)) if path == "src/debug.log"
+        ));
+        assert!(!staged(dir)?.contains("b.txt"));
+        // Options aren't read after --
+        assert!(matches!(
+            run(dir, "add", &["-f", "--", "src/debug.log", "-u"]),
+            Err(VcsError::PathNotFound(path)) if path == "-u"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn all_and_update() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        write(dir.join("kept.txt"), "kept")?;
+        write(dir.join("changed.txt"), "old")?;
+        write(dir.join("deleted.txt"), "deleted")?;
+        run(dir, "add", &["."])?;
+        commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add files"),
+            ],
+        )?;
+        write(dir.join("changed.txt"), "new")?;
+        remove_file(dir.join("deleted.txt"))?;
+        write(dir.join("new.txt"), "new")?;
+        let new_hash = hash_object(ObjectKind::Blob, b"new");
+
+        // -u stages changes to tracked files, deletions included, and leaves new files alone
+        run(dir, "add", &["-u"])?;
+        assert_eq!(
+            format!("blob 100644 {} changed.txt\nrm deleted.txt", new_hash),
+            staged(dir)?
+        );
+
+        // -A stages everything, and a deleted file can be named like any other
+        let repo = Repository::open(dir).unwrap();
+        repo.write_index(&[])?;
+        run(dir, "add", &["deleted.txt"])?;
+        assert_eq!("rm deleted.txt", staged(dir)?);
+        run(dir, "add", &["--all"])?;
+        assert_eq!(
+            format!(
+                "blob 100644 {} changed.txt\nrm deleted.txt\nblob 100644 {} new.txt",
+                new_hash, new_hash
+            ),
+            staged(dir)?
+        );
+
+        // A staged file that was deleted since is unstaged
+        remove_file(dir.join("new.txt"))?;
+        run(dir, "add", &["new.txt"])?;
+        assert_eq!(
+            format!("blob 100644 {} changed.txt\nrm deleted.txt", new_hash),
+            staged(dir)?
+        );
+        Ok(())
+    }
+
+    /// Runs `vcs add -p` in `dir` with `operands` as arguments, answering with the lines of
+    /// `answers` and editing hunks with `edit`. Outputs what was shown.
+    fn run_patch(
+        dir: &Path,
+        operands: &[&str],
+        answers: &str,
+        mut edit: impl FnMut(&str) -> Result<String>,
+    ) -> Result<String> {
+        let mut args = vec![
             String::from("target/debug/vcs"),
             String::from("add"),
-            String::from("test.txt"),
-        ])?;
+            String::from("-p"),
+        ];
+        args.extend(operands.iter().map(|operand| String::from(*operand)));
+        let mut output = vec![];
+        let repo = Repository::find(dir).unwrap();
+        add_with(
+            &repo,
+            dir,
+            &args,
+            Interaction {
+                input: &mut answers.as_bytes(),
+                output: &mut output,
+                edit: &mut edit,
+            },
+        )?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    /// Outputs the contents staged for `name` in the repository in `dir`
+    fn staged_contents(dir: &Path, name: &str) -> Result<String> {
+        let repo = Repository::open(dir).unwrap();
+        let index = repo.read_index()?;
+        let Some(IndexEntry::Blob { hash, .. }) = index.iter().find(|entry| entry.name() == name)
+        else {
+            panic!("{} is not staged", name);
+        };
+        Ok(String::from_utf8(read_blob(&repo, hash)?).unwrap())
+    }
+
+    /// Makes a repository in `dir` with `numbers.txt`, holding the numbers 1 to 20 one per line,
+    /// committed, then changes lines 2, 5 and 19 in the worktree. Outputs the original text.
+    fn setup_numbers(dir: &Path) -> Result<String> {
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        let text: String = (1..=20).map(|line| format!("{}\n", line)).collect();
+        write(dir.join("numbers.txt"), &text)?;
+        run(dir, "add", &["numbers.txt"])?;
+        commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add numbers"),
+            ],
+        )?;
+        write(
+            dir.join("numbers.txt"),
+            text.replace("\n2\n", "\ntwo\n")
+                .replace("\n5\n", "\nfive\n")
+                .replace("\n19\n", "\nnineteen\n"),
+        )?;
+        Ok(text)
+    }
+
+    #[test]
+    fn patch_chooses_hunks() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let text = setup_numbers(dir)?;
+        write(dir.join("untracked.txt"), "new")?;
+        let no_edits = |_: &str| -> Result<String> { panic!("nothing should be edited") };
+
+        assert!(matches!(
+            run_patch(dir, &["-A"], "", no_edits),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run_patch(dir, &["-f", "numbers.txt"], "", no_edits),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run_patch(dir, &["untracked.txt"], "", no_edits),
+            Err(VcsError::PathNotFound(path)) if path == "untracked.txt"
+        ));
+
+        // Split the first hunk, and stage the change to line 2 and the one to line 19
+        let output = run_patch(dir, &[], "s\ny\nn\ny\n", no_edits)?;
+        assert!(output.starts_with(
+            "diff --vcs a/numbers.txt b/numbers.txt\n--- a/numbers.txt\n+++ b/numbers.txt\n@@ -1,8 +1,8 @@\n"
+        ));
+        assert!(output.contains("(1/2) Stage this hunk [y,n,q,s,e,?]? Split into 2 hunks.\n"));
+        assert!(output.contains("(3/3) Stage this hunk [y,n,q,e,?]? "));
         assert_eq!(
-            format!("blob {} test.txt", hash),
-            get_file_contents(".vcs/index")?
+            text.replace("\n2\n", "\ntwo\n")
+                .replace("\n19\n", "\nnineteen\n"),
+            staged_contents(dir, "numbers.txt")?
         );
+
+        // Only the change to line 5 is left to ask about. Unknown answers show the help, and
+        // quitting, or running out of answers, stages nothing more.
+        let output = run_patch(dir, &["numbers.txt"], "x\nq\n", no_edits)?;
+        assert!(output.contains("@@ -2,7 +2,7 @@\n two\n 3\n 4\n-5\n+five\n"));
+        assert!(output.contains(PATCH_HELP));
+        run_patch(dir, &["."], "", no_edits)?;
+        assert!(!staged_contents(dir, "numbers.txt")?.contains("five"));
+        run_patch(dir, &["*.txt"], "y\n", no_edits)?;
+        assert_eq!(
+            get_file_contents(dir.join("numbers.txt"))?,
+            staged_contents(dir, "numbers.txt")?
+        );
+
+        // Nothing is left to ask about
+        assert_eq!("", run_patch(dir, &[], "", no_edits)?);
         Ok(())
     }
+
+    #[test]
+    fn patch_edits_hunks() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let text = setup_numbers(dir)?;
+
+        // The first edit changes a line that isn't being added, so the hunk is asked about again
+        let mut edits = 0;
+        let edit = |hunk: &str| -> Result<String> {
+            edits += 1;
+            assert!(hunk.starts_with("# Manual hunk edit mode"));
+            assert!(hunk.contains("\n-2\n+two\n"));
+            assert!(hunk.ends_with(EDIT_GUIDE));
+            if edits == 1 {
+                Ok(hunk.replace(" 3\n", " three\n"))
+            } else {
+                // Change the added line, and leave out the change to line 5
+                Ok(hunk
+                    .replace("+two\n", "+TWO\n")
+                    .replace("-5\n", " 5\n")
+                    .replace("+five\n", ""))
+            }
+        };
+        let output = run_patch(dir, &[], "e\ne\nn\n", edit)?;
+        assert_eq!(2, edits);
+        assert!(output.contains("Your edited hunk does not apply.\n@@ -1,8 +1,8 @@\n"));
+        assert_eq!(
+            text.replace("\n2\n", "\nTWO\n"),
+            staged_contents(dir, "numbers.txt")?
+        );
+
+        // An editor that fails stages nothing
+        assert!(matches!(
+            run_patch(dir, &[], "e\n", |_: &str| Err(VcsError::EditorFailed(
+                String::from("false")
+            ))),
+            Err(VcsError::EditorFailed(editor)) if editor == "false"
+        ));
+        assert_eq!(
+            text.replace("\n2\n", "\nTWO\n"),
+            staged_contents(dir, "numbers.txt")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn patch_asks_without_lock() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let text = setup_numbers(dir)?;
+        write(dir.join("other.txt"), "other")?;
+
+        // Another file can be staged while a hunk is being edited
+        let output = run_patch(
+            dir,
+            &[],
+            "e
+",
+            |hunk: &str| {
+                run(dir, "add", &["other.txt"])?;
+                Ok(hunk.to_string())
+            },
+        )?;
+        assert!(!output.contains("does not apply"));
+        assert!(staged(dir)?.contains(" other.txt"));
+        assert_eq!(
+            text.replace("\n2\n", "\ntwo\n")
+                .replace("\n5\n", "\nfive\n"),
+            staged_contents(dir, "numbers.txt")?
+        );
+
+        // Staging the same file meanwhile stages none of the hunks chosen
+        let output = run_patch(
+            dir,
+            &[],
+            "e
+",
+            |hunk: &str| {
+                run(dir, "add", &["numbers.txt"])?;
+                Ok(hunk.to_string())
+            },
+        );
+        assert!(matches!(
+            output,
+            Err(VcsError::IndexChanged(names)) if names == ["numbers.txt"]
+        ));
+        assert_eq!(
+            get_file_contents(dir.join("numbers.txt"))?,
+            staged_contents(dir, "numbers.txt")?
+        );
+        Ok(())
+    }
+
+    /// Returns true iff nothing is staged in the repository in `dir`
+    fn repo_index_is_empty(dir: &Path) -> Result<bool> {
+        Ok(Repository::open(dir).unwrap().read_index()?.is_empty())
+    }
 }
@@ -1,79 +1,79 @@
-use std::{
-    fs::{read_dir, remove_file, File},
-    io::{Result, Write},
-};
+use std::path::Path;
 
 use crate::{
-    objects::{commit::get_head_commit, get_branch_name},
-    utils::fs_utils::{directory_exists, file_exists, no_dir_string},
+    error::{Result, VcsError},
+    objects::{
+        reflog::{delete_branch, move_branch},
+        repo::Repository,
+    },
 };
 
+/// Usage text shown by `vcs help branch`
+pub const USAGE: &str = "usage: vcs branch [<branch> | -d <branch>]\n\nList branches, create <branch>, or delete <branch> with -d.";
+
 /// Executes `vcs branch` with `args` as arguments. returns the string that should be logged to the
 /// console.
 ///
 /// There are three possible uses of this function:
 ///     1. `vcs branch`: Lists the branches in alphabetical order, with a * to the right of the
 ///        current branch
-///     2. `vcs branch <BRANCH_NAME>`: Creates a new branch with name <BRANCH_NAME>. Will log `A
-///        branch named <BRANCH_NAME> already exist.` if trying to create a new branch of the same
-///        name.
+///     2. `vcs branch <BRANCH_NAME>`: Creates a new branch with name <BRANCH_NAME>. Returns
+///        `BranchExists` if trying to create a new branch of the same name, and
+///        `InvalidBranchName` if the name is empty, contains `/`, `..` or NUL, starts with `.`
+///        or ends in `.lock`.
 ///     3. `vcs branch -d <BRANCH_NAME>`: Deletes the branch named <BRANCH_NAME>. Will log `Deleted
-///        branch <BRANCH_NAME>.` if successful, `Branch <BRANCH_NAME> was not found.` if the
-///        requested branch doesn't exist, and `Cannot delete branch <BRANCH_NAME>. Switch to a
-///        different branch to delete.` if on the same branch as the one requested for deletion.
+///        branch <BRANCH_NAME>.` if successful, return `BranchNotFound` if the requested branch
+///        doesn't exist, and `CannotDeleteCurrentBranch` if on the same branch as the one
+///        requested for deletion. The branch's reflog is kept, so its commits can be found
+///        with `vcs reflog <BRANCH_NAME>`.
 ///
-/// If there are an incorrect number of arguments, log `Incorrect operands.`, and if not in an
-/// initialized vcs directory, log `Not in an initialized vcs directory.`.
-pub fn branch(args: &Vec<String>) -> Result<String> {
-    if !directory_exists(".vcs") {
-        return Ok(String::from("Not in an initialized vcs directory."));
-    }
+/// If there are an incorrect number of arguments, returns `IncorrectOperands`, and if not in an
+/// initialized vcs directory, returns `NotARepository`.
+///
+/// * `dir` - directory `branch` was called from
+/// * `args` - arguments `branch` was called with
+pub fn branch(dir: &Path, args: &[String]) -> Result<String> {
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
     assert_eq!(args[1], "branch");
     match args.len() {
         2 => {
             let mut branches: Vec<String> = vec![];
-            let current_branch = get_branch_name()?;
-            for entry in read_dir(".vcs/branches")? {
-                let entry = entry?;
-                let path = entry.path();
-                assert!(path.is_file());
-                let branchname = no_dir_string(path);
+            let current_branch = repo.get_branch_name()?;
+            for branchname in repo.list_branches()? {
                 if branchname == current_branch {
                     branches.push(format!("{} *", branchname));
                 } else {
                     branches.push(branchname);
                 }
             }
-            branches.sort();
-            return Ok(branches.join("\n"));
+            Ok(branches.join("\n"))
         }
         3 => {
             let new_branchname = &args[2];
-            let filename = format!(".vcs/branches/{}", new_branchname);
-            if file_exists(&filename) {
-                return Ok(format!("A branch named {} already exists.", new_branchname));
+            if repo.branch_exists(new_branchname) {
+                return Err(VcsError::BranchExists(new_branchname.clone()));
             }
-            let mut file = File::create(filename)?;
-            let current_commit = get_head_commit()?;
-            file.write_all(current_commit.as_bytes())?;
-            return Ok(String::from(""));
+            let current_commit = repo.get_head_commit()?;
+            move_branch(
+                &repo,
+                new_branchname,
+                &current_commit,
+                "branch: Created from HEAD",
+            )?;
+            Ok(String::from(""))
         }
         4 => {
-            let filename = format!(".vcs/branches/{}", args[3]);
             if args[2] != "-d" {
-                return Ok(String::from("Incorrect operands"));
-            } else if args[3] == get_branch_name()? {
-                return Ok(format!(
-                    "Cannot delete branch {}. Switch to a different branch to delete.",
-                    args[3]
-                ));
-            } else if !file_exists(&filename) {
-                return Ok(format!("Branch {} was not found.", args[3]));
+                return Err(VcsError::IncorrectOperands);
+            } else if args[3] == repo.get_branch_name()? {
+                return Err(VcsError::CannotDeleteCurrentBranch(args[3].clone()));
+            } else if !repo.branch_exists(&args[3]) {
+                return Err(VcsError::BranchNotFound(args[3].clone()));
             }
-            remove_file(format!(".vcs/branches/{}", args[3]))?;
-            return Ok(format!("Deleted branch {}.", args[3]));
+            delete_branch(&repo, &args[3])?;
+            Ok(format!("Deleted branch {}.", args[3]))
         }
-        _ => return Ok(String::from("Incorrect operands.")),
+        _ => Err(VcsError::IncorrectOperands),
     }
 }
 
@@ -85,13 +85,13 @@ pub mod tests {
     //      Error not in vcs, error incorrect operands, list branches, create branch, delete
     //      branch.
     //  Further partition on number of branches to list: 1, >1
-    //  Further partition on creation of branches: no error, one already exists
+    //  Further partition on creation of branches: no error, one already exists, invalid name
     //  Further partition on deleting branches: no error, doesn't exist, same branch
 
     use super::*;
 
     use crate::{
-        objects::commit::{get_head_commit, INITIAL_COMMIT_HASH},
+        objects::commit::INITIAL_COMMIT_HASH,
         operations::init::init,
         utils::{
             fs_utils::{file_exists, get_file_contents},
@@ -101,182 +101,256 @@ pub mod tests {
 
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
         let test_args: Vec<String> = vec![String::from("target/debug/vcs"), String::from("branch")];
-        assert_eq!("Not in an initialized vcs directory.", branch(&test_args)?);
+        assert!(matches!(
+            branch(dir, &test_args),
+            Err(VcsError::NotARepository)
+        ));
         Ok(())
     }
 
     #[test]
     pub fn incorrect_arguments() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(
-            "Incorrect operands.",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-                String::from("b"),
-                String::from("c"),
-                String::from("a"),
-            ])?
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
+        assert!(matches!(
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("b"),
+                    String::from("c"),
+                    String::from("a")
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
         Ok(())
     }
 
     #[test]
     pub fn list_one_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         assert_eq!(
             "main *",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-            ])?
+            branch(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("branch")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     pub fn list_many_branches() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         assert_eq!(
             "",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-                String::from("test_branch"),
-            ])?
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("test_branch")
+                ]
+            )?
         );
         assert_eq!(
             "main *\ntest_branch",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-            ])?
+            branch(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("branch")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     pub fn test_branch_creation() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
         assert_eq!(
             "",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-                String::from("test_branch"),
-            ])?
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("test_branch")
+                ]
+            )?
         );
-        assert!(file_exists(".vcs/branches/test_branch"));
-        assert_eq!("main", get_branch_name()?);
+        assert!(file_exists(dir.join(".vcs/branches/test_branch")));
+        assert_eq!("main", repo.get_branch_name()?);
         assert_eq!(
             INITIAL_COMMIT_HASH,
-            get_file_contents(".vcs/branches/test_branch")?
+            get_file_contents(dir.join(".vcs/branches/test_branch"))?
         );
         Ok(())
     }
 
     #[test]
     pub fn test_creating_already_existing_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = branch(&vec![
-            String::from("target/debug/vcs"),
-            String::from("branch"),
-            String::from("test_branch"),
-        ])?;
-        assert_eq!(
-            "A branch named test_branch already exists.",
-            branch(&vec![
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = branch(
+            dir,
+            &[
                 String::from("target/debug/vcs"),
                 String::from("branch"),
                 String::from("test_branch"),
-            ])?
+            ],
+        )?;
+        assert!(matches!(
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("test_branch")
+                ]
+            ),
+            Err(VcsError::BranchExists(name)) if name == "test_branch"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_invalid_branch_names() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        for name in ["", "../x", "a/b", "a..b", ".hidden", "foo.lock", "nul\0"] {
+            assert!(matches!(
+                branch(
+                    dir,
+                    &[
+                        String::from("target/debug/vcs"),
+                        String::from("branch"),
+                        String::from(name),
+                    ]
+                ),
+                Err(VcsError::InvalidBranchName(invalid)) if invalid == name
+            ));
+        }
+        assert!(!dir.join(".vcs/x").exists());
+        assert!(!dir.join(".vcs/branches/foo.lock").exists());
+        assert_eq!(
+            "main *",
+            branch(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("branch")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     pub fn test_delete_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = branch(&vec![
-            String::from("target/debug/vcs"),
-            String::from("branch"),
-            String::from("test_branch"),
-        ])?;
-        assert_eq!(
-            "Deleted branch test_branch.",
-            branch(&vec![
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let _ = branch(
+            dir,
+            &[
                 String::from("target/debug/vcs"),
                 String::from("branch"),
-                String::from("-d"),
                 String::from("test_branch"),
-            ])?
+            ],
+        )?;
+        assert_eq!(
+            "Deleted branch test_branch.",
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("-d"),
+                    String::from("test_branch")
+                ]
+            )?
         );
-        assert!(!file_exists(".vcs/branches/test_branch"));
-        assert_eq!(INITIAL_COMMIT_HASH, get_head_commit()?);
+        assert!(!file_exists(dir.join(".vcs/branches/test_branch")));
+        assert_eq!(INITIAL_COMMIT_HASH, repo.get_head_commit()?);
         Ok(())
     }
 
     #[test]
     pub fn test_delete_nonexistent_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(
-            "Branch test_branch was not found.",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-                String::from("-d"),
-                String::from("test_branch"),
-            ])?
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
+        assert!(matches!(
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("-d"),
+                    String::from("test_branch")
+                ]
+            ),
+            Err(VcsError::BranchNotFound(name)) if name == "test_branch"
+        ));
         Ok(())
     }
 
     #[test]
     pub fn test_delete_current_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(
-            "Cannot delete branch main. Switch to a different branch to delete.",
-            branch(&vec![
-                String::from("target/debug/vcs"),
-                String::from("branch"),
-                String::from("-d"),
-                String::from("main"),
-            ])?
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
+        assert!(matches!(
+            branch(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("branch"),
+                    String::from("-d"),
+                    String::from("main")
+                ]
+            ),
+            Err(VcsError::CannotDeleteCurrentBranch(name)) if name == "main"
+        ));
         Ok(())
     }
 }
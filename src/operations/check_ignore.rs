@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{ignore::IgnoreRules, repo::Repository},
+    utils::fs_utils::directory_exists,
+};
+
+/// Usage text shown by `vcs help check-ignore`
+pub const USAGE: &str = "usage: vcs check-ignore [-v | --verbose] <path>...\n\nShow which of the paths are ignored.\n\n    -v, --verbose    also show the rule deciding each path, as <file>:<line>:<pattern>";
+
+/// Executes `vcs check-ignore` with `args` as arguments. Returns the string that should be logged
+/// to the console.
+///
+/// Logs each of the given paths that a `.vcsignore` file or `.vcs/info/exclude` ignores, one per
+/// line. With `-v`, every path a rule matches is logged as `<file>:<line>:<pattern>\t<path>`,
+/// naming the rule that decides it, including `!` rules that re-include the path. Paths don't
+/// need to exist; those that are directories are matched as directories.
+///
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if no paths were given,
+/// and `OutsideRepository` if a path is outside the worktree.
+///
+/// * `dir` - directory `check-ignore` was called from
+/// * `args` - arguments `check-ignore` was called with
+pub fn check_ignore(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "check-ignore");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let verbose = args[2..]
+        .iter()
+        .any(|arg| arg == "-v" || arg == "--verbose");
+    let pathspecs: Vec<&String> = args[2..]
+        .iter()
+        .filter(|arg| *arg != "-v" && *arg != "--verbose")
+        .collect();
+    if pathspecs.is_empty() {
+        return Err(VcsError::IncorrectOperands);
+    }
+
+    let rules = IgnoreRules::load(&repo)?;
+    let mut output: Vec<String> = vec![];
+    for pathspec in pathspecs {
+        let name = repo
+            .relative_name(dir, pathspec)
+            .ok_or_else(|| VcsError::OutsideRepository(pathspec.to_string()))?;
+        let is_dir = directory_exists(repo.worktree_path(&name));
+        match rules.matching_rule(&name, is_dir) {
+            Some(rule) if verbose => output.push(format!("{}\t{}", rule, pathspec)),
+            Some(rule) if !rule.negated => output.push(pathspec.to_string()),
+            _ => {}
+        }
+    }
+    Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for check-ignore
+    //      Failure cases: not in vcs dir, no paths
+    //      Paths: ignored, re-included with `!`, not matched; files, directories
+    //      Verbose: yes, no
+
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+    use crate::{operations::init::init, utils::test_dir::make_test_dir};
+
+    #[test]
+    fn not_in_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            check_ignore(
+                test_dir.path(),
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("check-ignore"),
+                    String::from("a.txt"),
+                ]
+            ),
+            Err(VcsError::NotARepository)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn incorrect_operands() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        assert!(matches!(
+            check_ignore(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("check-ignore"),
+                    String::from("-v"),
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn explains_matches() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        write(dir.join(".vcsignore"), "# Build output\ntarget/\n*.log\n")?;
+        create_dir_all(dir.join("logs"))?;
+        write(dir.join("logs/.vcsignore"), "!keep.log\n")?;
+        create_dir_all(dir.join("target"))?;
+
+        let args = |verbose: bool| {
+            let mut args = vec![
+                String::from("target/debug/vcs"),
+                String::from("check-ignore"),
+            ];
+            if verbose {
+                args.push(String::from("-v"));
+            }
+            for path in ["target", "debug.log", "logs/keep.log", "src/main.rs"] {
+                args.push(String::from(path));
+            }
+            args
+        };
+        assert_eq!("target\ndebug.log", check_ignore(dir, &args(false))?);
+        assert_eq!(
+            ".vcsignore:2:target/\ttarget\n.vcsignore:3:*.log\tdebug.log\nlogs/.vcsignore:1:!keep.log\tlogs/keep.log",
+            check_ignore(dir, &args(true))?
+        );
+
+        // Paths are relative to the current directory
+        assert_eq!(
+            "../debug.log",
+            check_ignore(
+                &dir.join("logs"),
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("check-ignore"),
+                    String::from("../debug.log"),
+                ]
+            )?
+        );
+        Ok(())
+    }
+}
@@ -1,162 +1,384 @@
 use std::{
-    collections::HashSet,
-    fs::{read_dir, remove_file, File},
-    io::{Result, Write},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::symlink_metadata,
+    path::Path,
 };
 
 use crate::{
+    error::{Result, VcsError},
     objects::{
-        commit::{get_commit_tree, get_hash_in_commit, get_head_commit},
-        get_branch_name, get_object_contents, object_exists,
-        tree::serialize_tree,
-    },
-    utils::{
-        fs_utils::{
-            clear_file_contents, directory_exists, file_exists, get_file_contents, no_dir_string,
-        },
-        hash::sha2,
+        blob::{create_blob, get_blob_hash, read_blob},
+        commit::{get_commit_tree, get_entry_in_commit},
+        index::IndexEntry,
+        merge::{merge_trees, FileVersion},
+        mode::FileMode,
+        reflog::switch_head,
+        repo::Repository,
+        revision::{branch_name, resolve_commit},
+        stat_cache::StatCache,
+        tree::{read_tree, read_tree_files, update_tree},
+        worktree::{local_changes, remove_worktree_file, worktree_files, write_worktree_file},
     },
 };
 
+/// Usage text shown by `vcs help checkout`
+pub const USAGE: &str = "usage: vcs checkout [-f | --force | -m | --merge] <branch>\n   or: vcs checkout [-f | --force | -m | --merge] <commit>\n   or: vcs checkout [<commit>] -- <file>\n\nSwitch to a branch or commit, or restore <file> from a commit. <commit> can be any\nrevision, such as HEAD~2, an abbreviated hash or @{-1} for the previous branch.\n\n    -f, --force    throw away local changes and staged changes\n    -m, --merge    merge local changes to tracked files into the new commit's versions";
+
+/// What switching branches or commits does with local changes to the files it has to replace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SwitchMode {
+    /// Refuse to switch
+    Safe,
+    /// Throw the changes away
+    Force,
+    /// Merge the changes into the versions being checked out
+    Merge,
+}
+
 /// Executes `vcs checkout` with `args` as arguments. Returns the string that should be logged to
 /// the console.
 ///
 /// There are three possible use cases of this function:
 ///     1. `vcs checkout <BRANCH_NAME>`: Takes all files in the commit at the head of the given
-///         branch, and puts them in the working directory, overwriting the versions of the files
-///         that are already there if they exist. Also, at the end of this command, the given
-///         branch will now be considered the current branch (HEAD). Any files that are tracked in
-///         the current branch but are not present in the checked-out branch are deleted. Clears
-///         the index. Logs the text `Switched to branch <BRANCH_NAME>.` if it's a different branch.
-///         If the branch to check out is the current branch, log `Already on <BRANCH_NAME>.` If the
-///         branch does not exist, log `<BRANCH_NAME> does not exist.`
+///         branch that differ from the current HEAD, and puts them in the working directory. Also,
+///         at the end of this command, the given branch will now be considered the current branch
+///         (HEAD). Any files that are tracked in the current branch but are not present in the
+///         checked-out branch are deleted. Local and staged changes to the other files are kept.
+///         Logs the text `Switched to branch <BRANCH_NAME>.` if it's a different branch. If the
+///         branch to check out is the current branch, log `Already on <BRANCH_NAME>.` If the
+///         branch does not exist, returns `InvalidRef`.
 ///     2. `vcs checkout [commit_id] -- <FILE_NAME>`: Takes the version of the file as it exists in
 ///         the commit with the given id, and puts it in the working directory, overwriting the
 ///         version of the file that’s already there if there is one. The new version of the file is
 ///         not staged. If the commit_id is omitted, use the version of the file from the head
-///         commit instead. If the commit id doesn't exist, logs `No commit with ID <COMMIT_ID>
-///         exists.`
-///     3. `vcs checkout [commit_id]`: Takes all files in the commit specified, and puts them in
-///        the working directory, overwriting the version of the files that are already there if
-///        they exist. Any files not tracked in the commit will be deleted, and clears the index.
-///        Logs `Switched to commit <COMMIT_ID>.` The new head will be detached, and any
-///        modications to the vcs directory (via `add`, `rm`, or `commit`) will return the message
-///        `Currently in a detached HEAD state, check out a branch to modify the directory.`
+///         commit instead. If the commit id doesn't exist, returns `InvalidRef`.
+///     3. `vcs checkout [commit_id]`: Switches to the commit specified as for a branch, and logs
+///        `Switched to commit <COMMIT_ID>.` The new head will be detached, and any
+///        modications to the vcs directory (via `add`, `rm`, or `commit`) will return
+///        `DetachedHead`.
+///
+/// Before switching, checkout works out which files it has to overwrite or remove. If any of them
+/// has staged changes, differs in the worktree from both the current and the new version, or is
+/// untracked and in the way, it returns `WouldOverwrite` listing them and changes nothing.
+/// `--force` throws those changes away instead, putting every file of the new commit in the
+/// worktree and clearing the index, even when the branch is already checked out. `--merge`
+/// merges the local changes to tracked files into the new versions line by line, as `merge` does,
+/// leaving the results unstaged; files that don't merge cleanly get conflict markers and
+/// conflicted index entries, and the conflicts are logged before the `Switched to` line.
+/// Untracked files in the way still return `WouldOverwrite`.
+///
+/// Files are written with the mode recorded in the commit, so executables get their executable
+/// bits back and symlinks are recreated as symlinks.
+///
+/// Commits can be named by any revision `resolve_revision` understands. `@{-1}` switches back to
+/// the branch checked out before, or to the commit if HEAD was detached.
 ///
-/// If there are an incorrect number of arguments, log `Incorrect operands.`, and if not in an
-/// initialized vcs directory, log `Not in an initialized vcs directory.`.
-pub fn checkout(args: &Vec<String>) -> Result<String> {
+/// If there are an incorrect number of arguments, returns `IncorrectOperands`, and if not in an
+/// initialized vcs directory, returns `NotARepository`. Switching branches or commits while a
+/// merge has stopped for conflicts returns `MergeInProgress`.
+///
+/// * `dir` - directory `checkout` was called from
+/// * `args` - arguments `checkout` was called with
+pub fn checkout(dir: &Path, args: &[String]) -> Result<String> {
     assert!(args[1] == "checkout");
-    if !directory_exists(".vcs") {
-        return Ok(String::from("Not in an initialized vcs directory."));
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let mut mode = SwitchMode::Safe;
+    let mut operands = &args[2..];
+    while let Some(option) = operands.first() {
+        let option_mode = match option.as_str() {
+            "-f" | "--force" => SwitchMode::Force,
+            "-m" | "--merge" => SwitchMode::Merge,
+            _ => break,
+        };
+        if mode != SwitchMode::Safe && mode != option_mode {
+            return Err(VcsError::IncorrectOperands);
+        }
+        mode = option_mode;
+        operands = &operands[1..];
     }
-    match args.len() {
-        3 => {
-            if args[2] == get_branch_name()? {
-                return Ok(format!("Already on {}.", args[2]));
-            }
-            if file_exists(&format!(".vcs/branches/{}", args[2])) {
-                clear_file_contents(".vcs/HEAD")?;
-                // Modify HEAD file and index
-                let mut head_file = File::create(".vcs/HEAD")?;
-                head_file.write_all(args[2].as_bytes())?;
-                clear_file_contents(".vcs/index")?;
-                // // Modify directory state
-                let commit_hash = get_file_contents(&format!(".vcs/branches/{}", args[2]))?;
-                update_dir_state(commit_hash)?;
-                return Ok(format!("Switched to branch {}.", args[2]));
-            } else if object_exists(&args[2]) {
-                clear_file_contents(".vcs/HEAD")?;
-                // Modify HEAD file
-                let mut head_file = File::create(".vcs/HEAD")?;
-                head_file.write_all(args[2].as_bytes())?;
-                clear_file_contents(".vcs/index")?;
-                // // Modify directory state
-                update_dir_state(args[2].clone())?;
-                return Ok(format!("Switched to commit {}.", args[2]));
+
+    match operands {
+        [target] => switch(&repo, target, mode),
+        [separator, pathspec] if separator == "--" && mode == SwitchMode::Safe => {
+            restore_file(&repo, dir, &repo.get_head_commit()?, pathspec)
+        }
+        [commit, separator, pathspec] if separator == "--" && mode == SwitchMode::Safe => {
+            restore_file(&repo, dir, &resolve_commit(&repo, commit)?, pathspec)
+        }
+        _ => Err(VcsError::IncorrectOperands),
+    }
+}
+
+/// Switches to the branch or commit named by `target`, as `vcs checkout [<option>] <target>` does
+fn switch(repo: &Repository, target: &str, mode: SwitchMode) -> Result<String> {
+    if repo.merge_head()?.is_some() {
+        return Err(VcsError::MergeInProgress);
+    }
+    let previous = repo.get_branch_name()?;
+    let branch = branch_name(repo, target)?;
+    if branch.as_ref() == Some(&previous) {
+        if mode == SwitchMode::Force {
+            repo.update_index(|index| {
+                update_dir_state(repo, &repo.get_head_commit()?)?;
+                index.entries.clear();
+                Ok(())
+            })?;
+        }
+        return Ok(format!("Already on {}.", previous));
+    }
+    let commit_hash = match &branch {
+        Some(name) => repo.get_branch_commit(name)?,
+        None => resolve_commit(repo, target)?,
+    };
+
+    // Modify directory state and index, then HEAD file
+    let mut output = match mode {
+        SwitchMode::Force => {
+            repo.update_index(|index| {
+                update_dir_state(repo, &commit_hash)?;
+                index.entries.clear();
+                Ok(())
+            })?;
+            vec![]
+        }
+        _ => carry_dir_state(
+            repo,
+            &commit_hash,
+            branch.as_deref().unwrap_or(&commit_hash),
+            mode == SwitchMode::Merge,
+        )?,
+    };
+    match branch {
+        Some(name) => {
+            switch_head(repo, &name)?;
+            output.push(format!("Switched to branch {}.", name));
+        }
+        None => {
+            switch_head(repo, &commit_hash)?;
+            output.push(format!("Switched to commit {}.", commit_hash));
+        }
+    }
+    Ok(output.join("\n"))
+}
+
+/// Puts the version of the file `pathspec` (relative to `dir`) in the commit `commit_hash` in the
+/// worktree, or removes the file if the commit doesn't have it
+fn restore_file(
+    repo: &Repository,
+    dir: &Path,
+    commit_hash: &str,
+    pathspec: &str,
+) -> Result<String> {
+    let filename = &repo
+        .relative_name(dir, pathspec)
+        .ok_or_else(|| VcsError::OutsideRepository(pathspec.to_string()))?;
+    match get_entry_in_commit(repo, commit_hash, filename)? {
+        Some(entry) => write_file_given_hash(repo, filename, entry.mode, &entry.hash)?,
+        None => remove_worktree_file(repo, filename)?,
+    }
+    Ok(String::from(""))
+}
+
+/// Moves the worktree and index from the head commit to the commit `commit_hash`, touching only
+/// the files that differ between the two. Local and staged changes to every other file are kept.
+///
+/// If a file to be replaced has local changes, returns `WouldOverwrite`, unless `merge` is set and
+/// the file is tracked: then the local changes are merged into the new version, with `label`
+/// naming the new version in conflict markers. Outputs a description of each conflict. Must be
+/// called before HEAD moves to the new commit.
+fn carry_dir_state(
+    repo: &Repository,
+    commit_hash: &str,
+    label: &str,
+    merge: bool,
+) -> Result<Vec<String>> {
+    let current_tree = get_commit_tree(repo, &repo.get_head_commit()?)?;
+    let target_tree = get_commit_tree(repo, commit_hash)?;
+    let current = read_tree_files(repo, &current_tree)?;
+    let target = read_tree_files(repo, &target_tree)?;
+    let touched: BTreeSet<String> = current
+        .keys()
+        .chain(target.keys())
+        .filter(|name| current.get(*name) != target.get(*name))
+        .cloned()
+        .collect();
+    // The index stays locked while the worktree changes, so nothing staged meanwhile is lost
+    let mut conflicts = vec![];
+    repo.update_index(|index| {
+        // Local versions of the files to merge are saved before the worktree changes
+        let mut local: BTreeMap<String, Option<FileVersion>> = BTreeMap::new();
+        let mut refused = vec![];
+        for name in overwritten_files(
+            repo,
+            &mut index.stat_cache,
+            &current,
+            &target,
+            &touched,
+            &index.entries,
+        )? {
+            let path = repo.worktree_path(&name);
+            if !merge || !current.contains_key(&name) {
+                refused.push(name);
+            } else if symlink_metadata(&path).is_ok() {
+                let version = (FileMode::of_path(&path)?, create_blob(repo, &path)?);
+                local.insert(name, Some(version));
             } else {
-                return Ok(format!("{} does not exist.", args[2]));
+                local.insert(name, None);
             }
         }
-        4 => {
-            if args[2] != "--" {
-                return Ok(String::from("Incorrect operands."));
-            }
-            let new_args = vec![
-                args[0].clone(),
-                args[1].clone(),
-                get_head_commit()?,
-                args[2].clone(),
-                args[3].clone(),
-            ];
-            return checkout(&new_args);
+        if !refused.is_empty() {
+            return Err(VcsError::WouldOverwrite(refused));
+        }
+
+        // Removing first makes room for files that replace directories, and directories that
+        // replace files
+        for name in touched.iter().filter(|name| !target.contains_key(*name)) {
+            remove_worktree_file(repo, name)?;
         }
-        5 => {
-            if args[3] != "--" {
-                return Ok(String::from("Incorrect operands."));
-            } else if !object_exists(&args[2]) {
-                return Ok(format!("No commit with ID {} exists.", args[2]));
+        for (name, (mode, hash)) in &target {
+            if touched.contains(name) {
+                write_file_given_hash(repo, name, *mode, hash)?;
             }
-            let hash = get_hash_in_commit(&args[2], &args[4])?;
-            if hash == "DNE" {
-                if file_exists(&args[4]) {
-                    remove_file(args[4].clone())?;
+        }
+        index
+            .entries
+            .retain(|entry| !touched.contains(entry.name()));
+
+        if !local.is_empty() {
+            let local_tree = update_tree(repo, &current_tree, &local)?;
+            let merged = merge_trees(
+                repo,
+                &current_tree,
+                &target_tree,
+                &local_tree,
+                label,
+                "local",
+            )?;
+            for (name, change) in merged.changes {
+                match change {
+                    Some((mode, hash)) => write_file_given_hash(repo, &name, mode, &hash)?,
+                    None => remove_worktree_file(repo, &name)?,
                 }
-            } else {
-                write_file_given_hash(args[4].clone(), hash)?;
             }
-            return Ok(String::from(""));
+            for conflict in merged.conflicts {
+                write_worktree_file(repo, &conflict.path, &conflict.contents, conflict.mode)?;
+                index.entries.extend(conflict.index_entries());
+                conflicts.push(conflict.description);
+            }
         }
-        _ => return Ok(String::from("Incorrect operands.")),
-    }
+        Ok(())
+    })?;
+    Ok(conflicts)
 }
 
-/// Changes the directory to the state at the given commit hash
-fn update_dir_state(commit_hash: String) -> Result<()> {
-    assert!(file_exists(&format!(
-        ".vcs/objects/{}/{}",
-        &commit_hash[0..2],
-        &commit_hash[2..]
-    )));
-    let mut current_dir: HashSet<String> = HashSet::new();
-    for entry in read_dir("./")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            continue;
+/// Outputs the files whose local changes would be lost by switching from the files `current` to
+/// the files `target`, where `touched` holds the names whose versions differ between the two:
+/// files with staged changes, files in the worktree that match neither version, and untracked
+/// files in the way of a file or directory `target` needs. Files are hashed through `stat_cache`.
+fn overwritten_files(
+    repo: &Repository,
+    stat_cache: &mut StatCache,
+    current: &HashMap<String, (FileMode, String)>,
+    target: &HashMap<String, (FileMode, String)>,
+    touched: &BTreeSet<String>,
+    index: &[IndexEntry],
+) -> Result<BTreeSet<String>> {
+    let mut overwritten: BTreeSet<String> = index
+        .iter()
+        .map(IndexEntry::name)
+        .filter(|name| touched.contains(*name))
+        .map(String::from)
+        .collect();
+    let untracked: Vec<String> = worktree_files(repo)?
+        .into_iter()
+        .filter(|name| !current.contains_key(name))
+        .collect();
+    let is_dir = |name: &str| {
+        symlink_metadata(repo.worktree_path(name)).is_ok_and(|metadata| metadata.is_dir())
+    };
+
+    let mut files = vec![];
+    for name in touched {
+        if is_dir(name) {
+            // A directory in the way only has something to lose if it holds untracked files
+            let prefix = format!("{}/", name);
+            overwritten.extend(
+                untracked
+                    .iter()
+                    .filter(|file| file.starts_with(&prefix))
+                    .cloned(),
+            );
+        } else {
+            files.push(name);
         }
-        current_dir.insert(no_dir_string(path));
     }
-    let tree_contents = get_object_contents(&get_commit_tree(&commit_hash)?)?;
-    let serialized_tree = serialize_tree(&tree_contents);
-    for (filename, filehash) in serialized_tree {
-        if current_dir.remove(&filename) {
-            if sha2(&format!("blob\n{}", get_file_contents(&filename)?)) != filehash {
-                write_file_given_hash(filename, filehash)?;
-            }
-        } else {
-            write_file_given_hash(filename, filehash)?;
+    for name in local_changes(repo, stat_cache, current, files)? {
+        if !local_changes(repo, stat_cache, target, [&name])?.is_empty() {
+            overwritten.insert(name);
         }
     }
-    for filename in current_dir {
-        remove_file(filename)?;
+    for name in touched.iter().filter(|name| target.contains_key(*name)) {
+        // An untracked file where the target needs a directory
+        for (end, _) in name.match_indices('/') {
+            let parent = &name[..end];
+            if untracked.iter().any(|file| file == parent) {
+                overwritten.insert(parent.to_string());
+            }
+        }
     }
-    Ok(())
+    Ok(overwritten)
 }
 
-/// Given a filename and a blob hash, create a new file with the contents of the blob in the file
-fn write_file_given_hash(filename: String, hash: String) -> Result<()> {
-    if file_exists(&filename) {
-        clear_file_contents(&filename)?;
+/// Changes the worktree to the state at the given commit hash
+///
+/// Every file in the commit is written, unless the worktree already has the same contents, and
+/// every file tracked by the current HEAD or staged in the index but missing from the commit is
+/// removed, along with directories left empty. Untracked files are left alone. Must be called
+/// before HEAD and the index move to the new commit.
+pub fn update_dir_state(repo: &Repository, commit_hash: &str) -> Result<()> {
+    assert!(repo.object_exists(commit_hash));
+    let new_files = read_tree_files(repo, &get_commit_tree(repo, commit_hash)?)?;
+    let mut tracked: HashSet<String> =
+        read_tree(repo, &get_commit_tree(repo, &repo.get_head_commit()?)?)?
+            .into_keys()
+            .collect();
+    for entry in repo.read_index()? {
+        if let IndexEntry::Blob { name, .. } | IndexEntry::Conflict { name, .. } = entry {
+            tracked.insert(name);
+        }
+    }
+
+    // Removing first makes room for files that replace directories, and directories that replace
+    // files
+    for filename in tracked {
+        if !new_files.contains_key(&filename) {
+            remove_worktree_file(repo, &filename)?;
+        }
+    }
+    let current_files: HashSet<String> = worktree_files(repo)?.into_iter().collect();
+    for (filename, (mode, filehash)) in new_files {
+        let path = repo.worktree_path(&filename);
+        if !current_files.contains(&filename)
+            || get_blob_hash(&path)?.0 != filehash
+            || FileMode::of_path(&path)? != mode
+        {
+            write_file_given_hash(repo, &filename, mode, &filehash)?;
+        }
     }
-    let mut new_file = File::create(filename)?;
-    let mut blob_contents = get_object_contents(&hash)?;
-    // drains the first 5 characters since that's `blob\n`
-    blob_contents.drain(0..5);
-    new_file.write_all(blob_contents.as_str().as_bytes())?;
     Ok(())
 }
 
+/// Given a filename, a mode and a blob hash, create a new file with the contents of the blob in
+/// the file, and the mode given: an executable, a symlink, or a regular file
+fn write_file_given_hash(
+    repo: &Repository,
+    filename: &str,
+    mode: FileMode,
+    hash: &str,
+) -> Result<()> {
+    write_worktree_file(repo, filename, &read_blob(repo, hash)?, mode)
+}
+
 #[cfg(test)]
 pub mod tests {
 
@@ -169,19 +391,23 @@ pub mod tests {
     // Further partition on checkout file in commit:
     //      On whether commit is specified: yes, no
     //  For checkout commit: check that `add`, `rm`, and `commit` log correct response.
+    // Further partition on files: at the root, in subdirectories, untracked
+    // Further partition on file mode: regular, executable, symlink
+    // Partition on local changes to files checkout replaces: none, modified, staged, untracked in
+    //      the way; changes to other files: kept
+    // Partition on option: none, --force, --merge (clean, conflicts, untracked in the way)
 
     use std::{
-        env::set_current_dir,
-        fs::{create_dir, remove_file, File},
+        fs::{remove_file, File},
         io::Write,
     };
 
     use crate::{
-        objects::commit::{get_head_commit, INITIAL_COMMIT_HASH},
+        objects::{commit::INITIAL_COMMIT_HASH, index::index_text},
         operations::{add::add, branch::branch, commit::commit, init::init, rm::rm},
         utils::{
             fs_utils::{clear_file_contents, file_exists, get_file_contents},
-            test_dir::make_test_dir,
+            test_dir::{make_test_dir, write_and_add},
         },
     };
 
@@ -189,380 +415,858 @@ pub mod tests {
 
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
         let test_args: Vec<String> =
             vec![String::from("target/debug/vcs"), String::from("checkout")];
-        assert_eq!(
-            "Not in an initialized vcs directory.",
-            checkout(&test_args)?
-        );
+        assert!(matches!(
+            checkout(dir, &test_args),
+            Err(VcsError::NotARepository)
+        ));
         Ok(())
     }
 
     #[test]
     fn incorrect_operands() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(
-            "Incorrect operands.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-            ])?
-        );
-        assert_eq!(
-            "Incorrect operands.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-                INITIAL_COMMIT_HASH.to_string(),
-                String::from("--"),
-            ])?
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
+        assert!(matches!(
+            checkout(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("checkout")]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    INITIAL_COMMIT_HASH.to_string(),
+                    String::from("--")
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
         Ok(())
     }
 
     #[test]
     fn test_checkout_same_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         assert_eq!(
             "Already on main.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-                String::from("main"),
-            ])?
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from("main")
+                ]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn test_checkout_nonexistent_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(
-            "test does not exist.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-                String::from("test"),
-            ])?
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
+        assert!(matches!(
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from("test")
+                ]
+            ),
+            Err(VcsError::InvalidRef(name)) if name == "test"
+        ));
         Ok(())
     }
 
     #[test]
     fn test_checkout_diff_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = branch(&vec![
-            String::from("target/debug/vcs"),
-            String::from("branch"),
-            String::from("test_branch"),
-        ]);
-        let mut first_file = File::create("f1.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let _ = branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("test_branch"),
+            ],
+        );
+        let mut first_file = File::create(dir.join("f1.txt"))?;
         first_file.write_all("file one text".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f1.txt"),
-        ])?;
-        let mut modified_file = File::create("f2.txt")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f1.txt"),
+            ],
+        )?;
+        let mut modified_file = File::create(dir.join("f2.txt"))?;
         modified_file.write_all("to be changed".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f2.txt"),
-        ])?;
-        let (_, first_commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add two files"),
-        ])?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        let (_, first_commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add two files"),
+            ],
+        )?;
         assert_eq!(
             "Switched to branch test_branch.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-                String::from("test_branch"),
-            ])?
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from("test_branch")
+                ]
+            )?
         );
-        assert!(!file_exists("f1.txt"));
-        assert!(!file_exists("f2.txt"));
-        assert_eq!(INITIAL_COMMIT_HASH, get_head_commit()?);
-        let mut second_modified_file = File::create("f2.txt")?;
+        assert!(!file_exists(dir.join("f1.txt")));
+        assert!(!file_exists(dir.join("f2.txt")));
+        assert_eq!(INITIAL_COMMIT_HASH, repo.get_head_commit()?);
+        let mut second_modified_file = File::create(dir.join("f2.txt"))?;
         second_modified_file.write_all("to this!".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f2.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Switch f2.txt text"),
-        ])?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Switch f2.txt text"),
+            ],
+        )?;
         assert_eq!(
             "Switched to branch main.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-                String::from("main"),
-            ])?
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from("main")
+                ]
+            )?
         );
-        assert_eq!(first_commit_hash, get_head_commit()?);
-        assert!(file_exists("f1.txt"));
-        assert_eq!("file one text", get_file_contents("f1.txt")?);
-        assert!(file_exists("f2.txt"));
-        assert_eq!("to be changed", get_file_contents("f2.txt")?);
+        assert_eq!(first_commit_hash, repo.get_head_commit()?);
+        assert!(file_exists(dir.join("f1.txt")));
+        assert_eq!("file one text", get_file_contents(dir.join("f1.txt"))?);
+        assert!(file_exists(dir.join("f2.txt")));
+        assert_eq!("to be changed", get_file_contents(dir.join("f2.txt"))?);
         Ok(())
     }
 
     #[test]
     fn test_checkout_nonexistent_id() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        assert_eq!(
-            "No commit with ID dne exists.",
-            checkout(&vec![
-                String::from("target/debug/vcs"),
-                String::from("checkout"),
-                String::from("dne"),
-                String::from("--"),
-                String::from("f3.txt"),
-            ])?
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
+        assert!(matches!(
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from("dne"),
+                    String::from("--"),
+                    String::from("f3.txt")
+                ]
+            ),
+            Err(VcsError::InvalidRef(name)) if name == "dne"
+        ));
         Ok(())
     }
 
     #[test]
     fn test_checkout_file_no_id() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file_one = File::create("f1.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file_one = File::create(dir.join("f1.txt"))?;
         file_one.write_all("file 1 text".as_bytes())?;
-        let mut file_two = File::create("f2.txt")?;
+        let mut file_two = File::create(dir.join("f2.txt"))?;
         file_two.write_all("file 2 text".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f1.txt"),
-        ])?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f2.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Set up first commit"),
-        ]);
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            String::from("--"),
-            String::from("f3.txt"),
-        ])?;
-        assert!(!file_exists("f3.txt"));
-        remove_file("f1.txt")?;
-        assert!(!file_exists("f1.txt"));
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            String::from("--"),
-            String::from("f1.txt"),
-        ])?;
-        assert!(file_exists("f1.txt"));
-        assert_eq!("file 1 text", get_file_contents("f1.txt")?);
-        clear_file_contents("f2.txt")?;
-        assert_eq!("", get_file_contents("f2.txt")?);
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            String::from("--"),
-            String::from("f2.txt"),
-        ])?;
-        assert_eq!("file 2 text", get_file_contents("f2.txt")?);
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            String::from("--"),
-            String::from("f2.txt"),
-        ])?;
-        assert_eq!("file 2 text", get_file_contents("f2.txt")?);
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f1.txt"),
+            ],
+        )?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Set up first commit"),
+            ],
+        );
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--"),
+                String::from("f3.txt"),
+            ],
+        )?;
+        assert!(!file_exists(dir.join("f3.txt")));
+        remove_file(dir.join("f1.txt"))?;
+        assert!(!file_exists(dir.join("f1.txt")));
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--"),
+                String::from("f1.txt"),
+            ],
+        )?;
+        assert!(file_exists(dir.join("f1.txt")));
+        assert_eq!("file 1 text", get_file_contents(dir.join("f1.txt"))?);
+        clear_file_contents(dir.join("f2.txt"))?;
+        assert_eq!("", get_file_contents(dir.join("f2.txt"))?);
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        assert_eq!("file 2 text", get_file_contents(dir.join("f2.txt"))?);
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        assert_eq!("file 2 text", get_file_contents(dir.join("f2.txt"))?);
         Ok(())
     }
 
     #[test]
     fn test_checkout_file_with_id() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file_one = File::create("f1.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file_one = File::create(dir.join("f1.txt"))?;
         file_one.write_all("file 1 text".as_bytes())?;
-        let mut file_two = File::create("f2.txt")?;
+        let mut file_two = File::create(dir.join("f2.txt"))?;
         file_two.write_all("file 2 text".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f1.txt"),
-        ])?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f2.txt"),
-        ])?;
-        let (_, commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Set up first commit"),
-        ])?;
-        rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("f2.txt"),
-        ])?;
-        commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Remove f2.txt"),
-        ])?;
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            commit_hash.clone(),
-            String::from("--"),
-            String::from("f3.txt"),
-        ])?;
-        assert!(!file_exists("f3.txt"));
-        clear_file_contents("f1.txt")?;
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            commit_hash.clone(),
-            String::from("--"),
-            String::from("f1.txt"),
-        ])?;
-        assert_eq!("file 1 text", get_file_contents("f1.txt")?);
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            commit_hash.clone(),
-            String::from("--"),
-            String::from("f2.txt"),
-        ])?;
-        assert_eq!("file 2 text", get_file_contents("f2.txt")?);
-        checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            commit_hash.clone(),
-            String::from("--"),
-            String::from("f2.txt"),
-        ])?;
-        assert_eq!("file 2 text", get_file_contents("f2.txt")?);
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f1.txt"),
+            ],
+        )?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Set up first commit"),
+            ],
+        )?;
+        rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Remove f2.txt"),
+            ],
+        )?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                commit_hash.clone(),
+                String::from("--"),
+                String::from("f3.txt"),
+            ],
+        )?;
+        assert!(!file_exists(dir.join("f3.txt")));
+        clear_file_contents(dir.join("f1.txt"))?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                commit_hash.clone(),
+                String::from("--"),
+                String::from("f1.txt"),
+            ],
+        )?;
+        assert_eq!("file 1 text", get_file_contents(dir.join("f1.txt"))?);
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                commit_hash.clone(),
+                String::from("--"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        assert_eq!("file 2 text", get_file_contents(dir.join("f2.txt"))?);
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                commit_hash.clone(),
+                String::from("--"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        assert_eq!("file 2 text", get_file_contents(dir.join("f2.txt"))?);
         Ok(())
     }
 
     #[test]
     fn test_checkout_commit() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file_one = File::create("f1.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file_one = File::create(dir.join("f1.txt"))?;
         file_one.write_all("file 1 text".as_bytes())?;
-        let mut file_two = File::create("f2.txt")?;
+        let mut file_two = File::create(dir.join("f2.txt"))?;
         file_two.write_all("file 2 text".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f1.txt"),
-        ])?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f2.txt"),
-        ])?;
-        let (_, commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Set up first commit"),
-        ])?;
-        remove_file("f1.txt")?;
-        clear_file_contents("f2.txt")?;
-        let mut file_three = File::create("f3.txt")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f1.txt"),
+            ],
+        )?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f2.txt"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Set up first commit"),
+            ],
+        )?;
+        remove_file(dir.join("f1.txt"))?;
+        clear_file_contents(dir.join("f2.txt"))?;
+        let mut file_three = File::create(dir.join("f3.txt"))?;
         file_three.write_all("file 3 text".as_bytes())?;
-        add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f3.txt"),
-        ])?;
+        add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("f3.txt"),
+            ],
+        )?;
         assert_eq!(
             format!("Switched to commit {}.", commit_hash),
-            checkout(&vec![
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from("--force"),
+                    commit_hash
+                ]
+            )?
+        );
+        assert_eq!(
+            "",
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
+        assert!(file_exists(dir.join("f1.txt")));
+        assert_eq!("file 1 text", get_file_contents(dir.join("f1.txt"))?);
+        assert!(file_exists(dir.join("f2.txt")));
+        assert_eq!("file 2 text", get_file_contents(dir.join("f2.txt"))?);
+        assert!(!file_exists(dir.join("f3.txt")));
+        assert!(matches!(
+            rm(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("rm"),
+                    String::from("f1.txt")
+                ]
+            ),
+            Err(VcsError::DetachedHead)
+        ));
+        let mut file_three = File::create(dir.join("f3.txt"))?;
+        file_three.write_all("file 3 text".as_bytes())?;
+        assert!(matches!(
+            add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from("f3.txt"),
+                ],
+            ),
+            Err(VcsError::DetachedHead)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_binary_file() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let bytes: Vec<u8> = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, 0x00, 0xff];
+        let mut image = File::create(dir.join("image.png"))?;
+        image.write_all(&bytes)?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("image.png"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add image.png"),
+            ],
+        )?;
+        clear_file_contents(dir.join("image.png"))?;
+        checkout(
+            dir,
+            &[
                 String::from("target/debug/vcs"),
                 String::from("checkout"),
+                String::from("--"),
+                String::from("image.png"),
+            ],
+        )?;
+        assert_eq!(bytes, std::fs::read(dir.join("image.png"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_nested_files() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        std::fs::create_dir_all(dir.join("src/deep"))?;
+        let mut file = File::create(dir.join("src/deep/lib.rs"))?;
+        file.write_all(b"lib")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("src/deep/lib.rs"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add lib.rs"),
+            ],
+        )?;
+        std::fs::remove_dir_all(dir.join("src"))?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--force"),
                 commit_hash,
-            ])?
+            ],
+        )?;
+        assert_eq!("lib", get_file_contents(dir.join("src/deep/lib.rs"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_switching_branches_with_nested_files() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
-        assert_eq!("", get_file_contents(".vcs/index")?);
-        assert!(file_exists("f1.txt"));
-        assert_eq!("file 1 text", get_file_contents("f1.txt")?);
-        assert!(file_exists("f2.txt"));
-        assert_eq!("file 2 text", get_file_contents("f2.txt")?);
-        assert!(!file_exists("f3.txt"));
-        assert_eq!(
-            "Currently in a detached HEAD state. Check out a branch to modify the directory.",
-            rm(&vec![
+        branch(
+            dir,
+            &[
                 String::from("target/debug/vcs"),
-                String::from("rm"),
-                String::from("f1.txt"),
-            ])?
+                String::from("branch"),
+                String::from("other"),
+            ],
+        )?;
+        std::fs::create_dir_all(dir.join("src/deep"))?;
+        for (filename, text) in [("src/deep/lib.rs", "lib"), ("src/main.rs", "main")] {
+            let mut file = File::create(dir.join(filename))?;
+            file.write_all(text.as_bytes())?;
+            let _ = add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(filename),
+                ],
+            )?;
+        }
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add src"),
+            ],
+        )?;
+        let _ = File::create(dir.join("src/untracked.rs"))?;
+
+        // Tracked files go, along with the directories they leave empty
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("other"),
+            ],
+        )?;
+        assert!(!dir.join("src/deep").exists());
+        assert!(!file_exists(dir.join("src/main.rs")));
+        assert!(file_exists(dir.join("src/untracked.rs")));
+
+        // And come back, in new directories
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("main"),
+            ],
+        )?;
+        assert_eq!("lib", get_file_contents(dir.join("src/deep/lib.rs"))?);
+        assert_eq!("main", get_file_contents(dir.join("src/main.rs"))?);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_checkout_restores_modes() -> Result<()> {
+        use std::{
+            fs::{read_link, remove_file, set_permissions, Permissions},
+            os::unix::fs::{symlink, PermissionsExt},
+        };
+
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
         );
-        let mut file_three = File::create("f3.txt")?;
-        file_three.write_all("file 3 text".as_bytes())?;
-        let (output, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("f3.txt"),
-        ])?;
+        let mut file = File::create(dir.join("run.sh"))?;
+        file.write_all(b"echo hi")?;
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o755))?;
+        symlink("run.sh", dir.join("link"))?;
+        for filename in ["run.sh", "link"] {
+            let _ = add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(filename),
+                ],
+            )?;
+        }
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add run.sh and link"),
+            ],
+        )?;
+
+        // Forcing a checkout fixes the mode even when the contents are unchanged
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o644))?;
+        remove_file(dir.join("link"))?;
+        let mut file = File::create(dir.join("link"))?;
+        file.write_all(b"not a link")?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--force"),
+                commit_hash,
+            ],
+        )?;
+        assert_eq!(FileMode::Executable, FileMode::of_path(dir.join("run.sh"))?);
+        assert_eq!(Path::new("run.sh"), read_link(dir.join("link"))?);
+
+        // So does checking out a single file
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o644))?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("--"),
+                String::from("run.sh"),
+            ],
+        )?;
+        assert_eq!(FileMode::Executable, FileMode::of_path(dir.join("run.sh"))?);
+        assert_eq!("echo hi", get_file_contents(dir.join("link"))?);
+        Ok(())
+    }
+
+    /// Runs `vcs checkout` in `dir` with `operands` as arguments
+    fn run_checkout(dir: &Path, operands: &[&str]) -> Result<String> {
+        let mut args = vec![String::from("target/debug/vcs"), String::from("checkout")];
+        args.extend(operands.iter().map(|operand| String::from(*operand)));
+        checkout(dir, &args)
+    }
+
+    /// Sets up a repository in `dir` where `side` has `a.txt` and `keep.txt`, and `main` also
+    /// changes the first line of `a.txt` and adds `b.txt`. Leaves `side` checked out.
+    fn diverged(dir: &Path) -> Result<Repository> {
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        write_and_add(dir, "a.txt", "1\n2\n3\n")?;
+        write_and_add(dir, "keep.txt", "keep\n")?;
+        commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add a.txt and keep.txt"),
+            ],
+        )?;
+        branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("side"),
+            ],
+        )?;
+        write_and_add(dir, "a.txt", "one\n2\n3\n")?;
+        write_and_add(dir, "b.txt", "b\n")?;
+        commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Change a.txt and add b.txt"),
+            ],
+        )?;
+        run_checkout(dir, &["side"])?;
+        Ok(Repository::open(dir).unwrap())
+    }
+
+    #[test]
+    fn test_checkout_refuses_to_clobber() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = diverged(dir)?;
+
+        // A tracked file with local changes that checkout would replace
+        std::fs::write(dir.join("a.txt"), "1\n2\nthree\n")?;
+        assert!(matches!(
+            run_checkout(dir, &["main"]),
+            Err(VcsError::WouldOverwrite(names)) if names == ["a.txt"]
+        ));
+        assert_eq!("1\n2\nthree\n", get_file_contents(dir.join("a.txt"))?);
+        assert_eq!("side", repo.get_branch_name()?);
+
+        // An untracked file in the way, unless it already matches
+        std::fs::write(dir.join("a.txt"), "1\n2\n3\n")?;
+        std::fs::write(dir.join("b.txt"), "mine\n")?;
+        assert!(matches!(
+            run_checkout(dir, &["main"]),
+            Err(VcsError::WouldOverwrite(names)) if names == ["b.txt"]
+        ));
+        std::fs::write(dir.join("b.txt"), "b\n")?;
+
+        // A staged change, even when the worktree matches HEAD again
+        write_and_add(dir, "a.txt", "staged\n")?;
+        std::fs::write(dir.join("a.txt"), "1\n2\n3\n")?;
+        assert!(matches!(
+            run_checkout(dir, &["main"]),
+            Err(VcsError::WouldOverwrite(names)) if names == ["a.txt"]
+        ));
+        assert_eq!(1, repo.read_index()?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_keeps_other_changes() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = diverged(dir)?;
+        write_and_add(dir, "keep.txt", "staged\n")?;
+        std::fs::write(dir.join("notes.txt"), "notes\n")?;
+        assert_eq!("Switched to branch main.", run_checkout(dir, &["main"])?);
+        assert_eq!("one\n2\n3\n", get_file_contents(dir.join("a.txt"))?);
+        assert_eq!("staged\n", get_file_contents(dir.join("keep.txt"))?);
+        assert_eq!("notes\n", get_file_contents(dir.join("notes.txt"))?);
         assert_eq!(
-            "Currently in a detached HEAD state. Check out a branch to modify the directory.",
-            output
+            vec!["keep.txt"],
+            repo.read_index()?
+                .iter()
+                .map(IndexEntry::name)
+                .collect::<Vec<_>>()
         );
+
+        // --force throws every change away
+        std::fs::write(dir.join("a.txt"), "changed\n")?;
+        assert_eq!(
+            "Switched to branch side.",
+            run_checkout(dir, &["-f", "side"])?
+        );
+        assert_eq!("1\n2\n3\n", get_file_contents(dir.join("a.txt"))?);
+        assert_eq!("keep\n", get_file_contents(dir.join("keep.txt"))?);
+        assert!(!file_exists(dir.join("b.txt")));
+        assert!(repo.read_index()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_merges_local_changes() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = diverged(dir)?;
+        std::fs::write(dir.join("a.txt"), "1\n2\nthree\n")?;
+        assert_eq!(
+            "Switched to branch main.",
+            run_checkout(dir, &["--merge", "main"])?
+        );
+        assert_eq!("one\n2\nthree\n", get_file_contents(dir.join("a.txt"))?);
+        assert!(repo.read_index()?.is_empty());
+
+        // Changes to the same lines conflict, and leave conflicted entries to resolve
+        std::fs::write(dir.join("a.txt"), "mine\n2\nthree\n")?;
+        assert_eq!(
+            "CONFLICT (content): Merge conflict in a.txt\nSwitched to branch side.",
+            run_checkout(dir, &["-m", "side"])?
+        );
+        assert!(get_file_contents(dir.join("a.txt"))?.starts_with("<<<<<<< side\n"));
+        assert_eq!(3, repo.read_index()?.len());
+
+        // Untracked files in the way can't be merged
+        assert_eq!("Already on side.", run_checkout(dir, &["-f", "side"])?);
+        assert!(repo.read_index()?.is_empty());
+        std::fs::write(dir.join("b.txt"), "mine\n")?;
+        assert!(matches!(
+            run_checkout(dir, &["-m", "main"]),
+            Err(VcsError::WouldOverwrite(names)) if names == ["b.txt"]
+        ));
+        for operands in [&["-f", "-m", "main"][..], &["-f", "--", "a.txt"]] {
+            assert!(matches!(
+                run_checkout(dir, operands),
+                Err(VcsError::IncorrectOperands)
+            ));
+        }
         Ok(())
     }
 }
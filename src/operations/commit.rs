@@ -1,121 +1,138 @@
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{Result, Write},
-};
-
-use chrono::Utc;
+use std::{collections::BTreeMap, env, path::Path};
 
 use crate::{
+    error::{Result, VcsError},
     objects::{
-        commit::{get_commit_tree, get_head_commit, write_commit},
-        get_object_contents,
-        tree::write_tree,
+        commit::{get_commit_tree, write_commit},
+        index::IndexEntry,
+        mode::FileMode,
+        reflog::move_head,
+        repo::Repository,
+        signature::new_signatures,
+        tree::update_tree,
     },
-    utils::fs_utils::{clear_file_contents, directory_exists, file_exists, get_file_contents},
 };
 
+/// Usage text shown by `vcs help commit`
+pub const USAGE: &str = "usage: vcs commit [--author <name> <<email>>] <message>\n\nRecord the staged changes as a new commit on the current branch.\n\n    --author <name> <<email>>    record someone else as the author of the changes";
+
 /// Executes `vcs commit`. Returns the string that is logged to the console, and the hash of the
 /// commit object as well
 ///
-/// If not in an initialized vcs directory, log `Not in an initialized vcs directory.`
-/// If incorrect number of commands, log `Incorrect operands.`
-/// If there was no commit message, log `Please enter a commit message.`
-/// If there are no added files, log `No changes added to the commit`
-/// If correct, we will update the current head/branch to point at the new commit, logging
-/// information about time and author as well.
+/// If not in an initialized vcs directory, returns `NotARepository`
+/// If incorrect number of commands, returns `IncorrectOperands`
+/// If there was no commit message, returns `EmptyCommitMessage`
+/// If there are no added files, returns `NothingToCommit`
+/// If a merge stopped for conflicts, returns `MergeInProgress`: `vcs merge --continue` commits it
+/// If the author or committer can't be worked out, returns `InvalidIdentity`,
+/// `InvalidOptionValue` or `ConfigParse`, as for `new_signatures`
+/// If correct, we will update the current head/branch to point at the new commit, recording its
+/// author and committer: the author is `--author` if given, and otherwise both come from the
+/// `VCS_AUTHOR_*`/`VCS_COMMITTER_*` environment variables or `user.name` and `user.email` in the
+/// config.
 ///
+/// * `dir` - directory `commit` was called from
 /// * `args` - arguments `commit` was called with
-pub fn commit(args: &Vec<String>) -> Result<(String, String)> {
+pub fn commit(dir: &Path, args: &[String]) -> Result<(String, String)> {
     assert!(args[1] == "commit");
-    if !directory_exists(".vcs") {
-        return Ok((
-            String::from("Not in an initialized vcs directory."),
-            String::from(""),
-        ));
-    }
-    match args.len() {
-        2 => Ok((
-            String::from("Please enter a commit message."),
-            String::from(""),
-        )),
-        3 => {
-            if args[2] == "" {
-                return Ok((
-                    String::from("Please enter a commit message."),
-                    String::from(""),
-                ));
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let mut author: Option<&str> = None;
+    let mut operands: Vec<&String> = vec![];
+    let mut options = args[2..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--author" => {
+                let value = options
+                    .next()
+                    .ok_or_else(|| VcsError::MissingOptionValue(arg.clone()))?;
+                author = Some(value);
             }
-
-            let index_contents = get_file_contents(".vcs/index")?;
-            if index_contents == "" {
-                return Ok((
-                    String::from("No changes added to the commit"),
-                    String::from(""),
-                ));
+            _ => operands.push(arg),
+        }
+    }
+    match operands.as_slice() {
+        [] => Err(VcsError::EmptyCommitMessage),
+        [message] => {
+            if message.is_empty() {
+                return Err(VcsError::EmptyCommitMessage);
             }
 
-            let parent = &get_commit_tree(&get_head_commit()?)?;
-            let parent_contents: Vec<String> = get_object_contents(parent)?
-                .split('\n')
-                .filter(|line| *line != "Blobs" && *line != "Trees")
-                .map(str::to_string)
-                .collect();
-            let mut parent_lines: HashMap<String, String> = HashMap::new();
-            for line in parent_contents {
-                if let Some((object_name, object_hash)) = line.split_once(": ") {
-                    parent_lines.insert(object_name.to_string(), object_hash.to_string());
-                }
-            }
-            for change in index_contents.split('\n') {
-                let split_change: Vec<&str> = change.split(' ').collect();
-                match split_change[0] {
-                    "blob" => {
-                        let hash = split_change[1];
-                        let filename = split_change[2];
-                        parent_lines.insert(filename.to_string(), hash.to_string());
-                    }
-                    "rm" => {
-                        let filename = split_change[1];
-                        parent_lines.remove(filename);
-                    }
-                    _ => {
-                        panic!(
-                            "Expected change to be either `rm` or `blob`, got {}.",
-                            split_change[0]
-                        );
-                    }
-                }
+            if repo.merge_head()?.is_some() {
+                return Err(VcsError::MergeInProgress);
             }
-            let mut parent_contents: Vec<String> = vec![];
-            for (object_name, object_hash) in &parent_lines {
-                parent_contents.push(format!("{}: {}", object_name, object_hash));
+            if repo.read_index()?.is_empty() {
+                return Err(VcsError::NothingToCommit);
             }
-            parent_contents.sort();
-            let new_tree_hash = write_tree(&vec![], &parent_contents);
-            let message = &args[2];
-            let parent = &get_head_commit()?;
-            let time = Utc::now().timestamp();
-            let new_commit_hash = write_commit(message, parent, time, &new_tree_hash);
-            update_head(new_commit_hash.clone())?;
-            let _ = clear_file_contents(".vcs/index");
+            let new_commit_hash = commit_index(&repo, message, &[], author)?;
             Ok((String::from(""), new_commit_hash))
         }
-        _ => Ok((String::from("Incorrect operands."), String::from(""))),
+        _ => Err(VcsError::IncorrectOperands),
     }
 }
 
-/// Updates the commit that the current branch is pointing at.
+/// Records the changes staged in the index of `repo` as a new commit whose first parent is the
+/// head commit and whose other parents are `other_parents`, moves the head to it and clears the
+/// index. `author` is as for `--author`. The move is recorded in the reflogs of the branch and HEAD.
+/// Outputs the hash of the new commit.
 ///
-/// Will throw an error if the current checked out commit is not on a branch
-fn update_head(commit_hash: String) -> Result<()> {
-    let head = get_file_contents(".vcs/HEAD")?;
-    let branch_file_name = format!(".vcs/branches/{}", head);
-    assert!(file_exists(&branch_file_name));
-    clear_file_contents(&branch_file_name)?;
-    let mut branch_file = File::create(branch_file_name)?;
-    branch_file.write_all(&commit_hash.into_bytes())?;
-    Ok(())
+/// Returns `UnresolvedConflicts` if the index still has conflicts, and the errors of
+/// `new_signatures` if the author or committer can't be worked out
+pub fn commit_index(
+    repo: &Repository,
+    message: &str,
+    other_parents: &[&str],
+    author: Option<&str>,
+) -> Result<String> {
+    // The index stays locked from when it is read until it is cleared, so changes staged in the
+    // meantime can't be lost, and commits made at the same time can't share a parent
+    let mut committed = String::new();
+    repo.update_index(|index| {
+        let parent = repo.get_head_commit()?;
+        let parent_tree = get_commit_tree(repo, &parent)?;
+        let mut changes: BTreeMap<String, Option<(FileMode, String)>> = BTreeMap::new();
+        let mut conflicts: Vec<String> = vec![];
+        for change in &index.entries {
+            match change {
+                IndexEntry::Blob { hash, name, mode } => {
+                    changes.insert(name.clone(), Some((*mode, hash.clone())));
+                }
+                IndexEntry::Rm { name } => {
+                    changes.insert(name.clone(), None);
+                }
+                IndexEntry::Conflict { name, .. } => {
+                    if !conflicts.contains(name) {
+                        conflicts.push(name.clone());
+                    }
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(VcsError::UnresolvedConflicts(conflicts));
+        }
+        let (author, committer) = new_signatures(repo, author, |name| env::var(name).ok())?;
+        let new_tree_hash = update_tree(repo, &parent_tree, &changes)?;
+        let mut parents = vec![parent.as_str()];
+        parents.extend_from_slice(other_parents);
+        let new_commit_hash = write_commit(
+            repo,
+            message,
+            &parents,
+            committer.time,
+            &new_tree_hash,
+            Some(&author),
+            Some(&committer),
+        )?;
+        let kind = if other_parents.is_empty() {
+            "commit"
+        } else {
+            "commit (merge)"
+        };
+        move_head(repo, &new_commit_hash, &format!("{}: {}", kind, message))?;
+        index.entries.clear();
+        committed = new_commit_hash;
+        Ok(())
+    })?;
+    Ok(committed)
 }
 
 #[cfg(test)]
@@ -123,48 +140,60 @@ mod tests {
     use super::*;
     use crate::{
         objects::{
-            commit::{get_commit_tree, get_hash_in_commit, INITIAL_COMMIT_HASH},
-            object_exists,
+            commit::{
+                get_commit_message, get_hash_in_commit, parse_commit_header, INITIAL_COMMIT_HASH,
+            },
+            index::index_text,
+            object::{hash_object, ObjectKind},
         },
         operations::{add::add, init::init, rm::rm},
-        utils::{hash::sha2, test_dir::make_test_dir},
+        utils::{
+            fs_utils::{file_exists, get_file_contents},
+            test_dir::make_test_dir,
+        },
     };
     use std::{fs::File, io::Write};
 
     // Partitions for commit
-    //      Failure cases: Not in directory, incorrect operands, no commit message, no changes or
-    //          changes not added, correct
+    //      Failure cases: Not in directory, incorrect operands, no commit message, bad --author,
+    //          no changes or changes not added, correct
     //      If correct: Just adds, just removes, adds and removes
+    //      Another process holding the lock on: the index, the branch
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = File::create("test.txt");
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = File::create(dir.join("test.txt"));
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("commit"),
             String::from("\"commit test.txt\""),
         ];
-        assert_eq!(
-            "Not in an initialized vcs directory.",
-            commit(&test_args)?.0
-        );
+        assert!(matches!(
+            commit(dir, &test_args),
+            Err(VcsError::NotARepository)
+        ));
         Ok(())
     }
 
     #[test]
     fn incorrect_operands() -> Result<()> {
         // Setup
-        let _test_dir = make_test_dir();
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
 
         // Commit test
         let test_args: Vec<String> = vec![
@@ -173,45 +202,109 @@ mod tests {
             String::from("burn_arg1"),
             String::from("burn_arg2"),
         ];
-        assert_eq!("Incorrect operands.", commit(&test_args)?.0);
+        assert!(matches!(
+            commit(dir, &test_args),
+            Err(VcsError::IncorrectOperands)
+        ));
         Ok(())
     }
 
     #[test]
     fn no_commit_message() -> Result<()> {
         // Setup
-        let _test_dir = make_test_dir();
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
 
         // Commit test
         let test_args: Vec<String> = vec![String::from("target/debug/vcs"), String::from("commit")];
-        assert_eq!("Please enter a commit message.", commit(&test_args)?.0);
+        assert!(matches!(
+            commit(dir, &test_args),
+            Err(VcsError::EmptyCommitMessage)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_author() -> Result<()> {
+        // Setup
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+
+        // Commit test
+        assert!(matches!(
+            commit(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("commit"),
+                    String::from("Add test.txt"),
+                    String::from("--author"),
+                ]
+            ),
+            Err(VcsError::MissingOptionValue(option)) if option == "--author"
+        ));
+        assert!(matches!(
+            commit(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("commit"),
+                    String::from("--author"),
+                    String::from("pat@example.com"),
+                    String::from("Add test.txt"),
+                ]
+            ),
+            Err(VcsError::InvalidIdentity(author)) if author == "pat@example.com"
+        ));
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(INITIAL_COMMIT_HASH, repo.get_head_commit()?);
         Ok(())
     }
 
     #[test]
     fn empty_commit_message() -> Result<()> {
         // Setup
-        let _test_dir = make_test_dir();
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
 
         // Commit test
         let test_args: Vec<String> = vec![
@@ -219,19 +312,23 @@ mod tests {
             String::from("commit"),
             String::from(""),
         ];
-        assert_eq!("Please enter a commit message.", commit(&test_args)?.0);
+        assert!(matches!(
+            commit(dir, &test_args),
+            Err(VcsError::EmptyCommitMessage)
+        ));
         Ok(())
     }
 
     #[test]
     fn no_changes() -> Result<()> {
         // Setup
-        let _test_dir = make_test_dir();
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
 
         // Commit test
         let test_args: Vec<String> = vec![
@@ -239,29 +336,37 @@ mod tests {
             String::from("commit"),
             String::from("Add test.txt"),
         ];
-        assert_eq!("No changes added to the commit", commit(&test_args)?.0);
+        assert!(matches!(
+            commit(dir, &test_args),
+            Err(VcsError::NothingToCommit)
+        ));
         Ok(())
     }
 
     #[test]
     fn correct_just_adds() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         // init vcs dir
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
 
         // mutate and add a file
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         let file_text = "commit time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, file_hash) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let (_, file_hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
 
         // test commmit
         let test_args: Vec<String> = vec![
@@ -269,136 +374,323 @@ mod tests {
             String::from("commit"),
             String::from("Add test.txt"),
         ];
-        let (output_text, commit_hash) = commit(&test_args)?;
+        let (output_text, commit_hash) = commit(dir, &test_args)?;
         assert_eq!("", output_text);
-        let time = Utc::now().timestamp();
-        let tree_text = format!("Trees\nBlobs\ntest.txt: {}", file_hash);
-        let tree_hash = sha2(&tree_text);
-        assert!(object_exists(&tree_hash));
-        let commit_string = format!(
-            "Parent\n{}\nTime\n{}\nTree Hash\n{}\nMessage\n{}",
-            INITIAL_COMMIT_HASH,
-            time.to_string(),
-            tree_hash,
-            "Add test.txt",
-        );
-        assert_eq!(sha2(&commit_string), commit_hash);
-        let index_contents_after_commit = get_file_contents(".vcs/index")?;
+        let tree_text = format!("Trees\nBlobs\n100644 test.txt: {}", file_hash);
+        let tree_hash = hash_object(ObjectKind::Tree, tree_text.as_bytes());
+        assert!(repo.object_exists(&tree_hash));
+        let header = parse_commit_header(
+            &commit_hash,
+            &repo.read_object_text(&commit_hash, ObjectKind::Commit)?,
+        )?;
+        assert_eq!(vec![String::from(INITIAL_COMMIT_HASH)], header.parents);
+        assert_eq!(tree_hash, header.tree);
+        assert!(header.author.is_some());
+        assert_eq!(header.author, header.committer);
+        assert_eq!("Add test.txt", get_commit_message(&repo, &commit_hash)?);
+        let index_contents_after_commit = index_text(&Repository::open(dir).unwrap().read_index()?);
         assert_eq!(index_contents_after_commit, "");
-        assert_eq!(commit_hash, get_head_commit()?);
+        assert_eq!(commit_hash, repo.get_head_commit()?);
+        Ok(())
+    }
+
+    #[test]
+    fn files_in_subdirectories() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        std::fs::create_dir_all(dir.join("src/deep"))?;
+        std::fs::create_dir_all(dir.join("docs"))?;
+        for (filename, text) in [
+            ("src/main.rs", "main"),
+            ("src/deep/lib.rs", "lib"),
+            ("docs/guide.md", "guide"),
+        ] {
+            let mut file = File::create(dir.join(filename))?;
+            file.write_all(text.as_bytes())?;
+            let _ = add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(filename),
+                ],
+            )?;
+        }
+        let (_, first_commit) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add files"),
+            ],
+        )?;
+        assert_eq!(
+            Some(hash_object(ObjectKind::Blob, b"lib")),
+            get_hash_in_commit(&repo, &first_commit, "src/deep/lib.rs")?
+        );
+        let tree_text =
+            repo.read_object_text(&get_commit_tree(&repo, &first_commit)?, ObjectKind::Tree)?;
+        assert!(tree_text.starts_with("Trees\n040000 docs: "));
+        assert!(tree_text.ends_with("Blobs"));
+
+        // Changing src/main.rs leaves the docs tree as it was
+        let mut file = File::create(dir.join("src/main.rs"))?;
+        file.write_all(b"new main")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("src/main.rs"),
+            ],
+        )?;
+        let (_, second_commit) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Change main.rs"),
+            ],
+        )?;
+        let new_tree_text =
+            repo.read_object_text(&get_commit_tree(&repo, &second_commit)?, ObjectKind::Tree)?;
+        let docs_line = |text: &str| text.lines().nth(1).unwrap().to_string();
+        assert_eq!(docs_line(&tree_text), docs_line(&new_tree_text));
+        assert_ne!(tree_text, new_tree_text);
+        assert_eq!(
+            Some(hash_object(ObjectKind::Blob, b"new main")),
+            get_hash_in_commit(&repo, &second_commit, "src/main.rs")?
+        );
         Ok(())
     }
 
     #[test]
     fn just_remove() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         // init vcs dir
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
 
         // mutate and add a file
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         let file_text = "commit time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let mut file = File::create("test2.txt")?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let mut file = File::create(dir.join("test2.txt"))?;
         let file_text = "";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test2.txt"),
-        ])?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test2.txt"),
+            ],
+        )?;
 
         // Commit
-        let (_, _) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt and test2.txt"),
-        ])?;
-
-        let rm_text = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ])?;
+        let (_, _) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt and test2.txt"),
+            ],
+        )?;
+
+        let rm_text = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!("", rm_text);
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test2.txt"),
-        ])?;
-        assert!(!file_exists("test.txt"));
-        assert!(!file_exists("test2.txt"));
-        let (_, _) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Remove test.txt and test2.txt"),
-        ])?;
-        let head_commit = get_head_commit()?;
-        assert_eq!("DNE", get_hash_in_commit(&head_commit, "test2.txt")?);
-        assert_eq!("DNE", get_hash_in_commit(&head_commit, "test.txt")?);
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test2.txt"),
+            ],
+        )?;
+        assert!(!file_exists(dir.join("test.txt")));
+        assert!(!file_exists(dir.join("test2.txt")));
+        let (_, _) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Remove test.txt and test2.txt"),
+            ],
+        )?;
+        let head_commit = repo.get_head_commit()?;
+        assert_eq!(None, get_hash_in_commit(&repo, &head_commit, "test2.txt")?);
+        assert_eq!(None, get_hash_in_commit(&repo, &head_commit, "test.txt")?);
 
         Ok(())
     }
 
     #[test]
     fn add_and_remove() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         // init vcs dir
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
 
         // mutate and add a file
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         let file_text = "commit time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
 
         // Commit
-        let (_, _) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
-
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ])?;
-        let mut file = File::create("test2.txt")?;
+        let (_, _) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let mut file = File::create(dir.join("test2.txt"))?;
         let file_text = "";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test2.txt"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test2 and remove test1"),
+            ],
+        )?;
+        assert!(!file_exists(dir.join("test.txt")));
+        let expected_tree = format!(
+            "Trees\nBlobs\n100644 test2.txt: {}",
+            hash_object(ObjectKind::Blob, b"")
+        );
+        let tree_hash = get_commit_tree(&repo, &commit_hash)?;
+        assert_eq!(
+            expected_tree,
+            repo.read_object_text(&tree_hash, ObjectKind::Tree)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn locked_by_another_process() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        File::create(dir.join("test.txt"))?.write_all(b"text")?;
+        let add_args = [
             String::from("target/debug/vcs"),
             String::from("add"),
-            String::from("test2.txt"),
-        ])?;
-        let (_, commit_hash) = commit(&vec![
+            String::from("test.txt"),
+        ];
+        let commit_args = [
             String::from("target/debug/vcs"),
             String::from("commit"),
-            String::from("Add test2 and remove test1"),
-        ])?;
-        assert!(!file_exists("test.txt"));
-        let expected_tree = format!("Trees\nBlobs\ntest2.txt: {}", sha2("blob\n"));
-        let tree_hash = get_commit_tree(&commit_hash)?;
-        assert_eq!(expected_tree, get_object_contents(&tree_hash)?);
+            String::from("Add test.txt"),
+        ];
 
+        File::create(dir.join(".vcs/index.lock"))?;
+        assert!(matches!(
+            add(dir, &add_args),
+            Err(VcsError::RepositoryLocked(lock_path)) if lock_path.ends_with("index.lock")
+        ));
+        assert_eq!(
+            "",
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
+        std::fs::remove_file(dir.join(".vcs/index.lock"))?;
+        add(dir, &add_args)?;
+        let staged = index_text(&Repository::open(dir).unwrap().read_index()?);
+
+        // The branch is left alone and nothing is unstaged when the commit can't be recorded
+        File::create(dir.join(".vcs/branches/main.lock"))?;
+        assert!(matches!(
+            commit(dir, &commit_args),
+            Err(VcsError::RepositoryLocked(lock_path)) if lock_path.ends_with("main.lock")
+        ));
+        assert_eq!(
+            INITIAL_COMMIT_HASH,
+            get_file_contents(dir.join(".vcs/branches/main"))?
+        );
+        assert_eq!(
+            staged,
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
+        std::fs::remove_file(dir.join(".vcs/branches/main.lock"))?;
+
+        // The index stays locked for the whole commit, so a commit can't start while another
+        // process is staging changes
+        File::create(dir.join(".vcs/index.lock"))?;
+        assert!(matches!(
+            commit(dir, &commit_args),
+            Err(VcsError::RepositoryLocked(lock_path)) if lock_path.ends_with("index.lock")
+        ));
+        assert_eq!(
+            INITIAL_COMMIT_HASH,
+            get_file_contents(dir.join(".vcs/branches/main"))?
+        );
+        std::fs::remove_file(dir.join(".vcs/index.lock"))?;
+        let (_, commit_hash) = commit(dir, &commit_args)?;
+        assert_eq!(
+            commit_hash,
+            get_file_contents(dir.join(".vcs/branches/main"))?
+        );
         Ok(())
     }
 }
@@ -0,0 +1,425 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::symlink_metadata,
+    path::Path,
+};
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        blob::{get_blob_hash, read_blob},
+        commit::get_commit_tree,
+        diff::unified_diff,
+        index::{IndexEntry, Stage},
+        mode::FileMode,
+        repo::Repository,
+        revision::{resolve_commit, resolve_diff_range},
+        tree::read_tree_files,
+    },
+};
+
+/// Usage text shown by `vcs help diff`
+pub const USAGE: &str = "usage: vcs diff [-U <n>] [--staged] [-- <path>...]\n   or: vcs diff [-U <n>] <commit> <commit> [-- <path>...]\n   or: vcs diff [-U <n>] <commit>..<commit> [-- <path>...]\n   or: vcs diff [-U <n>] <commit>...<commit> [-- <path>...]\n\nShow changes between the worktree and the index, the index and HEAD, or two commits.\n\n    --staged, --cached    compare the staged changes with HEAD\n    -U, --unified <n>     show <n> lines of context around each change (default: 3)";
+
+/// How many unchanged lines are shown around each change, unless `-U` is given
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// The version of a file on one side of a diff: its mode and blob hash, and whether its contents
+/// are read from the worktree rather than the object store
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileState {
+    mode: FileMode,
+    hash: String,
+    in_worktree: bool,
+}
+
+/// Executes `vcs diff` with `args` as arguments. Returns the string that should be logged to the
+/// console.
+///
+/// There are three possible use cases of this function:
+///     1. `vcs diff`: Shows the changes in the worktree that aren't staged, comparing each tracked
+///         file with its version in the index (or in HEAD, if nothing is staged for it).
+///         Untracked files are left out.
+///     2. `vcs diff --staged`: Shows the staged changes, comparing the index with HEAD.
+///     3. `vcs diff <COMMIT> <COMMIT>`: Shows the changes from the first commit to the second.
+///         `vcs diff <A>..<B>` is the same as `vcs diff <A> <B>`, and `vcs diff <A>...<B>` shows
+///         the changes on `B` since it forked from `A`, from their best common ancestor to `B`.
+/// Each can be limited to files at or under the paths given after `--`.
+///
+/// Each changed file is shown as a unified diff, sorted by path:
+///     diff --vcs a/<PATH> b/<PATH>
+///     <new file mode/deleted file mode/old mode and new mode lines, if the mode changed>
+///     --- a/<PATH>
+///     +++ b/<PATH>
+///     @@ -<OLD START>,<OLD LINES> +<NEW START>,<NEW LINES> @@
+///     <lines, prefixed by ` `, `-` or `+`>
+/// where a missing side is `/dev/null`, and `-U <n>` sets the number of unchanged lines around
+/// each change. Binary files are only reported as `Binary files a/<PATH> and b/<PATH> differ`.
+/// Logs nothing if there are no changes.
+///
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if the arguments are not
+/// one of the forms above, `MissingOptionValue` or `InvalidOptionValue` if `-U` isn't followed by
+/// a number, the errors of `resolve_commit` if a commit can't be resolved, `NoCommonAncestor` if
+/// the sides of `A...B` share no history, and `OutsideRepository` if a path is outside
+/// the repository.
+///
+/// * `dir` - directory `diff` was called from
+/// * `args` - arguments `diff` was called with
+pub fn diff(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "diff");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let mut staged = false;
+    let mut context = DEFAULT_CONTEXT;
+    let mut operands: Vec<&String> = vec![];
+    let mut paths: Vec<String> = vec![];
+    let mut options = args[2..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--staged" | "--cached" => staged = true,
+            "-U" | "--unified" => {
+                let value = options
+                    .next()
+                    .ok_or_else(|| VcsError::MissingOptionValue(arg.clone()))?;
+                context = value.parse().map_err(|_| VcsError::InvalidOptionValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                })?;
+            }
+            "--" => {
+                for path in options.by_ref() {
+                    let name = repo
+                        .relative_name(dir, path)
+                        .ok_or_else(|| VcsError::OutsideRepository(path.clone()))?;
+                    paths.push(name);
+                }
+            }
+            _ => operands.push(arg),
+        }
+    }
+
+    let (old, new) = match (staged, operands.as_slice()) {
+        (false, []) => {
+            let index = index_files(&repo)?;
+            let worktree = worktree_versions(&repo, &index)?;
+            (index, worktree)
+        }
+        (true, []) => (head_files(&repo)?, index_files(&repo)?),
+        (false, [old_commit, new_commit]) => (
+            commit_files(&repo, &resolve_commit(&repo, old_commit)?)?,
+            commit_files(&repo, &resolve_commit(&repo, new_commit)?)?,
+        ),
+        (false, [range]) => match resolve_diff_range(&repo, range)? {
+            Some((old_commit, new_commit)) => (
+                commit_files(&repo, &old_commit)?,
+                commit_files(&repo, &new_commit)?,
+            ),
+            None => return Err(VcsError::IncorrectOperands),
+        },
+        _ => return Err(VcsError::IncorrectOperands),
+    };
+
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut output = String::new();
+    for name in names {
+        let in_paths = paths.is_empty()
+            || paths.iter().any(|path| {
+                path.is_empty() || name == path || name.starts_with(&format!("{}/", path))
+            });
+        if in_paths {
+            output.push_str(&diff_file(
+                &repo,
+                name,
+                old.get(name),
+                new.get(name),
+                context,
+            )?);
+        }
+    }
+    Ok(output.trim_end_matches('\n').to_string())
+}
+
+/// Outputs the diff of the file `name` from `old` to `new`, or an empty string if they are the
+/// same. `None` stands for a missing file.
+fn diff_file(
+    repo: &Repository,
+    name: &str,
+    old: Option<&FileState>,
+    new: Option<&FileState>,
+    context: usize,
+) -> Result<String> {
+    let same = |old: &FileState, new: &FileState| old.mode == new.mode && old.hash == new.hash;
+    if old.zip(new).is_some_and(|(old, new)| same(old, new)) {
+        return Ok(String::new());
+    }
+    let mut output = format!("diff --vcs a/{} b/{}\n", name, name);
+    match (old, new) {
+        (None, Some(new)) => output.push_str(&format!("new file mode {}\n", new.mode)),
+        (Some(old), None) => output.push_str(&format!("deleted file mode {}\n", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            output.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode))
+        }
+        _ => {}
+    }
+    if old.zip(new).is_some_and(|(old, new)| old.hash == new.hash) {
+        return Ok(output);
+    }
+
+    let old_contents = contents(repo, name, old)?;
+    let new_contents = contents(repo, name, new)?;
+    let old_label = old.map_or(String::from("/dev/null"), |_| format!("a/{}", name));
+    let new_label = new.map_or(String::from("/dev/null"), |_| format!("b/{}", name));
+    if old_contents.contains(&0) || new_contents.contains(&0) {
+        output.push_str(&format!(
+            "Binary files {} and {} differ\n",
+            old_label, new_label
+        ));
+    } else {
+        output.push_str(&format!("--- {}\n+++ {}\n", old_label, new_label));
+        output.push_str(&unified_diff(&old_contents, &new_contents, context));
+    }
+    Ok(output)
+}
+
+/// Reads the contents of `version` of the file `name`, or nothing if it is `None`
+fn contents(repo: &Repository, name: &str, version: Option<&FileState>) -> Result<Vec<u8>> {
+    match version {
+        None => Ok(vec![]),
+        Some(version) if version.in_worktree => Ok(get_blob_hash(repo.worktree_path(name))?.1),
+        Some(version) => read_blob(repo, &version.hash),
+    }
+}
+
+/// Outputs the files in the tree of the commit `commit_hash`
+fn commit_files(repo: &Repository, commit_hash: &str) -> Result<BTreeMap<String, FileState>> {
+    Ok(read_tree_files(repo, &get_commit_tree(repo, commit_hash)?)?
+        .into_iter()
+        .map(|(name, (mode, hash))| {
+            let state = FileState {
+                mode,
+                hash,
+                in_worktree: false,
+            };
+            (name, state)
+        })
+        .collect())
+}
+
+/// Outputs the files in the head commit
+fn head_files(repo: &Repository) -> Result<BTreeMap<String, FileState>> {
+    commit_files(repo, &repo.get_head_commit()?)
+}
+
+/// Outputs the files the next commit would have: the head commit's, with the staged changes
+/// applied. A file with conflicts from a merge stands at our version.
+fn index_files(repo: &Repository) -> Result<BTreeMap<String, FileState>> {
+    let mut files = head_files(repo)?;
+    for entry in repo.read_index()? {
+        match entry {
+            IndexEntry::Blob { hash, name, mode }
+            | IndexEntry::Conflict {
+                stage: Stage::Ours,
+                hash,
+                name,
+                mode,
+            } => {
+                let state = FileState {
+                    mode,
+                    hash,
+                    in_worktree: false,
+                };
+                files.insert(name, state);
+            }
+            IndexEntry::Rm { name } => {
+                files.remove(&name);
+            }
+            IndexEntry::Conflict { .. } => {}
+        }
+    }
+    Ok(files)
+}
+
+/// Outputs the version in the worktree of each file in `tracked`, leaving out those that are
+/// missing
+///
+/// Files whose stat data is unchanged since the index's stat cache last saw them are not read,
+/// and the cache is updated with the files that were, unless another process holds the lock on
+/// the index.
+fn worktree_versions(
+    repo: &Repository,
+    tracked: &BTreeMap<String, FileState>,
+) -> Result<BTreeMap<String, FileState>> {
+    let mut stat_cache = repo.load_index()?.stat_cache;
+    let mut files = BTreeMap::new();
+    for name in tracked.keys() {
+        let path = repo.worktree_path(name);
+        if symlink_metadata(&path).is_err() || FileMode::of_path(&path)? == FileMode::Directory {
+            continue;
+        }
+        let (mode, hash) = stat_cache.hash_file(repo, name)?;
+        let state = FileState {
+            mode,
+            hash,
+            in_worktree: true,
+        };
+        files.insert(name.clone(), state);
+    }
+    match repo.write_stat_cache(&stat_cache) {
+        Ok(()) | Err(VcsError::RepositoryLocked(_)) => Ok(files),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for diff
+    //      Failure cases: not in vcs dir, incorrect operands, bad -U, unknown commit
+    //      Comparison: worktree and index, index and HEAD, two commits
+    //      Files: unchanged, modified, added, deleted, mode changed, binary, untracked
+    //      Paths: none, a file, a directory
+
+    use std::fs::{remove_file, write};
+
+    use super::*;
+    use crate::{
+        operations::init::init,
+        utils::test_dir::{commit_staged, make_test_dir, write_and_add},
+    };
+
+    /// Runs `vcs diff` in `dir` with `options`
+    fn run_diff(dir: &Path, options: &[&str]) -> Result<String> {
+        let mut args = vec![String::from("target/debug/vcs"), String::from("diff")];
+        args.extend(options.iter().map(|option| option.to_string()));
+        diff(dir, &args)
+    }
+
+    #[test]
+    fn failure_cases() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        assert!(matches!(run_diff(dir, &[]), Err(VcsError::NotARepository)));
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        assert!(matches!(
+            run_diff(dir, &["main"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run_diff(dir, &["--staged", "main", "main"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run_diff(dir, &["-U"]),
+            Err(VcsError::MissingOptionValue(option)) if option == "-U"
+        ));
+        assert!(matches!(
+            run_diff(dir, &["-U", "lots"]),
+            Err(VcsError::InvalidOptionValue { value, .. }) if value == "lots"
+        ));
+        assert!(matches!(
+            run_diff(dir, &["main", "nope"]),
+            Err(VcsError::InvalidRef(name)) if name == "nope"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn worktree_and_staged() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        write_and_add(dir, "a.txt", "1\n2\n3\n")?;
+        write_and_add(dir, "gone.txt", "gone\n")?;
+        let _ = commit_staged(dir, "Change files")?;
+        assert_eq!("", run_diff(dir, &[])?);
+
+        write_and_add(dir, "a.txt", "1\ntwo\n3\n")?;
+        write(dir.join("a.txt"), "1\ntwo\n3\n4")?;
+        remove_file(dir.join("gone.txt"))?;
+        write(dir.join("untracked.txt"), "u\n")?;
+        assert_eq!(
+            "diff --vcs a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -1,3 +1,4 @@\n 1\n two\n 3\n+4\n\\ No newline at end of file\ndiff --vcs a/gone.txt b/gone.txt\ndeleted file mode 100644\n--- a/gone.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone",
+            run_diff(dir, &[])?
+        );
+        assert_eq!(
+            "diff --vcs a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -2 +2 @@\n-2\n+two",
+            run_diff(dir, &["--staged", "-U", "0"])?
+        );
+        assert_eq!(run_diff(dir, &["--staged"])?, run_diff(dir, &["--cached"])?);
+        assert_eq!("", run_diff(dir, &["--staged", "--", "gone.txt"])?);
+        Ok(())
+    }
+
+    #[test]
+    fn between_commits() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        std::fs::create_dir_all(dir.join("src"))?;
+        write_and_add(dir, "src/lib.rs", "lib\n")?;
+        write_and_add(dir, "data.bin", "\0old")?;
+        let first = commit_staged(dir, "Change files")?;
+        write_and_add(dir, "src/main.rs", "main\n")?;
+        write_and_add(dir, "data.bin", "\0new")?;
+        let second = commit_staged(dir, "Change files")?;
+
+        assert_eq!(
+            "diff --vcs a/data.bin b/data.bin\nBinary files a/data.bin and b/data.bin differ\ndiff --vcs a/src/main.rs b/src/main.rs\nnew file mode 100644\n--- /dev/null\n+++ b/src/main.rs\n@@ -0,0 +1 @@\n+main",
+            run_diff(dir, &[&first, &second])?
+        );
+        assert_eq!(
+            "diff --vcs a/src/main.rs b/src/main.rs\ndeleted file mode 100644\n--- a/src/main.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-main",
+            run_diff(dir, &["main", &first, "--", "src"])?
+        );
+        // Paths are relative to where diff runs
+        assert_eq!(
+            run_diff(dir, &[&first, "main", "--", "src/main.rs"])?,
+            run_diff(&dir.join("src"), &[&first, "main", "--", "main.rs"])?
+        );
+        // Ranges and other revisions name the same commits
+        assert_eq!(
+            run_diff(dir, &[&first, &second])?,
+            run_diff(dir, &[&format!("{}..", &first[..8])])?
+        );
+        assert_eq!(
+            run_diff(dir, &[&first, &second])?,
+            run_diff(dir, &["HEAD~...main"])?
+        );
+        assert!(matches!(
+            run_diff(dir, &["HEAD"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_changes() -> Result<()> {
+        use std::{
+            fs::{set_permissions, Permissions},
+            os::unix::fs::PermissionsExt,
+        };
+
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        write_and_add(dir, "run.sh", "echo hi\n")?;
+        let _ = commit_staged(dir, "Change files")?;
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o755))?;
+        assert_eq!(
+            "diff --vcs a/run.sh b/run.sh\nold mode 100644\nnew mode 100755",
+            run_diff(dir, &[])?
+        );
+        Ok(())
+    }
+}
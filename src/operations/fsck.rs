@@ -0,0 +1,425 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        commit::parse_commit_header,
+        object::{decode_object, hash_object, is_legacy_object, ObjectKind},
+        reachable::roots,
+        repo::Repository,
+        tree::parse_tree_entries,
+    },
+    utils::{
+        fs_utils::get_file_bytes,
+        hash::{is_hash, sha2_bytes},
+    },
+};
+
+/// Usage text shown by `vcs help fsck`
+pub const USAGE: &str = "usage: vcs fsck\n\nCheck every object for corruption, and report missing and dangling objects.";
+
+/// Executes `vcs fsck` with `args` as arguments. Returns the string that should be logged to the
+/// console.
+///
+/// Rehashes every loose and packed object and compares the result with the name it is stored
+/// under, then checks that every tree and commit parses, and that every tree entry, commit parent
+/// and commit tree, as well as every branch, HEAD and index entry, points at an existing object of
+/// the right kind. Objects nothing points at are reported as `dangling <kind> <hash>`; those are
+/// harmless, and are left for `vcs gc` to clean up. Logs the dangling objects followed by `No
+/// problems found.`.
+///
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if any arguments were
+/// supplied, and `IntegrityCheckFailed` with the whole report if any object is corrupt, missing
+/// or of the wrong kind.
+///
+/// * `dir` - directory `fsck` was called from
+/// * `args` - arguments `fsck` was called with
+pub fn fsck(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "fsck");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    if args.len() != 2 {
+        return Err(VcsError::IncorrectOperands);
+    }
+
+    let mut check = Check::default();
+    for hash in repo.loose_object_hashes()? {
+        if !is_hash(&hash) {
+            check
+                .problems
+                .push(format!("object file {} is not named by a hash", hash));
+            continue;
+        }
+        let stored = get_file_bytes(repo.object_path(&hash))?;
+        match decode_object(&hash, &stored) {
+            Ok((kind, body)) => {
+                // Legacy objects were hashed as they are stored
+                let actual = if is_legacy_object(&stored) {
+                    sha2_bytes(&stored)
+                } else {
+                    hash_object(kind, &body)
+                };
+                check.found_object(hash, &actual, kind, body);
+            }
+            Err(e) => check.found_corrupt(hash, e.to_string()),
+        }
+    }
+    match repo.packs() {
+        Ok(packs) => {
+            for pack in packs {
+                match pack.read_objects() {
+                    Ok(objects) => {
+                        for object in objects {
+                            // A loose copy of the object has already been checked
+                            if !check.kinds.contains_key(&object.hash) {
+                                let actual = hash_object(object.kind, &object.body);
+                                check.found_object(object.hash, &actual, object.kind, object.body);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let problem = format!("pack {}: {}", pack.pack_path().display(), e);
+                        check.problems.push(problem);
+                        check.corrupt.extend(pack.hashes());
+                    }
+                }
+            }
+        }
+        Err(e) => check.problems.push(e.to_string()),
+    }
+
+    for (hash, kind, body) in std::mem::take(&mut check.containers) {
+        check.check_contents(&hash, kind, &body);
+    }
+    for root in roots(&repo)? {
+        check.check_link(&root.name, &root.hash, root.kind);
+    }
+
+    let mut dangling: Vec<(&String, &ObjectKind)> = check
+        .kinds
+        .iter()
+        .filter(|(hash, _)| !check.referenced.contains(*hash))
+        .collect();
+    dangling.sort_by_key(|(hash, _)| *hash);
+    let mut report = check.problems.clone();
+    report.extend(
+        dangling
+            .iter()
+            .map(|(hash, kind)| format!("dangling {} {}", kind, hash)),
+    );
+    if !check.problems.is_empty() {
+        return Err(VcsError::IntegrityCheckFailed(report));
+    }
+    report.push(String::from("No problems found."));
+    Ok(report.join("\n"))
+}
+
+/// What `fsck` has found so far
+#[derive(Default)]
+struct Check {
+    /// A line describing each problem found
+    problems: Vec<String>,
+    /// The kind of every object that hashes correctly, by hash
+    kinds: HashMap<String, ObjectKind>,
+    /// Objects that exist but can't be read, so they aren't also reported as missing
+    corrupt: HashSet<String>,
+    /// Trees and commits, whose contents point at other objects
+    containers: Vec<(String, ObjectKind, Vec<u8>)>,
+    /// Every object something points at
+    referenced: HashSet<String>,
+}
+
+impl Check {
+    /// Records the object stored as `hash`, whose contents hash to `actual`
+    fn found_object(&mut self, hash: String, actual: &str, kind: ObjectKind, body: Vec<u8>) {
+        if hash != actual {
+            self.problems.push(format!(
+                "{} {} is stored under the wrong hash: its contents hash to {}",
+                kind, hash, actual
+            ));
+            self.corrupt.insert(hash);
+            return;
+        }
+        if kind != ObjectKind::Blob {
+            self.containers.push((hash.clone(), kind, body));
+        }
+        self.kinds.insert(hash, kind);
+    }
+
+    /// Records that the object stored as `hash` can't be read, because of `problem`
+    fn found_corrupt(&mut self, hash: String, problem: String) {
+        self.problems.push(problem);
+        self.corrupt.insert(hash);
+    }
+
+    /// Checks that the tree or commit `hash`, of kind `kind` and holding `body`, parses, and that
+    /// everything it points to exists
+    fn check_contents(&mut self, hash: &str, kind: ObjectKind, body: &[u8]) {
+        let from = format!("{} {}", kind, hash);
+        let text = match std::str::from_utf8(body) {
+            Ok(text) => text,
+            Err(_) => {
+                let problem = format!("Object {} is corrupt: not valid UTF-8", hash);
+                return self.found_corrupt(hash.to_string(), problem);
+            }
+        };
+        let links = match kind {
+            ObjectKind::Tree => parse_tree_entries(hash, text).map(|entries| {
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        let kind = entry.kind();
+                        (entry.hash, kind)
+                    })
+                    .collect()
+            }),
+            ObjectKind::Commit => parse_commit_header(hash, text).map(|header| {
+                let mut links = vec![(header.tree, ObjectKind::Tree)];
+                links.extend(
+                    header
+                        .parents
+                        .into_iter()
+                        .map(|parent| (parent, ObjectKind::Commit)),
+                );
+                links
+            }),
+            ObjectKind::Blob => Ok(vec![]),
+        };
+        match links {
+            Ok(links) => {
+                for (link_hash, link_kind) in links {
+                    self.check_link(&from, &link_hash, Some(link_kind));
+                }
+            }
+            Err(e) => self.found_corrupt(hash.to_string(), e.to_string()),
+        }
+    }
+
+    /// Checks that `hash`, which `from` points to, exists and is of kind `expected`, if given
+    fn check_link(&mut self, from: &str, hash: &str, expected: Option<ObjectKind>) {
+        self.referenced.insert(hash.to_string());
+        let expected_name = expected.map_or("object", |kind| kind.name());
+        match self.kinds.get(hash) {
+            None if self.corrupt.contains(hash) => {}
+            None => self.problems.push(format!(
+                "missing {} {}, pointed to by {}",
+                expected_name, hash, from
+            )),
+            Some(found) if expected.is_some_and(|expected| expected != *found) => {
+                self.problems.push(format!(
+                    "{} points to {} as a {}, but it is a {}",
+                    from, hash, expected_name, found
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for fsck
+    //      Failure cases: not in vcs dir, incorrect operands
+    //      Objects: loose, packed, legacy
+    //      Problems: none, dangling object, truncated object, object under the wrong hash,
+    //          missing object, ref to an object of the wrong kind
+
+    use std::fs::{copy, remove_file, write};
+
+    use super::*;
+    use crate::{
+        objects::commit::{get_commit_tree, get_hash_in_commit},
+        operations::{add::add, commit::commit, init::init, log::log, repack::repack},
+        utils::{hash::sha2, test_dir::make_test_dir},
+    };
+
+    /// Creates a repository in `dir` with a commit adding `test.txt`, and outputs it
+    fn repository_with_commit(dir: &Path) -> Result<Repository> {
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        write(dir.join("test.txt"), "text")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        Ok(Repository::open(dir).unwrap())
+    }
+
+    /// Runs `vcs fsck` in `dir`
+    fn run_fsck(dir: &Path) -> Result<String> {
+        fsck(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("fsck")],
+        )
+    }
+
+    /// Runs `vcs fsck` in `dir`, which should fail, and outputs its report
+    fn failed_report(dir: &Path) -> Vec<String> {
+        match run_fsck(dir) {
+            Err(VcsError::IntegrityCheckFailed(report)) => report,
+            other => panic!("expected fsck to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn not_in_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            run_fsck(test_dir.path()),
+            Err(VcsError::NotARepository)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn incorrect_operands() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        assert!(matches!(
+            fsck(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("fsck"),
+                    String::from("--full"),
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn healthy_repositories() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        repository_with_commit(dir)?;
+        assert_eq!("No problems found.", run_fsck(dir)?);
+        repack(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("repack")],
+        )?;
+        assert_eq!("No problems found.", run_fsck(dir)?);
+        Ok(())
+    }
+
+    #[test]
+    fn dangling_objects() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = repository_with_commit(dir)?;
+        let dangling = repo.write_object(ObjectKind::Blob, "nobody points here")?;
+        let legacy = sha2("blob\nlegacy");
+        let legacy_path = repo.object_path(&legacy);
+        std::fs::create_dir_all(legacy_path.parent().unwrap())?;
+        write(legacy_path, "blob\nlegacy")?;
+        let output = run_fsck(dir)?;
+        assert!(output.contains(&format!("dangling blob {}", dangling)));
+        assert!(output.contains(&format!("dangling blob {}", legacy)));
+        assert!(output.ends_with("No problems found."));
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_commit() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = repository_with_commit(dir)?;
+        let head = repo.get_head_commit()?;
+        let path = repo.object_path(&head);
+        let stored = get_file_bytes(&path)?;
+        write(&path, &stored[..stored.len() / 2])?;
+
+        let report = failed_report(dir);
+        assert!(report.contains(&format!(
+            "Object {} is corrupt: could not decompress object",
+            head
+        )));
+        // Other operations fail cleanly too
+        assert!(matches!(
+            log(dir, &[String::from("target/debug/vcs"), String::from("log")]),
+            Err(VcsError::CorruptObject { hash, .. }) if hash == head
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_commit() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = repository_with_commit(dir)?;
+        let bad_commit = repo.write_object(ObjectKind::Commit, "Parent\nNo parent")?;
+        repo.set_branch_commit("bad", &bad_commit)?;
+        let report = failed_report(dir);
+        assert_eq!(
+            vec![format!(
+                "Object {} is corrupt: expected `Time` on line 3",
+                bad_commit
+            )],
+            report
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_hash_and_missing_objects() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = repository_with_commit(dir)?;
+        let head = repo.get_head_commit()?;
+        let tree = get_commit_tree(&repo, &head)?;
+        let blob = get_hash_in_commit(&repo, &head, "test.txt")?.unwrap();
+        let impostor = sha2("impostor");
+        std::fs::create_dir_all(repo.object_path(&impostor).parent().unwrap())?;
+        copy(repo.object_path(&blob), repo.object_path(&impostor))?;
+        remove_file(repo.object_path(&blob))?;
+
+        let report = failed_report(dir);
+        assert!(report.contains(&format!(
+            "blob {} is stored under the wrong hash: its contents hash to {}",
+            impostor, blob
+        )));
+        assert!(report.contains(&format!(
+            "missing blob {}, pointed to by tree {}",
+            blob, tree
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_kind() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let repo = repository_with_commit(dir)?;
+        let blob = repo.write_object(ObjectKind::Blob, "not a commit")?;
+        repo.set_branch_commit("bad", &blob)?;
+        assert_eq!(
+            vec![format!(
+                "branch bad points to {} as a commit, but it is a blob",
+                blob
+            )],
+            failed_report(dir)
+        );
+        Ok(())
+    }
+}
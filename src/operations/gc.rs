@@ -0,0 +1,455 @@
+use std::{
+    fs::{metadata, read_dir, remove_dir, remove_file},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{reachable::reachable_objects, reflog::expire_reflogs, repo::Repository},
+};
+
+/// Usage text shown by `vcs help gc`
+pub const USAGE: &str = "usage: vcs gc [--dry-run] [--grace-period <seconds>] [--reflog-expire <seconds>]\n\nDelete loose objects that can't be reached from a branch, HEAD, the index or a reflog, and\ntemporary files left by interrupted writes.\n\n    --dry-run                    list the objects that would be deleted, without deleting them\n    --grace-period <seconds>     keep unreachable objects and temporary files newer than this\n                                 (default: two weeks)\n    --reflog-expire <seconds>    delete reflog entries older than this first (default: 90 days)";
+
+/// How old an unreachable object must be before `gc` deletes it, unless `--grace-period` is given
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// How old a reflog entry must be before `gc` deletes it, unless `--reflog-expire` is given
+pub const DEFAULT_REFLOG_EXPIRY: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Outputs the temporary files `write_atomically` left in the objects and pack directories, such
+/// as after a crash, that were last modified at least `grace_period` before `now`, sorted
+fn stale_temp_files(
+    repo: &Repository,
+    now: SystemTime,
+    grace_period: Duration,
+) -> Result<Vec<PathBuf>> {
+    let mut stale = vec![];
+    for dir in [repo.vcs_dir().join("objects"), repo.pack_dir()] {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_name().to_string_lossy().starts_with("tmp_") || !entry.path().is_file() {
+                continue;
+            }
+            let age = now
+                .duration_since(entry.metadata()?.modified()?)
+                .unwrap_or_default();
+            if age >= grace_period {
+                stale.push(entry.path());
+            }
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+/// Executes `vcs gc` with `args` as arguments. Returns the string that should be logged to the
+/// console.
+///
+/// First deletes every reflog entry older than the reflog expiry, logging `Expired <N> reflog
+/// entries.` if there were any. Then marks every object reachable from a branch, HEAD, the index or
+/// a reflog, and deletes every unmarked loose object last modified longer ago than the grace
+/// period. The grace period keeps objects that another operation has just written, but not yet
+/// pointed anything at. Packed objects are never deleted. Logs `Removed <N> unreachable
+/// objects.`; with `--dry-run`, logs `Would remove <hash>` for each object instead, followed by
+/// `Would remove <N> unreachable objects.`, and deletes nothing. A dry run logs `Would expire <N>
+/// reflog entries.` first, and since it keeps them, doesn't list the objects only they reach.
+///
+/// Also deletes the temporary files an interrupted write left in the objects and pack
+/// directories once they are older than the grace period, logging `Removed <N> temporary files.`
+/// (or `Would remove <N> temporary files.`) before the objects if there were any.
+///
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if an unknown argument
+/// was supplied, `MissingOptionValue` or `InvalidOptionValue` if `--grace-period` or
+/// `--reflog-expire` isn't followed by a number of seconds, `ReflogParse` if a reflog can't be
+/// parsed, and `ObjectNotFound` if a reachable object is missing, in which case nothing is
+/// deleted.
+///
+/// * `dir` - directory `gc` was called from
+/// * `args` - arguments `gc` was called with
+pub fn gc(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "gc");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let mut dry_run = false;
+    let mut grace_period = DEFAULT_GRACE_PERIOD;
+    let mut reflog_expiry = DEFAULT_REFLOG_EXPIRY;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--dry-run" => dry_run = true,
+            "--grace-period" | "--reflog-expire" => {
+                let value = options
+                    .next()
+                    .ok_or_else(|| VcsError::MissingOptionValue(option.clone()))?;
+                let seconds = value.parse().map_err(|_| VcsError::InvalidOptionValue {
+                    option: option.clone(),
+                    value: value.clone(),
+                })?;
+                match option.as_str() {
+                    "--grace-period" => grace_period = Duration::from_secs(seconds),
+                    _ => reflog_expiry = Duration::from_secs(seconds),
+                }
+            }
+            _ => return Err(VcsError::IncorrectOperands),
+        }
+    }
+
+    let now = SystemTime::now();
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let cutoff = since_epoch.as_secs() as i64 - reflog_expiry.as_secs() as i64;
+    let expired = expire_reflogs(&repo, cutoff, dry_run)?;
+    let mut output: Vec<String> = vec![];
+    if expired > 0 {
+        let verb = if dry_run { "Would expire" } else { "Expired" };
+        output.push(format!("{} {} reflog entries.", verb, expired));
+    }
+
+    let temp_files = stale_temp_files(&repo, now, grace_period)?;
+    if !temp_files.is_empty() {
+        if !dry_run {
+            for path in &temp_files {
+                remove_file(path)?;
+            }
+        }
+        let verb = if dry_run { "Would remove" } else { "Removed" };
+        output.push(format!("{} {} temporary files.", verb, temp_files.len()));
+    }
+
+    let reachable = reachable_objects(&repo)?;
+    let mut unreachable = vec![];
+    for hash in repo.loose_object_hashes()? {
+        if reachable.contains(&hash) {
+            continue;
+        }
+        let modified = metadata(repo.object_path(&hash))?.modified()?;
+        // An object modified "in the future" is as new as it gets
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age >= grace_period {
+            unreachable.push(hash);
+        }
+    }
+
+    if dry_run {
+        output.extend(
+            unreachable
+                .iter()
+                .map(|hash| format!("Would remove {}", hash)),
+        );
+        output.push(format!(
+            "Would remove {} unreachable objects.",
+            unreachable.len()
+        ));
+        return Ok(output.join("\n"));
+    }
+    for hash in &unreachable {
+        let path = repo.object_path(hash);
+        remove_file(&path)?;
+        let parent = path.parent().unwrap();
+        if read_dir(parent)?.next().is_none() {
+            remove_dir(parent)?;
+        }
+    }
+    output.push(format!(
+        "Removed {} unreachable objects.",
+        unreachable.len()
+    ));
+    Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for gc
+    //      Failure cases: not in vcs dir, unknown option, missing or invalid grace period
+    //      Unreachable objects: none, orphaned blob, commit of a deleted branch
+    //      Objects: older than the grace period, newer than the grace period
+    //      --dry-run: given, not given
+    //      Temporary files: none, older than the grace period, newer than the grace period
+
+    use std::fs::{write, File};
+
+    use super::*;
+    use crate::{
+        objects::commit::get_hash_in_commit,
+        operations::{branch::branch, checkout::checkout, init::init, log::log},
+        utils::test_dir::{commit_staged, make_test_dir, write_and_add},
+    };
+
+    #[test]
+    fn not_in_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            gc(
+                test_dir.path(),
+                &[String::from("target/debug/vcs"), String::from("gc")]
+            ),
+            Err(VcsError::NotARepository)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_options() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        assert!(matches!(
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--aggressive"),
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--grace-period"),
+                ]
+            ),
+            Err(VcsError::MissingOptionValue(option)) if option == "--grace-period"
+        ));
+        assert!(matches!(
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--grace-period"),
+                    String::from("soon"),
+                ]
+            ),
+            Err(VcsError::InvalidOptionValue { value, .. }) if value == "soon"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_unreachable() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        write_and_add(dir, "test.txt", "text")?;
+        commit_staged(dir, "Add test.txt")?;
+        write_and_add(dir, "staged.txt", "staged")?;
+        let repo = Repository::open(dir).unwrap();
+        let before = repo.loose_object_hashes()?;
+        assert_eq!(
+            "Removed 0 unreachable objects.",
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--grace-period"),
+                    String::from("0"),
+                ]
+            )?
+        );
+        assert_eq!(before, repo.loose_object_hashes()?);
+        Ok(())
+    }
+
+    #[test]
+    fn removes_orphaned_blobs() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let orphan = write_and_add(dir, "test.txt", "first draft")?;
+        let kept = write_and_add(dir, "test.txt", "final draft")?;
+        commit_staged(dir, "Add test.txt")?;
+        let repo = Repository::open(dir).unwrap();
+
+        // Too new to remove by default
+        assert_eq!(
+            "Would remove 0 unreachable objects.",
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--dry-run"),
+                ]
+            )?
+        );
+        assert_eq!(
+            format!(
+                "Would remove {}\nWould remove 1 unreachable objects.",
+                orphan
+            ),
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--dry-run"),
+                    String::from("--grace-period"),
+                    String::from("0"),
+                ]
+            )?
+        );
+        assert!(repo.object_exists(&orphan));
+
+        assert_eq!(
+            "Removed 1 unreachable objects.",
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--grace-period"),
+                    String::from("0"),
+                ]
+            )?
+        );
+        assert!(!repo.object_exists(&orphan));
+        assert_eq!(
+            Some(kept),
+            get_hash_in_commit(&repo, &repo.get_head_commit()?, "test.txt")?
+        );
+        assert!(log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")]
+        )?
+        .contains("Add test.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn removes_deleted_branches() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("topic"),
+            ],
+        )?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("topic"),
+            ],
+        )?;
+        write_and_add(dir, "topic.txt", "topic")?;
+        commit_staged(dir, "Add topic.txt")?;
+        let repo = Repository::open(dir).unwrap();
+        let topic_commit = repo.get_head_commit()?;
+        checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("main"),
+            ],
+        )?;
+        branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("-d"),
+                String::from("topic"),
+            ],
+        )?;
+
+        // The reflogs of HEAD and the deleted branch keep its commit until they expire
+        assert_eq!(
+            "Removed 0 unreachable objects.",
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--grace-period"),
+                    String::from("0"),
+                ]
+            )?
+        );
+        assert!(repo.object_exists(&topic_commit));
+
+        // Then the commit, its tree and the blob go
+        assert_eq!(
+            "Expired 6 reflog entries.\nRemoved 3 unreachable objects.",
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--grace-period"),
+                    String::from("0"),
+                    String::from("--reflog-expire"),
+                    String::from("0"),
+                ]
+            )?
+        );
+        assert!(!repo.object_exists(&topic_commit));
+        Ok(())
+    }
+
+    #[test]
+    fn removes_stale_temp_files() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let stale = repo.vcs_dir().join("objects").join("tmp_1_0");
+        let fresh = repo.vcs_dir().join("objects").join("tmp_1_1");
+        write(&stale, "half an object")?;
+        write(&fresh, "an object being written")?;
+        File::options()
+            .write(true)
+            .open(&stale)?
+            .set_modified(SystemTime::now() - 2 * DEFAULT_GRACE_PERIOD)?;
+
+        assert_eq!(
+            "Would remove 1 temporary files.\nWould remove 0 unreachable objects.",
+            gc(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("gc"),
+                    String::from("--dry-run"),
+                ]
+            )?
+        );
+        assert!(stale.exists());
+
+        assert_eq!(
+            "Removed 1 temporary files.\nRemoved 0 unreachable objects.",
+            gc(dir, &[String::from("target/debug/vcs"), String::from("gc")])?
+        );
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+        Ok(())
+    }
+}
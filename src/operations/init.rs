@@ -1,73 +1,67 @@
-use std::{
-    env::set_current_dir,
-    fs::{create_dir, File},
-    io::{Result, Write},
-};
+use std::{fs::create_dir, path::Path};
 
 use crate::{
-    objects::{commit::write_commit, tree::write_tree},
+    error::{Result, VcsError},
+    objects::{commit::write_commit, repo::Repository, tree::write_tree},
     utils::fs_utils::directory_exists,
 };
 
+/// Usage text shown by `vcs help init`
+pub const USAGE: &str = "usage: vcs init [<directory>]\n\nCreate an empty vcs repository in the current directory, or in <directory>.";
+
 /// Executes `vcs init` with `args` as arguments
 ///
 /// If there are no arguments, executes `vcs init` in the current directory.
 /// If there is one argument, executes `vcs init` in the directory named by the argument.
 /// In the directory `init` is working in, creates the `.vcs` directory with `HEAD`, `branches`,
 ///     `objects` subfolders
-/// If already in a vcs directory, logs `Already in a vcs directory.` to the console.
+/// If already in a vcs directory, returns `AlreadyARepository`.
 ///
+/// * `dir` - directory `init` was called from
 /// * `args` - arguments `init` was called with
-pub fn init(args: &Vec<String>) -> String {
+pub fn init(dir: &Path, args: &[String]) -> Result<String> {
     assert!(args[1] == "init");
     match args.len() {
         2 => {
-            if directory_exists(".vcs") {
-                return String::from("Already in a vcs directory.");
+            if directory_exists(dir.join(".vcs")) {
+                return Err(VcsError::AlreadyARepository);
             }
-            let _ = create_empty_vcs_dir();
-            return String::from("");
+            create_empty_vcs_dir(dir)?;
+            Ok(String::from(""))
         }
         3 => {
-            if !directory_exists(&args[2]) {
-                let _ = create_dir(&args[2]);
+            let target = dir.join(&args[2]);
+            if !directory_exists(&target) {
+                create_dir(&target)?;
             }
 
-            if directory_exists(".vcs") {
-                return String::from("Already in a vcs directory.");
+            if directory_exists(dir.join(".vcs")) {
+                return Err(VcsError::AlreadyARepository);
             }
 
-            let _ = set_current_dir(&args[2]);
-            let _ = create_empty_vcs_dir();
-            let _ = set_current_dir("..");
-            return String::from("");
+            create_empty_vcs_dir(&target)?;
+            Ok(String::from(""))
         }
-        _ => String::from("Incorrect number of arguments. Expected 0 or 1 arguments."),
+        _ => Err(VcsError::IncorrectArgumentCount(String::from("0 or 1"))),
     }
 }
 
 /// Creates a commit with date Jan. 1, 1970, on branch `main`, and initial message `Initial commit`
 ///
-/// Will throw error if not in a vcs directory
-fn create_first_commit() -> String {
-    assert!(directory_exists(".vcs"));
+/// It has no author or committer, so every repository starts from the same root commit.
+fn create_first_commit(repo: &Repository) -> Result<String> {
     let subtrees: Vec<String> = vec![];
     let subblobs: Vec<String> = vec![];
-    let tree_hash = write_tree(&subtrees, &subblobs);
-    write_commit("Initial commit", "No parent", 0, &tree_hash)
+    let tree_hash = write_tree(repo, &subtrees, &subblobs)?;
+    write_commit(repo, "Initial commit", &[], 0, &tree_hash, None, None)
 }
 
-/// Assuming program is in the correct directory, create an empty `.vcs` directory
-fn create_empty_vcs_dir() -> Result<()> {
-    let _ = create_dir(".vcs");
-    let _ = create_dir(".vcs/objects");
-    let _ = create_dir(".vcs/branches");
-    let _ = File::create(".vcs/index");
-    let commit_hash = create_first_commit();
-    let mut file = File::create(".vcs/HEAD")?;
-    let _ = file.write_all("main".as_bytes());
-    let mut file = File::create(".vcs/branches/main")?;
-    let _ = file.write_all(&commit_hash.as_bytes());
+/// Create an empty `.vcs` directory in `dir`
+fn create_empty_vcs_dir(dir: &Path) -> Result<()> {
+    let repo = Repository::create(dir)?;
+    let commit_hash = create_first_commit(&repo)?;
+    repo.set_head("main")?;
+    repo.set_branch_commit("main", &commit_hash)?;
 
     Ok(())
 }
@@ -81,43 +75,50 @@ mod tests {
      */
 
     use super::*;
-    use crate::utils::{
-        fs_utils::{file_exists, get_file_contents},
-        hash::sha2,
-        test_dir::make_test_dir,
+    use crate::{
+        objects::object::{hash_object, ObjectKind},
+        utils::{
+            fs_utils::{file_exists, get_file_contents},
+            test_dir::make_test_dir,
+        },
     };
-    use std::env::set_current_dir;
 
     #[test]
     fn more_than_one_argument() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("init"),
             String::from("arg1"),
             String::from("arg2"),
         ];
-        assert_eq!(
-            "Incorrect number of arguments. Expected 0 or 1 arguments.",
-            init(&test_args)
-        );
+        assert!(matches!(
+            init(dir, &test_args),
+            Err(VcsError::IncorrectArgumentCount(expected)) if expected == "0 or 1"
+        ));
         Ok(())
     }
 
     #[test]
     fn zero_arguments_not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         let test_args: Vec<String> = vec![String::from("target/debug/vcs"), String::from("init")];
 
-        assert_eq!("", init(&test_args));
-        assert_eq!("Already in a vcs directory.", init(&test_args));
-        check_empty_vcs_directory_exists()
+        assert_eq!("", init(dir, &test_args)?);
+        assert!(matches!(
+            init(dir, &test_args),
+            Err(VcsError::AlreadyARepository)
+        ));
+        check_empty_vcs_directory_exists(dir)
     }
 
     #[test]
     fn one_argument_not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
@@ -125,46 +126,49 @@ mod tests {
             String::from("test_dir"),
         ];
 
-        assert_eq!("", init(&test_args));
-        let _ = set_current_dir("test_dir");
-        check_empty_vcs_directory_exists()?;
-        assert_eq!("Already in a vcs directory.", init(&test_args));
+        assert_eq!("", init(dir, &test_args)?);
+        let dir = dir.join("test_dir");
+        check_empty_vcs_directory_exists(&dir)?;
+        assert!(matches!(
+            init(&dir, &test_args),
+            Err(VcsError::AlreadyARepository)
+        ));
         Ok(())
     }
 
-    fn check_empty_vcs_directory_exists() -> Result<()> {
-        assert!(directory_exists(".vcs"));
-        assert!(directory_exists(".vcs/branches"));
-        assert!(directory_exists(".vcs/objects"));
-        assert!(file_exists(".vcs/HEAD"));
-        assert!(file_exists(".vcs/index"));
+    fn check_empty_vcs_directory_exists(dir: &Path) -> Result<()> {
+        assert!(directory_exists(dir.join(".vcs")));
+        assert!(directory_exists(dir.join(".vcs/branches")));
+        assert!(directory_exists(dir.join(".vcs/objects")));
+        assert!(file_exists(dir.join(".vcs/HEAD")));
+        assert!(file_exists(dir.join(".vcs/index")));
         let empty_tree = "Trees\nBlobs";
-        let empty_tree_hash = sha2(empty_tree);
+        let empty_tree_hash = hash_object(ObjectKind::Tree, empty_tree.as_bytes());
         let tree_path = format!(
             ".vcs/objects/{}/{}",
-            empty_tree_hash[0..2].to_string(),
-            empty_tree_hash[2..].to_string()
+            &empty_tree_hash[0..2],
+            &empty_tree_hash[2..]
         );
-        assert!(file_exists(&tree_path));
+        assert!(file_exists(dir.join(tree_path)));
 
         let first_commit = format!(
             "Parent\nNo parent\nTime\n0\nTree Hash\n{}\nMessage\nInitial commit",
             empty_tree_hash
         );
-        let first_commit_hash = sha2(&first_commit);
+        let first_commit_hash = hash_object(ObjectKind::Commit, first_commit.as_bytes());
         let commit_path = format!(
             ".vcs/objects/{}/{}",
-            first_commit_hash[0..2].to_string(),
-            first_commit_hash[2..].to_string()
+            &first_commit_hash[0..2],
+            &first_commit_hash[2..]
         );
-        assert!(file_exists(&commit_path));
+        assert!(file_exists(dir.join(commit_path)));
 
-        assert!(file_exists(".vcs/branches/main"));
-        let contents = get_file_contents(".vcs/branches/main")?;
+        assert!(file_exists(dir.join(".vcs/branches/main")));
+        let contents = get_file_contents(dir.join(".vcs/branches/main"))?;
         assert_eq!(first_commit_hash, contents);
 
-        assert!(file_exists(".vcs/HEAD"));
-        let contents = get_file_contents(".vcs/HEAD")?;
+        assert!(file_exists(dir.join(".vcs/HEAD")));
+        let contents = get_file_contents(dir.join(".vcs/HEAD"))?;
         assert_eq!("main", contents);
         Ok(())
     }
@@ -1,57 +1,119 @@
-use std::io::Result;
+use std::{cmp::Reverse, collections::HashSet, path::Path};
 
 use chrono::DateTime;
 
 use crate::{
+    error::{Result, VcsError},
     objects::{
         commit::{
-            get_commit_message, get_commit_parent, get_commit_time, get_head_commit,
-            INITIAL_COMMIT_HASH,
+            get_commit_author, get_commit_committer, get_commit_message, get_commit_parents,
+            get_commit_time,
         },
-        get_branch_name,
+        repo::Repository,
+        revision::{resolve_range, RevisionRange},
     },
-    utils::fs_utils::directory_exists,
 };
 
+/// Usage text shown by `vcs help log`
+pub const USAGE: &str = "usage: vcs log [<commit> | <commit>..<commit> | <commit>...<commit>]\n\nShow the commits reachable from HEAD or <commit>, newest first. A..B shows the\ncommits on B that aren't on A, and A...B those on either but not both.";
+
 /// Executes `vcs log` with `args` as arguments
 ///
-/// Will output each commit that the current HEAD is descended from in reverse chronological order.
-/// Each commit will be output in the following format:
+/// Will output each commit that the current HEAD is descended from in reverse chronological order,
+/// following every parent of merge commits. Given a revision, starts from that commit instead.
+/// Given `A..B`, outputs the commits `B` is descended from that `A` isn't, and given `A...B`, the
+/// commits either is descended from but not both. Each commit will be output in the following format:
 ///     Commit: <COMMIT HASH>
-///     Date: <COMMIT DATE IN DOW, MM, DD, YY, H:M:S, UTC time>
+///     Merge: <PARENT HASHES, SEPARATED BY SPACES>
+///     Author: <AUTHOR NAME> <<AUTHOR EMAIL>>
+///     Committer: <COMMITTER NAME> <<COMMITTER EMAIL>>
+///     Date: <AUTHOR DATE IN DOW, MM, DD, H:M:S, YY, TIMEZONE OFFSET>
 ///     <COMMIT MESSAGE>.
+/// The `Merge` line is only shown for merge commits, and the `Committer` line is left out when the
+/// committer is the author. Commits made before authors
+/// were recorded have neither line, and their date is in UTC, without an offset.
 ///
-/// Will log `Not in an initialized vcs directory.` if no vcs dir was found, and will log
-/// `Incorrect operands.` if more than 1 argument was supplied. If no commits have been made by the
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if more than 1
+/// argument was supplied, and the errors of `resolve_range` if the revision can't be resolved. If no commits have been made by the
 /// user, will log `Your current branch <BRANCH_NAME> has no commits yet.`.
-pub fn log(args: &Vec<String>) -> Result<String> {
+///
+/// * `dir` - directory `log` was called from
+/// * `args` - arguments `log` was called with
+pub fn log(dir: &Path, args: &[String]) -> Result<String> {
     assert!(args[1] == "log");
-    if !directory_exists(".vcs") {
-        return Ok(String::from("Not in an initialized vcs directory."));
-    } else if args.len() != 2 {
-        return Ok(String::from("Incorrect operands."));
-    } else if get_head_commit()? == INITIAL_COMMIT_HASH {
-        let branch = get_branch_name()?;
-        return Ok(format!(
-            "Your current branch {} has no commits yet.",
-            branch
-        ));
-    }
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let range = match args.len() {
+        2 => {
+            // Every branch starts at the root commit made by `init`, which has no parent and
+            // isn't shown
+            let head_commit = repo.get_head_commit()?;
+            if get_commit_parents(&repo, &head_commit)?.is_empty() {
+                let branch = repo.get_branch_name()?;
+                return Ok(format!(
+                    "Your current branch {} has no commits yet.",
+                    branch
+                ));
+            }
+            RevisionRange::Commit(head_commit)
+        }
+        3 => resolve_range(&repo, &args[2])?,
+        _ => return Err(VcsError::IncorrectOperands),
+    };
     let mut output: Vec<String> = vec![];
-    let mut current_commit_hash = get_head_commit()?;
-    while current_commit_hash != INITIAL_COMMIT_HASH {
-        let date = get_commit_time(&current_commit_hash)?;
-        let naive_date =
-            DateTime::from_timestamp(date, 0).expect("Expected commit time to be parsable.");
-        let formatted_time = naive_date.format("%a %b %d %H:%M:%S %Y").to_string();
-        let commit_message = get_commit_message(&current_commit_hash)?;
+    let mut seen: HashSet<String> = range.excluded(&repo)?;
+    let mut pending: Vec<(i64, String)> = vec![];
+    for tip in range.tips() {
+        if seen.insert(tip.clone()) {
+            pending.push((get_commit_time(&repo, &tip)?, tip));
+        }
+    }
+    while !pending.is_empty() {
+        // The newest commit waiting to be shown comes next, and the first found breaks ties
+        let next = (0..pending.len())
+            .max_by_key(|&i| (pending[i].0, Reverse(i)))
+            .unwrap();
+        let (_, current_commit_hash) = pending.remove(next);
+        let parents = get_commit_parents(&repo, &current_commit_hash)?;
+        if parents.is_empty() {
+            continue;
+        }
+        let out_of_range = |date: i64| VcsError::CorruptObject {
+            hash: current_commit_hash.clone(),
+            reason: format!("commit time {} is out of range", date),
+        };
+        let mut people = String::new();
+        if parents.len() > 1 {
+            people.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        let formatted_time = match get_commit_author(&repo, &current_commit_hash)? {
+            Some(author) => {
+                people.push_str(&format!("Author: {}\n", author.person()));
+                if let Some(committer) = get_commit_committer(&repo, &current_commit_hash)? {
+                    if committer.person() != author.person() {
+                        people.push_str(&format!("Committer: {}\n", committer.person()));
+                    }
+                }
+                author.date().ok_or_else(|| out_of_range(author.time))?
+            }
+            None => {
+                let date = get_commit_time(&repo, &current_commit_hash)?;
+                let naive_date =
+                    DateTime::from_timestamp(date, 0).ok_or_else(|| out_of_range(date))?;
+                naive_date.format("%a %b %d %H:%M:%S %Y").to_string()
+            }
+        };
+        let commit_message = get_commit_message(&repo, &current_commit_hash)?;
         output.push(format!(
-            "Commit: {}\nDate: {}\n{}\n",
-            current_commit_hash, formatted_time, commit_message
+            "Commit: {}\n{}Date: {}\n{}\n",
+            current_commit_hash, people, formatted_time, commit_message
         ));
-        current_commit_hash = get_commit_parent(&current_commit_hash)?.unwrap();
+        for parent in parents {
+            if seen.insert(parent.clone()) {
+                pending.push((get_commit_time(&repo, &parent)?, parent));
+            }
+        }
     }
-    return Ok(output.join("\n"));
+    Ok(output.join("\n"))
 }
 
 #[cfg(test)]
@@ -64,16 +126,14 @@ pub mod tests {
     //      no commits on main, no commits on another branch
     // Further partition on no error:
     //      One commit have been made, two or more commits have been made
+    // Further partition on people: author is the committer, author given by --author
+    // Partition on revision: none, single, A..B, A...B, empty range
 
-    use std::{
-        env::set_current_dir,
-        fs::{create_dir, File},
-        io::{Result, Write},
-    };
-
-    use chrono::{Local, Utc};
+    use std::{fs::File, io::Write, path::Path};
 
     use crate::{
+        error::{Result, VcsError},
+        objects::{commit::get_commit_author, repo::Repository},
         operations::{
             add::add, branch::branch, checkout::checkout, commit::commit, init::init, log::log,
             rm::rm,
@@ -83,36 +143,49 @@ pub mod tests {
 
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
         let test_args: Vec<String> = vec![String::from("target/debug/vcs"), String::from("log")];
-        assert_eq!("Not in an initialized vcs directory.", log(&test_args)?);
+        assert!(matches!(
+            log(dir, &test_args),
+            Err(VcsError::NotARepository)
+        ));
         Ok(())
     }
 
     #[test]
     fn incorrect_arg_number() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("log"),
-            String::from("test.txt"),
+            String::from("HEAD"),
+            String::from("HEAD"),
         ];
-        assert_eq!("Incorrect operands.", log(&test_args)?);
+        assert!(matches!(
+            log(dir, &test_args),
+            Err(VcsError::IncorrectOperands)
+        ));
         Ok(())
     }
 
     #[test]
     fn no_commits() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let logged_output = log(&vec![String::from("target/debug/vcs"), String::from("log")])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let logged_output = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
         assert_eq!(
             "Your current branch main has no commits yet.",
             logged_output
@@ -122,24 +195,32 @@ pub mod tests {
 
     #[test]
     fn no_commits_on_another_branch() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = branch(&vec![
-            String::from("target/debug/vcs"),
-            String::from("branch"),
-            String::from("testbranch"),
-        ])?;
-        let _ = checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            String::from("testbranch"),
-        ])?;
-        let logged_output = log(&vec![String::from("target/debug/vcs"), String::from("log")])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("testbranch"),
+            ],
+        )?;
+        let _ = checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("testbranch"),
+            ],
+        )?;
+        let logged_output = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
         assert_eq!(
             "Your current branch testbranch has no commits yet.",
             logged_output
@@ -149,27 +230,40 @@ pub mod tests {
 
     #[test]
     fn one_commit_made() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        let (_, hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
-        let utc_time = Local::now().with_timezone(&Utc);
-        let time = utc_time.format("%a %b %d %H:%M:%S %Y").to_string();
-        let logged_output = log(&vec![String::from("target/debug/vcs"), String::from("log")])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        set_identity(dir)?;
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        let (_, hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        let time = author_date(dir, &hash)?;
+        let logged_output = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
         assert_eq!(
-            format!("Commit: {}\nDate: {}\nAdd test.txt\n", hash, time),
+            format!(
+                "Commit: {}\nAuthor: Jane Doe <jane@example.com>\nDate: {}\nAdd test.txt\n",
+                hash, time
+            ),
             logged_output
         );
         Ok(())
@@ -177,65 +271,160 @@ pub mod tests {
 
     #[test]
     fn more_than_one_commit_made() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
         let mut total_log: Vec<String> = vec![];
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file = File::create("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        let (_, first_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
-        let first_utc_time = Local::now().with_timezone(&Utc);
-        let first_time = first_utc_time.format("%a %b %d %H:%M:%S %Y").to_string();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        set_identity(dir)?;
+        let mut file = File::create(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        let (_, first_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        let first_time = author_date(dir, &first_hash)?;
         total_log.push(format!(
-            "Commit: {}\nDate: {}\nAdd test.txt\n",
+            "Commit: {}\nAuthor: Jane Doe <jane@example.com>\nDate: {}\nAdd test.txt\n",
             first_hash, first_time
         ));
         file.write_all("hi!".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
-        let (_, second_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Write to test.txt"),
-        ])?;
-        let second_utc_time = Local::now().with_timezone(&Utc);
-        let second_time = second_utc_time.format("%a %b %d %H:%M:%S %Y").to_string();
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        let (_, second_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Write to test.txt"),
+            ],
+        )?;
+        let second_time = author_date(dir, &second_hash)?;
         total_log.push(format!(
-            "Commit: {}\nDate: {}\nWrite to test.txt\n",
+            "Commit: {}\nAuthor: Jane Doe <jane@example.com>\nDate: {}\nWrite to test.txt\n",
             second_hash, second_time
         ));
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ])?;
-        let (_, third_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Remove test.txt"),
-        ])?;
-        let third_utc_time = Local::now().with_timezone(&Utc);
-        let third_time = third_utc_time.format("%a %b %d %H:%M:%S %Y").to_string();
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let (_, third_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Remove test.txt"),
+            ],
+        )?;
+        let third_time = author_date(dir, &third_hash)?;
         total_log.push(format!(
-            "Commit: {}\nDate: {}\nRemove test.txt\n",
+            "Commit: {}\nAuthor: Jane Doe <jane@example.com>\nDate: {}\nRemove test.txt\n",
             third_hash, third_time
         ));
-        let logged_output = log(&vec![String::from("target/debug/vcs"), String::from("log")])?;
+        let logged_output = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
         total_log.reverse();
         assert_eq!(total_log.join("\n"), logged_output);
+
+        let log_revision = |rev: &str| {
+            log(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("log"),
+                    String::from(rev),
+                ],
+            )
+        };
+        assert_eq!(total_log[1..].join("\n"), log_revision("HEAD~")?);
+        assert_eq!(total_log[..2].join("\n"), log_revision("HEAD~2..")?);
+        assert_eq!(total_log[0], log_revision("main~...main")?);
+        assert_eq!("", log_revision("main..main~2")?);
+        Ok(())
+    }
+
+    #[test]
+    fn authors_and_committers() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        set_identity(dir)?;
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
+        let (_, hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("--author"),
+                String::from("Pat Patch <pat@example.com>"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        let time = author_date(dir, &hash)?;
+        let logged_output = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
+        assert_eq!(
+            format!(
+                "Commit: {}\nAuthor: Pat Patch <pat@example.com>\nCommitter: Jane Doe <jane@example.com>\nDate: {}\nAdd test.txt\n",
+                hash, time
+            ),
+            logged_output
+        );
+        Ok(())
+    }
+
+    /// Outputs the date `log` should show for the commit `hash` in the repository in `dir`: the
+    /// time it was made, in the timezone it was made in
+    ///
+    /// Reading the time back from the commit keeps the tests from failing when a commit is made
+    /// just before the clock ticks over to the next second.
+    fn author_date(dir: &Path, hash: &str) -> Result<String> {
+        let repo = Repository::open(dir).unwrap();
+        Ok(get_commit_author(&repo, hash)?.unwrap().date().unwrap())
+    }
+
+    /// Sets the name and email commits are made with in the repository in `dir`
+    fn set_identity(dir: &Path) -> Result<()> {
+        let mut config = File::create(dir.join(".vcs/config"))?;
+        config.write_all(b"[user]\n\tname = Jane Doe\n\temail = jane@example.com\n")?;
         Ok(())
     }
 }
@@ -0,0 +1,470 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        blob::read_blob,
+        commit::get_commit_tree,
+        index::IndexEntry,
+        merge::{merge_base, merge_trees},
+        mode::FileMode,
+        reflog::move_head,
+        repo::Repository,
+        revision::{branch_name, resolve_commit},
+        stat_cache::StatCache,
+        tree::read_tree_files,
+        worktree::{local_changes, remove_worktree_file, write_worktree_file},
+    },
+    operations::{checkout::update_dir_state, commit::commit_index},
+};
+
+/// Usage text shown by `vcs help merge`
+pub const USAGE: &str = "usage: vcs merge <branch>\n   or: vcs merge <commit>\n   or: vcs merge (--continue | --abort)\n\nJoin the history of <branch> into the current branch.\n\n    --continue    commit the merge once its conflicts are resolved\n    --abort       give up on the merge and go back to the current branch's head";
+
+/// Executes `vcs merge` with `args` as arguments. Returns the string that should be logged to the
+/// console.
+///
+/// There are three possible use cases of this function:
+///     1. `vcs merge <BRANCH_NAME or COMMIT_ID>`: Merges the given commit into the current branch.
+///         If the commit is already part of the current branch's history, logs
+///         `Already up to date.`. If the current branch's head is part of the commit's history,
+///         the branch just moves to the commit and the worktree follows, logging
+///         `Fast-forwarded to <COMMIT_ID>.`. Otherwise the changes each side made since their
+///         best common ancestor are combined, file by file and line by line, into a merge commit
+///         whose parents are the current head and the given commit, logging
+///         `Made merge commit <COMMIT_ID>.`. If both sides changed the same lines differently,
+///         the merge stops: the files are left in the worktree with conflict markers, the cleanly
+///         merged changes are staged along with each version of the conflicted files, and
+///         `MergeConflicts` is returned, listing the conflicts.
+///     2. `vcs merge --continue`: Once every conflict has been resolved by staging the file with
+///         `vcs add` or `vcs rm`, makes the merge commit with the staged changes. Returns
+///         `UnresolvedConflicts` if some files still have conflicts.
+///     3. `vcs merge --abort`: Puts the files the merge wrote, which are the ones in the index,
+///         back to their state in the current head, unstages everything and forgets the merge.
+///         Local changes to other files are kept. Logs `Merge aborted.`.
+///
+/// A merge refuses to start on a detached head (`DetachedHead`), during another merge
+/// (`MergeInProgress`), with staged changes (`StagedChanges`), or if it would overwrite files
+/// with local changes or untracked files (`WouldOverwrite`). The given branch or revision must
+/// name a commit (`InvalidRef`) and share history with the current branch (`NoCommonAncestor`).
+/// `--continue` and `--abort` return `NoMergeInProgress` if no merge has stopped for conflicts.
+///
+/// If there are an incorrect number of arguments, returns `IncorrectOperands`, and if not in an
+/// initialized vcs directory, returns `NotARepository`.
+///
+/// * `dir` - directory `merge` was called from
+/// * `args` - arguments `merge` was called with
+pub fn merge(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "merge");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    match args.len() {
+        3 if args[2] == "--continue" => {
+            let merge_head = repo.merge_head()?.ok_or(VcsError::NoMergeInProgress)?;
+            let commit_hash = commit_index(&repo, &repo.merge_message()?, &[&merge_head], None)?;
+            repo.end_merge()?;
+            Ok(format!("Made merge commit {}.", commit_hash))
+        }
+        3 if args[2] == "--abort" => {
+            if repo.merge_head()?.is_none() {
+                return Err(VcsError::NoMergeInProgress);
+            }
+            let head_files =
+                read_tree_files(&repo, &get_commit_tree(&repo, &repo.get_head_commit()?)?)?;
+            repo.update_index(|index| {
+                // A merge only starts with nothing staged, so every file in the index is one it
+                // wrote, or one resolved since
+                let written: BTreeSet<&str> = index.entries.iter().map(IndexEntry::name).collect();
+                for name in written {
+                    match head_files.get(name) {
+                        Some((mode, hash)) => {
+                            write_worktree_file(&repo, name, &read_blob(&repo, hash)?, *mode)?
+                        }
+                        None => remove_worktree_file(&repo, name)?,
+                    }
+                }
+                index.entries.clear();
+                Ok(())
+            })?;
+            repo.end_merge()?;
+            Ok(String::from("Merge aborted."))
+        }
+        3 => {
+            if repo.is_detached()? {
+                return Err(VcsError::DetachedHead);
+            }
+            if repo.merge_head()?.is_some() {
+                return Err(VcsError::MergeInProgress);
+            }
+            let (theirs, message) = match branch_name(&repo, &args[2])? {
+                Some(name) => (
+                    repo.get_branch_commit(&name)?,
+                    format!("Merge branch '{}'", name),
+                ),
+                None => {
+                    let commit_hash = resolve_commit(&repo, &args[2])?;
+                    let message = format!("Merge commit '{}'", commit_hash);
+                    (commit_hash, message)
+                }
+            };
+            let ours = repo.get_head_commit()?;
+            // The index stays locked from the check for staged changes until the merge is
+            // staged, so nothing staged meanwhile is lost. Set inside: what to log if the merge
+            // is already done, and the conflicts it stopped for.
+            let mut done: Option<String> = None;
+            let mut descriptions = vec![];
+            repo.update_index(|index| {
+                if !index.entries.is_empty() {
+                    return Err(VcsError::StagedChanges);
+                }
+                let base = merge_base(&repo, &ours, &theirs)?
+                    .ok_or(VcsError::NoCommonAncestor(theirs.clone()))?;
+                if base == theirs {
+                    done = Some(String::from("Already up to date."));
+                    return Ok(());
+                }
+
+                let our_tree = get_commit_tree(&repo, &ours)?;
+                let their_tree = get_commit_tree(&repo, &theirs)?;
+                let our_files = read_tree_files(&repo, &our_tree)?;
+                if base == ours {
+                    let their_files = read_tree_files(&repo, &their_tree)?;
+                    let touched = our_files
+                        .keys()
+                        .chain(their_files.keys())
+                        .filter(|name| our_files.get(*name) != their_files.get(*name));
+                    check_overwrites(&repo, &mut index.stat_cache, &our_files, touched)?;
+                    update_dir_state(&repo, &theirs)?;
+                    move_head(&repo, &theirs, &format!("merge {}: Fast-forward", args[2]))?;
+                    done = Some(format!("Fast-forwarded to {}.", theirs));
+                    return Ok(());
+                }
+
+                let base_tree = get_commit_tree(&repo, &base)?;
+                let merge =
+                    merge_trees(&repo, &base_tree, &our_tree, &their_tree, "HEAD", &args[2])?;
+                let touched = merge
+                    .changes
+                    .keys()
+                    .chain(merge.conflicts.iter().map(|conflict| &conflict.path));
+                check_overwrites(&repo, &mut index.stat_cache, &our_files, touched)?;
+
+                for (name, change) in merge.changes {
+                    match change {
+                        Some((mode, hash)) => {
+                            write_worktree_file(&repo, &name, &read_blob(&repo, &hash)?, mode)?;
+                            index.entries.push(IndexEntry::Blob { hash, name, mode });
+                        }
+                        None => {
+                            remove_worktree_file(&repo, &name)?;
+                            index.entries.push(IndexEntry::Rm { name });
+                        }
+                    }
+                }
+                for conflict in merge.conflicts {
+                    write_worktree_file(&repo, &conflict.path, &conflict.contents, conflict.mode)?;
+                    index.entries.extend(conflict.index_entries());
+                    descriptions.push(conflict.description);
+                }
+                Ok(())
+            })?;
+            if let Some(output) = done {
+                return Ok(output);
+            }
+            if descriptions.is_empty() {
+                let commit_hash = commit_index(&repo, &message, &[&theirs], None)?;
+                return Ok(format!("Made merge commit {}.", commit_hash));
+            }
+            repo.start_merge(&theirs, &message)?;
+            Err(VcsError::MergeConflicts(descriptions))
+        }
+        _ => Err(VcsError::IncorrectOperands),
+    }
+}
+
+/// Returns `WouldOverwrite` if writing or removing any of the files in `names` would lose local
+/// changes or untracked files in the worktree. `tracked` holds the files of the head commit, and
+/// files are hashed through `stat_cache`.
+fn check_overwrites<'a>(
+    repo: &Repository,
+    stat_cache: &mut StatCache,
+    tracked: &HashMap<String, (FileMode, String)>,
+    names: impl IntoIterator<Item = &'a String>,
+) -> Result<()> {
+    let changed = local_changes(repo, stat_cache, tracked, names)?;
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        Err(VcsError::WouldOverwrite(changed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for merge
+    //      Failure cases: not in vcs dir, incorrect operands, unknown branch, staged changes,
+    //          local changes in the way, --continue or --abort without a merge in progress,
+    //          index locked by another process
+    //      History: already merged, fast-forward, diverged
+    //      If diverged: merges cleanly, stops for conflicts
+    //      After conflicts: commit, checkout, --continue with conflicts left, resolved and
+    //          --continue, --abort
+    //      --abort: local changes to files the merge didn't write are kept
+
+    use std::fs::{read_to_string, write};
+
+    use super::*;
+    use crate::{
+        objects::commit::{get_commit_message, get_commit_parents},
+        operations::{add::add, commit::commit, init::init, log::log, status::status},
+        utils::{
+            fs_utils::file_exists,
+            test_dir::{commit_file, make_test_dir, run},
+        },
+    };
+
+    /// Sets up a repository in `dir` where `main` and `side` both changed `a.txt` since they
+    /// split. `side` changes it to `side_text`, and adds `b.txt`. Outputs the heads of `main` and
+    /// `side`, with `main` checked out.
+    fn diverged(dir: &Path, side_text: &str) -> Result<(String, String)> {
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        let _ = commit_file(dir, "a.txt", "1\n2\n3\n", "Add a.txt")?;
+        let _ = run(dir, "branch", &["side"])?;
+        let main = commit_file(dir, "a.txt", "one\n2\n3\n", "Change main")?;
+        let _ = run(dir, "checkout", &["side"])?;
+        let _ = commit_file(dir, "b.txt", "b\n", "Add b.txt")?;
+        let side = commit_file(dir, "a.txt", side_text, "Change side")?;
+        let _ = run(dir, "checkout", &["main"])?;
+        Ok((main, side))
+    }
+
+    #[test]
+    fn not_in_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            run(test_dir.path(), "merge", &["side"]),
+            Err(VcsError::NotARepository)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn failure_cases() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = diverged(dir, "1\n2\nthree\n")?;
+        assert!(matches!(
+            merge(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("merge")]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "merge", &["nope"]),
+            Err(VcsError::InvalidRef(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            run(dir, "merge", &["--continue"]),
+            Err(VcsError::NoMergeInProgress)
+        ));
+        assert!(matches!(
+            run(dir, "merge", &["--abort"]),
+            Err(VcsError::NoMergeInProgress)
+        ));
+
+        // Local changes to a file the merge writes, or staged changes, stop it
+        write(dir.join("a.txt"), "local\n")?;
+        assert!(matches!(
+            run(dir, "merge", &["side"]),
+            Err(VcsError::WouldOverwrite(names)) if names == vec![String::from("a.txt")]
+        ));
+        write(dir.join("a.txt"), "one\n2\n3\n")?;
+        write(dir.join("b.txt"), "untracked\n")?;
+        assert!(matches!(
+            run(dir, "merge", &["side"]),
+            Err(VcsError::WouldOverwrite(names)) if names == vec![String::from("b.txt")]
+        ));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("b.txt"),
+            ],
+        )?;
+        assert!(matches!(
+            run(dir, "merge", &["side"]),
+            Err(VcsError::StagedChanges)
+        ));
+        assert_eq!("untracked\n", read_to_string(dir.join("b.txt"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn locked_index() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let (main, _) = diverged(dir, "1\n2\nthree\n")?;
+        write(dir.join(".vcs/index.lock"), "")?;
+        assert!(matches!(
+            run(dir, "merge", &["side"]),
+            Err(VcsError::RepositoryLocked(lock_path)) if lock_path.ends_with("index.lock")
+        ));
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(main, repo.get_head_commit()?);
+        assert_eq!("one\n2\n3\n", read_to_string(dir.join("a.txt"))?);
+        assert!(!file_exists(dir.join("b.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn fast_forward_and_up_to_date() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        let first = commit_file(dir, "a.txt", "a\n", "Add a.txt")?;
+        let _ = run(dir, "branch", &["side"])?;
+        let _ = run(dir, "checkout", &["side"])?;
+        let second = commit_file(dir, "b.txt", "b\n", "Add b.txt")?;
+        assert_eq!("Already up to date.", run(dir, "merge", &["main"])?);
+        assert_eq!("Already up to date.", run(dir, "merge", &[&first])?);
+
+        let _ = run(dir, "checkout", &["main"])?;
+        assert!(!file_exists(dir.join("b.txt")));
+        assert_eq!(
+            format!("Fast-forwarded to {}.", second),
+            run(dir, "merge", &["side"])?
+        );
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(second, repo.get_branch_commit("main")?);
+        assert_eq!("b\n", read_to_string(dir.join("b.txt"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn clean_merge() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let (main, side) = diverged(dir, "1\n2\nthree\n")?;
+        let output = run(dir, "merge", &["side"])?;
+        let repo = Repository::open(dir).unwrap();
+        let head = repo.get_head_commit()?;
+        assert_eq!(format!("Made merge commit {}.", head), output);
+        assert_eq!(vec![main, side], get_commit_parents(&repo, &head)?);
+        assert_eq!("Merge branch 'side'", get_commit_message(&repo, &head)?);
+        assert_eq!("one\n2\nthree\n", read_to_string(dir.join("a.txt"))?);
+        assert_eq!("b\n", read_to_string(dir.join("b.txt"))?);
+        assert!(repo.read_index()?.is_empty());
+
+        // Log shows both sides of the merge
+        let log_text = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
+        assert!(log_text.starts_with(&format!("Commit: {}\nMerge: ", head)));
+        for message in ["Change main", "Change side", "Add b.txt", "Add a.txt"] {
+            assert!(log_text.contains(&format!("{}\n", message)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn conflicts_then_continue() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let (main, side) = diverged(dir, "ONE\n2\n3\n")?;
+        assert!(matches!(
+            run(dir, "merge", &["side"]),
+            Err(VcsError::MergeConflicts(conflicts))
+                if conflicts == vec![String::from("CONFLICT (content): Merge conflict in a.txt")]
+        ));
+        assert_eq!(
+            "<<<<<<< HEAD\none\n=======\nONE\n>>>>>>> side\n2\n3\n",
+            read_to_string(dir.join("a.txt"))?
+        );
+        assert_eq!(
+            "On branch main\nChanges to be committed:\n\tnew file: b.txt\n\nUnmerged paths:\n\tboth modified: a.txt\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+
+        // Nothing else can happen until the conflicts are resolved
+        assert!(matches!(
+            commit(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("commit"),
+                    String::from("Merge"),
+                ]
+            ),
+            Err(VcsError::MergeInProgress)
+        ));
+        assert!(matches!(
+            run(dir, "checkout", &["side"]),
+            Err(VcsError::MergeInProgress)
+        ));
+        assert!(matches!(
+            run(dir, "merge", &["side"]),
+            Err(VcsError::MergeInProgress)
+        ));
+        assert!(matches!(
+            run(dir, "merge", &["--continue"]),
+            Err(VcsError::UnresolvedConflicts(names)) if names == vec![String::from("a.txt")]
+        ));
+
+        write(dir.join("a.txt"), "One\n2\n3\n")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("a.txt"),
+            ],
+        )?;
+        let output = run(dir, "merge", &["--continue"])?;
+        let repo = Repository::open(dir).unwrap();
+        let head = repo.get_head_commit()?;
+        assert_eq!(format!("Made merge commit {}.", head), output);
+        assert_eq!(vec![main, side], get_commit_parents(&repo, &head)?);
+        assert_eq!(None, repo.merge_head()?);
+        assert_eq!(
+            "On branch main\nnothing to commit\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn conflicts_then_abort() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = diverged(dir, "ONE\n2\n3\n")?;
+        let main = commit_file(dir, "c.txt", "c\n", "Add c.txt")?;
+        write(dir.join("c.txt"), "local c\n")?;
+        assert!(run(dir, "merge", &["side"]).is_err());
+        assert_eq!("Merge aborted.", run(dir, "merge", &["--abort"])?);
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(main, repo.get_head_commit()?);
+        assert_eq!(None, repo.merge_head()?);
+        assert!(repo.read_index()?.is_empty());
+        assert_eq!("one\n2\n3\n", read_to_string(dir.join("a.txt"))?);
+        assert!(!file_exists(dir.join("b.txt")));
+        assert_eq!("local c\n", read_to_string(dir.join("c.txt"))?);
+        Ok(())
+    }
+}
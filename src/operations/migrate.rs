@@ -0,0 +1,356 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        commit::{get_commit_parents, parse_commit_header},
+        index::IndexEntry,
+        object::ObjectKind,
+        reflog::{move_branch, switch_head},
+        repo::Repository,
+    },
+};
+
+/// Usage text shown by `vcs help migrate`
+pub const USAGE: &str = "usage: vcs migrate\n\nRewrite objects from older repositories into the compressed object format.";
+
+/// Message recorded in the reflog of each branch `migrate` moves
+const MIGRATE_MESSAGE: &str = "migrate: Rewrote objects in the compressed format";
+
+/// Executes `vcs migrate` with `args` as arguments. Returns the string that should be logged to
+/// the console.
+///
+/// Repositories made before objects had a `<type> <size>` header store them as plain text. Those
+/// objects are still readable, but every object reachable from a branch, a detached HEAD, the
+/// index or a merge in progress is rewritten in the compressed format, and they are pointed at
+/// the new hashes, which their reflogs record. Objects written in the new format that refer to old
+/// objects are rewritten too, since their hashes change. Logs `Migrated <N> objects.`, where N is the number of objects whose
+/// hash changed. The old objects are left in place.
+///
+/// Returns `NotARepository` if no vcs dir was found, and `IncorrectOperands` if any arguments were
+/// supplied.
+///
+/// * `dir` - directory `migrate` was called from
+/// * `args` - arguments `migrate` was called with
+pub fn migrate(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "migrate");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    if args.len() != 2 {
+        return Err(VcsError::IncorrectOperands);
+    }
+
+    let mut migrated: HashMap<String, String> = HashMap::new();
+    for branch in repo.list_branches()? {
+        let commit = repo.get_branch_commit(&branch)?;
+        let new_commit = migrate_commit(&repo, &commit, &mut migrated)?;
+        if new_commit != commit {
+            move_branch(&repo, &branch, &new_commit, MIGRATE_MESSAGE)?;
+        }
+    }
+    if repo.is_detached()? {
+        let commit = repo.get_head_commit()?;
+        let new_commit = migrate_commit(&repo, &commit, &mut migrated)?;
+        if new_commit != commit {
+            switch_head(&repo, &new_commit)?;
+        }
+    }
+    let mut index = repo.read_index()?;
+    for entry in index.iter_mut() {
+        if let IndexEntry::Blob { hash, .. } | IndexEntry::Conflict { hash, .. } = entry {
+            *hash = migrate_object(&repo, hash, &mut migrated)?;
+        }
+    }
+    repo.write_index(&index)?;
+    if let Some(merge_head) = repo.merge_head()? {
+        let new_merge_head = migrate_commit(&repo, &merge_head, &mut migrated)?;
+        repo.start_merge(&new_merge_head, &repo.merge_message()?)?;
+    }
+
+    let changed = migrated.iter().filter(|(old, new)| old != new).count();
+    Ok(format!("Migrated {} objects.", changed))
+}
+
+/// Rewrites the commit `commit` and its history in the compressed format. Returns the new hash of
+/// the commit.
+///
+/// Walks parents iteratively, so long histories don't overflow the stack.
+fn migrate_commit(
+    repo: &Repository,
+    commit: &str,
+    migrated: &mut HashMap<String, String>,
+) -> Result<String> {
+    // Each commit is visited twice: once to queue its parents, then again, once they have all
+    // been migrated, to migrate the commit itself
+    let mut pending = vec![(commit.to_string(), false)];
+    while let Some((hash, parents_migrated)) = pending.pop() {
+        if migrated.contains_key(&hash) {
+            continue;
+        }
+        if parents_migrated {
+            migrate_object(repo, &hash, migrated)?;
+            continue;
+        }
+        pending.push((hash.clone(), true));
+        for parent in get_commit_parents(repo, &hash)? {
+            if !migrated.contains_key(&parent) {
+                pending.push((parent, false));
+            }
+        }
+    }
+    Ok(migrated[commit].clone())
+}
+
+/// Rewrites the object `hash`, and every object it refers to, in the compressed format. Returns
+/// the new hash of the object.
+///
+/// A commit's parents must already be in `migrated`.
+fn migrate_object(
+    repo: &Repository,
+    hash: &str,
+    migrated: &mut HashMap<String, String>,
+) -> Result<String> {
+    if let Some(new_hash) = migrated.get(hash) {
+        return Ok(new_hash.clone());
+    }
+    let (kind, body) = repo.read_object(hash)?;
+    let new_body = match kind {
+        ObjectKind::Blob => body,
+        ObjectKind::Tree => {
+            let text = repo.read_object_text(hash, ObjectKind::Tree)?;
+            let mut lines = vec![];
+            for line in text.split('\n') {
+                match line.rsplit_once(": ") {
+                    Some((name, entry_hash)) => {
+                        let new_entry_hash = migrate_object(repo, entry_hash, migrated)?;
+                        lines.push(format!("{}: {}", name, new_entry_hash));
+                    }
+                    None => lines.push(line.to_string()),
+                }
+            }
+            lines.join("\n").into_bytes()
+        }
+        ObjectKind::Commit => {
+            let text = repo.read_object_text(hash, ObjectKind::Commit)?;
+            parse_commit_header(hash, &text)?;
+            let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+            // Fields come in label and value pairs, up to the message
+            let mut line_num = 0;
+            while lines[line_num] != "Message" {
+                let value = &lines[line_num + 1];
+                match lines[line_num].as_str() {
+                    "Parent" if value != "No parent" => {
+                        lines[line_num + 1] = migrated[value].clone();
+                    }
+                    "Tree Hash" => lines[line_num + 1] = migrate_object(repo, value, migrated)?,
+                    _ => {}
+                }
+                line_num += 2;
+            }
+            lines.join("\n").into_bytes()
+        }
+    };
+    let new_hash = repo.write_object(kind, &new_body)?;
+    migrated.insert(hash.to_string(), new_hash.clone());
+    Ok(new_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for migrate
+    //      Failure cases: not in vcs dir, incorrect operands
+    //      Objects: all legacy, mixed legacy and compressed, all compressed
+    //      HEAD: on a branch, detached; index: empty, staged blob
+
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+    use crate::{
+        objects::{
+            commit::{
+                get_commit_message, get_commit_parent, get_commit_tree, get_hash_in_commit,
+                INITIAL_COMMIT_HASH,
+            },
+            mode::FileMode,
+            object::{hash_object, is_legacy_object},
+            reflog::{branch_ref, read_reflog},
+        },
+        operations::{add::add, commit::commit, init::init, log::log},
+        utils::{fs_utils::get_file_bytes, hash::sha2, test_dir::make_test_dir},
+    };
+
+    /// Writes `text` as an object in the format used before objects had headers, and outputs its
+    /// hash
+    fn write_legacy_object(repo: &Repository, text: &str) -> Result<String> {
+        let hash = sha2(text);
+        let path = repo.object_path(&hash);
+        create_dir_all(path.parent().unwrap())?;
+        write(path, text)?;
+        Ok(hash)
+    }
+
+    /// Creates a repository laid out the way older versions of vcs did, with one commit on `main`
+    /// adding `test.txt`. Outputs the repository and the hash of that commit.
+    fn legacy_repository(dir: &Path) -> Result<(Repository, String)> {
+        let repo = Repository::create(dir)?;
+        let empty_tree = write_legacy_object(&repo, "Trees\nBlobs")?;
+        let root = write_legacy_object(
+            &repo,
+            &format!(
+                "Parent\nNo parent\nTime\n0\nTree Hash\n{}\nMessage\nInitial commit",
+                empty_tree
+            ),
+        )?;
+        let blob = write_legacy_object(&repo, "blob\nold text")?;
+        let tree = write_legacy_object(&repo, &format!("Trees\nBlobs\ntest.txt: {}", blob))?;
+        let commit = write_legacy_object(
+            &repo,
+            &format!(
+                "Parent\n{}\nTime\n100\nTree Hash\n{}\nMessage\nAdd test.txt",
+                root, tree
+            ),
+        )?;
+        repo.set_branch_commit("main", &commit)?;
+        repo.set_head("main")?;
+        write(dir.join("test.txt"), "old text")?;
+        Ok((repo, commit))
+    }
+
+    #[test]
+    fn not_in_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            migrate(
+                test_dir.path(),
+                &[String::from("target/debug/vcs"), String::from("migrate")]
+            ),
+            Err(VcsError::NotARepository)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn incorrect_operands() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        assert!(matches!(
+            migrate(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("migrate"),
+                    String::from("now"),
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_repository_is_readable() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let (repo, commit) = legacy_repository(dir)?;
+        assert_eq!(
+            Some(sha2("blob\nold text")),
+            get_hash_in_commit(&repo, &commit, "test.txt")?
+        );
+        assert_eq!("Add test.txt", get_commit_message(&repo, &commit)?);
+        let output = log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")],
+        )?;
+        assert!(output.starts_with(&format!("Commit: {}\n", commit)));
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_history_and_index() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let (repo, _) = legacy_repository(dir)?;
+
+        // A compressed commit on top of legacy history, and a staged blob
+        write(dir.join("test.txt"), "new text")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Change test.txt"),
+            ],
+        )?;
+        write(dir.join("staged.txt"), "staged")?;
+        let (_, staged_hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("staged.txt"),
+            ],
+        )?;
+
+        let old_head = repo.get_head_commit()?;
+        let output = migrate(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("migrate")],
+        )?;
+        // Legacy root tree, root commit, blob, tree and commit, plus the compressed commit on top
+        assert_eq!("Migrated 6 objects.", output);
+
+        // The root commit is now the one `init` makes, and the branch's reflog records the move
+        let head = repo.get_head_commit()?;
+        let moves = read_reflog(&repo, &branch_ref("main"))?;
+        let last = moves.last().unwrap();
+        assert_eq!(
+            (old_head.as_str(), head.as_str()),
+            (last.old.as_str(), last.new.as_str())
+        );
+        assert_eq!(MIGRATE_MESSAGE, last.message);
+        let parent = get_commit_parent(&repo, &head)?.unwrap();
+        assert_eq!(
+            Some(String::from(INITIAL_COMMIT_HASH)),
+            get_commit_parent(&repo, &parent)?
+        );
+        assert_eq!(
+            Some(hash_object(ObjectKind::Blob, b"old text")),
+            get_hash_in_commit(&repo, &parent, "test.txt")?
+        );
+        assert_eq!(
+            Some(hash_object(ObjectKind::Blob, b"new text")),
+            get_hash_in_commit(&repo, &head, "test.txt")?
+        );
+        let tree = get_commit_tree(&repo, &head)?;
+        assert!(!is_legacy_object(&get_file_bytes(repo.object_path(&tree))?));
+        assert_eq!(
+            vec![IndexEntry::Blob {
+                hash: staged_hash,
+                name: String::from("staged.txt"),
+                mode: FileMode::Regular,
+            }],
+            repo.read_index()?
+        );
+
+        // Running it again changes nothing
+        let output = migrate(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("migrate")],
+        )?;
+        assert_eq!("Migrated 0 objects.", output);
+        assert_eq!(head, repo.get_head_commit()?);
+        assert_eq!(moves, read_reflog(&repo, &branch_ref("main"))?);
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        reflog::{branch_ref, read_reflog, reflog_exists},
+        repo::Repository,
+    },
+};
+
+/// Usage text shown by `vcs help reflog`
+pub const USAGE: &str = "usage: vcs reflog [<branch> | HEAD]\n\nShow where HEAD or a branch has pointed, newest first.\n\nEach line is <hash> <ref>@{<n>}: <reason>, and <ref>@{<n>} names that commit in\nother operations. Deleted branches keep their reflog until `vcs gc` expires it.";
+
+/// Executes `vcs reflog` with `args` as arguments. Returns the string that should be logged to
+/// the console.
+///
+/// Logs the reflog of HEAD, or of the branch given, newest entry first. Each entry is logged as
+/// `<NEW HASH> <REF>@{<N>}: <REASON>`, where `N` counts back from the newest entry, so that
+/// `<REF>@{<N>}` resolves to the hash. The reflog of a deleted branch can still be shown.
+///
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if more than 1 argument
+/// was supplied, `InvalidRef` if the argument is neither HEAD nor a branch with a reflog, and
+/// `ReflogParse` if the reflog can't be parsed.
+///
+/// * `dir` - directory `reflog` was called from
+/// * `args` - arguments `reflog` was called with
+pub fn reflog(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "reflog");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    let (name, reference) = match args.len() {
+        2 => (String::from("HEAD"), String::from("HEAD")),
+        3 if args[2] == "HEAD" => (args[2].clone(), args[2].clone()),
+        3 => (args[2].clone(), branch_ref(&args[2])),
+        _ => return Err(VcsError::IncorrectOperands),
+    };
+    if name != "HEAD" && !repo.branch_exists(&name) && !reflog_exists(&repo, &reference) {
+        return Err(VcsError::InvalidRef(name));
+    }
+    let output: Vec<String> = read_reflog(&repo, &reference)?
+        .into_iter()
+        .rev()
+        .enumerate()
+        .map(|(n, entry)| format!("{} {}@{{{}}}: {}", entry.new, name, n, entry.message))
+        .collect();
+    Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for reflog
+    //      Failure cases: not in vcs dir, too many arguments, unknown ref
+    //      Ref: HEAD by default, HEAD, current branch, deleted branch
+    //      Entries: none, commits, checkouts, merges, branch creation and deletion
+
+    use super::*;
+    use crate::{
+        objects::{reflog::NULL_HASH, revision::resolve_revision},
+        operations::init::init,
+        utils::test_dir::{commit_file, make_test_dir, run},
+    };
+
+    #[test]
+    fn failure_cases() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        assert!(matches!(
+            run(dir, "reflog", &[]),
+            Err(VcsError::NotARepository)
+        ));
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        assert_eq!("", run(dir, "reflog", &[])?);
+        assert!(matches!(
+            run(dir, "reflog", &["HEAD", "main"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "reflog", &["nope"]),
+            Err(VcsError::InvalidRef(name)) if name == "nope"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn records_every_move() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        let first = commit_file(dir, "a.txt", "a\n", "Add a.txt\n\nWith a body")?;
+        run(dir, "branch", &["side"])?;
+        run(dir, "checkout", &["side"])?;
+        let second = commit_file(dir, "b.txt", "b\n", "Add b.txt")?;
+        run(dir, "checkout", &["main"])?;
+        run(dir, "merge", &["side"])?;
+        run(dir, "checkout", &[&first])?;
+        run(dir, "checkout", &["@{-1}"])?;
+        run(dir, "branch", &["-d", "side"])?;
+
+        assert_eq!(
+            [
+                format!(
+                    "{} HEAD@{{0}}: checkout: moving from {} to main",
+                    second, first
+                ),
+                format!(
+                    "{} HEAD@{{1}}: checkout: moving from main to {}",
+                    first, first
+                ),
+                format!("{} HEAD@{{2}}: merge side: Fast-forward", second),
+                format!("{} HEAD@{{3}}: checkout: moving from side to main", first),
+                format!("{} HEAD@{{4}}: commit: Add b.txt", second),
+                format!("{} HEAD@{{5}}: checkout: moving from main to side", first),
+                format!("{} HEAD@{{6}}: commit: Add a.txt", first),
+            ]
+            .join("\n"),
+            run(dir, "reflog", &[])?
+        );
+        assert_eq!(
+            [
+                format!("{} main@{{0}}: merge side: Fast-forward", second),
+                format!("{} main@{{1}}: commit: Add a.txt", first),
+            ]
+            .join("\n"),
+            run(dir, "reflog", &["main"])?
+        );
+        // A deleted branch's commits can still be found
+        assert_eq!(
+            [
+                format!("{} side@{{0}}: branch: Deleted side", NULL_HASH),
+                format!("{} side@{{1}}: commit: Add b.txt", second),
+                format!("{} side@{{2}}: branch: Created from HEAD", first),
+            ]
+            .join("\n"),
+            run(dir, "reflog", &["side"])?
+        );
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(second, resolve_revision(&repo, "side@{1}")?);
+        assert_eq!(first, resolve_revision(&repo, "main@{1}")?);
+        Ok(())
+    }
+}
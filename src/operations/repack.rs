@@ -0,0 +1,263 @@
+use std::{
+    collections::HashSet,
+    fs::{read_dir, remove_dir, remove_file},
+    path::Path,
+};
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        object::{decode_object, is_legacy_object},
+        pack::{write_pack, PackObject},
+        repo::Repository,
+    },
+    utils::fs_utils::get_file_bytes,
+};
+
+/// Usage text shown by `vcs help repack`
+pub const USAGE: &str =
+    "usage: vcs repack\n\nPack loose objects and existing packs into a single delta-compressed pack.";
+
+/// Executes `vcs repack` with `args` as arguments. Returns the string that should be logged to the
+/// console.
+///
+/// Writes every loose object and every object in an existing pack into one new pack, storing
+/// objects as deltas against similar objects where that is smaller, then removes the old packs and
+/// the loose objects that were packed. Objects from before objects were compressed are left loose;
+/// run `vcs migrate` first to pack those too. Logs `Packed <N> objects, <M> of them as deltas.`,
+/// or `Nothing to pack.` if there are no loose objects and at most one pack.
+///
+/// Returns `NotARepository` if no vcs dir was found, and `IncorrectOperands` if any arguments were
+/// supplied.
+///
+/// * `dir` - directory `repack` was called from
+/// * `args` - arguments `repack` was called with
+pub fn repack(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "repack");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    if args.len() != 2 {
+        return Err(VcsError::IncorrectOperands);
+    }
+
+    let mut objects: Vec<PackObject> = vec![];
+    let mut loose: Vec<String> = vec![];
+    for hash in repo.loose_object_hashes()? {
+        let stored = get_file_bytes(repo.object_path(&hash))?;
+        if is_legacy_object(&stored) {
+            continue;
+        }
+        let (kind, body) = decode_object(&hash, &stored)?;
+        objects.push(PackObject {
+            hash: hash.clone(),
+            kind,
+            body,
+        });
+        loose.push(hash);
+    }
+    let packs = repo.packs()?;
+    if loose.is_empty() && packs.len() <= 1 {
+        return Ok(String::from("Nothing to pack."));
+    }
+    let mut seen: HashSet<String> = loose.iter().cloned().collect();
+    for pack in packs {
+        for object in pack.read_objects()? {
+            if seen.insert(object.hash.clone()) {
+                objects.push(object);
+            }
+        }
+    }
+
+    let (pack_path, delta_count) = write_pack(repo.pack_dir(), &objects)?;
+
+    // Only remove the old copies once the new pack is complete
+    for pack in packs {
+        if pack.pack_path() != pack_path {
+            remove_file(pack.pack_path())?;
+            remove_file(pack.pack_path().with_extension("idx"))?;
+        }
+    }
+    for hash in &loose {
+        let path = repo.object_path(hash);
+        remove_file(&path)?;
+        let parent = path.parent().unwrap();
+        if read_dir(parent)?.next().is_none() {
+            remove_dir(parent)?;
+        }
+    }
+    Ok(format!(
+        "Packed {} objects, {} of them as deltas.",
+        objects.len(),
+        delta_count
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for repack
+    //      Failure cases: not in vcs dir, incorrect operands
+    //      Loose objects: none, some; existing packs: none, one, many
+    //      Legacy loose objects: none, some
+
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+    use crate::{
+        objects::{
+            blob::read_blob,
+            commit::{get_commit_message, get_hash_in_commit},
+        },
+        operations::{add::add, commit::commit, init::init, log::log},
+        utils::{hash::sha2, test_dir::make_test_dir},
+    };
+
+    /// Commits a new version of `big.txt` in `dir`, with line `line` changed. Outputs the hash of
+    /// the blob of that version.
+    fn commit_version(dir: &Path, line: usize) -> Result<String> {
+        let text: String = (0..400)
+            .map(|i| {
+                if i == line {
+                    format!("changed line {}\n", i)
+                } else {
+                    format!("line {}\n", i)
+                }
+            })
+            .collect();
+        write(dir.join("big.txt"), text)?;
+        let (_, hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("big.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                format!("Change line {}", line),
+            ],
+        )?;
+        Ok(hash)
+    }
+
+    #[test]
+    fn not_in_vcs_dir() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        assert!(matches!(
+            repack(
+                test_dir.path(),
+                &[String::from("target/debug/vcs"), String::from("repack")]
+            ),
+            Err(VcsError::NotARepository)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn incorrect_operands() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        assert!(matches!(
+            repack(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("repack"),
+                    String::from("-a"),
+                ]
+            ),
+            Err(VcsError::IncorrectOperands)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn packs_loose_objects() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let blobs: Vec<String> = (0..4)
+            .map(|line| commit_version(dir, line * 100))
+            .collect::<Result<_>>()?;
+        let loose_count = repo.loose_object_hashes()?.len();
+
+        let output = repack(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("repack")],
+        )?;
+        assert!(output.starts_with(&format!("Packed {} objects, ", loose_count)));
+        assert!(repo.loose_object_hashes()?.is_empty());
+        assert_eq!(1, repo.packs()?.len());
+
+        // Objects are still found, through the pack
+        let head = repo.get_head_commit()?;
+        assert_eq!("Change line 300", get_commit_message(&repo, &head)?);
+        assert_eq!(
+            Some(blobs[3].clone()),
+            get_hash_in_commit(&repo, &head, "big.txt")?
+        );
+        for blob in &blobs {
+            assert!(repo.object_exists(blob));
+            assert!(read_blob(&repo, blob)?.starts_with(b"line 0\n") || blob == &blobs[0]);
+        }
+        assert!(log(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("log")]
+        )?
+        .contains("Change line 0"));
+
+        // Nothing new to pack
+        assert_eq!(
+            "Nothing to pack.",
+            repack(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("repack")]
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merges_packs_and_keeps_legacy_objects() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
+        let first = commit_version(dir, 10)?;
+        repack(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("repack")],
+        )?;
+        let second = commit_version(dir, 20)?;
+
+        let legacy_hash = sha2("blob\nlegacy");
+        let legacy_path = repo.object_path(&legacy_hash);
+        create_dir_all(legacy_path.parent().unwrap())?;
+        write(&legacy_path, "blob\nlegacy")?;
+
+        let output = repack(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("repack")],
+        )?;
+        assert!(output.ends_with("1 of them as deltas."), "{}", output);
+        assert_eq!(1, repo.packs()?.len());
+        assert_eq!(vec![legacy_hash.clone()], repo.loose_object_hashes()?);
+        assert_eq!(b"legacy".to_vec(), read_blob(&repo, &legacy_hash)?);
+        assert!(repo.object_exists(&first));
+        assert!(repo.object_exists(&second));
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use crate::{
+    error::{Result, VcsError},
+    objects::{
+        merge::merge_base,
+        repo::Repository,
+        revision::{resolve_range, resolve_revision, RevisionRange},
+    },
+};
+
+/// Usage text shown by `vcs help rev-parse`
+pub const USAGE: &str = "usage: vcs rev-parse <revision>...\n\nShow the full hash of the object each revision names.\n\nRevisions are HEAD (or @), @{-1} for the previous branch, <ref>@{<n>} for where\na ref pointed n moves ago, branch names, and hashes or unique abbreviations of at least 4 digits, followed by any number of\n~<n> (the nth first parent) and ^<n> (the nth parent) suffixes. <rev>:<path>\nnames a file or directory in a commit, and A..B and A...B name ranges.";
+
+/// Executes `vcs rev-parse` with `args` as arguments. Returns the string that should be logged to
+/// the console.
+///
+/// Logs the hash of the object each revision names, one per line, as resolved by
+/// `resolve_revision`. A range `A..B` is logged as `B` then `^A`: the commit the range starts from
+/// and the one whose history it leaves out. `A...B` is logged as `A`, `B`, then `^<BASE>` for
+/// their best common ancestor.
+///
+/// Returns `NotARepository` if no vcs dir was found, `IncorrectOperands` if no revision was given,
+/// and the errors of `resolve_revision` and `resolve_range` for revisions that can't be resolved.
+///
+/// * `dir` - directory `rev-parse` was called from
+/// * `args` - arguments `rev-parse` was called with
+pub fn rev_parse(dir: &Path, args: &[String]) -> Result<String> {
+    assert!(args[1] == "rev-parse");
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    if args.len() < 3 {
+        return Err(VcsError::IncorrectOperands);
+    }
+    let mut output: Vec<String> = vec![];
+    for rev in &args[2..] {
+        if !rev.contains("..") || rev.contains(':') {
+            output.push(resolve_revision(&repo, rev)?);
+            continue;
+        }
+        match resolve_range(&repo, rev)? {
+            RevisionRange::Commit(commit) => output.push(commit),
+            RevisionRange::Between(a, b) => {
+                output.push(b);
+                output.push(format!("^{}", a));
+            }
+            RevisionRange::Symmetric(a, b) => {
+                let base = merge_base(&repo, &a, &b)?;
+                output.push(a);
+                output.push(b);
+                if let Some(base) = base {
+                    output.push(format!("^{}", base));
+                }
+            }
+        }
+    }
+    Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for rev-parse
+    //      Failure cases: not in vcs dir, no revisions, unknown revision
+    //      Revisions: one, several; single revisions, A..B, A...B, <rev>:<path>
+
+    use super::*;
+    use crate::{
+        objects::blob::read_blob,
+        operations::{branch::branch, checkout::checkout, init::init},
+        utils::test_dir::{commit_file, make_test_dir, run},
+    };
+
+    #[test]
+    fn failure_cases() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        assert!(matches!(
+            run(dir, "rev-parse", &["HEAD"]),
+            Err(VcsError::NotARepository)
+        ));
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        assert!(matches!(
+            run(dir, "rev-parse", &[]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "rev-parse", &["HEAD", "nope..HEAD"]),
+            Err(VcsError::InvalidRef(name)) if name == "nope"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn revisions_and_ranges() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        let first = commit_file(dir, "a.txt", "a\n", "Add a.txt")?;
+        branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("side"),
+            ],
+        )?;
+        let second = commit_file(dir, "a.txt", "b\n", "Change a.txt")?;
+        let switch = |target: &str| {
+            checkout(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("checkout"),
+                    String::from(target),
+                ],
+            )
+        };
+        switch("side")?;
+        let third = commit_file(dir, "c.txt", "c\n", "Add c.txt")?;
+
+        assert_eq!(
+            format!("{}\n{}\n{}", third, second, first),
+            run(dir, "rev-parse", &["HEAD", "@{-1}", &first[..7]])?
+        );
+        assert_eq!(
+            format!("{}\n^{}", third, second),
+            run(dir, "rev-parse", &["main..side"])?
+        );
+        assert_eq!(
+            format!("{}\n{}\n^{}", second, third, first),
+            run(dir, "rev-parse", &["main...side"])?
+        );
+        let blob = run(dir, "rev-parse", &["main:a.txt"])?;
+        assert_eq!(
+            b"b\n".to_vec(),
+            read_blob(&Repository::open(dir).unwrap(), &blob)?
+        );
+
+        // @{-1} moves with each checkout
+        switch("@{-1}")?;
+        assert_eq!(second, run(dir, "rev-parse", &["HEAD"])?);
+        assert_eq!(third, run(dir, "rev-parse", &["@{-1}"])?);
+        Ok(())
+    }
+}
@@ -1,161 +1,243 @@
 use std::{
-    fs::{remove_file, File},
-    io::{Result, Write},
+    collections::{BTreeSet, HashMap, HashSet},
+    path::Path,
 };
 
 use crate::{
+    error::{Result, VcsError},
     objects::{
-        commit::{get_hash_in_commit, get_head_commit},
-        get_branch_name,
+        commit::get_commit_tree,
+        ignore::glob_match,
+        index::IndexEntry,
+        mode::FileMode,
+        repo::Repository,
+        tree::read_tree_files,
+        worktree::{local_changes, match_pathspec, remove_worktree_file},
     },
-    utils::fs_utils::{clear_file_contents, directory_exists, file_exists, get_file_contents},
 };
 
+/// Usage text shown by `vcs help rm`
+pub const USAGE: &str = "usage: vcs rm [--cached] [-r] [-f | --force] [--] <pathspec>...\n\nStage the files each <pathspec> matches for removal, deleting them from the working\ndirectory if they are tracked. Pathspecs are matched like `vcs add` matches them.\n\nFiles whose changes aren't committed are not removed, so that nothing is lost.\n\n    --cached       only unstage and untrack the files, keeping them on disk\n    -r             remove the files inside a directory the <pathspec> names\n    -f, --force    remove the files even if they have changes that aren't committed";
+
 /// Executes `vcs rm` with `args` as arguments. Returns the string that should be logged to the
 /// console.
 ///
-/// If there is one argument, stage the file for removal. If the file is tracked in the current
-///     commit, stage it for removal and remove the file from the working directory if the user
-///     has not already done so (do not remove it unless it is tracked in the current commit).
-/// If not in a vcs directory, log `Not in an initialized vcs directory.`
-/// If incorrect number of commands, log `Incorrect operands.`
-/// If file is neither staged nor tracked by the head commit, log `No reason to remove the file.`
-/// Explicitly, this function either removes a file from the index if the file was previously
-///     staged, or removes the file from the directory and adds a line in the index file to remove
-///     the file on the next commit.
+/// Stages every file each pathspec matches for removal, as `add` matches them: a file that is
+/// tracked in the head commit is staged for removal and deleted from the working directory, and
+/// one that is only staged is unstaged and left on disk. A file already staged for removal stays
+/// so. With `--cached`, no file is deleted from the working directory. Removing a file with
+/// conflicts from a merge resolves the conflict by deleting it.
+///
+/// Unless `-f` (or `--force`) is given, a file is not removed if that would lose changes that
+/// aren't committed: a file to be deleted whose contents differ from its staged version, or whose
+/// staged version differs from the head commit's, and a file kept on disk whose staged version
+/// differs from both the file and the head commit's version.
+///
+/// If not in a vcs directory, returns `NotARepository`
+/// If no pathspecs are given, returns `IncorrectOperands`
+/// If a pathspec is outside the worktree, returns `OutsideRepository`
+/// If a pathspec matches no file that is staged or tracked by the head commit, returns
+/// `NotStagedOrTracked`
+/// If a pathspec names a directory without `-r`, returns `NotRemovingRecursively`
+/// If files would lose changes, returns `WouldLoseChanges`, and removes nothing
+/// Explicitly, this function removes each file's entries from the index, adds an `rm` entry for
+/// the files tracked by the head commit, then deletes those files from the working directory,
+/// along with the directories they leave empty.
 ///
+/// * `dir` - directory `rm` was called from
 /// * `args` - arguments `rm` was called with
-pub fn rm(args: &Vec<String>) -> Result<String> {
+pub fn rm(dir: &Path, args: &[String]) -> Result<String> {
     assert!(args[1] == "rm");
-    if !directory_exists(".vcs") {
-        return Ok(String::from("Not in an initialized vcs directory."));
-    } else if !file_exists(&format!(".vcs/branches/{}", get_branch_name()?)) {
-        return Ok(String::from(
-            "Currently in a detached HEAD state. Check out a branch to modify the directory.",
-        ));
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    if repo.is_detached()? {
+        return Err(VcsError::DetachedHead);
     }
-    match args.len() {
-        3 => {
-            let prev_commit_hash_of_file = get_hash_in_commit(&get_head_commit()?, &args[2])?;
-            let seen_file = remove_from_index(&args[2])?;
-            if prev_commit_hash_of_file == "DNE" {
-                if seen_file {
-                    return Ok(String::from(""));
-                } else {
-                    return Ok(String::from("No reason to remove the file."));
-                }
-            } else {
-                remove_file(&args[2])?;
-                return Ok(String::from(""));
-            }
+
+    let mut cached = false;
+    let mut recursive = false;
+    let mut force = false;
+    let mut pathspecs: Vec<&String> = vec![];
+    let mut options = args[2..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--cached" => cached = true,
+            "-r" => recursive = true,
+            "-f" | "--force" => force = true,
+            "--" => pathspecs.extend(options.by_ref()),
+            _ => pathspecs.push(arg),
         }
-        _ => Ok(String::from("Incorrect operands.")),
     }
-}
+    if pathspecs.is_empty() {
+        return Err(VcsError::IncorrectOperands);
+    }
 
-/// Removes a line from the `.vcs/index`
-///
-/// Returns true iff the file existed in the index file
-fn remove_from_index(rm_filename: &str) -> Result<bool> {
-    let mut seen_file = false;
-    let mut new_index: Vec<String> = vec![];
-    for line in get_file_contents(".vcs/index")?.lines() {
-        let line_split = line.split(' ').collect::<Vec<&str>>();
-        match line_split[0] {
-            "rm" => {
-                if line_split[1] != rm_filename {
-                    new_index.push(line.to_string());
-                } else {
-                    panic!("Should not be removing {} twice in a row", rm_filename);
+    let head_files = read_tree_files(&repo, &get_commit_tree(&repo, &repo.get_head_commit()?)?)?;
+    let mut to_delete: Vec<String> = vec![];
+    repo.update_index(|index| {
+        // The staged version of each file, and the files with conflicts
+        let mut staged: HashMap<String, (FileMode, String)> = head_files.clone();
+        let mut conflicted: HashSet<String> = HashSet::new();
+        for entry in &index.entries {
+            match entry {
+                IndexEntry::Blob { hash, name, mode } => {
+                    staged.insert(name.clone(), (*mode, hash.clone()));
+                }
+                IndexEntry::Rm { name } => {
+                    staged.remove(name);
+                }
+                IndexEntry::Conflict { name, .. } => {
+                    conflicted.insert(name.clone());
                 }
             }
-            "blob" => {
-                let line_filename = line_split[2];
-                if rm_filename == line_filename {
-                    seen_file = true;
-                    continue;
+        }
+        let mut candidates: BTreeSet<String> = head_files.keys().cloned().collect();
+        candidates.extend(index.entries.iter().map(|entry| entry.name().to_string()));
+
+        let mut names: BTreeSet<String> = BTreeSet::new();
+        for pathspec in &pathspecs {
+            let name = repo
+                .relative_name(dir, pathspec)
+                .ok_or_else(|| VcsError::OutsideRepository(pathspec.to_string()))?;
+            let matches = match_pathspec(&name, &candidates);
+            if matches.is_empty() {
+                return Err(VcsError::NotStagedOrTracked(name));
+            }
+            // A match that isn't the file named, or one the glob matches, is inside a directory
+            let in_directory = !candidates.contains(&name)
+                && matches
+                    .iter()
+                    .any(|file| !glob_match(name.as_bytes(), file.as_bytes()));
+            if in_directory && !recursive {
+                return Err(VcsError::NotRemovingRecursively(pathspec.to_string()));
+            }
+            names.extend(matches);
+        }
+
+        if !force {
+            let mut lost: Vec<String> = vec![];
+            for name in names.iter().filter(|name| !conflicted.contains(*name)) {
+                let committed = head_files.get(name);
+                let staged_version = staged.get(name);
+                let modified =
+                    !local_changes(&repo, &mut index.stat_cache, &staged, [name])?.is_empty();
+                let staged_changes = staged_version.is_some() && staged_version != committed;
+                if !cached && committed.is_some() {
+                    if modified {
+                        lost.push(format!("{} has local modifications", name));
+                    } else if staged_changes {
+                        lost.push(format!("{} has changes staged in the index", name));
+                    }
+                } else if modified && staged_changes {
+                    lost.push(format!(
+                        "{} has staged content different from both the file and the head commit",
+                        name
+                    ));
                 }
-                new_index.push(line.to_string());
             }
-            _ => panic!("Expected either `rm` or `blob`, but got {}", line_split[0]),
+            if !lost.is_empty() {
+                return Err(VcsError::WouldLoseChanges(lost));
+            }
         }
+
+        for name in names {
+            index.entries.retain(|entry| entry.name() != name);
+            // Removing a conflicted file resolves the conflict by deleting it
+            if head_files.contains_key(&name) || conflicted.contains(&name) {
+                index.entries.push(IndexEntry::Rm { name: name.clone() });
+            }
+            if !cached && head_files.contains_key(&name) {
+                to_delete.push(name);
+            }
+        }
+        Ok(())
+    })?;
+    for name in to_delete {
+        remove_worktree_file(&repo, &name)?;
     }
-    if !seen_file {
-        new_index.push(format!("rm {}", rm_filename));
-    }
-    clear_file_contents(".vcs/index")?;
-    let mut index_file = File::create(".vcs/index")?;
-    let index_contents = new_index.join("\n");
-    index_file.write_all(&index_contents.into_bytes())?;
-    Ok(seen_file)
+    Ok(String::from(""))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
+        objects::{commit::get_hash_in_commit, index::index_text},
         operations::{add::add, commit::commit, init::init},
         utils::{
-            fs_utils::{file_exists, get_file_contents},
-            test_dir::make_test_dir,
+            fs_utils::file_exists,
+            test_dir::{make_test_dir, run},
         },
     };
-    use std::{fs::File, io::Write};
+    use std::{
+        fs::{create_dir_all, write, File},
+        io::Write,
+    };
 
     // Partitions for rm
     //      Failure cases: Not in directory, incorrect operands, file is neither staged nor tracked
-    //          by head commit, correct
-    //      If correct: File trac
+    //          by head commit, directory without -r, changes that would be lost, correct
+    //      If correct: File tracked by head commit, only staged, already staged for removal
+    //      Options: --cached, -r, -f; one pathspec, several, a directory, a glob
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        let _ = File::create("test.txt");
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = File::create(dir.join("test.txt"));
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("rm"),
             String::from("test.txt"),
         ];
-        assert_eq!("Not in an initialized vcs directory.", rm(&test_args)?);
+        assert!(matches!(rm(dir, &test_args), Err(VcsError::NotARepository)));
         Ok(())
     }
 
     #[test]
     fn incorrect_operands() -> Result<()> {
         // Setup
-        let _test_dir = make_test_dir();
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        );
 
         // rm test
         let test_args: Vec<String> = vec![String::from("target/debug/vcs"), String::from("rm")];
-        assert_eq!("Incorrect operands.", rm(&test_args)?);
-        let test_args: Vec<String> = vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("burn_arg 1"),
-            String::from("burn_arg 2"),
-        ];
-        assert_eq!("Incorrect operands.", rm(&test_args)?);
+        assert!(matches!(
+            rm(dir, &test_args),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "rm", &["--cached", "-f"]),
+            Err(VcsError::IncorrectOperands)
+        ));
+        assert!(matches!(
+            run(dir, "rm", &["test.txt", "burn_arg 2"]),
+            Err(VcsError::NotStagedOrTracked(name)) if name == "burn_arg 2"
+        ));
         Ok(())
     }
 
     #[test]
     fn file_not_tracked_or_staged() -> Result<()> {
         // Setup
-        let _test_dir = make_test_dir();
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt");
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"));
 
         // rm test
         let test_args: Vec<String> = vec![
@@ -163,36 +245,47 @@ mod tests {
             String::from("rm"),
             String::from("test.txt"),
         ];
-        assert_eq!("No reason to remove the file.", rm(&test_args)?);
+        assert!(matches!(
+            rm(dir, &test_args),
+            Err(VcsError::NotStagedOrTracked(_))
+        ));
         Ok(())
     }
 
     #[test]
     fn correct_file_tracked_by_head_commit() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         // init vcs dir
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let repo = Repository::open(dir).unwrap();
 
         // mutate and add a file
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         let file_text = "commit time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
 
         // Commit the file
-        let (_, _) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
+        let (_, _) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
 
         // Test rm
         let test_args = vec![
@@ -200,41 +293,48 @@ mod tests {
             String::from("rm"),
             String::from("test.txt"),
         ];
-        assert_eq!("", rm(&test_args)?);
-        assert!(!file_exists("test.txt"));
-        let index_contents = get_file_contents(".vcs/index")?;
+        assert_eq!("", rm(dir, &test_args)?);
+        assert!(!file_exists(dir.join("test.txt")));
+        let index_contents = index_text(&Repository::open(dir).unwrap().read_index()?);
         assert_eq!("rm test.txt", index_contents);
 
         // Commit the remove
-        let (_, commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Remove test.txt"),
-        ])?;
-        assert_eq!(get_hash_in_commit(&commit_hash, "test.txt")?, "DNE");
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Remove test.txt"),
+            ],
+        )?;
+        assert_eq!(get_hash_in_commit(&repo, &commit_hash, "test.txt")?, None);
 
         Ok(())
     }
 
     #[test]
     fn correct_file_not_tracked_by_head_commit() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         // init vcs dir
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
 
         // mutate and add a file
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         let file_text = "commit time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
 
         // Test rm
         let test_args = vec![
@@ -242,51 +342,203 @@ mod tests {
             String::from("rm"),
             String::from("test.txt"),
         ];
-        assert_eq!("", rm(&test_args)?);
-        assert!(file_exists("test.txt"));
-        let index_contents = get_file_contents(".vcs/index")?;
+        assert_eq!("", rm(dir, &test_args)?);
+        assert!(file_exists(dir.join("test.txt")));
+        let index_contents = index_text(&Repository::open(dir).unwrap().read_index()?);
         assert_eq!("", index_contents);
         Ok(())
     }
 
     #[test]
     fn coverage_for_blob_not_same() -> Result<()> {
-        let _test_dir = make_test_dir()?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
 
         // init vcs dir
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
 
         // mutate and add two files
-        let mut file = File::create("test.txt")?;
+        let mut file = File::create(dir.join("test.txt"))?;
         let file_text = "commit time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, _) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let mut file = File::create("test2.txt")?;
+        let (_, _) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let mut file = File::create(dir.join("test2.txt"))?;
         let file_text = "commit 2 time!";
         let _ = file.write(file_text.as_bytes());
-        let (_, add_hash) = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test2.txt"),
-        ])?;
+        let (_, add_hash) = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test2.txt"),
+            ],
+        )?;
 
         // remove one of them
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ]);
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        );
+        assert_eq!(
+            format!("blob 100644 {} test2.txt", add_hash),
+            index_text(&Repository::open(dir).unwrap().read_index()?)
+        );
+        Ok(())
+    }
+
+    /// Makes a repository in `dir` with each of `files` written with its own name as contents,
+    /// and commits them
+    fn commit_files(dir: &Path, files: &[&str]) -> Result<()> {
+        init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        )?;
+        for name in files {
+            if let Some(parent) = dir.join(name).parent() {
+                create_dir_all(parent)?;
+            }
+            write(dir.join(name), name)?;
+            add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(*name),
+                ],
+            )?;
+        }
+        commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add files"),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Outputs the index of the repository in `dir` as text
+    fn staged(dir: &Path) -> Result<String> {
+        Ok(index_text(&Repository::open(dir).unwrap().read_index()?))
+    }
+
+    #[test]
+    fn cached_and_twice() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        commit_files(dir, &["a.txt", "b.txt"])?;
+
+        run(dir, "rm", &["--cached", "a.txt"])?;
+        assert!(file_exists(dir.join("a.txt")));
+        assert_eq!("rm a.txt", staged(dir)?);
+
+        // Removing a file already staged for removal leaves it so
+        run(dir, "rm", &["b.txt"])?;
+        run(dir, "rm", &["b.txt"])?;
+        assert!(!file_exists(dir.join("b.txt")));
+        assert_eq!("rm a.txt\nrm b.txt", staged(dir)?);
+        Ok(())
+    }
+
+    #[test]
+    fn directories_and_globs() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        commit_files(
+            dir,
+            &[
+                "src/main.rs",
+                "src/nested/lib.rs",
+                "notes.md",
+                "todo.md",
+                "keep.txt",
+            ],
+        )?;
+
+        assert!(matches!(
+            run(dir, "rm", &["notes.md", "src"]),
+            Err(VcsError::NotRemovingRecursively(path)) if path == "src"
+        ));
+        assert!(file_exists(dir.join("notes.md")));
+        assert_eq!("", staged(dir)?);
+
+        run(dir, "rm", &["-r", "src", "*.md"])?;
+        assert!(!dir.join("src").exists());
+        assert!(!file_exists(dir.join("notes.md")));
+        assert!(file_exists(dir.join("keep.txt")));
         assert_eq!(
-            format!("blob {} test2.txt", add_hash),
-            get_file_contents(".vcs/index")?
+            "rm notes.md\nrm src/main.rs\nrm src/nested/lib.rs\nrm todo.md",
+            staged(dir)?
         );
         Ok(())
     }
+
+    #[test]
+    fn refuses_to_lose_changes() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        commit_files(dir, &["a.txt", "b.txt"])?;
+        let add_file = |name: &str| {
+            add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(name),
+                ],
+            )
+        };
+
+        // Nothing is removed if any file would lose its changes
+        write(dir.join("a.txt"), "edited")?;
+        assert!(matches!(
+            run(dir, "rm", &["a.txt", "b.txt"]),
+            Err(VcsError::WouldLoseChanges(lines)) if lines == vec!["a.txt has local modifications"]
+        ));
+        assert!(file_exists(dir.join("b.txt")));
+        assert_eq!("", staged(dir)?);
+
+        // Staged changes would be lost too, unless the file is kept on disk
+        add_file("a.txt")?;
+        assert!(matches!(
+            run(dir, "rm", &["a.txt"]),
+            Err(VcsError::WouldLoseChanges(lines))
+                if lines == vec!["a.txt has changes staged in the index"]
+        ));
+        write(dir.join("a.txt"), "edited again")?;
+        assert!(matches!(
+            run(dir, "rm", &["--cached", "a.txt"]),
+            Err(VcsError::WouldLoseChanges(lines)) if lines == vec![
+                "a.txt has staged content different from both the file and the head commit"
+            ]
+        ));
+        write(dir.join("new.txt"), "new")?;
+        add_file("new.txt")?;
+        write(dir.join("new.txt"), "newer")?;
+        assert!(matches!(
+            run(dir, "rm", &["new.txt"]),
+            Err(VcsError::WouldLoseChanges(lines)) if lines.len() == 1
+        ));
+
+        run(dir, "rm", &["-f", "a.txt", "new.txt"])?;
+        assert!(!file_exists(dir.join("a.txt")));
+        assert!(file_exists(dir.join("new.txt")));
+        assert_eq!("rm a.txt", staged(dir)?);
+        Ok(())
+    }
 }
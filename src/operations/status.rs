@@ -1,44 +1,63 @@
-use std::{collections::HashMap, fs::read_dir, io::Result};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
 
 use crate::{
+    error::{Result, VcsError},
     objects::{
-        blob::get_blob_hash,
-        commit::{get_hash_in_commit, get_head_commit},
-        get_branch_name,
+        commit::get_commit_tree,
+        ignore::IgnoreRules,
+        index::{IndexEntry, Stage},
+        mode::FileMode,
+        repo::Repository,
+        tree::read_tree_files,
+        worktree::worktree_files,
     },
-    utils::fs_utils::{directory_exists, file_exists, no_dir_string, read_lines},
 };
 
-/// Executes `vcs log` with `args` as arguments
+/// Usage text shown by `vcs help status`
+pub const USAGE: &str = "usage: vcs status\n\nShow staged, unstaged and untracked changes.";
+
+/// Executes `vcs status` with `args` as arguments
 ///
 /// Outputs the current status of the directory. Specifically, will log a message of the following
 /// form:
 ///     On branch <CURRENT_BRANCH_NAME>
 ///     Changes to be commited:
-///         <modified/deleted/new file>: <filename>
+///         <modified/mode changed/deleted/new file>: <filename>
+///
+///     Unmerged paths:
+///         <both modified/both added/deleted by us/deleted by them>: <filename>
 ///
 ///     Changes not staged for commit:
-///         modified: <filename>
+///         <modified/mode changed>: <filename>
 ///
 ///     Untracked files:
 ///         <filename>
 ///
 /// based on the current state of the vcs directory. Within each section, the entries will be
-/// sorted alphabetically
-/// Will log `Not in an initialized vcs directory.` if no vcs dir was found, and will log
-/// `Incorrect operands.` if more than 1 argument was supplied.
-pub fn status(args: &Vec<String>) -> Result<String> {
+/// sorted alphabetically. Untracked files matched by a `.vcsignore` rule are not listed.
+///
+/// Tracked files are only read if their stat data changed since the index's stat cache last saw
+/// them, and the cache is then updated with what was read.
+/// Returns `NotARepository` if no vcs dir was found, and `IncorrectOperands` if more than 1
+/// argument was supplied.
+///
+/// * `dir` - directory `status` was called from
+/// * `args` - arguments `status` was called with
+pub fn status(dir: &Path, args: &[String]) -> Result<String> {
     assert!(args[1] == "status");
-    if !directory_exists(".vcs") {
-        return Ok(String::from("Not in an initialized vcs directory."));
-    } else if args.len() != 2 {
-        return Ok(String::from("Incorrect operands."));
+    let repo = Repository::find(dir).ok_or(VcsError::NotARepository)?;
+    if args.len() != 2 {
+        return Err(VcsError::IncorrectOperands);
     }
     let mut output: Vec<String> = vec![];
+    let head_files = read_tree_files(&repo, &get_commit_tree(&repo, &repo.get_head_commit()?)?)?;
 
     // Branch name line
-    let branch_name = get_branch_name()?;
-    if file_exists(&format!(".vcs/branches/{}", branch_name)) {
+    let branch_name = repo.get_branch_name()?;
+    if repo.branch_exists(&branch_name) {
         output.push(format!("On branch {}", branch_name));
     } else {
         output.push(format!("On commit {}", branch_name));
@@ -47,33 +66,32 @@ pub fn status(args: &Vec<String>) -> Result<String> {
     // Changes to be committed section
     let mut to_be_committed: Vec<String> = vec![];
     let mut files_to_hashes: HashMap<String, FileStatus> = HashMap::new();
-    for line in read_lines(".vcs/index")?.flatten() {
-        let split_line: Vec<&str> = line.split(" ").collect();
-        match split_line[0] {
-            "blob" => {
-                let line_filename = split_line[2];
-                files_to_hashes.insert(
-                    line_filename.to_string(),
-                    FileStatus::Modified(split_line[1].to_string()),
-                );
-                let commit_hash = get_hash_in_commit(&get_head_commit()?, line_filename)?;
-                if commit_hash == "DNE" {
-                    to_be_committed.push(format!("new file: {}", line_filename));
-                } else {
-                    to_be_committed.push(format!("modified: {}", line_filename));
+    let mut conflict_stages: BTreeMap<String, Vec<Stage>> = BTreeMap::new();
+    let index = repo.load_index()?;
+    let mut stat_cache = index.stat_cache;
+    for entry in index.entries {
+        match entry {
+            IndexEntry::Blob { hash, name, mode } => {
+                match head_files.get(&name) {
+                    None => to_be_committed.push(format!("new file: {}", name)),
+                    Some(head_file) => {
+                        let change = describe_change(head_file, mode, &hash).unwrap_or("modified");
+                        to_be_committed.push(format!("{}: {}", change, name));
+                    }
                 }
+                files_to_hashes.insert(name, FileStatus::Modified(mode, hash));
             }
-            "rm" => {
-                let line_filename = split_line[1];
-                files_to_hashes.insert(line_filename.to_string(), FileStatus::Removed);
-                to_be_committed.push(format!("deleted: {}", line_filename));
+            IndexEntry::Rm { name } => {
+                to_be_committed.push(format!("deleted: {}", name));
+                files_to_hashes.insert(name, FileStatus::Removed);
             }
-            _ => {
-                panic!("Expected either `blob` or `rm`. Got {}", split_line[0])
+            IndexEntry::Conflict { stage, name, .. } => {
+                conflict_stages.entry(name.clone()).or_default().push(stage);
+                files_to_hashes.insert(name, FileStatus::Conflicted);
             }
         }
     }
-    if to_be_committed.len() > 0 {
+    if !to_be_committed.is_empty() {
         to_be_committed.sort();
         output.push(format!(
             "Changes to be committed:\n\t{}\n",
@@ -81,46 +99,71 @@ pub fn status(args: &Vec<String>) -> Result<String> {
         ));
     }
 
+    // Unmerged paths section, for conflicts left by a merge
+    if !conflict_stages.is_empty() {
+        let unmerged: Vec<String> = conflict_stages
+            .into_iter()
+            .map(|(name, stages)| format!("{}: {}", describe_conflict(&stages), name))
+            .collect();
+        output.push(format!("Unmerged paths:\n\t{}\n", unmerged.join("\n\t")));
+    }
+
     // Unadded changes section
     let mut not_staged: Vec<String> = vec![];
     let mut untracked: Vec<String> = vec![];
-    for entry in read_dir(".")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            continue;
-        }
-        let filename = no_dir_string(path);
-        let (current_file_hash, _) = get_blob_hash(&filename)?;
+    let ignore_rules = IgnoreRules::load(&repo)?;
+    let mut tracked: HashSet<String> = HashSet::new();
+    for filename in worktree_files(&repo)? {
         if files_to_hashes.contains_key(&filename) {
             let status = files_to_hashes.get(&filename).unwrap();
             match status {
-                FileStatus::Modified(staged_hash) => {
+                FileStatus::Modified(staged_mode, staged_hash) => {
+                    let (current_mode, current_file_hash) =
+                        stat_cache.hash_file(&repo, &filename)?;
+                    tracked.insert(filename.clone());
                     if *staged_hash != current_file_hash {
                         not_staged.push(format!("modified: {}", filename));
+                    } else if *staged_mode != current_mode {
+                        not_staged.push(format!("mode changed: {}", filename));
                     }
                 }
                 FileStatus::Removed => {
                     not_staged.push(format!("modified: {}", filename));
                 }
+                FileStatus::Conflicted => {}
             }
         } else {
-            let prev_hash = get_hash_in_commit(&get_head_commit()?, &filename)?;
-            if prev_hash == "DNE" {
-                untracked.push(filename);
-            } else if prev_hash != current_file_hash {
-                not_staged.push(format!("modified: {}", filename));
+            match head_files.get(&filename) {
+                None if ignore_rules.is_ignored(&filename, false) => {}
+                None => untracked.push(filename),
+                Some(head_file) => {
+                    let (current_mode, current_file_hash) =
+                        stat_cache.hash_file(&repo, &filename)?;
+                    tracked.insert(filename.clone());
+                    if let Some(change) =
+                        describe_change(head_file, current_mode, &current_file_hash)
+                    {
+                        not_staged.push(format!("{}: {}", change, filename));
+                    }
+                }
             }
         }
     }
-    if not_staged.len() > 0 {
+    // Only tracked files that are still there stay cached. Another process holding the lock on
+    // the index only means the cache isn't saved this time.
+    stat_cache.retain(|name| tracked.contains(name));
+    match repo.write_stat_cache(&stat_cache) {
+        Ok(()) | Err(VcsError::RepositoryLocked(_)) => {}
+        Err(e) => return Err(e),
+    }
+    if !not_staged.is_empty() {
         not_staged.sort();
         output.push(format!(
             "Changes not staged for commit:\n\t{}\n",
             not_staged.join("\n\t")
         ));
     }
-    if untracked.len() > 0 {
+    if !untracked.is_empty() {
         untracked.sort();
         output.push(format!("Untracked files:\n\t{}\n", untracked.join("\n\t")));
     }
@@ -133,10 +176,36 @@ pub fn status(args: &Vec<String>) -> Result<String> {
     Ok(output.join("\n"))
 }
 
+/// Describes how a file with mode `mode` and blob hash `hash` differs from its version in a
+/// commit, the mode and blob hash `old`: `modified` if its contents changed, `mode changed` if
+/// only its mode did, and `None` if neither did
+fn describe_change(old: &(FileMode, String), mode: FileMode, hash: &str) -> Option<&'static str> {
+    if old.1 != hash {
+        Some("modified")
+    } else if old.0 != mode {
+        Some("mode changed")
+    } else {
+        None
+    }
+}
+
+/// Describes a conflict from the versions of the file a merge staged: `both modified` if all
+/// three exist, `both added` without a common ancestor, and otherwise the side that deleted it
+fn describe_conflict(stages: &[Stage]) -> &'static str {
+    let has = |stage: Stage| stages.contains(&stage);
+    match (has(Stage::Base), has(Stage::Ours), has(Stage::Theirs)) {
+        (false, _, _) => "both added",
+        (true, false, _) => "deleted by us",
+        (true, _, false) => "deleted by them",
+        (true, true, true) => "both modified",
+    }
+}
+
 /// Auxiliary enum to help with remembering status of file in index
 enum FileStatus {
-    Modified(String),
+    Modified(FileMode, String),
     Removed,
+    Conflicted,
 }
 
 #[cfg(test)]
@@ -150,15 +219,14 @@ pub mod tests {
     // On changes to be committed: Empty, just modify, just delete, just new file, multiple mixed
     // On changes not staged for commit: there is file modified, there's not a file modified
     // On Untracked files: empty, nonempty
+    // Files: at the root, in subdirectories, ignored, with spaces, colons, newlines or
+    //      non-ASCII in their names
+    // Changes: contents, only the mode
+    // Stat cache: file unchanged since cached, racily clean file
 
-    use std::{
-        env::set_current_dir,
-        fs::{create_dir, File},
-        io::Write,
-    };
+    use std::{fs::File, io::Write};
 
     use crate::{
-        objects::commit,
         operations::{
             add::add, branch::branch, checkout::checkout, commit::commit, init::init, rm::rm,
         },
@@ -169,323 +237,639 @@ pub mod tests {
 
     #[test]
     fn not_in_vcs_dir() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
         let test_args: Vec<String> = vec![String::from("target/debug/vcs"), String::from("status")];
-        assert_eq!("Not in an initialized vcs directory.", status(&test_args)?);
+        assert!(matches!(
+            status(dir, &test_args),
+            Err(VcsError::NotARepository)
+        ));
         Ok(())
     }
 
     #[test]
     fn incorrect_arg_number() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         let test_args: Vec<String> = vec![
             String::from("target/debug/vcs"),
             String::from("status"),
             String::from("test.txt"),
         ];
-        assert_eq!("Incorrect operands.", status(&test_args)?);
+        assert!(matches!(
+            status(dir, &test_args),
+            Err(VcsError::IncorrectOperands)
+        ));
         Ok(())
     }
 
     #[test]
     fn not_on_main() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = branch(&vec![
-            String::from("target/debug/vcs"),
-            String::from("branch"),
-            String::from("testbranch"),
-        ])?;
-        let _ = checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            String::from("testbranch"),
-        ])?;
-        let _ = File::create("test.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = branch(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("branch"),
+                String::from("testbranch"),
+            ],
+        )?;
+        let _ = checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                String::from("testbranch"),
+            ],
+        )?;
+        let _ = File::create(dir.join("test.txt"))?;
         assert_eq!(
             "On branch testbranch\nUntracked files:\n\ttest.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn no_changes() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
         assert_eq!(
             "On branch main\nnothing to commit\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn covers_empty_no_modification_nonempty() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt")?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"))?;
         assert_eq!(
             "On branch main\nUntracked files:\n\ttest.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn covers_empty_modification_empty() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file = File::create("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file = File::create(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
         file.write_all("Test modification".as_bytes())?;
         assert_eq!(
             "On branch main\nChanges not staged for commit:\n\tmodified: test.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn covers_modified_no_empty() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let mut file = File::create("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file = File::create(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
         file.write_all("Test modification".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!(
             "On branch main\nChanges to be committed:\n\tmodified: test.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn covers_deleted_no_empty() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test.txt"),
-        ])?;
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!(
             "On branch main\nChanges to be committed:\n\tdeleted: test.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn covers_new_file_no_empty() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
         assert_eq!(
             "On branch main\nChanges to be committed:\n\tnew file: test.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
         );
         Ok(())
     }
 
     #[test]
     fn covers_mix_modification_nonempty() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt")?;
-        let mut test2 = File::create("test2.txt")?;
-        let mut test3 = File::create("test3.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test2.txt"),
-        ])?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let _ = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add test2.txt"),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"))?;
+        let mut test2 = File::create(dir.join("test2.txt"))?;
+        let mut test3 = File::create(dir.join("test3.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test2.txt"),
+            ],
+        )?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test2.txt"),
+            ],
+        );
         test2.write_all("big test!".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test2.txt"),
-        ])?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test2.txt"),
+            ],
+        )?;
         test3.write_all("big test for test3!".as_bytes())?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test3.txt"),
-        ])?;
-        let _ = rm(&vec![
-            String::from("target/debug/vcs"),
-            String::from("rm"),
-            String::from("test.txt"),
-        ]);
-        clear_file_contents("test2.txt")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test3.txt"),
+            ],
+        )?;
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("test.txt"),
+            ],
+        );
+        clear_file_contents(dir.join("test2.txt"))?;
         test2.write_all("big test for test 2!".as_bytes())?;
-        let _ = File::create("test4.txt")?;
+        let _ = File::create(dir.join("test4.txt"))?;
         assert_eq!(
             "On branch main\nChanges to be committed:\n\tdeleted: test.txt\n\tmodified: test2.txt\n\tnew file: test3.txt\n\nChanges not staged for commit:\n\tmodified: test2.txt\n\nUntracked files:\n\ttest4.txt\n",
-            status(&vec![
-                String::from("target/debug/vcs"),
-                String::from("status")
-            ])?
+            status(dir, &[String::from("target/debug/vcs"),
+                String::from("status")])?
         );
         Ok(())
     }
 
     #[test]
     fn test_detached_head() -> Result<()> {
-        let _test_dir = make_test_dir()?;
-        create_dir("test_dir")?;
-        set_current_dir("test_dir")?;
-        let _ = init(&vec![
-            String::from("target/debug/vcs"),
-            String::from("init"),
-        ]);
-        let _ = File::create("test.txt")?;
-        let _ = File::create("test2.txt")?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test2.txt"),
-        ])?;
-        let _ = add(&vec![
-            String::from("target/debug/vcs"),
-            String::from("add"),
-            String::from("test.txt"),
-        ])?;
-        let (_, commit_hash) = commit(&vec![
-            String::from("target/debug/vcs"),
-            String::from("commit"),
-            String::from("Add files"),
-        ])?;
-        let _ = checkout(&vec![
-            String::from("target/debug/vcs"),
-            String::from("checkout"),
-            commit_hash.clone(),
-        ]);
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let _ = File::create(dir.join("test.txt"))?;
+        let _ = File::create(dir.join("test2.txt"))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test2.txt"),
+            ],
+        )?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let (_, commit_hash) = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add files"),
+            ],
+        )?;
+        let _ = checkout(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("checkout"),
+                commit_hash.clone(),
+            ],
+        );
         assert_eq!(
             format!("On commit {}\nnothing to commit\n", commit_hash),
-            status(&vec![
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn files_in_subdirectories() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        std::fs::create_dir_all(dir.join("src/deep"))?;
+        let mut file = File::create(dir.join("src/deep/lib.rs"))?;
+        file.write_all(b"lib")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("src/deep/lib.rs"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add lib.rs"),
+            ],
+        )?;
+        let mut file = File::create(dir.join("src/deep/lib.rs"))?;
+        file.write_all(b"changed")?;
+        let _ = File::create(dir.join("src/new.rs"))?;
+        assert_eq!(
+            "On branch main\nChanges not staged for commit:\n\tmodified: src/deep/lib.rs\n\nUntracked files:\n\tsrc/new.rs\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ignored_files() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut ignore = File::create(dir.join(".vcsignore"))?;
+        ignore.write_all(b"target/\n*.swp\n")?;
+        std::fs::create_dir_all(dir.join("target/debug"))?;
+        let _ = File::create(dir.join("target/debug/vcs"))?;
+        let _ = File::create(dir.join(".notes.swp"))?;
+        let _ = File::create(dir.join("notes.txt"))?;
+        assert_eq!(
+            "On branch main\nUntracked files:\n\t.vcsignore\n\tnotes.txt\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_changes() -> Result<()> {
+        use std::{
+            fs::{set_permissions, Permissions},
+            os::unix::fs::PermissionsExt,
+        };
+
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file = File::create(dir.join("run.sh"))?;
+        file.write_all(b"echo hi")?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("run.sh"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add run.sh"),
+            ],
+        )?;
+        set_permissions(dir.join("run.sh"), Permissions::from_mode(0o755))?;
+        assert_eq!(
+            "On branch main\nChanges not staged for commit:\n\tmode changed: run.sh\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("run.sh"),
+            ],
+        )?;
+        assert_eq!(
+            "On branch main\nChanges to be committed:\n\tmode changed: run.sh\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        Ok(())
+    }
+
+    // Colons and newlines can't be in file names on Windows
+    #[cfg(unix)]
+    #[test]
+    fn special_file_names() -> Result<()> {
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let names = [
+            "my notes.txt",
+            "a: b.md",
+            "line\nbreak.txt",
+            "naïve 日本語.txt",
+        ];
+        for name in names {
+            File::create(dir.join(name))?.write_all(name.as_bytes())?;
+            let _ = add(
+                dir,
+                &[
+                    String::from("target/debug/vcs"),
+                    String::from("add"),
+                    String::from(name),
+                ],
+            )?;
+        }
+        let _ = commit(
+            dir,
+            &[
                 String::from("target/debug/vcs"),
-                String::from("status"),
-            ])?
+                String::from("commit"),
+                String::from("Add files with special names"),
+            ],
+        )?;
+        assert_eq!(
+            "On branch main\nnothing to commit\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+
+        File::create(dir.join("my notes.txt"))?.write_all(b"changed")?;
+        let _ = rm(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("rm"),
+                String::from("line\nbreak.txt"),
+            ],
+        )?;
+        assert_eq!(
+            "On branch main\nChanges to be committed:\n\tdeleted: line\nbreak.txt\n\nChanges not staged for commit:\n\tmodified: my notes.txt\n",
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")]
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn uses_stat_cache() -> Result<()> {
+        use std::time::{Duration, SystemTime};
+
+        let test_dir = make_test_dir()?;
+        let dir = test_dir.path();
+        let _ = init(
+            dir,
+            &[String::from("target/debug/vcs"), String::from("init")],
+        );
+        let mut file = File::create(dir.join("test.txt"))?;
+        file.write_all(b"text")?;
+        file.set_modified(SystemTime::now() - Duration::from_secs(100))?;
+        let _ = add(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("add"),
+                String::from("test.txt"),
+            ],
+        )?;
+        let _ = commit(
+            dir,
+            &[
+                String::from("target/debug/vcs"),
+                String::from("commit"),
+                String::from("Add test.txt"),
+            ],
+        )?;
+        File::create(dir.join("untracked.txt"))?;
+        let run_status = || {
+            status(
+                dir,
+                &[String::from("target/debug/vcs"), String::from("status")],
+            )
+        };
+        let clean = "On branch main\nUntracked files:\n\tuntracked.txt\n";
+        assert_eq!(clean, run_status()?);
+        let repo = Repository::open(dir).unwrap();
+        assert_eq!(
+            vec!["test.txt"],
+            repo.load_index()?
+                .stat_cache
+                .files()
+                .keys()
+                .collect::<Vec<_>>()
         );
+
+        // Replaces the cached hash of test.txt, which status believes while the file's stat data
+        // is unchanged
+        let poison_cache = || {
+            repo.update_index(|index| {
+                let mut cached = index.stat_cache.files()["test.txt"].clone();
+                cached.hash = "0".repeat(64);
+                index.stat_cache.record("test.txt", cached);
+                Ok(())
+            })
+        };
+        poison_cache()?;
+        assert_eq!(
+            "On branch main\nChanges not staged for commit:\n\tmodified: test.txt\n\nUntracked files:\n\tuntracked.txt\n",
+            run_status()?
+        );
+
+        // A file modified after the index was written is racily clean: it could have changed
+        // without its stat data changing, so it is read again
+        File::options()
+            .write(true)
+            .open(dir.join("test.txt"))?
+            .set_modified(SystemTime::now() + Duration::from_secs(100))?;
+        assert_eq!(clean, run_status()?);
+        poison_cache()?;
+        assert_eq!(clean, run_status()?);
         Ok(())
     }
 }
@@ -1,3 +1,4 @@
 pub mod fs_utils;
 pub mod hash;
+pub mod quote;
 pub mod test_dir;
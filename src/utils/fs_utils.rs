@@ -1,60 +1,72 @@
 use std::{
-    fs::{metadata, File, OpenOptions},
-    io::{BufRead, BufReader, Lines, Read, Result},
-    path::{Path, PathBuf},
+    fs::{metadata, remove_file, rename, File, OpenOptions},
+    io::{BufRead, BufReader, Lines, Read, Result, Write},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::objects::object_exists;
+/// Counts the temporary files made by this process, so that each gets a distinct name
+static TEMP_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 /// Returns true iff `path` is a directory that exists
-pub fn directory_exists(path: &str) -> bool {
-    let path = Path::new(path);
+pub fn directory_exists<P: AsRef<Path>>(path: P) -> bool {
     metadata(path)
         .map(|metadata| metadata.is_dir())
         .unwrap_or(false)
 }
 
 /// Returns true iff `path` is a directory that exists
-pub fn file_exists(path: &str) -> bool {
-    let path = Path::new(path);
+pub fn file_exists<P: AsRef<Path>>(path: P) -> bool {
     metadata(path)
         .map(|metadata| metadata.is_file())
         .unwrap_or(false)
 }
 
 /// Gets the text in file
-pub fn get_file_contents(path: &str) -> Result<String> {
-    assert!(file_exists(path));
+///
+/// Throws an error if the file is not valid UTF-8; use `get_file_bytes` for arbitrary files
+pub fn get_file_contents<P: AsRef<Path>>(path: P) -> Result<String> {
     let mut file = File::open(path)?;
     let mut contents = String::new();
-    let _ = file.read_to_string(&mut contents);
+    file.read_to_string(&mut contents)?;
     Ok(contents)
 }
 
-/// Gets the line number in file. Throws an error if the line number doesn't exist
-pub fn get_line_in_file(filename: &str, line_num: usize) -> Result<String> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    reader.lines().nth(line_num).expect(&format!(
-        "{} is not {} lines long",
-        filename,
-        line_num.to_string()
-    ))
-}
-
-/// Gets the line number in the object corresponding to hash. Throws an error if the line number doesn't exist
-pub fn get_line_in_object(hash: &str, line_num: usize) -> Result<String> {
-    assert!(object_exists(hash));
-    let filename = format!(".vcs/objects/{}/{}", &hash[0..2], &hash[2..]);
-    get_line_in_file(&filename, line_num)
+/// Gets the raw bytes in file
+pub fn get_file_bytes<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut contents = vec![];
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
 }
 
 /// Removes all contents from a file
-pub fn clear_file_contents(path: &str) -> Result<()> {
+pub fn clear_file_contents<P: AsRef<Path>>(path: P) -> Result<()> {
     OpenOptions::new().write(true).truncate(true).open(path)?;
     Ok(())
 }
 
+/// Writes `contents` to `path` by writing a temporary file in `temp_dir`, syncing it to disk, then
+/// renaming it to `path`, so that `path` never holds a partial write. `temp_dir` must be on the
+/// same filesystem as `path`, and the temporary file's name starts with `tmp_`.
+pub fn write_atomically<P: AsRef<Path>>(temp_dir: &Path, path: P, contents: &[u8]) -> Result<()> {
+    let temp_path = temp_dir.join(format!(
+        "tmp_{}_{}",
+        process::id(),
+        TEMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| rename(&temp_path, path)) {
+        let _ = remove_file(&temp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
 /// Returns the lines of a file that can be flattened to an iterator
 ///
 /// Code from https://doc.rust-lang.org/rust-by-example/std_misc/file/read_lines.html
@@ -70,3 +82,20 @@ where
 pub fn no_dir_string(path: PathBuf) -> String {
     path.file_name().unwrap().to_str().unwrap().to_string()
 }
+
+/// Lexically removes `.` and `..` components from `path`, without touching the filesystem
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -5,9 +5,21 @@ use hex::encode;
 use sha2::{Digest, Sha256};
 
 pub fn sha2(string: &str) -> String {
+    sha2_bytes(string.as_bytes())
+}
+
+pub fn sha2_bytes(bytes: &[u8]) -> String {
     let mut hasher = Sha256::new();
-    hasher.update(string.as_bytes());
+    hasher.update(bytes);
     let result = hasher.finalize();
     let byte_arr: [u8; 32] = result.into();
-    encode(&byte_arr)
+    encode(byte_arr)
+}
+
+/// Returns true iff `word` looks like an object hash: 64 lowercase hex digits
+pub fn is_hash(word: &str) -> bool {
+    word.len() == 64
+        && word
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
 }
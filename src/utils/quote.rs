@@ -0,0 +1,110 @@
+use std::fmt::Write;
+
+/// Returns true iff `name` has to be quoted to be stored on one line of the index or a tree
+///
+/// A name is quoted if it holds a control character, such as a newline or tab, or if it starts
+/// with a `"` and would otherwise be read back as a quoted name.
+fn needs_quoting(name: &str) -> bool {
+    name.starts_with('"') || name.chars().any(char::is_control)
+}
+
+/// Outputs `name` as it is written in the index and in trees
+///
+/// Most names are written as they are, spaces, colons and non-ASCII characters included. A name
+/// that `needs_quoting` is written between double quotes, with `\` and `"` escaped by a `\`,
+/// newlines, carriage returns and tabs written `\n`, `\r` and `\t`, and any other control
+/// character written `\u{<hex code point>}`.
+pub fn quote_name(name: &str) -> String {
+    if !needs_quoting(name) {
+        return name.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in name.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{{{:x}}}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads back a name written by `quote_name`. Names that don't start with `"` are output as they
+/// are.
+///
+/// Returns `None` if a quoted name is not closed or holds an unknown escape
+pub fn unquote_name(text: &str) -> Option<String> {
+    let Some(quoted) = text.strip_prefix('"') else {
+        return Some(text.to_string());
+    };
+    let mut name = String::new();
+    let mut chars = quoted.chars();
+    loop {
+        match chars.next()? {
+            '"' => break,
+            '\\' => match chars.next()? {
+                '\\' => name.push('\\'),
+                '"' => name.push('"'),
+                'n' => name.push('\n'),
+                'r' => name.push('\r'),
+                't' => name.push('\t'),
+                'u' => {
+                    let rest = chars.as_str().strip_prefix('{')?;
+                    let (code, rest) = rest.split_once('}')?;
+                    name.push(char::from_u32(u32::from_str_radix(code, 16).ok()?)?);
+                    chars = rest.chars();
+                }
+                _ => return None,
+            },
+            c => name.push(c),
+        }
+    }
+    // Nothing may follow the closing quote
+    match chars.next() {
+        Some(_) => None,
+        None => Some(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Partitions for quote_name and unquote_name
+    //      name: plain, spaces and colons, non-ASCII, newlines and tabs, other control characters,
+    //          starting with a quote, backslashes
+    //      malformed quoted text: unclosed, unknown escape, text after the closing quote
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for (name, quoted) in [
+            ("test.txt", "test.txt"),
+            ("my notes.txt", "my notes.txt"),
+            ("a: b.md", "a: b.md"),
+            ("naïve 日本語.txt", "naïve 日本語.txt"),
+            ("back\\slash", "back\\slash"),
+            ("line\nbreak.txt", "\"line\\nbreak.txt\""),
+            ("tab\there\r", "\"tab\\there\\r\""),
+            ("bell\u{7}", "\"bell\\u{7}\""),
+            ("\"quoted\".txt", "\"\\\"quoted\\\".txt\""),
+            ("new\nback\\slash", "\"new\\nback\\\\slash\""),
+        ] {
+            assert_eq!(quoted, quote_name(name));
+            assert_eq!(Some(String::from(name)), unquote_name(quoted));
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        for bad in ["\"unclosed", "\"bad \\q escape\"", "\"a\" b", "\"\\u{zz}\""] {
+            assert_eq!(None, unquote_name(bad));
+        }
+    }
+}
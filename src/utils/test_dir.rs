@@ -1,54 +1,89 @@
 use std::{
-    collections::HashSet,
-    env::{current_dir, set_current_dir},
-    fs::{read_dir, remove_dir_all, remove_file},
-    io::Result,
-    path::PathBuf,
+    env::temp_dir,
+    fs::{create_dir_all, remove_dir_all, write},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-pub struct TestDir {
-    dir_name: PathBuf,          // Make this the directory that TestDir restores
-    children: HashSet<PathBuf>, // this is the original children in dir_name
-}
+use crate::{
+    error::Result,
+    operations::{add::add, commit::commit, dispatch},
+};
 
-pub fn make_test_dir() -> Result<TestDir> {
-    let path: PathBuf = current_dir()?;
+static NEXT_TEST_DIR: AtomicUsize = AtomicUsize::new(0);
 
-    let mut children: HashSet<PathBuf> = HashSet::new();
+/// A fresh, empty directory that is deleted (along with everything in it) when dropped
+///
+/// Every `TestDir` gets its own directory, so tests using them can run in parallel.
+pub struct TestDir {
+    dir_name: PathBuf,
+}
 
-    for entry_result in read_dir(&path)? {
-        let entry = entry_result?;
-        let entry_path = entry.path();
-        children.insert(entry_path);
+pub fn make_test_dir() -> io::Result<TestDir> {
+    let id = NEXT_TEST_DIR.fetch_add(1, Ordering::SeqCst);
+    let dir_name = temp_dir().join(format!("vcs-test-{}-{}", process::id(), id));
+    if dir_name.exists() {
+        remove_dir_all(&dir_name)?;
     }
+    create_dir_all(&dir_name)?;
+    Ok(TestDir { dir_name })
+}
 
-    Ok(TestDir {
-        dir_name: path,
-        children,
-    })
+impl TestDir {
+    /// The path of the directory
+    pub fn path(&self) -> &Path {
+        &self.dir_name
+    }
 }
 
 impl Drop for TestDir {
     fn drop(&mut self) {
-        Result::expect(
-            set_current_dir(&self.dir_name),
-            "Could not move to directory",
-        );
+        let _ = remove_dir_all(&self.dir_name);
+    }
+}
 
-        let mut paths =
-            Result::expect(read_dir(&self.dir_name), "Could not read current directory");
+/// Runs `vcs <operation> <operands>` in `dir`, as the command line would, and outputs what it
+/// logs
+pub fn run(dir: &Path, operation: &str, operands: &[&str]) -> Result<String> {
+    let mut args = vec![String::from("target/debug/vcs"), String::from(operation)];
+    args.extend(operands.iter().map(|operand| String::from(*operand)));
+    dispatch(dir, &args)
+}
 
-        while let Some(path) = paths.next() {
-            let path = Result::expect(path, "Could not get path").path();
-            if !&self.children.contains(&path) {
-                if path.is_dir() {
-                    let _ = remove_dir_all(path);
-                } else if path.is_file() {
-                    let _ = remove_file(path);
-                }
-            }
-        }
-    }
+/// Writes `text` to `filename` in `dir`, and stages it. Outputs the hash of the blob.
+pub fn write_and_add(dir: &Path, filename: &str, text: &str) -> Result<String> {
+    write(dir.join(filename), text)?;
+    let (_, hash) = add(
+        dir,
+        &[
+            String::from("target/debug/vcs"),
+            String::from("add"),
+            String::from(filename),
+        ],
+    )?;
+    Ok(hash)
+}
+
+/// Commits whatever is staged in `dir` with message `message`, and outputs the hash of the commit
+pub fn commit_staged(dir: &Path, message: &str) -> Result<String> {
+    let (_, commit_hash) = commit(
+        dir,
+        &[
+            String::from("target/debug/vcs"),
+            String::from("commit"),
+            String::from(message),
+        ],
+    )?;
+    Ok(commit_hash)
+}
+
+/// Writes `text` to `filename` in `dir`, stages it and commits it with message `message`.
+/// Outputs the hash of the commit.
+pub fn commit_file(dir: &Path, filename: &str, text: &str, message: &str) -> Result<String> {
+    write_and_add(dir, filename, text)?;
+    commit_staged(dir, message)
 }
 
 #[cfg(test)]
@@ -58,23 +93,23 @@ mod tests {
     use super::*;
 
     #[test]
-    fn check_remove_file() -> Result<()> {
-        let mut paths_before: HashSet<PathBuf> = HashSet::new();
-
-        let cur_dir: PathBuf = current_dir()?;
-        for path in read_dir(&cur_dir)? {
-            paths_before.insert(path?.path());
-        }
-
+    fn check_remove_file() -> io::Result<()> {
+        let path: PathBuf;
         {
-            let _test_dir = make_test_dir();
-            let _ = File::create("test_file.rs");
-        }
-
-        for path in read_dir(&cur_dir)? {
-            assert!(paths_before.contains(&path?.path()));
+            let test_dir = make_test_dir()?;
+            path = test_dir.path().to_path_buf();
+            File::create(path.join("test_file.rs"))?;
+            assert!(path.join("test_file.rs").exists());
         }
+        assert!(!path.exists());
+        Ok(())
+    }
 
+    #[test]
+    fn test_dirs_are_distinct() -> io::Result<()> {
+        let first = make_test_dir()?;
+        let second = make_test_dir()?;
+        assert_ne!(first.path(), second.path());
         Ok(())
     }
 }